@@ -0,0 +1,303 @@
+use std::time::{Duration, SystemTime};
+
+use crate::context::ConnectionContext;
+use crate::hook::ResponseHook;
+use crate::http::{Request, Response};
+use crate::logging::{self, LogWriter};
+
+/// The line format used by the access log.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessLogFormat {
+    /// The Common Log Format, as written by Apache and NGINX.
+    Common,
+    /// The Apache/NGINX combined log format, followed by the time taken to handle the request in milliseconds.
+    Combined,
+    /// One JSON object per line, for log pipelines such as Logstash or Fluent Bit.
+    Ndjson,
+}
+
+impl AccessLogFormat {
+    pub fn parse(format: &str) -> Option<AccessLogFormat> {
+        match format {
+            "common" => Some(AccessLogFormat::Common),
+            "combined" => Some(AccessLogFormat::Combined),
+            "ndjson" => Some(AccessLogFormat::Ndjson),
+            _ => None,
+        }
+    }
+}
+
+/// Writes one line per request in the Common Log Format, or in the combined format if asked to.
+///
+/// The combined format adds the `Referer` and `User-Agent` headers and ends with the time taken to handle the request
+/// in milliseconds, e.g.
+/// `127.0.0.1 - - [01/Jun/2023:12:34:56 +0000] "GET / HTTP/1.1" 200 512 "-" "curl/8.0.1" 3`.
+pub struct CombinedLogger {
+    writer: LogWriter,
+    combined: bool,
+}
+
+impl CombinedLogger {
+    pub fn new(writer: LogWriter) -> CombinedLogger {
+        CombinedLogger {
+            writer,
+            combined: true,
+        }
+    }
+    
+    /// Like `new`, but writes the plain Common Log Format.
+    pub fn common(writer: LogWriter) -> CombinedLogger {
+        CombinedLogger {
+            writer,
+            combined: false,
+        }
+    }
+}
+
+impl ResponseHook for CombinedLogger {
+    fn after_send(&self, context: &ConnectionContext, request: &Request, response: &Response, duration: Duration) {
+        let mut entry = format!(
+            "{} - {} [{}] \"{} {} {}\" {} {}",
+            context.get_client_ip(),
+            context.get_user().unwrap_or("-"),
+            logging::format_clf_timestamp(SystemTime::now()),
+            request.get_method(),
+            request.get_path(),
+            request.get_version(),
+            response.get_status_code(),
+            response.get_body_length().unwrap_or(0),
+        );
+        
+        if self.combined {
+            entry += &format!(
+                " \"{}\" \"{}\" {}",
+                request.get_header("Referer").unwrap_or("-"),
+                request.get_header("User-Agent").unwrap_or("-"),
+                duration.as_millis(),
+            );
+        }
+        
+        entry.push('\n');
+        self.writer.write(&entry);
+    }
+    
+    fn flush(&self) {
+        self.writer.flush();
+    }
+}
+
+/// Writes one JSON object per request, as newline-delimited JSON.
+pub struct NdjsonLogger {
+    writer: LogWriter,
+}
+
+impl NdjsonLogger {
+    pub fn new(writer: LogWriter) -> NdjsonLogger {
+        NdjsonLogger {
+            writer,
+        }
+    }
+}
+
+impl ResponseHook for NdjsonLogger {
+    fn after_send(&self, context: &ConnectionContext, request: &Request, response: &Response, duration: Duration) {
+        let entry = json::object! {
+            timestamp: logging::format_timestamp(SystemTime::now()),
+            method: request.get_method().to_string(),
+            path: request.get_path(),
+            status: response.get_status_code(),
+            duration_ms: duration.as_millis() as u64,
+            bytes: response.get_body_length().unwrap_or(0),
+            ip: context.get_client_ip().to_string(),
+            request_id: context.get_request_id().to_string(),
+            listener: context.get_listener(),
+            upstream: context.get_upstream(),
+            upstream_address: context.get_upstream_address().map(|address| address.to_string()),
+            upstream_timeout_ms: context.get_upstream_timeout().map(|timeout| timeout.as_millis() as u64),
+            user: context.get_user(),
+        };
+        
+        self.writer.write(&format!("{}\n", entry.dump()));
+    }
+    
+    fn flush(&self) {
+        self.writer.flush();
+    }
+}
+
+/// Passes only a fraction of the successful requests on to an access logger, errors are always logged.
+pub struct SampledLogger {
+    logger: Box<dyn ResponseHook + Send + Sync>,
+    sample_rate: f64,
+}
+
+impl SampledLogger {
+    /// Wraps a logger, `sample_rate` is the fraction of requests to log between 0.0 and 1.0.
+    pub fn new(logger: Box<dyn ResponseHook + Send + Sync>, sample_rate: f64) -> SampledLogger {
+        SampledLogger {
+            logger,
+            sample_rate,
+        }
+    }
+}
+
+impl ResponseHook for SampledLogger {
+    fn after_send(&self, context: &ConnectionContext, request: &Request, response: &Response, duration: Duration) {
+        // The random number generator is kept per thread, so sampling doesn't contend on a lock.
+        if response.get_status_code() >= 400 || rand::random::<f64>() < self.sample_rate {
+            self.logger.after_send(context, request, response, duration);
+        }
+    }
+    
+    fn flush(&self) {
+        self.logger.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    use std::env;
+    use std::fs;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::path::PathBuf;
+    use std::process;
+    use std::time::Instant;
+    
+    /// A log file that's removed once the test is done with it.
+    struct LogFile(PathBuf);
+    
+    impl LogFile {
+        fn new(name: &str) -> LogFile {
+            let path = env::temp_dir().join(format!("web_server_access_log_{}_{}.log", process::id(), name));
+            let _ = fs::remove_file(&path);
+            
+            LogFile(path)
+        }
+        
+        fn writer(&self) -> LogWriter {
+            LogWriter::open(self.0.to_str().unwrap()).unwrap()
+        }
+        
+        fn lines(&self) -> Vec<String> {
+            fs::read_to_string(&self.0).unwrap_or_default().lines().map(str::to_string).collect()
+        }
+    }
+    
+    impl Drop for LogFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+    
+    fn exchange(path: &str, status_code: u16) -> (ConnectionContext, Request, Response) {
+        let request = Request::parse(&format!("GET {} HTTP/1.1\r\nHost: localhost\r\nUser-Agent: curl/8.0.1\r\n\r\n", path)).unwrap();
+        let context = ConnectionContext::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), &request, Instant::now(), false, "http");
+        
+        let mut response = Response::ok();
+        response.set_status_code(status_code);
+        response.set_body("hello");
+        
+        (context, request, response)
+    }
+    
+    #[test]
+    fn formats_are_parsed_by_name() {
+        assert_eq!(AccessLogFormat::parse("common"), Some(AccessLogFormat::Common));
+        assert_eq!(AccessLogFormat::parse("combined"), Some(AccessLogFormat::Combined));
+        assert_eq!(AccessLogFormat::parse("ndjson"), Some(AccessLogFormat::Ndjson));
+        assert_eq!(AccessLogFormat::parse("json"), None);
+    }
+    
+    #[test]
+    fn ndjson_entries_are_one_object_per_line() {
+        let log = LogFile::new("ndjson");
+        let logger = NdjsonLogger::new(log.writer());
+        
+        let (context, request, response) = exchange("/a", 200);
+        context.set_user("alice");
+        context.set_upstream("http://127.0.0.1:3000");
+        context.set_upstream_address(SocketAddr::from((Ipv4Addr::LOCALHOST, 3000)));
+        context.set_upstream_timeout(Duration::from_secs(30));
+        
+        logger.after_send(&context, &request, &response, Duration::from_millis(12));
+        logger.after_send(&context, &request, &response, Duration::from_millis(3));
+        
+        let lines = log.lines();
+        let entry = json::parse(&lines[0]).unwrap();
+        
+        assert_eq!(lines.len(), 2);
+        assert_eq!(entry["method"], "GET");
+        assert_eq!(entry["path"], "/a");
+        assert_eq!(entry["status"], 200);
+        assert_eq!(entry["duration_ms"], 12);
+        assert_eq!(entry["bytes"], 5);
+        assert_eq!(entry["ip"], "192.0.2.1");
+        assert_eq!(entry["request_id"], context.get_request_id().to_string().as_str());
+        assert_eq!(entry["listener"], "http");
+        assert_eq!(entry["user"], "alice");
+        assert_eq!(entry["upstream"], "http://127.0.0.1:3000");
+        assert_eq!(entry["upstream_address"], "127.0.0.1:3000");
+        assert_eq!(entry["upstream_timeout_ms"], 30_000);
+        assert!(entry["timestamp"].is_string());
+    }
+    
+    #[test]
+    fn ndjson_entries_without_an_upstream_have_nulls() {
+        let log = LogFile::new("nulls");
+        let logger = NdjsonLogger::new(log.writer());
+        
+        let (context, request, response) = exchange("/a", 200);
+        logger.after_send(&context, &request, &response, Duration::ZERO);
+        
+        let entry = json::parse(&log.lines()[0]).unwrap();
+        
+        for field in ["upstream", "upstream_address", "upstream_timeout_ms", "user"] {
+            assert!(entry[field].is_null(), "{}", field);
+        }
+    }
+    
+    #[test]
+    fn ndjson_entries_escape_what_clients_send() {
+        let log = LogFile::new("escaped");
+        let logger = NdjsonLogger::new(log.writer());
+        
+        let (context, request, response) = exchange("/a\"b\\c", 200);
+        logger.after_send(&context, &request, &response, Duration::ZERO);
+        
+        let lines = log.lines();
+        
+        assert_eq!(lines.len(), 1);
+        assert_eq!(json::parse(&lines[0]).unwrap()["path"], "/a\"b\\c");
+    }
+    
+    #[test]
+    fn combined_entries_end_with_the_duration() {
+        let log = LogFile::new("combined");
+        let logger = CombinedLogger::new(log.writer());
+        
+        let (context, request, response) = exchange("/a", 404);
+        logger.after_send(&context, &request, &response, Duration::from_millis(7));
+        
+        let line = &log.lines()[0];
+        
+        assert!(line.starts_with("192.0.2.1 - - ["), "{}", line);
+        assert!(line.ends_with("] \"GET /a HTTP/1.1\" 404 5 \"-\" \"curl/8.0.1\" 7"), "{}", line);
+    }
+    
+    #[test]
+    fn sampling_keeps_every_error() {
+        let log = LogFile::new("sampled");
+        let logger = SampledLogger::new(Box::new(NdjsonLogger::new(log.writer())), 0.0);
+        
+        for status_code in [200, 304, 404, 500] {
+            let (context, request, response) = exchange("/a", status_code);
+            logger.after_send(&context, &request, &response, Duration::ZERO);
+        }
+        
+        let statuses = log.lines().iter().map(|line| json::parse(line).unwrap()["status"].as_u16().unwrap()).collect::<Vec<_>>();
+        
+        assert_eq!(statuses, [404, 500]);
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ring::digest;
+
+use crate::http::Request;
+
+/// The realm shown by the browser's login prompt if none is configured.
+pub const DEFAULT_REALM: &str = "Restricted";
+
+/// The salt and hash of a bcrypt hash whose password nobody knows, which unknown users' passwords are checked against.
+const DUMMY_BCRYPT_HASH: &str = "2E.EucRiPPG.1sXtzotriuke3Yt2rfyaP75ZzzVKi8l56aR4fZvT.";
+
+/// A user's password, as written in the configuration or an htpasswd file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Password {
+    Plain(String),
+    /// A hash created with `htpasswd -B`, starting with `$2a$`, `$2b$` or `$2y$`.
+    Bcrypt(String),
+}
+
+impl Password {
+    fn parse(password: &str) -> Password {
+        match is_bcrypt_hash(password) {
+            true => Password::Bcrypt(password.to_string()),
+            false => Password::Plain(password.to_string()),
+        }
+    }
+    
+    fn verify(&self, candidate: &str) -> bool {
+        match self {
+            // Compare digests, so how long the comparison takes says nothing about the password.
+            Password::Plain(password) => {
+                let expected = digest::digest(&digest::SHA256, password.as_bytes());
+                let actual = digest::digest(&digest::SHA256, candidate.as_bytes());
+                
+                expected.as_ref() == actual.as_ref()
+            }
+            Password::Bcrypt(hash) => bcrypt::verify(candidate, hash).unwrap_or(false),
+        }
+    }
+}
+
+/// Asks for a user name and password on every request for a path prefix, with HTTP Basic authentication (RFC 7617).
+#[derive(Clone, Debug)]
+pub struct BasicAuth {
+    prefix: String,
+    realm: String,
+    users: HashMap<String, Password>,
+    /// What an unknown user's password is checked against, so the answer takes as long as for a known user.
+    dummy: Password,
+}
+
+impl BasicAuth {
+    pub fn new(prefix: &str, realm: &str) -> BasicAuth {
+        BasicAuth {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            realm: realm.to_string(),
+            users: HashMap::new(),
+            dummy: Password::Plain(String::new()),
+        }
+    }
+    
+    /// Adds a user, whose password is either in plain text or a bcrypt hash. A later password for the same user
+    /// replaces the earlier one.
+    pub fn with_user(mut self, user: &str, password: &str) -> BasicAuth {
+        let password = Password::parse(password);
+        
+        // Check unknown users with the same cost as the real hashes, e.g. `$2y$05$`.
+        if let Password::Bcrypt(hash) = &password {
+            if let Some(prefix) = hash.get(..7) {
+                self.dummy = Password::Bcrypt(format!("{}{}", prefix, DUMMY_BCRYPT_HASH));
+            }
+        }
+        
+        self.users.insert(user.to_string(), password);
+        
+        self
+    }
+    
+    pub fn get_prefix(&self) -> &str {
+        &self.prefix
+    }
+    
+    pub fn get_realm(&self) -> &str {
+        &self.realm
+    }
+    
+    /// Returns the names of the users that may sign in, sorted.
+    pub fn get_users(&self) -> Vec<&str> {
+        let mut users = self.users.keys().map(String::as_str).collect::<Vec<_>>();
+        users.sort();
+        
+        users
+    }
+    
+    /// Checks if a path is protected, see `covers`.
+    pub fn covers(&self, path: &str) -> bool {
+        covers(&self.prefix, path)
+    }
+    
+    /// Returns the user a request signed in as, or `None` if its `Authorization` header is missing, malformed or
+    /// names an unknown user or the wrong password.
+    pub fn authenticate(&self, request: &Request) -> Option<String> {
+        let (scheme, credentials) = request.get_header("Authorization")?.trim().split_once(' ')?;
+        
+        if !scheme.eq_ignore_ascii_case("Basic") {
+            return None;
+        }
+        
+        let credentials = String::from_utf8(BASE64.decode(credentials.trim()).ok()?).ok()?;
+        let (user, password) = credentials.split_once(':')?;
+        
+        match self.users.get(user) {
+            Some(expected) if expected.verify(password) => Some(user.to_string()),
+            Some(_) => None,
+            // Check the password anyway, so how long the answer takes doesn't tell which users exist.
+            None => {
+                self.dummy.verify(password);
+                
+                None
+            }
+        }
+    }
+    
+    /// Returns the `WWW-Authenticate` header value that asks the client to sign in.
+    pub fn challenge(&self) -> String {
+        format!("Basic realm=\"{}\", charset=\"UTF-8\"", self.realm.replace(['"', '\\'], ""))
+    }
+}
+
+/// Checks if a path is at or below a prefix, whole segments are compared so `/admin` doesn't cover `/administrator`.
+///
+/// Empty and `.` segments are skipped, since they name the same file and would otherwise get around the check.
+pub fn covers(prefix: &str, path: &str) -> bool {
+    let mut segments = path.split('/').filter(|segment| !segment.is_empty() && *segment != ".");
+    
+    prefix.split('/')
+        .filter(|segment| !segment.is_empty())
+        .all(|prefix_segment| segments.next() == Some(prefix_segment))
+}
+
+/// Checks if a password is a bcrypt hash rather than plain text.
+pub fn is_bcrypt_hash(password: &str) -> bool {
+    ["$2a$", "$2b$", "$2y$"].iter().any(|prefix| password.starts_with(prefix))
+}
+
+/// Reads the `user:hash` lines of an htpasswd file, skipping blank lines and comments.
+///
+/// Only bcrypt hashes are accepted, the older MD5 and crypt formats are too weak to protect anything.
+pub fn parse_htpasswd(contents: &str) -> Result<Vec<(String, String)>, String> {
+    let mut users = Vec::new();
+    
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        
+        match line.split_once(':') {
+            Some((user, hash)) if !user.is_empty() && is_bcrypt_hash(hash) => users.push((user.to_string(), hash.to_string())),
+            Some((user, _)) if !user.is_empty() => {
+                return Err(format!("line {} must use a bcrypt hash, which htpasswd -B creates", index + 1));
+            }
+            _ => return Err(format!("line {} must be user:hash", index + 1)),
+        }
+    }
+    
+    Ok(users)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn request(authorization: &str) -> Request {
+        Request::parse(&format!("GET /admin HTTP/1.1\r\nHost: localhost\r\nAuthorization: {}\r\n\r\n", authorization)).unwrap()
+    }
+    
+    fn basic(credentials: &str) -> Request {
+        request(&format!("Basic {}", BASE64.encode(credentials)))
+    }
+    
+    #[test]
+    fn right_password_signs_in() {
+        let auth = BasicAuth::new("/admin", DEFAULT_REALM).with_user("alice", "wonderland");
+        
+        assert_eq!(auth.authenticate(&basic("alice:wonderland")), Some("alice".to_string()));
+    }
+    
+    #[test]
+    fn wrong_password_is_refused() {
+        let auth = BasicAuth::new("/admin", DEFAULT_REALM).with_user("alice", "wonderland");
+        
+        for credentials in ["alice:looking-glass", "alice:", "alice:wonderland ", "bob:wonderland"] {
+            assert_eq!(auth.authenticate(&basic(credentials)), None, "{}", credentials);
+        }
+    }
+    
+    #[test]
+    fn wrong_bcrypt_password_is_refused() {
+        let hash = bcrypt::hash("wonderland", 4).unwrap();
+        let auth = BasicAuth::new("/admin", DEFAULT_REALM).with_user("alice", &hash);
+        
+        assert_eq!(auth.authenticate(&basic("alice:wonderland")), Some("alice".to_string()));
+        assert_eq!(auth.authenticate(&basic("alice:looking-glass")), None);
+        assert_eq!(auth.authenticate(&basic("bob:wonderland")), None);
+    }
+    
+    #[test]
+    fn unknown_users_are_checked_against_a_hash_of_the_same_cost() {
+        let hash = bcrypt::hash("wonderland", 5).unwrap();
+        let auth = BasicAuth::new("/admin", DEFAULT_REALM).with_user("alice", &hash);
+        
+        match &auth.dummy {
+            Password::Bcrypt(dummy) => {
+                assert_eq!(&dummy[..7], &hash[..7]);
+                assert!(!bcrypt::verify("", dummy).unwrap());
+            }
+            Password::Plain(_) => panic!("expected a bcrypt dummy"),
+        }
+    }
+    
+    #[test]
+    fn other_schemes_and_garbage_are_refused() {
+        let auth = BasicAuth::new("/admin", DEFAULT_REALM).with_user("alice", "wonderland");
+        
+        for authorization in [format!("Bearer {}", BASE64.encode("alice:wonderland")), "Basic !!!".to_string(), format!("Basic {}", BASE64.encode("alice"))] {
+            assert_eq!(auth.authenticate(&request(&authorization)), None, "{}", authorization);
+        }
+    }
+    
+    #[test]
+    fn prefixes_cover_whole_segments() {
+        assert!(covers("/admin", "/admin"));
+        assert!(covers("/admin", "/admin/users"));
+        assert!(covers("/admin", "//admin/./users"));
+        assert!(!covers("/admin", "/administrator"));
+        assert!(!covers("/admin", "/"));
+    }
+}
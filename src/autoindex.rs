@@ -0,0 +1,109 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::http::escape_html;
+use crate::logging;
+
+/// An entry shown in a directory listing.
+struct Entry {
+    name: String,
+    is_dir: bool,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+/// Renders an HTML listing of a directory's entries with their size and modification time, like nginx's autoindex.
+///
+/// Directories come first, hidden entries starting with a dot are left out. Links are made absolute from the request
+/// path, so they work whether or not it ends with a slash.
+pub fn render(request_path: &str, directory: &Path) -> io::Result<String> {
+    let mut entries = Vec::new();
+    
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        
+        if name.starts_with('.') {
+            continue;
+        }
+        
+        // Entries that disappear while the directory is read are skipped rather than failing the listing.
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        
+        entries.push(Entry {
+            name,
+            is_dir: metadata.is_dir(),
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+        });
+    }
+    
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+    
+    let base = request_path.trim_end_matches('/');
+    let title = escape_html(&format!("Index of {}/", base));
+    let mut rows = String::new();
+    
+    // Link to the parent directory, unless this is the root.
+    if !base.is_empty() {
+        let parent = &base[..base.rfind('/').unwrap_or(0)];
+        
+        rows.push_str(&format!("<tr><td><a href=\"{}/\">../</a></td><td></td><td></td></tr>\n", escape_html(parent)));
+    }
+    
+    for entry in &entries {
+        let suffix = if entry.is_dir { "/" } else { "" };
+        let size = if entry.is_dir { "-".to_string() } else { entry.size.to_string() };
+        let modified = entry.modified.map(logging::format_timestamp).unwrap_or_default();
+        
+        rows.push_str(&format!(
+            "<tr><td><a href=\"{}/{}{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>\n",
+            escape_html(base), encode_path_segment(&entry.name), suffix, escape_html(&entry.name), suffix, size, modified,
+        ));
+    }
+    
+    Ok(format!(
+        concat!(
+            "<!doctype html>\n",
+            "<html lang=\"en\">\n",
+            "<head>\n",
+            "<meta charset=\"utf-8\">\n",
+            "<title>{title}</title>\n",
+            "<style>",
+            "body{{font-family:system-ui,sans-serif;margin:2rem}}",
+            "td{{padding:.125rem 1.5rem .125rem 0}}",
+            "th{{text-align:left;border-bottom:1px solid #ddd}}",
+            "</style>\n",
+            "</head>\n",
+            "<body>\n",
+            "<h1>{title}</h1>\n",
+            "<table>\n",
+            "<tr><th>Name</th><th>Size</th><th>Last modified</th></tr>\n",
+            "{rows}",
+            "</table>\n",
+            "</body>\n",
+            "</html>\n",
+        ),
+        title = title,
+        rows = rows,
+    ))
+}
+
+/// Percent-encodes a file name for use in a URL path, leaving only unreserved characters as they are.
+fn encode_path_segment(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    
+    encoded
+}
@@ -0,0 +1,118 @@
+use std::path::PathBuf;
+
+use json::JsonValue;
+
+/// The help text printed by `--help`.
+pub const USAGE: &str = "\
+Usage: web_server [OPTIONS] [COMMAND]
+
+Commands:
+  check               Validate the configuration and the files it names, then exit without starting the server
+  generate-config     Print the default configuration, with the options below applied, and exit
+
+Options:
+  --config <PATH>     Read the configuration from PATH instead of config.json, config.toml or config.yaml
+  --port <PORT>       Listen on PORT, 0 lets the OS pick a free one
+  --bind <ADDRESS>    Listen on ADDRESS, e.g. 127.0.0.1 or ::
+  --web-root <DIR>    Serve the files in DIR
+  --threads <COUNT>   Handle requests on COUNT threads
+  --verbose           Log the details of every request
+  --dry-run           The same as the check command
+  -V, --version       Print the version and exit
+  -h, --help          Print this help and exit
+
+Options override the values in the configuration file, also when it's reloaded.";
+
+/// What the binary was asked to do.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    /// Start the server, the default.
+    Run,
+    /// Validate the configuration and the files it names, then exit.
+    Check,
+    /// Print the default configuration and exit.
+    GenerateConfig,
+    Version,
+    Help,
+}
+
+/// The parsed command line.
+pub struct Args {
+    pub command: Command,
+    /// The configuration file given with `--config`, the default ones are looked for if it's not given.
+    pub config_path: Option<PathBuf>,
+    /// The configuration values set by flags, keyed like the configuration file.
+    pub overrides: JsonValue,
+}
+
+/// Parses the arguments after the program name, returning a message for the user if they're invalid.
+///
+/// Options take their value either as the next argument or after an equals sign, e.g. `--port 8080` or `--port=8080`.
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args {
+        command: Command::Run,
+        config_path: None,
+        overrides: JsonValue::new_object(),
+    };
+    
+    let mut args = args.into_iter();
+    
+    while let Some(arg) = args.next() {
+        // Anything that isn't an option is the command, of which there can only be one.
+        if !arg.starts_with('-') {
+            if parsed.command != Command::Run {
+                return Err(format!("Unexpected argument {}", arg));
+            }
+            
+            parsed.command = match arg.as_str() {
+                "check" => Command::Check,
+                "generate-config" => Command::GenerateConfig,
+                _ => return Err(format!("Unknown command {}", arg)),
+            };
+            
+            continue;
+        }
+        
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) => (name.to_string(), Some(value.to_string())),
+            None => (arg, None),
+        };
+        
+        // Flags don't take a value, every other option needs one.
+        let is_flag = matches!(name.as_str(), "--verbose" | "--dry-run" | "-V" | "--version" | "-h" | "--help");
+        
+        if is_flag && inline_value.is_some() {
+            return Err(format!("{} doesn't take a value", name));
+        }
+        
+        match name.as_str() {
+            "--verbose" => parsed.overrides["verbose"] = true.into(),
+            "--dry-run" => parsed.command = Command::Check,
+            "-V" | "--version" => parsed.command = Command::Version,
+            "-h" | "--help" => parsed.command = Command::Help,
+            "--config" | "--port" | "--bind" | "--web-root" | "--threads" => {
+                let value = match inline_value.or_else(|| args.next()) {
+                    Some(value) => value,
+                    None => return Err(format!("{} needs a value", name)),
+                };
+                
+                match name.as_str() {
+                    "--config" => parsed.config_path = Some(PathBuf::from(value)),
+                    "--port" => match value.parse::<u16>() {
+                        Ok(port) => parsed.overrides["port"] = port.into(),
+                        Err(_) => return Err(format!("Invalid --port {}, it must be a number between 0 and 65535", value)),
+                    },
+                    "--threads" => match value.parse::<usize>() {
+                        Ok(thread_count) if thread_count > 0 => parsed.overrides["thread_count"] = thread_count.into(),
+                        _ => return Err(format!("Invalid --threads {}, it must be a number greater than 0", value)),
+                    },
+                    "--bind" => parsed.overrides["bind_address"] = value.into(),
+                    _ => parsed.overrides["web_root"] = value.into(),
+                }
+            }
+            _ => return Err(format!("Unknown option {}", name)),
+        }
+    }
+    
+    Ok(parsed)
+}
@@ -0,0 +1,313 @@
+use std::io::{self, Read, Write};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+/// The smallest body that's compressed unless a rule says otherwise, below it the headers cost more than is saved.
+pub const DEFAULT_MIN_SIZE_BYTES: usize = 1_024;
+
+/// Brotli quality from 0 to 11, the higher levels are too slow to run on every response.
+const BROTLI_QUALITY: u32 = 5;
+
+/// Brotli window size as a power of two, the encoder's default.
+const BROTLI_WINDOW_BITS: u32 = 22;
+
+/// The content types that are compressed without a rule, everything else is usually compressed already.
+const COMPRESSIBLE_TYPES: [&str; 7] = [
+    "text/",
+    "application/json",
+    "application/javascript",
+    "application/xml",
+    "application/manifest+json",
+    "application/wasm",
+    "image/svg+xml",
+];
+
+/// A content coding the server can compress responses with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    Brotli,
+}
+
+impl Encoding {
+    /// Returns the name used in `Accept-Encoding` and `Content-Encoding`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Brotli => "br",
+        }
+    }
+    
+    /// Returns the encoding of a precompressed file by its extension, e.g. `gz` for `index.html.gz`.
+    pub fn from_extension(extension: &str) -> Option<Encoding> {
+        match extension.to_ascii_lowercase().as_str() {
+            "gz" => Some(Encoding::Gzip),
+            "br" => Some(Encoding::Brotli),
+            _ => None,
+        }
+    }
+    
+    pub fn compress(&self, body: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(body)?;
+                
+                encoder.finish()
+            }
+            Encoding::Brotli => {
+                let mut compressed = Vec::new();
+                
+                // The writer only flushes everything once it's dropped.
+                {
+                    let mut encoder = brotli::CompressorWriter::new(&mut compressed, 4_096, BROTLI_QUALITY, BROTLI_WINDOW_BITS);
+                    encoder.write_all(body)?;
+                }
+                
+                Ok(compressed)
+            }
+        }
+    }
+    
+    /// Wraps a reader of encoded data in one that decompresses it while it's read, e.g. to stream a file.
+    pub fn decoder(&self, reader: impl Read + Send + 'static) -> Box<dyn Read + Send> {
+        match self {
+            Encoding::Gzip => Box::new(GzDecoder::new(reader)),
+            Encoding::Brotli => Box::new(brotli::Decompressor::new(reader, 4_096)),
+        }
+    }
+    
+    pub fn decompress(&self, body: &[u8]) -> io::Result<Vec<u8>> {
+        let mut decompressed = Vec::new();
+        
+        match self {
+            Encoding::Gzip => GzDecoder::new(body).read_to_end(&mut decompressed)?,
+            Encoding::Brotli => brotli::Decompressor::new(body, 4_096).read_to_end(&mut decompressed)?,
+        };
+        
+        Ok(decompressed)
+    }
+}
+
+/// Returns whether the client accepts an encoding, according to the `Accept-Encoding` header.
+pub fn accepts(accept_encoding: &str, encoding: Encoding) -> bool {
+    quality(accept_encoding, encoding) > 0.0
+}
+
+/// Picks the encoding the client prefers, brotli wins a tie because it compresses better.
+pub fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    let brotli = quality(accept_encoding, Encoding::Brotli);
+    let gzip = quality(accept_encoding, Encoding::Gzip);
+    
+    if brotli > 0.0 && brotli >= gzip {
+        Some(Encoding::Brotli)
+    } else if gzip > 0.0 {
+        Some(Encoding::Gzip)
+    } else {
+        None
+    }
+}
+
+/// Returns the quality the client gives an encoding, an exact match takes precedence over `*`.
+fn quality(accept_encoding: &str, encoding: Encoding) -> f32 {
+    let mut wildcard = 0.0;
+    
+    for coding in accept_encoding.split(',') {
+        let mut parameters = coding.split(';');
+        let name = parameters.next().unwrap_or_default().trim();
+        let quality = parameters
+            .filter_map(|parameter| parameter.trim().strip_prefix("q="))
+            .find_map(|quality| quality.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        
+        if name.eq_ignore_ascii_case(encoding.name()) {
+            return quality;
+        }
+        
+        if name == "*" {
+            wildcard = quality;
+        }
+    }
+    
+    wildcard
+}
+
+/// Overrides whether responses of a content type are compressed, e.g. never for `image/` or always for JSON.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompressionRule {
+    content_type_prefix: String,
+    min_size_bytes: Option<usize>,
+    never: bool,
+}
+
+impl CompressionRule {
+    pub fn new(content_type_prefix: &str, min_size_bytes: Option<usize>, never: bool) -> CompressionRule {
+        CompressionRule {
+            content_type_prefix: content_type_prefix.to_ascii_lowercase(),
+            min_size_bytes,
+            never,
+        }
+    }
+    
+    pub fn get_content_type_prefix(&self) -> &str {
+        &self.content_type_prefix
+    }
+    
+    pub fn get_min_size_bytes(&self) -> Option<usize> {
+        self.min_size_bytes
+    }
+    
+    pub fn is_never(&self) -> bool {
+        self.never
+    }
+}
+
+/// Decides which responses are worth compressing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompressionConfig {
+    enabled: bool,
+    min_size_bytes: usize,
+    rules: Vec<CompressionRule>,
+}
+
+impl CompressionConfig {
+    pub fn new(enabled: bool, min_size_bytes: usize, rules: Vec<CompressionRule>) -> CompressionConfig {
+        CompressionConfig {
+            enabled,
+            min_size_bytes,
+            rules,
+        }
+    }
+    
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+    
+    pub fn get_min_size_bytes(&self) -> usize {
+        self.min_size_bytes
+    }
+    
+    pub fn get_rules(&self) -> &Vec<CompressionRule> {
+        &self.rules
+    }
+    
+    /// Checks whether a content type could be compressed at all, which is what `Vary` has to account for.
+    pub fn is_eligible(&self, content_type: &str) -> bool {
+        let content_type = content_type.to_ascii_lowercase();
+        
+        match self.find_rule(&content_type) {
+            Some(rule) => !rule.never,
+            None => COMPRESSIBLE_TYPES.iter().any(|prefix| content_type.starts_with(prefix)),
+        }
+    }
+    
+    /// Checks whether a body of a content type and size should be compressed, the first matching rule decides.
+    pub fn should_compress(&self, content_type: &str, size: usize) -> bool {
+        let min_size_bytes = self.find_rule(&content_type.to_ascii_lowercase())
+            .and_then(|rule| rule.min_size_bytes)
+            .unwrap_or(self.min_size_bytes);
+        
+        self.is_eligible(content_type) && size >= min_size_bytes
+    }
+    
+    fn find_rule(&self, content_type: &str) -> Option<&CompressionRule> {
+        self.rules.iter().find(|rule| content_type.starts_with(&rule.content_type_prefix))
+    }
+}
+
+impl Default for CompressionConfig {
+    fn default() -> CompressionConfig {
+        CompressionConfig::new(true, DEFAULT_MIN_SIZE_BYTES, Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn encodings_are_accepted_by_name_or_wildcard() {
+        assert!(accepts("gzip, deflate", Encoding::Gzip));
+        assert!(accepts("GZIP", Encoding::Gzip));
+        assert!(accepts("*", Encoding::Brotli));
+        assert!(!accepts("deflate", Encoding::Gzip));
+        assert!(!accepts("", Encoding::Brotli));
+    }
+    
+    #[test]
+    fn a_zero_quality_refuses_an_encoding() {
+        assert!(!accepts("gzip;q=0", Encoding::Gzip));
+        assert!(!accepts("*;q=0", Encoding::Brotli));
+        
+        // An exact match takes precedence over the wildcard, in either order.
+        assert!(!accepts("*, br;q=0", Encoding::Brotli));
+        assert!(accepts("*;q=0, gzip", Encoding::Gzip));
+    }
+    
+    #[test]
+    fn the_preferred_encoding_is_negotiated() {
+        assert_eq!(negotiate("gzip, br"), Some(Encoding::Brotli));
+        assert_eq!(negotiate("gzip;q=1, br;q=0.5"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("br;q=0, gzip;q=0.1"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("*"), Some(Encoding::Brotli));
+        assert_eq!(negotiate("identity"), None);
+        assert_eq!(negotiate("gzip;q=0, br;q=0"), None);
+    }
+    
+    #[test]
+    fn malformed_qualities_count_as_one() {
+        assert_eq!(negotiate("br;q=high, gzip;q=0.9"), Some(Encoding::Brotli));
+    }
+    
+    #[test]
+    fn compressed_bodies_decompress_to_the_original() {
+        let body = "<p>Compress me.</p>".repeat(100);
+        
+        for encoding in [Encoding::Gzip, Encoding::Brotli] {
+            let compressed = encoding.compress(body.as_bytes()).unwrap();
+            
+            assert!(compressed.len() < body.len(), "{}", encoding.name());
+            assert_eq!(encoding.decompress(&compressed).unwrap(), body.as_bytes(), "{}", encoding.name());
+            
+            let mut streamed = Vec::new();
+            encoding.decoder(io::Cursor::new(compressed)).read_to_end(&mut streamed).unwrap();
+            
+            assert_eq!(streamed, body.as_bytes(), "{}", encoding.name());
+        }
+    }
+    
+    #[test]
+    fn precompressed_extensions_are_recognised() {
+        assert_eq!(Encoding::from_extension("gz"), Some(Encoding::Gzip));
+        assert_eq!(Encoding::from_extension("BR"), Some(Encoding::Brotli));
+        assert_eq!(Encoding::from_extension("zip"), None);
+    }
+    
+    #[test]
+    fn text_is_compressed_once_it_is_large_enough() {
+        let config = CompressionConfig::default();
+        
+        assert!(config.should_compress("text/html; charset=utf-8", DEFAULT_MIN_SIZE_BYTES));
+        assert!(config.should_compress("Application/JSON", DEFAULT_MIN_SIZE_BYTES));
+        assert!(!config.should_compress("text/html", DEFAULT_MIN_SIZE_BYTES - 1));
+        assert!(!config.should_compress("image/png", DEFAULT_MIN_SIZE_BYTES * 10));
+    }
+    
+    #[test]
+    fn the_first_matching_rule_decides() {
+        let config = CompressionConfig::new(true, DEFAULT_MIN_SIZE_BYTES, vec![
+            CompressionRule::new("text/csv", None, true),
+            CompressionRule::new("text/", Some(10), false),
+            CompressionRule::new("image/bmp", None, false),
+        ]);
+        
+        assert!(!config.is_eligible("text/csv"));
+        assert!(!config.should_compress("text/csv", 1_000_000));
+        assert!(config.should_compress("text/plain", 10));
+        assert!(!config.should_compress("text/plain", 9));
+        assert!(config.is_eligible("image/bmp"));
+        assert!(config.should_compress("image/bmp", DEFAULT_MIN_SIZE_BYTES));
+    }
+}
@@ -0,0 +1,84 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::compression::Encoding;
+
+use crate::http::{self, Response};
+use crate::status::StatusCode;
+
+/// The headers a 304 keeps from the response it replaces, so caches can update their stored copy (RFC 9110 §15.4.5).
+const NOT_MODIFIED_HEADERS: [&str; 6] = ["Cache-Control", "Content-Location", "ETag", "Expires", "Last-Modified", "Vary"];
+
+/// Returns a strong entity tag for a body, e.g. `"3f9a0c2e11d4b7a8"`.
+pub fn etag(body: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(body);
+    
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+/// Returns a weak entity tag for a file that isn't read into memory, made from its size and modification time.
+pub fn file_etag(length: u64, modified: SystemTime) -> String {
+    let modified = modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+    
+    format!("W/\"{:x}-{:x}\"", length, modified.as_nanos())
+}
+
+/// Marks an entity tag as belonging to the encoded body, so it stays unique per representation.
+pub fn encoded_etag(etag: &str, encoding: Encoding) -> String {
+    match etag.strip_suffix('"') {
+        Some(etag) => format!("{}-{}\"", etag, encoding.name()),
+        None => etag.to_string(),
+    }
+}
+
+/// Checks whether an `If-None-Match` header matches an entity tag.
+///
+/// The comparison is weak, so `W/` prefixes and the suffixes added for encoded bodies are ignored.
+pub fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if if_none_match.trim() == "*" {
+        return true;
+    }
+    
+    let etag = strip_etag(etag);
+    
+    if_none_match.split(',').any(|candidate| strip_etag(candidate) == etag)
+}
+
+/// Returns the opaque part of an entity tag, without the quotes and any encoding suffix.
+fn strip_etag(etag: &str) -> &str {
+    let etag = etag.trim();
+    let etag = etag.strip_prefix("W/").unwrap_or(etag).trim_matches('"');
+    
+    [Encoding::Gzip, Encoding::Brotli]
+        .iter()
+        .find_map(|encoding| etag.strip_suffix(encoding.name()).and_then(|etag| etag.strip_suffix('-')))
+        .unwrap_or(etag)
+}
+
+/// Returns the `Last-Modified` value for a modification time, which can't lie in the future (RFC 9110 §8.8.2.1).
+pub fn last_modified(modified: SystemTime) -> String {
+    http::format_http_date(modified.min(SystemTime::now()))
+}
+
+/// Checks whether a resource is unchanged since the `If-Modified-Since` date, an unreadable date never matches.
+pub fn not_modified_since(if_modified_since: &str, last_modified: &str) -> bool {
+    match (http::parse_http_date(if_modified_since), http::parse_http_date(last_modified)) {
+        (Some(if_modified_since), Some(last_modified)) => last_modified <= if_modified_since,
+        _ => false,
+    }
+}
+
+/// Turns a response into a 304 Not Modified without a body, keeping only the headers caches need.
+pub fn not_modified(response: &Response) -> Response {
+    let mut not_modified = Response::with_status(StatusCode::NotModified);
+    
+    for (name, value) in response.get_headers().iter() {
+        if NOT_MODIFIED_HEADERS.iter().any(|header| header.eq_ignore_ascii_case(name)) {
+            not_modified.add_header(name, value);
+        }
+    }
+    
+    not_modified
+}
@@ -0,0 +1,258 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use json::JsonValue;
+use yaml_rust2::{Yaml, YamlLoader};
+
+/// An error that occurred while loading or validating the server configuration.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// A file or directory could not be read or created.
+    Io { path: String, source: io::Error },
+    /// The configuration file is not valid JSON.
+    Parse(json::Error),
+    /// The configuration file is not valid TOML or YAML.
+    Syntax { format: ConfigFormat, message: String },
+    /// The configuration file has an extension that isn't supported.
+    UnsupportedFormat(String),
+    /// A configuration value has the wrong type or range.
+    InvalidField { field: String, message: String, received: Option<String> },
+    /// A required configuration value is missing.
+    MissingField { field: String, message: String },
+    /// A configuration key isn't used by the server, which is usually a typo of one that is.
+    UnknownField { field: String, suggestion: Option<String> },
+    /// More than one page is configured with the same file path.
+    DuplicatePagePath(String),
+    /// Several configuration values are invalid, all of them are reported at once.
+    Multiple(Vec<ConfigError>),
+}
+
+impl ConfigError {
+    pub fn io(path: &str, source: io::Error) -> ConfigError {
+        ConfigError::Io {
+            path: path.to_string(),
+            source,
+        }
+    }
+    
+    pub fn invalid(field: &str, message: &str) -> ConfigError {
+        ConfigError::InvalidField {
+            field: field.to_string(),
+            message: message.to_string(),
+            received: None,
+        }
+    }
+    
+    /// Records the value that was found in the configuration, so it can be shown next to what was expected.
+    ///
+    /// A value that isn't there at all turns the error into a `MissingField`.
+    pub fn with_value(self, value: &JsonValue) -> ConfigError {
+        match self {
+            ConfigError::InvalidField { field, message, .. } if value.is_null() => ConfigError::MissingField {
+                field,
+                message,
+            },
+            ConfigError::InvalidField { field, message, .. } => ConfigError::InvalidField {
+                field,
+                message,
+                received: Some(value.dump()),
+            },
+            error => error,
+        }
+    }
+    
+    /// Reports a key of the object at `parent` that isn't one of the known ones, suggesting the closest known key.
+    ///
+    /// The parent is the path of the object, e.g. `tls` or `pages[2]`, and empty for the top level.
+    pub fn unknown(parent: &str, key: &str, known: &[&str]) -> ConfigError {
+        let path = |key: &str| if parent.is_empty() { key.to_string() } else { format!("{}.{}", parent, key) };
+        
+        // Only suggest keys that are a couple of typos away, anything further is more likely a different setting.
+        let suggestion = known.iter()
+            .map(|known_key| (edit_distance(key, known_key), known_key))
+            .filter(|(distance, known_key)| *distance <= 2.max(known_key.len() / 4))
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, known_key)| path(known_key));
+        
+        ConfigError::UnknownField {
+            field: path(key),
+            suggestion,
+        }
+    }
+    
+    /// Turns the collected errors into a single error, if there are any.
+    pub fn check_all(mut errors: Vec<ConfigError>) -> Result<(), ConfigError> {
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.remove(0)),
+            _ => Err(ConfigError::Multiple(errors)),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io { path, source } => write!(f, "Failed to access {}: {}", path, source),
+            ConfigError::Parse(error) => write!(f, "Failed to parse the configuration file: {}", error),
+            ConfigError::Syntax { format, message } => write!(f, "Failed to parse the {} configuration file: {}", format, message.trim_end()),
+            ConfigError::UnsupportedFormat(extension) => write!(f, "Unsupported configuration format: {}", extension),
+            ConfigError::InvalidField { field, message, received: None } => write!(f, "Invalid {}, {}!", field, message),
+            ConfigError::InvalidField { field, message, received: Some(received) } => {
+                write!(f, "Invalid {}, {} but got {}!", field, message, received)
+            }
+            ConfigError::MissingField { field, message } => write!(f, "Missing {}, it {}!", field, message),
+            ConfigError::UnknownField { field, suggestion: None } => write!(f, "Unknown setting {}!", field),
+            ConfigError::UnknownField { field, suggestion: Some(suggestion) } => {
+                write!(f, "Unknown setting {}, did you mean {}?", field, suggestion)
+            }
+            ConfigError::DuplicatePagePath(path) => write!(f, "Duplicate page path, {} is used by more than one page!", path),
+            ConfigError::Multiple(errors) => {
+                write!(f, "Found {} configuration errors:", errors.len())?;
+                
+                for (index, error) in errors.iter().enumerate() {
+                    write!(f, "\n  {}. {}", index + 1, error)?;
+                }
+                
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfigError::Io { source, .. } => Some(source),
+            ConfigError::Parse(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<json::Error> for ConfigError {
+    fn from(error: json::Error) -> ConfigError {
+        ConfigError::Parse(error)
+    }
+}
+
+/// A format the configuration file can be written in, all of them are read into the same JSON structure.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// The file names looked for when no configuration file is given, in order of preference.
+    pub const DEFAULT_FILE_NAMES: [&'static str; 4] = ["config.json", "config.toml", "config.yaml", "config.yml"];
+    
+    /// Picks the format by the file's extension, files without one are assumed to be JSON.
+    pub fn from_path(path: &Path) -> Result<ConfigFormat, ConfigError> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") | None => Ok(ConfigFormat::Json),
+            Some("toml") => Ok(ConfigFormat::Toml),
+            Some("yaml") | Some("yml") => Ok(ConfigFormat::Yaml),
+            Some(extension) => Err(ConfigError::UnsupportedFormat(extension.to_string())),
+        }
+    }
+    
+    /// Parses a configuration into the JSON structure the server reads its settings from.
+    pub fn parse(&self, source: &str) -> Result<JsonValue, ConfigError> {
+        match self {
+            ConfigFormat::Json => Ok(json::parse(source)?),
+            ConfigFormat::Toml => source.parse::<toml::Table>()
+                .map(|table| toml_to_json(toml::Value::Table(table)))
+                .map_err(|error| ConfigError::Syntax { format: *self, message: error.to_string() }),
+            ConfigFormat::Yaml => {
+                let documents = YamlLoader::load_from_str(source)
+                    .map_err(|error| ConfigError::Syntax { format: *self, message: error.to_string() })?;
+                
+                // Only the first document is used, an empty file is the same as an empty JSON file.
+                Ok(documents.into_iter().next().map(yaml_to_json).unwrap_or(JsonValue::Null))
+            }
+        }
+    }
+}
+
+impl fmt::Display for ConfigFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigFormat::Json => write!(f, "JSON"),
+            ConfigFormat::Toml => write!(f, "TOML"),
+            ConfigFormat::Yaml => write!(f, "YAML"),
+        }
+    }
+}
+
+/// Converts a TOML value to JSON, dates and times become strings since JSON has no type for them.
+fn toml_to_json(value: toml::Value) -> JsonValue {
+    match value {
+        toml::Value::String(string) => string.into(),
+        toml::Value::Integer(integer) => integer.into(),
+        toml::Value::Float(float) => float.into(),
+        toml::Value::Boolean(boolean) => boolean.into(),
+        toml::Value::Datetime(datetime) => datetime.to_string().into(),
+        toml::Value::Array(array) => JsonValue::Array(array.into_iter().map(toml_to_json).collect()),
+        toml::Value::Table(table) => {
+            let mut object = JsonValue::new_object();
+            
+            for (key, value) in table {
+                object[key] = toml_to_json(value);
+            }
+            
+            object
+        }
+    }
+}
+
+/// Converts a YAML value to JSON, keys that aren't strings are written the way they appear in the file.
+fn yaml_to_json(value: Yaml) -> JsonValue {
+    match value {
+        Yaml::String(string) => string.into(),
+        Yaml::Integer(integer) => integer.into(),
+        Yaml::Real(real) => real.parse::<f64>().map(JsonValue::from).unwrap_or(real.into()),
+        Yaml::Boolean(boolean) => boolean.into(),
+        Yaml::Array(array) => JsonValue::Array(array.into_iter().map(yaml_to_json).collect()),
+        Yaml::Hash(hash) => {
+            let mut object = JsonValue::new_object();
+            
+            for (key, value) in hash {
+                let key = match key {
+                    Yaml::String(key) | Yaml::Real(key) => key,
+                    Yaml::Integer(key) => key.to_string(),
+                    Yaml::Boolean(key) => key.to_string(),
+                    _ => continue,
+                };
+                
+                object[key] = yaml_to_json(value);
+            }
+            
+            object
+        }
+        Yaml::Alias(_) | Yaml::Null | Yaml::BadValue => JsonValue::Null,
+    }
+}
+
+/// Counts the single-character insertions, deletions and substitutions it takes to turn one key into another.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        
+        previous = current;
+    }
+    
+    previous[b.len()]
+}
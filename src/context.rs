@@ -0,0 +1,138 @@
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use uuid::Uuid;
+
+use crate::head_cache::CacheStatus;
+use crate::http::{HttpVersion, Method, Request};
+
+/// Per-request state shared by everything that runs or logs while handling a request, so it doesn't have to be
+/// passed around piece by piece.
+pub struct ConnectionContext {
+    request_id: Uuid,
+    client_ip: IpAddr,
+    tls: bool,
+    listener: String,
+    http_version: HttpVersion,
+    start_time: Instant,
+    method: Method,
+    path: String,
+    upstream: OnceLock<String>,
+    upstream_address: OnceLock<SocketAddr>,
+    upstream_timeout: OnceLock<Duration>,
+    user: OnceLock<String>,
+    head_cache_status: OnceLock<CacheStatus>,
+}
+
+impl ConnectionContext {
+    pub fn new(client_ip: IpAddr, request: &Request, start_time: Instant, tls: bool, listener: &str) -> ConnectionContext {
+        ConnectionContext {
+            request_id: Uuid::new_v4(),
+            client_ip,
+            tls,
+            listener: listener.to_string(),
+            http_version: request.get_version(),
+            start_time,
+            method: *request.get_method(),
+            path: request.get_path().to_string(),
+            upstream: OnceLock::new(),
+            upstream_address: OnceLock::new(),
+            upstream_timeout: OnceLock::new(),
+            user: OnceLock::new(),
+            head_cache_status: OnceLock::new(),
+        }
+    }
+    
+    pub fn get_request_id(&self) -> &Uuid {
+        &self.request_id
+    }
+    
+    pub fn get_client_ip(&self) -> IpAddr {
+        self.client_ip
+    }
+    
+    /// Returns whether the connection was terminated with TLS by the server.
+    pub fn is_tls(&self) -> bool {
+        self.tls
+    }
+    
+    /// Returns the name of the listener the connection arrived on.
+    pub fn get_listener(&self) -> &str {
+        &self.listener
+    }
+    
+    pub fn get_http_version(&self) -> HttpVersion {
+        self.http_version
+    }
+    
+    /// Returns when the server started reading the request.
+    pub fn get_start_time(&self) -> Instant {
+        self.start_time
+    }
+    
+    pub fn get_method(&self) -> &Method {
+        &self.method
+    }
+    
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+    
+    /// Records the upstream a proxied request was forwarded to, only the first one counts.
+    pub fn set_upstream(&self, upstream: &str) {
+        let _ = self.upstream.set(upstream.to_string());
+    }
+    
+    /// Returns the upstream the request was forwarded to, if it was proxied.
+    pub fn get_upstream(&self) -> Option<&str> {
+        self.upstream.get().map(String::as_str)
+    }
+    
+    /// Records the address the upstream resolved to, only the first one counts.
+    pub fn set_upstream_address(&self, address: SocketAddr) {
+        let _ = self.upstream_address.set(address);
+    }
+    
+    /// Returns the address the upstream resolved to, if the request was proxied and the connection succeeded.
+    pub fn get_upstream_address(&self) -> Option<SocketAddr> {
+        self.upstream_address.get().copied()
+    }
+    
+    /// Records how long the upstream had to answer, only the first one counts.
+    pub fn set_upstream_timeout(&self, timeout: Duration) {
+        let _ = self.upstream_timeout.set(timeout);
+    }
+    
+    /// Returns how long the upstream had to answer, if the request was proxied.
+    pub fn get_upstream_timeout(&self) -> Option<Duration> {
+        self.upstream_timeout.get().copied()
+    }
+    
+    /// Records the user the request was authenticated as, only the first one counts.
+    pub fn set_user(&self, user: &str) {
+        let _ = self.user.set(user.to_string());
+    }
+    
+    /// Returns the user the request was authenticated as, if the path asked for credentials.
+    pub fn get_user(&self) -> Option<&str> {
+        self.user.get().map(String::as_str)
+    }
+    
+    /// Records whether the HEAD cache answered the request, only the first one counts.
+    pub fn set_head_cache_status(&self, status: CacheStatus) {
+        let _ = self.head_cache_status.set(status);
+    }
+    
+    /// Returns whether the HEAD cache answered the request, if it's a HEAD request for a static file that may be cached.
+    pub fn get_head_cache_status(&self) -> Option<CacheStatus> {
+        self.head_cache_status.get().copied()
+    }
+}
+
+impl fmt::Display for ConnectionContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}] {} {} {}", self.request_id, self.client_ip, self.method, self.path)
+    }
+}
@@ -0,0 +1,53 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+
+use crate::config::ConfigError;
+
+/// An error that stops the server, or a single connection, from doing its work.
+#[derive(Debug)]
+pub enum ServerError {
+    /// The configuration couldn't be loaded or is invalid.
+    Config(ConfigError),
+    /// A listening socket couldn't be set up on its address.
+    Bind { address: SocketAddr, source: io::Error },
+    /// Reading from or writing to a connection failed, usually because the client went away.
+    Io(io::Error),
+    /// The thread accepting connections panicked.
+    Panicked,
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ServerError::Config(error) => error.fmt(f),
+            ServerError::Bind { address, source } => write!(f, "Failed to bind to {}: {}", address, source),
+            ServerError::Io(error) => write!(f, "Connection error: {}", error),
+            ServerError::Panicked => write!(f, "The server thread panicked"),
+        }
+    }
+}
+
+impl Error for ServerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ServerError::Config(error) => Some(error),
+            ServerError::Bind { source, .. } => Some(source),
+            ServerError::Io(error) => Some(error),
+            ServerError::Panicked => None,
+        }
+    }
+}
+
+impl From<ConfigError> for ServerError {
+    fn from(error: ConfigError) -> ServerError {
+        ServerError::Config(error)
+    }
+}
+
+impl From<io::Error> for ServerError {
+    fn from(error: io::Error) -> ServerError {
+        ServerError::Io(error)
+    }
+}
@@ -0,0 +1,771 @@
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::warn;
+
+use crate::http::{BodyReader, HttpVersion, Method, Request, Response};
+use crate::proxy::ProxyError;
+
+/// How long a backend gets to accept the connection and to send each part of its response.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+const FCGI_VERSION: u8 = 1;
+
+/// The record types of the FastCGI protocol, see the FastCGI specification §8.
+const FCGI_BEGIN_REQUEST: u8 = 1;
+const FCGI_END_REQUEST: u8 = 3;
+const FCGI_PARAMS: u8 = 4;
+const FCGI_STDIN: u8 = 5;
+const FCGI_STDOUT: u8 = 6;
+const FCGI_STDERR: u8 = 7;
+
+/// The role of a backend that answers requests, as opposed to authorizing or filtering them.
+const FCGI_RESPONDER: u16 = 1;
+
+/// Every connection carries a single request, so they all use the same ID.
+const REQUEST_ID: u16 = 1;
+
+/// The most content a single record can hold.
+const MAX_RECORD_BYTES: usize = 65_535;
+
+/// The largest response head a backend may send, so a broken one can't make the server buffer without end.
+const MAX_RESPONSE_HEAD_BYTES: usize = 65_536;
+
+/// Where a FastCGI backend, e.g. PHP-FPM, accepts connections.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FastCgiAddress {
+    /// A `host:port` pair, e.g. `127.0.0.1:9000`.
+    Tcp(String),
+    /// A Unix domain socket, e.g. `/run/php/php-fpm.sock`.
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl FastCgiAddress {
+    /// Parses a `host:port` pair, or a Unix socket path prefixed with `unix:`.
+    pub fn parse(address: &str) -> Result<FastCgiAddress, String> {
+        if let Some(path) = address.strip_prefix("unix:") {
+            #[cfg(unix)]
+            return match path.is_empty() {
+                true => Err("must have a socket path after unix:".to_string()),
+                false => Ok(FastCgiAddress::Unix(PathBuf::from(path))),
+            };
+            
+            #[cfg(not(unix))]
+            return Err(format!("can't use the Unix socket {} on this platform", path));
+        }
+        
+        match address.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok_and(|port| port > 0) => Ok(FastCgiAddress::Tcp(address.to_string())),
+            _ => Err("must be host:port or unix:/path/to/socket".to_string()),
+        }
+    }
+}
+
+impl fmt::Display for FastCgiAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FastCgiAddress::Tcp(address) => write!(f, "{}", address),
+            #[cfg(unix)]
+            FastCgiAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// A FastCGI backend a route's requests are passed to, which answers them like a CGI script would, but without a
+/// process being spawned for each one.
+///
+/// Every request gets a connection of its own, which is closed once the response has been relayed.
+#[derive(Clone, Debug)]
+pub struct FastCgi {
+    address: FastCgiAddress,
+    script: Option<String>,
+    params: Vec<(String, String)>,
+    timeout: Duration,
+}
+
+impl FastCgi {
+    pub fn new(address: FastCgiAddress) -> FastCgi {
+        FastCgi {
+            address,
+            script: None,
+            params: Vec::new(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+    
+    /// Passes every request to a single script relative to the web root, e.g. a framework's `index.php`, rather than
+    /// to the file the path names.
+    pub fn with_script(mut self, script: &str) -> FastCgi {
+        self.script = Some(script.trim_start_matches('/').to_string());
+        
+        self
+    }
+    
+    /// Adds a parameter that's passed along with the CGI ones, which are overridden by it.
+    pub fn with_param(mut self, name: &str, value: &str) -> FastCgi {
+        self.params.push((name.to_string(), value.to_string()));
+        
+        self
+    }
+    
+    pub fn with_timeout(mut self, timeout: Duration) -> FastCgi {
+        self.timeout = timeout;
+        
+        self
+    }
+    
+    pub fn get_address(&self) -> &FastCgiAddress {
+        &self.address
+    }
+    
+    pub fn get_script(&self) -> Option<&str> {
+        self.script.as_deref()
+    }
+    
+    pub fn get_params(&self) -> &Vec<(String, String)> {
+        &self.params
+    }
+    
+    pub fn get_timeout(&self) -> Duration {
+        self.timeout
+    }
+    
+    /// Passes a request to the backend and returns its response, whose body is relayed while it's sent to the client.
+    ///
+    /// `body` passes the request body on as it's read from the client, its length is then taken from the Content-Length
+    /// header. Without it, the body buffered in the request is sent. `document_root` is the absolute web root the script
+    /// is looked up in. Anything the backend writes to its error stream is logged as a warning.
+    pub fn send(&self, request: &Request, body: Option<&mut BodyReader>, document_root: &Path, client_ip: IpAddr, is_tls: bool) -> Result<Response, ProxyError> {
+        let mut connection = Connection::open(&self.address, self.timeout)?;
+        connection.set_timeouts(self.timeout)?;
+        
+        // Build every record up front, so the request goes out in as few writes as possible.
+        let mut records = Vec::new();
+        
+        let mut begin_request = FCGI_RESPONDER.to_be_bytes().to_vec();
+        begin_request.extend([0; 6]);
+        write_record(&mut records, FCGI_BEGIN_REQUEST, &begin_request);
+        
+        let content_length = match body {
+            Some(_) => request.get_header("Content-Length").and_then(|length| length.trim().parse().ok()).unwrap_or(0),
+            None => request.get_body().len(),
+        };
+        
+        let mut params = Vec::new();
+        
+        for (name, value) in self.cgi_params(request, content_length, document_root, client_ip, is_tls) {
+            encode_length(&mut params, name.len());
+            encode_length(&mut params, value.len());
+            params.extend(name.as_bytes());
+            params.extend(value.as_bytes());
+        }
+        
+        // Each stream ends with an empty record.
+        write_stream(&mut records, FCGI_PARAMS, &params);
+        
+        match body {
+            // Send every piece of the body as soon as it's read, so it never has to be held in memory as a whole.
+            Some(body) => {
+                connection.write_all(&records)?;
+                
+                while let Some(piece) = body.read_chunk().map_err(ProxyError::RequestBody)? {
+                    records.clear();
+                    write_record(&mut records, FCGI_STDIN, &piece);
+                    connection.write_all(&records)?;
+                }
+                
+                records.clear();
+                write_record(&mut records, FCGI_STDIN, &[]);
+            }
+            None => write_stream(&mut records, FCGI_STDIN, request.get_body()),
+        }
+        
+        connection.write_all(&records)?;
+        connection.flush()?;
+        
+        let reader = BufReader::new(StdoutReader::new(BufReader::new(connection), self.address.to_string()));
+        
+        read_cgi_response(request, reader)
+    }
+    
+    /// Returns the parameters of RFC 3875, plus the ones PHP expects, followed by the configured ones.
+    fn cgi_params(&self, request: &Request, content_length: usize, document_root: &Path, client_ip: IpAddr, is_tls: bool) -> Vec<(String, String)> {
+        let path = request.path();
+        
+        let (script_name, path_info) = match &self.script {
+            Some(script) => (format!("/{}", script), Some(path)),
+            None => (path.to_string(), None),
+        };
+        
+        let script_filename = document_root.join(script_name.trim_start_matches('/'));
+        
+        let host = request.get_header("Host").unwrap_or_default();
+        let (server_name, server_port) = match host.rsplit_once(':') {
+            Some((name, port)) if !port.contains(']') => (name, port.to_string()),
+            _ => (host, if is_tls { "443" } else { "80" }.to_string()),
+        };
+        
+        let query_string = request.get_path().split_once('?').map_or("", |(_, query)| query);
+        
+        let mut params = vec![
+            ("GATEWAY_INTERFACE", "CGI/1.1".to_string()),
+            ("SERVER_SOFTWARE", format!("web_server/{}", env!("CARGO_PKG_VERSION"))),
+            ("SERVER_PROTOCOL", request.get_version().to_string()),
+            ("SERVER_NAME", server_name.to_string()),
+            ("SERVER_PORT", server_port),
+            ("REQUEST_METHOD", request.get_method().to_string()),
+            ("REQUEST_URI", request.get_path().to_string()),
+            ("QUERY_STRING", query_string.to_string()),
+            ("DOCUMENT_ROOT", document_root.display().to_string()),
+            ("SCRIPT_NAME", script_name),
+            ("SCRIPT_FILENAME", script_filename.display().to_string()),
+            ("REMOTE_ADDR", client_ip.to_string()),
+            ("CONTENT_LENGTH", content_length.to_string()),
+            ("CONTENT_TYPE", request.get_header("Content-Type").unwrap_or_default().to_string()),
+        ];
+        
+        if let Some(path_info) = path_info {
+            params.push(("PATH_INFO", path_info.to_string()));
+        }
+        
+        if is_tls {
+            params.push(("HTTPS", "on".to_string()));
+        }
+        
+        let mut params = params.into_iter().map(|(name, value)| (name.to_string(), value)).collect::<Vec<_>>();
+        
+        // Headers are passed with an HTTP_ prefix. Proxy is left out, since scripts would read it as HTTP_PROXY and use
+        // it as their outgoing proxy (httpoxy), and the body's headers already have parameters of their own.
+        for (name, value) in request.get_headers().iter() {
+            let skipped = ["Proxy", "Content-Type", "Content-Length"].iter().any(|skipped| name.eq_ignore_ascii_case(skipped));
+            
+            if !skipped {
+                params.push((format!("HTTP_{}", name.to_ascii_uppercase().replace('-', "_")), value.to_string()));
+            }
+        }
+        
+        params.extend(self.params.iter().cloned());
+        
+        params
+    }
+}
+
+/// A connection to a backend, over TCP or a Unix socket.
+enum Connection {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Connection {
+    fn open(address: &FastCgiAddress, timeout: Duration) -> Result<Connection, ProxyError> {
+        match address {
+            FastCgiAddress::Tcp(address) => {
+                let addresses = address.to_socket_addrs().map_err(ProxyError::Connect)?;
+                let mut last_error = io::Error::new(io::ErrorKind::NotFound, format!("{} didn't resolve to any address", address));
+                
+                for address in addresses {
+                    match TcpStream::connect_timeout(&address, timeout) {
+                        Ok(stream) => return Ok(Connection::Tcp(stream)),
+                        Err(error) if error.kind() == io::ErrorKind::TimedOut => return Err(ProxyError::Timeout),
+                        Err(error) => last_error = error,
+                    }
+                }
+                
+                Err(ProxyError::Connect(last_error))
+            }
+            #[cfg(unix)]
+            FastCgiAddress::Unix(path) => UnixStream::connect(path).map(Connection::Unix).map_err(ProxyError::Connect),
+        }
+    }
+    
+    fn set_timeouts(&self, timeout: Duration) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))
+            }
+            #[cfg(unix)]
+            Connection::Unix(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))
+            }
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.read(buffer),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.read(buffer),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.write(buffer),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.write(buffer),
+        }
+    }
+    
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Reads what the backend writes to its standard output, which is spread over records, until it ends the request.
+struct StdoutReader {
+    connection: BufReader<Connection>,
+    address: String,
+    remaining: usize,
+    padding: usize,
+    done: bool,
+}
+
+impl StdoutReader {
+    fn new(connection: BufReader<Connection>, address: String) -> StdoutReader {
+        StdoutReader {
+            connection,
+            address,
+            remaining: 0,
+            padding: 0,
+            done: false,
+        }
+    }
+    
+    fn skip(&mut self, length: usize) -> io::Result<()> {
+        io::copy(&mut (&mut self.connection).take(length as u64), &mut io::sink())?;
+        
+        Ok(())
+    }
+}
+
+impl Read for StdoutReader {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.done || buffer.is_empty() {
+                return Ok(0);
+            }
+            
+            // Finish the record being read before looking at the next one.
+            if self.remaining > 0 {
+                let length = buffer.len().min(self.remaining);
+                let bytes_read = self.connection.read(&mut buffer[..length])?;
+                
+                if bytes_read == 0 {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the backend closed the connection mid-record"));
+                }
+                
+                self.remaining -= bytes_read;
+                
+                if self.remaining == 0 {
+                    self.skip(self.padding)?;
+                }
+                
+                return Ok(bytes_read);
+            }
+            
+            let mut header = [0; 8];
+            self.connection.read_exact(&mut header)?;
+            
+            if header[0] != FCGI_VERSION {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unsupported FastCGI version {}", header[0])));
+            }
+            
+            let length = usize::from(u16::from_be_bytes([header[4], header[5]]));
+            let padding = usize::from(header[6]);
+            
+            match header[1] {
+                FCGI_STDOUT if length > 0 => {
+                    self.remaining = length;
+                    self.padding = padding;
+                }
+                FCGI_STDERR if length > 0 => {
+                    let mut message = vec![0; length];
+                    self.connection.read_exact(&mut message)?;
+                    self.skip(padding)?;
+                    
+                    warn!("The FastCGI backend {} reported: {}", self.address, String::from_utf8_lossy(&message).trim_end());
+                }
+                FCGI_END_REQUEST => {
+                    self.skip(length + padding)?;
+                    self.done = true;
+                }
+                // Empty stream records only mark the end of the stream, which END_REQUEST does too.
+                _ => self.skip(length + padding)?,
+            }
+        }
+    }
+}
+
+fn write_record(records: &mut Vec<u8>, record_type: u8, content: &[u8]) {
+    records.extend([FCGI_VERSION, record_type]);
+    records.extend(REQUEST_ID.to_be_bytes());
+    records.extend((content.len() as u16).to_be_bytes());
+    // No padding, and a reserved byte.
+    records.extend([0, 0]);
+    records.extend(content);
+}
+
+/// Writes a stream in records of the largest size, followed by the empty record that ends it.
+fn write_stream(records: &mut Vec<u8>, record_type: u8, content: &[u8]) {
+    for chunk in content.chunks(MAX_RECORD_BYTES) {
+        write_record(records, record_type, chunk);
+    }
+    
+    write_record(records, record_type, &[]);
+}
+
+/// Writes the length of a parameter name or value, in one byte if it's short and in four otherwise.
+fn encode_length(params: &mut Vec<u8>, length: usize) {
+    if length < 128 {
+        params.push(length as u8);
+    } else {
+        params.extend((length as u32 | 0x8000_0000).to_be_bytes());
+    }
+}
+
+/// Reads the headers a CGI script answers with, including the `Status` header that sets the status code, and relays the
+/// rest as the body.
+fn read_cgi_response(request: &Request, mut reader: BufReader<StdoutReader>) -> Result<Response, ProxyError> {
+    let mut headers = Vec::new();
+    let mut head_bytes = 0;
+    
+    loop {
+        let mut line = String::new();
+        let bytes_read = reader.read_line(&mut line)?;
+        head_bytes += bytes_read;
+        
+        if bytes_read == 0 {
+            return Err(ProxyError::InvalidResponse("the backend ended its response before the headers did".to_string()));
+        }
+        
+        if head_bytes > MAX_RESPONSE_HEAD_BYTES {
+            return Err(ProxyError::InvalidResponse(format!("the headers are larger than {} bytes", MAX_RESPONSE_HEAD_BYTES)));
+        }
+        
+        let line = line.trim_end_matches(['\r', '\n']);
+        
+        if line.is_empty() {
+            break;
+        }
+        
+        match line.split_once(':') {
+            Some((name, value)) => headers.push((name.trim().to_string(), value.trim().to_string())),
+            None => return Err(ProxyError::InvalidResponse(format!("malformed header {}", line))),
+        }
+    }
+    
+    // Scripts set the status with a header, a redirect without one is a 302 (RFC 3875 §6.3.3).
+    let status = headers.iter().find(|(name, _)| name.eq_ignore_ascii_case("Status")).map(|(_, value)| value.as_str());
+    let is_redirect = headers.iter().any(|(name, _)| name.eq_ignore_ascii_case("Location"));
+    
+    let (status_code, status_message) = match status {
+        Some(status) => {
+            let (code, message) = status.split_once(' ').unwrap_or((status, ""));
+            
+            match code.parse::<u16>() {
+                Ok(code) if (100..600).contains(&code) => (code, message.to_string()),
+                _ => return Err(ProxyError::InvalidResponse(format!("invalid Status {}", status))),
+            }
+        }
+        None if is_redirect => (302, "Found".to_string()),
+        None => (200, "OK".to_string()),
+    };
+    
+    let mut response = Response::new(HttpVersion::Http11, status_code, &status_message);
+    let mut content_length = None;
+    
+    // The server frames the body itself, so headers about the script's framing aren't passed on.
+    for (name, value) in headers {
+        if name.eq_ignore_ascii_case("Content-Length") {
+            content_length = value.parse::<u64>().ok();
+        }
+        
+        let skipped = ["Status", "Connection", "Transfer-Encoding"].iter().any(|skipped| name.eq_ignore_ascii_case(skipped));
+        
+        if !skipped {
+            response.add_header(&name, &value);
+        }
+    }
+    
+    // Answers to HEAD, 204 and 304 never have a body, whatever the script wrote (RFC 9112 §6.3).
+    let has_body = *request.get_method() != Method::Head && status_code != 204 && status_code != 304;
+    
+    if has_body {
+        match content_length {
+            Some(content_length) => response.set_body_stream(reader.take(content_length), Some(content_length)),
+            None => response.set_body_stream(reader, None),
+        }
+    }
+    
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    use std::collections::HashMap;
+    use std::net::{Ipv4Addr, TcpListener};
+    use std::thread::{self, JoinHandle};
+    
+    /// A record as the backend received it.
+    type Record = (u8, Vec<u8>);
+    
+    /// Reads a record, skipping its padding.
+    fn read_record(stream: &mut impl Read) -> Record {
+        let mut header = [0; 8];
+        stream.read_exact(&mut header).unwrap();
+        
+        assert_eq!(header[0], FCGI_VERSION);
+        assert_eq!(u16::from_be_bytes([header[2], header[3]]), REQUEST_ID);
+        
+        let mut content = vec![0; usize::from(u16::from_be_bytes([header[4], header[5]]))];
+        stream.read_exact(&mut content).unwrap();
+        stream.read_exact(&mut vec![0; usize::from(header[6])]).unwrap();
+        
+        (header[1], content)
+    }
+    
+    /// Writes a record with some padding, which the server has to skip.
+    fn padded_record(record_type: u8, content: &[u8]) -> Vec<u8> {
+        let mut record = vec![FCGI_VERSION, record_type];
+        record.extend(REQUEST_ID.to_be_bytes());
+        record.extend((content.len() as u16).to_be_bytes());
+        record.extend([3, 0]);
+        record.extend(content);
+        record.extend([0; 3]);
+        
+        record
+    }
+    
+    /// Starts a backend that records the request it gets and answers it with `stdout`, split over two records and
+    /// preceded by a message on its error stream.
+    fn backend(stdout: &'static [u8]) -> (FastCgi, JoinHandle<Vec<Record>>) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let fastcgi = FastCgi::new(FastCgiAddress::Tcp(listener.local_addr().unwrap().to_string()));
+        
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut records = Vec::new();
+            
+            // The request is complete once its body ends with an empty record.
+            loop {
+                let record = read_record(&mut stream);
+                let is_end = record.0 == FCGI_STDIN && record.1.is_empty();
+                records.push(record);
+                
+                if is_end {
+                    break;
+                }
+            }
+            
+            let (first, second) = stdout.split_at(stdout.len() / 2);
+            
+            let mut response = padded_record(FCGI_STDERR, b"PHP Notice: something");
+            response.extend(padded_record(FCGI_STDOUT, first));
+            response.extend(padded_record(FCGI_STDOUT, second));
+            response.extend(padded_record(FCGI_STDOUT, &[]));
+            response.extend(padded_record(FCGI_END_REQUEST, &[0; 8]));
+            stream.write_all(&response).unwrap();
+            
+            records
+        });
+        
+        (fastcgi, handle)
+    }
+    
+    /// Decodes the name-value pairs of the PARAMS stream.
+    fn decode_params(mut params: &[u8]) -> HashMap<String, String> {
+        let mut decoded = HashMap::new();
+        
+        let decode_length = |params: &mut &[u8]| -> usize {
+            if params[0] < 128 {
+                let length = usize::from(params[0]);
+                *params = &params[1..];
+                
+                length
+            } else {
+                let length = u32::from_be_bytes([params[0] & 0x7F, params[1], params[2], params[3]]) as usize;
+                *params = &params[4..];
+                
+                length
+            }
+        };
+        
+        while !params.is_empty() {
+            let name_length = decode_length(&mut params);
+            let value_length = decode_length(&mut params);
+            let (name, rest) = params.split_at(name_length);
+            let (value, rest) = rest.split_at(value_length);
+            decoded.insert(String::from_utf8(name.to_vec()).unwrap(), String::from_utf8(value.to_vec()).unwrap());
+            params = rest;
+        }
+        
+        decoded
+    }
+    
+    /// Joins the content of every record of a stream.
+    fn stream_content(records: &[Record], record_type: u8) -> Vec<u8> {
+        records.iter().filter(|(kind, _)| *kind == record_type).flat_map(|(_, content)| content.clone()).collect()
+    }
+    
+    /// Writes a response out, returning its head and body.
+    fn written(mut response: Response) -> (String, Vec<u8>) {
+        let mut bytes = Vec::new();
+        response.write_to(&mut bytes).unwrap();
+        
+        let header_end = bytes.windows(4).position(|window| window == b"\r\n\r\n").unwrap() + 4;
+        
+        (String::from_utf8_lossy(&bytes[..header_end]).to_string(), bytes[header_end..].to_vec())
+    }
+    
+    fn client_ip() -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))
+    }
+    
+    #[test]
+    fn addresses_are_parsed() {
+        assert_eq!(FastCgiAddress::parse("127.0.0.1:9000"), Ok(FastCgiAddress::Tcp("127.0.0.1:9000".to_string())));
+        #[cfg(unix)]
+        assert_eq!(FastCgiAddress::parse("unix:/run/php/php-fpm.sock"), Ok(FastCgiAddress::Unix(PathBuf::from("/run/php/php-fpm.sock"))));
+        
+        for address in ["127.0.0.1", "127.0.0.1:0", ":9000", "unix:"] {
+            assert!(FastCgiAddress::parse(address).is_err(), "{}", address);
+        }
+    }
+    
+    #[test]
+    fn lengths_use_four_bytes_from_128() {
+        let mut params = Vec::new();
+        encode_length(&mut params, 127);
+        encode_length(&mut params, 128);
+        
+        assert_eq!(params, [127, 0x80, 0, 0, 128]);
+    }
+    
+    #[test]
+    fn streams_are_split_into_records_and_ended_by_an_empty_one() {
+        let mut records = Vec::new();
+        write_stream(&mut records, FCGI_STDIN, &vec![b'a'; MAX_RECORD_BYTES + 1]);
+        
+        let mut reader = records.as_slice();
+        let lengths = (0..3).map(|_| read_record(&mut reader).1.len()).collect::<Vec<_>>();
+        
+        assert_eq!(lengths, [MAX_RECORD_BYTES, 1, 0]);
+        assert!(reader.is_empty());
+    }
+    
+    #[test]
+    fn requests_are_sent_as_records() {
+        let (fastcgi, backend) = backend(b"Status: 201 Created\r\nContent-Type: text/plain\r\n\r\ncreated");
+        let fastcgi = fastcgi.with_script("index.php").with_param("APP_ENV", "test");
+        
+        // The server buffers the body into the request before it's passed on.
+        let mut request = Request::parse("POST /users?page=2 HTTP/1.1\r\nHost: example.com:8080\r\nProxy: http://evil\r\nContent-Type: text/plain\r\nContent-Length: 5\r\nX-Trace: abc\r\n\r\n").unwrap();
+        request.set_body("hello");
+        
+        let response = fastcgi.send(&request, None, Path::new("/srv/www"), client_ip(), false).unwrap();
+        let records = backend.join().unwrap();
+        
+        // A responder role, without keeping the connection open.
+        assert_eq!(records[0], (FCGI_BEGIN_REQUEST, vec![0, 1, 0, 0, 0, 0, 0, 0]));
+        
+        let params = decode_params(&stream_content(&records, FCGI_PARAMS));
+        
+        assert_eq!(params["REQUEST_METHOD"], "POST");
+        assert_eq!(params["REQUEST_URI"], "/users?page=2");
+        assert_eq!(params["QUERY_STRING"], "page=2");
+        assert_eq!(params["SCRIPT_NAME"], "/index.php");
+        assert_eq!(params["SCRIPT_FILENAME"], "/srv/www/index.php");
+        assert_eq!(params["PATH_INFO"], "/users");
+        assert_eq!(params["SERVER_NAME"], "example.com");
+        assert_eq!(params["SERVER_PORT"], "8080");
+        assert_eq!(params["REMOTE_ADDR"], "192.0.2.1");
+        assert_eq!(params["CONTENT_LENGTH"], "5");
+        assert_eq!(params["CONTENT_TYPE"], "text/plain");
+        assert_eq!(params["HTTP_X_TRACE"], "abc");
+        assert_eq!(params["APP_ENV"], "test");
+        assert!(!params.contains_key("HTTP_PROXY"));
+        assert!(!params.contains_key("HTTP_CONTENT_LENGTH"));
+        assert_eq!(stream_content(&records, FCGI_STDIN), b"hello");
+        
+        let (head, body) = written(response);
+        
+        assert!(head.starts_with("HTTP/1.1 201 Created\r\n"), "{}", head);
+        assert!(head.contains("Content-Type: text/plain\r\n"), "{}", head);
+        assert!(!head.contains("Status:"), "{}", head);
+        assert_eq!(body, b"created");
+    }
+    
+    #[test]
+    fn streamed_bodies_are_sent_a_record_per_piece() {
+        let (fastcgi, backend) = backend(b"Content-Type: text/plain\r\n\r\nok");
+        
+        let request = Request::parse("POST /upload.php HTTP/1.1\r\nHost: localhost\r\nContent-Length: 20000\r\n\r\n").unwrap();
+        let mut stream = io::Cursor::new(vec![b'a'; 20_000]);
+        let mut body = BodyReader::new(&mut stream, &[], &request);
+        
+        fastcgi.send(&request, Some(&mut body), Path::new("/srv/www"), client_ip(), true).unwrap();
+        let records = backend.join().unwrap();
+        
+        let params = decode_params(&stream_content(&records, FCGI_PARAMS));
+        let stdin = records.iter().filter(|(kind, _)| *kind == FCGI_STDIN).map(|(_, content)| content.len()).collect::<Vec<_>>();
+        
+        assert_eq!(params["CONTENT_LENGTH"], "20000");
+        assert_eq!(params["SERVER_PORT"], "443");
+        assert_eq!(params["HTTPS"], "on");
+        assert_eq!(stdin, [8_192, 8_192, 3_616, 0]);
+    }
+    
+    #[test]
+    fn redirects_without_a_status_are_found() {
+        let (fastcgi, backend) = backend(b"Location: /login\r\n\r\n");
+        
+        let request = Request::parse("GET /account HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let response = fastcgi.send(&request, None, Path::new("/srv/www"), client_ip(), false).unwrap();
+        backend.join().unwrap();
+        
+        assert_eq!(response.get_status_code(), 302);
+        assert_eq!(response.get_header("Location"), Some("/login"));
+    }
+    
+    #[test]
+    fn invalid_statuses_are_refused() {
+        let (fastcgi, backend) = backend(b"Status: teapot\r\n\r\n");
+        
+        let request = Request::parse("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let result = fastcgi.send(&request, None, Path::new("/srv/www"), client_ip(), false);
+        backend.join().unwrap();
+        
+        assert!(matches!(result, Err(ProxyError::InvalidResponse(_))));
+    }
+    
+    #[test]
+    fn heads_are_answered_without_a_body() {
+        let (fastcgi, backend) = backend(b"Content-Type: text/plain\r\n\r\nbody");
+        
+        let request = Request::parse("HEAD / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let response = fastcgi.send(&request, None, Path::new("/srv/www"), client_ip(), false).unwrap();
+        backend.join().unwrap();
+        
+        assert!(!response.is_streamed());
+        assert!(response.get_body().is_empty());
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// The largest file that's cached by default, bigger ones are streamed from disk on every request.
+pub const DEFAULT_MAX_FILE_SIZE_BYTES: u64 = 262_144;
+
+/// The default total size of the cached files, in bytes.
+pub const DEFAULT_MAX_SIZE_BYTES: u64 = 33_554_432;
+
+/// The size of the buffer files are streamed through, in bytes.
+pub const STREAM_BUFFER_BYTES: usize = 65_536;
+
+/// A cached file, along with what's used to notice it changed on disk.
+struct CachedFile {
+    contents: Arc<Vec<u8>>,
+    modified: Option<SystemTime>,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct CacheEntries {
+    files: HashMap<PathBuf, CachedFile>,
+    size_bytes: u64,
+    clock: u64,
+}
+
+/// Keeps recently served small files in memory, evicting the least recently used ones when it's full.
+///
+/// Files are checked against their modification time and size on every read, so changes on disk are picked up.
+pub struct FileCache {
+    max_file_size_bytes: u64,
+    max_size_bytes: u64,
+    entries: Mutex<CacheEntries>,
+}
+
+impl FileCache {
+    pub fn new(max_file_size_bytes: u64, max_size_bytes: u64) -> FileCache {
+        FileCache {
+            max_file_size_bytes,
+            max_size_bytes,
+            entries: Mutex::new(CacheEntries::default()),
+        }
+    }
+    
+    pub fn get_max_file_size_bytes(&self) -> u64 {
+        self.max_file_size_bytes
+    }
+    
+    pub fn get_max_size_bytes(&self) -> u64 {
+        self.max_size_bytes
+    }
+    
+    /// Returns the total size of the files in the cache, in bytes.
+    pub fn get_size_bytes(&self) -> u64 {
+        self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).size_bytes
+    }
+    
+    /// Checks whether a file of the given size is small enough to be served from memory rather than streamed.
+    pub fn is_cacheable(&self, length: u64) -> bool {
+        length <= self.max_file_size_bytes
+    }
+    
+    /// Returns the contents of a file, from the cache if the cached copy is still current.
+    pub fn read(&self, path: &Path, metadata: &Metadata) -> io::Result<Arc<Vec<u8>>> {
+        let modified = metadata.modified().ok();
+        
+        {
+            let mut entries = self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            entries.clock += 1;
+            
+            let clock = entries.clock;
+            
+            if let Some(file) = entries.files.get_mut(path) {
+                if file.modified == modified && file.contents.len() as u64 == metadata.len() {
+                    file.last_used = clock;
+                    
+                    return Ok(Arc::clone(&file.contents));
+                }
+            }
+        }
+        
+        // Read the file without holding the lock, so other requests aren't held up by the disk.
+        let contents = Arc::new(fs::read(path)?);
+        let length = contents.len() as u64;
+        
+        if !self.is_cacheable(length) || length > self.max_size_bytes {
+            return Ok(contents);
+        }
+        
+        let mut entries = self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        
+        if let Some(file) = entries.files.remove(path) {
+            entries.size_bytes -= file.contents.len() as u64;
+        }
+        
+        // Make room by evicting the files that went unused the longest.
+        while entries.size_bytes + length > self.max_size_bytes {
+            let oldest = match entries.files.iter().min_by_key(|(_, file)| file.last_used) {
+                Some((path, _)) => path.clone(),
+                None => break,
+            };
+            
+            if let Some(file) = entries.files.remove(&oldest) {
+                entries.size_bytes -= file.contents.len() as u64;
+            }
+        }
+        
+        let last_used = entries.clock;
+        
+        entries.size_bytes += length;
+        entries.files.insert(path.to_path_buf(), CachedFile {
+            contents: Arc::clone(&contents),
+            modified,
+            last_used,
+        });
+        
+        Ok(contents)
+    }
+}
+
+impl Default for FileCache {
+    fn default() -> FileCache {
+        FileCache::new(DEFAULT_MAX_FILE_SIZE_BYTES, DEFAULT_MAX_SIZE_BYTES)
+    }
+}
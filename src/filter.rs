@@ -0,0 +1,155 @@
+use std::fs;
+use std::path::Path;
+
+use crate::http::{Request, Response};
+
+/// The attributes whose URLs are rewritten, up to the opening quote.
+const ATTRIBUTES: [&[u8]; 2] = [b"src=", b"href="];
+
+/// A post-processing step that may rewrite a response body before it's sent.
+pub trait BodyFilter {
+    fn filter(&self, request: &Request, response: &mut Response);
+}
+
+/// Appends a content hash to `src` and `href` URLs of locally served files in HTML responses, e.g. `/app.js` becomes
+/// `/app.js?v=1a2b3c4d`, so browsers fetch a fresh copy whenever the file changes.
+pub struct HtmlRewritingFilter {
+    web_root: String,
+}
+
+impl HtmlRewritingFilter {
+    pub fn new(web_root: &str) -> HtmlRewritingFilter {
+        HtmlRewritingFilter {
+            web_root: web_root.to_string(),
+        }
+    }
+    
+    fn rewrite(&self, html: &str) -> String {
+        let mut rewritten = String::with_capacity(html.len());
+        let mut rest = html;
+        
+        // Look for the next src= or href= attribute.
+        while let Some(index) = find_attribute(rest) {
+            let (before, after) = rest.split_at(index);
+            rewritten += before;
+            
+            // Copy the attribute name, the equals sign and the opening quote.
+            let quote_index = after.find('=').unwrap() + 1;
+            let quote = after[quote_index..].chars().next().unwrap();
+            rewritten += &after[..quote_index + 1];
+            
+            let value_start = quote_index + 1;
+            let value_end = match after[value_start..].find(quote) {
+                Some(end) => value_start + end,
+                None => {
+                    rest = &after[value_start..];
+                    
+                    continue;
+                }
+            };
+            
+            let url = &after[value_start..value_end];
+            rewritten += url;
+            
+            // Append the version to URLs that point at a local file.
+            if let Some(hash) = self.hash_for(url) {
+                rewritten += if url.contains('?') { "&v=" } else { "?v=" };
+                rewritten += &hash;
+            }
+            
+            rest = &after[value_end..];
+        }
+        
+        rewritten += rest;
+        
+        rewritten
+    }
+    
+    fn hash_for(&self, url: &str) -> Option<String> {
+        // Only root-relative URLs can point at files under the web root.
+        if !url.starts_with('/') || url.starts_with("//") {
+            return None;
+        }
+        
+        let path = url.split(['?', '#']).next().unwrap_or("");
+        
+        // Never look outside of the web root.
+        if path.split('/').any(|segment| segment == "..") {
+            return None;
+        }
+        
+        let contents = fs::read(Path::new(&self.web_root).join(path.trim_start_matches('/'))).ok()?;
+        
+        Some(format!("{:08x}", fnv1a(&contents) as u32))
+    }
+}
+
+impl BodyFilter for HtmlRewritingFilter {
+    fn filter(&self, _request: &Request, response: &mut Response) {
+        if !is_html(response) {
+            return;
+        }
+        
+        // Bodies that aren't valid UTF-8 can't be rewritten.
+        if let Ok(body) = std::str::from_utf8(response.get_body()) {
+            let body = self.rewrite(body);
+            
+            response.set_body(&body);
+        }
+    }
+}
+
+fn is_html(response: &Response) -> bool {
+    if let Some(content_type) = response.get_header("Content-Type") {
+        return content_type.starts_with("text/html");
+    }
+    
+    // Without a content type, fall back to sniffing the start of the body.
+    let start = String::from_utf8_lossy(response.get_body()).trim_start().to_ascii_lowercase();
+    
+    start.starts_with("<!doctype html") || start.starts_with("<html")
+}
+
+/// Finds the start of the next quoted `src` or `href` attribute.
+///
+/// The names are compared in place rather than on a lowercased copy, since the rewriter calls this for every attribute
+/// and copying the rest of the document each time would make it quadratic.
+fn find_attribute(html: &str) -> Option<usize> {
+    let bytes = html.as_bytes();
+    
+    (1..bytes.len()).find(|&index| {
+        // The attribute must be preceded by whitespace so e.g. data-src isn't matched.
+        bytes[index - 1].is_ascii_whitespace() && ATTRIBUTES.iter().any(|name| {
+            let candidate = &bytes[index..];
+            
+            candidate.len() > name.len()
+                && candidate[..name.len()].eq_ignore_ascii_case(name)
+                && matches!(candidate[name.len()], b'"' | b'\'')
+        })
+    })
+}
+
+/// Hashes bytes with 64-bit FNV-1a, which is fast and stable across builds.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn attributes_are_found_in_any_case() {
+        assert_eq!(find_attribute("<img SRC=\"/a.png\">"), Some(5));
+        assert_eq!(find_attribute("<a\nHref='/b'>"), Some(3));
+        assert_eq!(find_attribute("<img data-src=\"/a.png\" src=/a.png>"), None);
+        assert_eq!(find_attribute("<p>é src=\"/c\"</p>"), Some(6));
+    }
+}
@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::http::{Headers, Method, Request, Response};
+
+/// The default number of seconds the metadata of a HEAD response is reused for.
+pub const DEFAULT_TTL_SECS: u64 = 5;
+
+/// The headers that belong to a single response rather than to the resource, which aren't reused.
+const UNCACHED_HEADERS: [&str; 4] = ["Date", "Connection", "Keep-Alive", "Set-Cookie"];
+
+/// Whether a HEAD request for a static file could be answered from the cache.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CacheStatus {
+    /// The cached headers were used, the file wasn't read.
+    Hit,
+    /// Nothing was cached yet, so the complete response is stored once it's ready.
+    Miss,
+}
+
+/// Remembers the headers of recent HEAD responses for static files for a short while, so clients polling a file for
+/// changes are answered without the server reading, hashing and compressing the same body every few seconds.
+///
+/// Only HEAD requests without credentials use it, everything else is always served fresh.
+pub struct HeadCache {
+    ttl: Duration,
+    entries: HashMap<String, (Instant, Headers)>,
+}
+
+impl HeadCache {
+    /// Creates a cache whose entries expire after the given time, a zero TTL turns it off.
+    pub fn new(ttl: Duration) -> HeadCache {
+        HeadCache {
+            ttl,
+            entries: HashMap::new(),
+        }
+    }
+    
+    pub fn get_ttl(&self) -> Duration {
+        self.ttl
+    }
+    
+    /// Returns the key a request's metadata is stored under, which includes `Host` since virtual hosts serve different
+    /// files for the same path, and `Accept-Encoding` since it picks the body.
+    ///
+    /// Returns `None` for anything but HEAD requests, and for requests with an `Authorization` or `Cookie` header, whose
+    /// answer may be meant for that client alone.
+    pub fn key(request: &Request) -> Option<String> {
+        if *request.get_method() != Method::Head || request.get_header("Authorization").is_some() || request.get_header("Cookie").is_some() {
+            return None;
+        }
+        
+        Some(format!(
+            "{}\n{}\n{}",
+            request.get_header("Host").unwrap_or_default().to_ascii_lowercase(),
+            request.get_path(),
+            request.get_header("Accept-Encoding").unwrap_or_default(),
+        ))
+    }
+    
+    /// Returns the cached headers for a key, unless they've expired.
+    pub fn get(&self, key: &str) -> Option<&Headers> {
+        self.entries.get(key)
+            .filter(|(stored, _)| stored.elapsed() < self.ttl)
+            .map(|(_, headers)| headers)
+    }
+    
+    /// Stores the headers of a response, dropping the entries that have expired in the meantime.
+    pub fn insert(&mut self, key: String, response: &Response) {
+        if self.ttl.is_zero() {
+            return;
+        }
+        
+        let ttl = self.ttl;
+        self.entries.retain(|_, (stored, _)| stored.elapsed() < ttl);
+        
+        let mut headers = response.get_headers().clone();
+        
+        for name in UNCACHED_HEADERS {
+            headers.remove(name);
+        }
+        
+        self.entries.insert(key, (Instant::now(), headers));
+    }
+}
+
+impl Default for HeadCache {
+    fn default() -> HeadCache {
+        HeadCache::new(Duration::from_secs(DEFAULT_TTL_SECS))
+    }
+}
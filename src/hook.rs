@@ -0,0 +1,14 @@
+use std::time::Duration;
+
+use crate::context::ConnectionContext;
+use crate::http::{Request, Response};
+
+/// A hook that runs after a response has been fully sent to the client.
+///
+/// Useful for work that must not delay the response, such as recording metrics or cleaning up temporary files.
+pub trait ResponseHook {
+    fn after_send(&self, context: &ConnectionContext, request: &Request, response: &Response, duration: Duration);
+    
+    /// Writes out anything the hook buffered, called once the server has stopped.
+    fn flush(&self) {}
+}
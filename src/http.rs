@@ -1,100 +1,779 @@
+use std::collections::HashMap;
+use std::error::Error;
 use std::fmt;
+use std::io::{self, Read, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use json::JsonValue;
+
+use crate::logging;
+use crate::sse::{self, EventSender};
+use crate::status::StatusCode;
+
+/// The month names used in HTTP dates.
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// The day names used in HTTP dates, starting with Thursday because the Unix epoch was one.
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+
+/// The size of the pieces a body is read in, chunks of a chunked body included.
+const BODY_CHUNK_SIZE: usize = 8_192;
+
+/// The longest chunk size or trailer line a chunked body may have, extensions included.
+const MAX_CHUNK_LINE_LENGTH: usize = 4_096;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Method {
     Get,
+    Head,
     Post,
     Put,
+    Patch,
     Delete,
+    Options,
+    Connect,
+}
+
+impl Method {
+    /// Every method the server understands.
+    pub const ALL: [Method; 8] = [
+        Method::Get,
+        Method::Head,
+        Method::Post,
+        Method::Put,
+        Method::Patch,
+        Method::Delete,
+        Method::Options,
+        Method::Connect,
+    ];
+}
+
+/// A method name the server doesn't understand.
+#[derive(Debug, PartialEq, Eq)]
+pub struct UnknownMethod(pub String);
+
+impl fmt::Display for UnknownMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid method: {}", self.0)
+    }
+}
+
+impl Error for UnknownMethod {}
+
+impl TryFrom<&str> for Method {
+    type Error = UnknownMethod;
+    
+    /// Converts a method name as it appears in the request line, which is case-sensitive.
+    fn try_from(method: &str) -> Result<Method, UnknownMethod> {
+        match method {
+            "GET" => Ok(Method::Get),
+            "HEAD" => Ok(Method::Head),
+            "POST" => Ok(Method::Post),
+            "PUT" => Ok(Method::Put),
+            "PATCH" => Ok(Method::Patch),
+            "DELETE" => Ok(Method::Delete),
+            "OPTIONS" => Ok(Method::Options),
+            "CONNECT" => Ok(Method::Connect),
+            _ => Err(UnknownMethod(method.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let method = match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Patch => "PATCH",
+            Method::Delete => "DELETE",
+            Method::Options => "OPTIONS",
+            Method::Connect => "CONNECT",
+        };
+        
+        method.fmt(f)
+    }
+}
+
+/// The HTTP versions the server understands.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HttpVersion {
+    Http10,
+    Http11,
+    Http20,
+}
+
+impl TryFrom<&str> for HttpVersion {
+    type Error = HttpParseError;
+    
+    fn try_from(version: &str) -> Result<HttpVersion, HttpParseError> {
+        match version {
+            "HTTP/1.0" => Ok(HttpVersion::Http10),
+            "HTTP/1.1" => Ok(HttpVersion::Http11),
+            "HTTP/2.0" | "HTTP/2" => Ok(HttpVersion::Http20),
+            _ => Err(HttpParseError::UnknownVersion(version.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for HttpVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let version = match self {
+            HttpVersion::Http10 => "HTTP/1.0",
+            HttpVersion::Http11 => "HTTP/1.1",
+            HttpVersion::Http20 => "HTTP/2.0",
+        };
+        
+        version.fmt(f)
+    }
+}
+
+/// An error that occurred while parsing the head of a request.
+#[derive(Debug, PartialEq, Eq)]
+pub enum HttpParseError {
+    /// The request is empty.
+    MissingRequestLine,
+    /// The request line doesn't consist of a method, a target and a version.
+    MalformedRequestLine(String),
+    /// The method isn't one the server understands.
+    UnknownMethod(String),
+    /// The version isn't one the server understands.
+    UnknownVersion(String),
+    /// A header line isn't a name, a colon and a value.
+    MalformedHeader(String),
+    /// The path doesn't decode to UTF-8, or contains a NUL byte.
+    InvalidPath(String),
+}
+
+impl HttpParseError {
+    /// Returns the status code to answer the request with.
+    pub fn status_code(&self) -> u16 {
+        match self {
+            HttpParseError::MissingRequestLine
+            | HttpParseError::MalformedRequestLine(_)
+            | HttpParseError::MalformedHeader(_)
+            | HttpParseError::InvalidPath(_) => 400,
+            HttpParseError::UnknownMethod(_) => 501,
+            HttpParseError::UnknownVersion(_) => 505,
+        }
+    }
+}
+
+impl fmt::Display for HttpParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HttpParseError::MissingRequestLine => write!(f, "Missing request line"),
+            HttpParseError::MalformedRequestLine(line) => write!(f, "Malformed request line: {:?}", line),
+            HttpParseError::UnknownMethod(method) => write!(f, "Invalid method: {}", method),
+            HttpParseError::UnknownVersion(version) => write!(f, "Unsupported HTTP version: {}", version),
+            HttpParseError::MalformedHeader(line) => write!(f, "Malformed header: {:?}", line),
+            HttpParseError::InvalidPath(path) => write!(f, "Invalid path: {:?}", path),
+        }
+    }
 }
 
+impl Error for HttpParseError {}
+
+impl From<UnknownMethod> for HttpParseError {
+    fn from(error: UnknownMethod) -> HttpParseError {
+        HttpParseError::UnknownMethod(error.0)
+    }
+}
+
+/// Header fields, looked up case-insensitively and kept in the order they were added.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Headers {
+    fields: Vec<(String, String)>,
+}
+
+impl Headers {
+    pub fn new() -> Headers {
+        Headers::default()
+    }
+    
+    /// Returns the first value of a header.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+    
+    /// Returns every value of a header that may appear more than once, like `Set-Cookie`.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.fields.iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+    
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+    
+    /// Sets a header, replacing all of its earlier values.
+    pub fn insert(&mut self, name: &str, value: &str) {
+        self.remove(name);
+        self.append(name, value);
+    }
+    
+    /// Adds a value to a header, keeping its earlier values.
+    pub fn append(&mut self, name: &str, value: &str) {
+        self.fields.push((name.to_string(), value.to_string()));
+    }
+    
+    pub fn remove(&mut self, name: &str) {
+        self.fields.retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+    }
+    
+    /// Iterates over the names and values, with the names as they were added.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+    
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
+
+#[derive(Clone)]
 pub struct Request {
     method: Method,
+    target: String,
     path: String,
-    version: String,
-    headers: Vec<String>,
-    body: String,
+    query: HashMap<String, String>,
+    version: HttpVersion,
+    headers: Headers,
+    body: Vec<u8>,
+    claims: Option<JsonValue>,
 }
 
 impl Request {
-    pub fn new(request: &str) -> Request {
-        let mut headers = Vec::new();
+    /// Parses the request line and the headers.
+    pub fn parse(request: &str) -> Result<Request, HttpParseError> {
+        // Split the request into lines, the head ends at the first empty one.
+        let mut lines = request.split("\r\n").take_while(|line| !line.is_empty());
+        
+        // Split the first line into words.
+        let request_line = lines.next().ok_or(HttpParseError::MissingRequestLine)?;
+        let words: Vec<&str> = request_line.split(' ').collect();
+        
+        let [method, path, version] = words[..] else {
+            return Err(HttpParseError::MalformedRequestLine(request_line.to_string()));
+        };
         
-        // Split the request into lines.
-        let lines = request.split("\r\n");
+        let method = Method::try_from(method)?;
+        let version = HttpVersion::try_from(version)?;
+        let target = path.to_string();
+        
+        // Split the target into the decoded path and the query parameters, which are decoded like a form.
+        let (raw_path, query) = target.split_once('?').unwrap_or((&target, ""));
+        let path = decode_path(raw_path).ok_or_else(|| HttpParseError::InvalidPath(raw_path.to_string()))?;
+        let query = parse_form(query).into_iter().collect();
+        
+        // Every other line is a header, whitespace around the name would let it be read differently by proxies.
+        let mut headers = Headers::new();
         
-        // Iterate over the lines.
         for line in lines {
-            // Check if the line is empty.
-            if line.is_empty() {
-                break;
+            match line.split_once(':') {
+                Some((name, value)) if !name.is_empty() && !name.contains(char::is_whitespace) => headers.append(name, value.trim()),
+                _ => return Err(HttpParseError::MalformedHeader(line.to_string())),
             }
-            
-            // Add the line to the headers vector.
-            headers.push(line.to_string());
         }
         
-        // Split the first line into words.
-        let words = headers[0].split(' ');
-        
         // Create a new request instance.
-        Request {
-            method: match words.clone().next().unwrap() {
-                "GET" => Method::Get,
-                "POST" => Method::Post,
-                "PUT" => Method::Put,
-                "DELETE" => Method::Delete,
-                _ => panic!("Invalid method: {}", words.clone().next().unwrap()),
-            },
-            path: words.clone().nth(1).unwrap().to_string(),
-            version: words.clone().nth(2).unwrap().to_string(),
+        Ok(Request {
+            method,
+            target,
+            path,
+            query,
+            version,
             headers,
-            body: "".to_string(),
-        }
+            body: Vec::new(),
+            claims: None,
+        })
     }
     
     pub fn get_method(&self) -> &Method {
         &self.method
     }
     
+    /// Returns the request target as it was sent, still percent-encoded and including the query string.
     pub fn get_path(&self) -> &str {
+        &self.target
+    }
+    
+    /// Returns the percent-decoded path without the query string, e.g. `/hello world` for `/hello%20world?x=1`.
+    pub fn path(&self) -> &str {
         &self.path
     }
     
-    pub fn get_version(&self) -> &str {
-        &self.version
+    /// Returns the decoded query parameters, a parameter that's repeated keeps its last value.
+    pub fn query(&self) -> &HashMap<String, String> {
+        &self.query
     }
     
-    pub fn get_headers(&self) -> &Vec<String> {
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query.get(name).map(String::as_str)
+    }
+    
+    pub fn get_version(&self) -> HttpVersion {
+        self.version
+    }
+    
+    pub fn get_headers(&self) -> &Headers {
         &self.headers
     }
     
-    pub fn get_body(&self) -> &str {
+    /// Returns the body exactly as it was sent, which is empty until the server has read it.
+    pub fn get_body(&self) -> &[u8] {
         &self.body
     }
+    
+    /// Returns the body as text, or `None` if it isn't valid UTF-8.
+    pub fn get_body_text(&self) -> Option<&str> {
+        std::str::from_utf8(&self.body).ok()
+    }
+    
+    /// Returns the fields of an `application/x-www-form-urlencoded` body, in the order they were sent.
+    ///
+    /// Any other body has no form fields.
+    pub fn get_form_params(&self) -> Vec<(String, String)> {
+        let is_form = self.get_header("Content-Type")
+            .and_then(|content_type| content_type.split(';').next())
+            .is_some_and(|mime_type| mime_type.trim().eq_ignore_ascii_case("application/x-www-form-urlencoded"));
+        
+        if !is_form {
+            return Vec::new();
+        }
+        
+        parse_form(&String::from_utf8_lossy(&self.body))
+    }
+    
+    pub fn get_header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+    
+    pub fn set_body(&mut self, body: &str) {
+        self.body = body.as_bytes().to_vec();
+    }
+    
+    pub fn set_body_bytes(&mut self, body: &[u8]) {
+        self.body = body.to_vec();
+    }
+    
+    /// Returns the claims of the JSON Web Token the request was authenticated with, if its path requires one.
+    ///
+    /// The claims are only set once the token's signature and validity have been checked, so handlers can trust them.
+    pub fn get_claims(&self) -> Option<&JsonValue> {
+        self.claims.as_ref()
+    }
+    
+    pub fn set_claims(&mut self, claims: JsonValue) {
+        self.claims = Some(claims);
+    }
+    
+    /// Checks whether the client accepts a MIME type with a quality above zero, according to the `Accept` header.
+    pub fn accepts(&self, mime_type: &str) -> bool {
+        self.accept_quality(mime_type) > 0.0
+    }
+    
+    /// Returns the candidate the client prefers, the earliest one wins if several are equally preferred.
+    pub fn preferred_mime<'a>(&self, candidates: &[&'a str]) -> Option<&'a str> {
+        let mut preferred = None;
+        let mut best_quality = 0.0;
+        
+        for candidate in candidates {
+            let quality = self.accept_quality(candidate);
+            
+            if quality > best_quality {
+                preferred = Some(*candidate);
+                best_quality = quality;
+            }
+        }
+        
+        preferred
+    }
+    
+    /// Returns the quality the client gives a MIME type, taken from the most specific matching media range.
+    fn accept_quality(&self, mime_type: &str) -> f32 {
+        // Without an Accept header, anything goes.
+        let accept = match self.get_header("Accept") {
+            Some(accept) => accept,
+            None => return 1.0,
+        };
+        
+        let (main_type, sub_type) = mime_type.split_once('/').unwrap_or((mime_type, ""));
+        
+        // The specificity is 0 for */*, 1 for type/* and 2 for an exact match.
+        let mut best: Option<(u8, f32)> = None;
+        
+        for range in accept.split(',') {
+            let mut parameters = range.split(';');
+            let media_range = parameters.next().unwrap_or_default().trim();
+            
+            let quality = parameters
+                .filter_map(|parameter| parameter.trim().strip_prefix("q="))
+                .find_map(|quality| quality.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            
+            let (range_type, range_sub_type) = media_range.split_once('/').unwrap_or((media_range, ""));
+            
+            let specificity = if range_type == "*" && range_sub_type == "*" {
+                0
+            } else if range_type.eq_ignore_ascii_case(main_type) && range_sub_type == "*" {
+                1
+            } else if range_type.eq_ignore_ascii_case(main_type) && range_sub_type.eq_ignore_ascii_case(sub_type) {
+                2
+            } else {
+                continue;
+            };
+            
+            if best.is_none_or(|(best_specificity, _)| specificity > best_specificity) {
+                best = Some((specificity, quality));
+            }
+        }
+        
+        best.map_or(0.0, |(_, quality)| quality)
+    }
+}
+
+enum BodyLength {
+    Known(usize),
+    /// `remaining` is what's left of the chunk being read, a new chunk starts once it reaches 0.
+    Chunked { remaining: usize, finished: bool },
+}
+
+/// Splits a form-encoded string like `a=1&b=two+words` into its fields, decoding each name and value.
+pub fn parse_form(form: &str) -> Vec<(String, String)> {
+    form.split('&')
+        .filter(|field| !field.is_empty())
+        .map(|field| {
+            let (name, value) = field.split_once('=').unwrap_or((field, ""));
+            
+            (decode_form_component(name), decode_form_component(value))
+        })
+        .collect()
+}
+
+/// Decodes the `%XX` escapes in a URL path, e.g. `/hello%20world` to `/hello world`.
+///
+/// Unlike in forms, `+` stays as it is. Paths that don't decode to UTF-8 or contain a NUL byte are refused with `None`.
+pub fn decode_path(path: &str) -> Option<String> {
+    String::from_utf8(percent_decode(path, false))
+        .ok()
+        .filter(|path| !path.contains('\0'))
+}
+
+/// Decodes `+` as a space and `%XX` escapes as bytes, leaving malformed escapes as they are.
+fn decode_form_component(component: &str) -> String {
+    String::from_utf8_lossy(&percent_decode(component, true)).into_owned()
+}
+
+/// Decodes `%XX` escapes as bytes, and `+` as a space if asked to, leaving malformed escapes as they are.
+fn percent_decode(component: &str, plus_as_space: bool) -> Vec<u8> {
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    
+    while index < bytes.len() {
+        let escaped = bytes.get(index + 1..index + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        
+        match (bytes[index], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            (b'+', _) if plus_as_space => {
+                decoded.push(b' ');
+                index += 1;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+    
+    decoded
+}
+
+/// Writes a body with chunked transfer encoding, so it can be sent before its length is known (RFC 9112 §7.1).
+pub struct ChunkedWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    pub fn new(writer: W) -> ChunkedWriter<W> {
+        ChunkedWriter { writer }
+    }
+    
+    /// Writes the last chunk, which tells the client the body is complete.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.writer.write_all(b"0\r\n\r\n")?;
+        self.writer.flush()
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        // An empty chunk would end the body, so there's nothing to write.
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+        
+        self.writer.write_all(format!("{:x}\r\n", buffer.len()).as_bytes())?;
+        self.writer.write_all(buffer)?;
+        self.writer.write_all(b"\r\n")?;
+        
+        Ok(buffer.len())
+    }
+    
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Reads a request body from the stream one chunk at a time, so the whole body never has to be buffered up front.
+pub struct BodyReader<'a> {
+    stream: &'a mut dyn Read,
+    buffered: Vec<u8>,
+    position: usize,
+    length: BodyLength,
+    limit: usize,
+    read: usize,
+    over_limit: bool,
+    failed: bool,
+}
+
+impl<'a> BodyReader<'a> {
+    /// Creates a body reader for the given request.
+    ///
+    /// `buffered` holds any body bytes that were already read from the stream together with the headers.
+    pub fn new(stream: &'a mut dyn Read, buffered: &[u8], request: &Request) -> BodyReader<'a> {
+        // Chunked encoding takes precedence over the content length.
+        let chunked = request
+            .get_header("Transfer-Encoding")
+            .map(|encoding| encoding.to_ascii_lowercase().contains("chunked"))
+            .unwrap_or(false);
+        
+        let length = if chunked {
+            BodyLength::Chunked { remaining: 0, finished: false }
+        } else {
+            BodyLength::Known(request
+                .get_header("Content-Length")
+                .and_then(|length| length.parse().ok())
+                .unwrap_or(0))
+        };
+        
+        BodyReader {
+            stream,
+            buffered: buffered.to_vec(),
+            position: 0,
+            length,
+            limit: usize::MAX,
+            read: 0,
+            over_limit: false,
+            failed: false,
+        }
+    }
+    
+    /// Sets the largest body the client may send, a chunk that would go over it is refused before it's read.
+    pub fn with_limit(mut self, limit: usize) -> BodyReader<'a> {
+        self.limit = limit;
+        
+        self
+    }
+    
+    /// Checks if reading failed because the client announced a chunk larger than the limit.
+    pub fn is_over_limit(&self) -> bool {
+        self.over_limit
+    }
+    
+    /// Returns the next piece of the body, or `None` once the body has been fully read.
+    ///
+    /// Pieces are at most `BODY_CHUNK_SIZE` bytes, however large the chunks of a chunked body are. Once reading failed,
+    /// there's no telling where the body continues, so every further read fails too.
+    pub fn read_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if self.failed {
+            return Err(io::Error::other("Reading the body already failed!"));
+        }
+        
+        let piece = self.read_piece();
+        self.failed = piece.is_err();
+        
+        piece
+    }
+    
+    fn read_piece(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let piece = match self.length {
+            BodyLength::Known(0) | BodyLength::Chunked { finished: true, .. } => return Ok(None),
+            BodyLength::Known(remaining) => {
+                let piece = self.read_some(remaining.min(BODY_CHUNK_SIZE))?;
+                
+                self.length = BodyLength::Known(remaining - piece.len());
+                
+                piece
+            }
+            BodyLength::Chunked { remaining: 0, .. } => {
+                // Parse the chunk size, ignoring any chunk extensions.
+                let line = self.read_line()?;
+                let size = line.split(';').next().unwrap_or("").trim();
+                let size = usize::from_str_radix(size, 16)
+                    .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid chunk size: {}", size)))?;
+                
+                // The last chunk is followed by optional trailers and an empty line.
+                if size == 0 {
+                    while !self.read_line()?.is_empty() {}
+                    
+                    self.length = BodyLength::Chunked { remaining: 0, finished: true };
+                    
+                    return Ok(None);
+                }
+                
+                // Refuse the chunk before reading it, the size is whatever the client claims.
+                if size > self.limit - self.read {
+                    self.over_limit = true;
+                    
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("A chunk of {} bytes is larger than the body limit!", size)));
+                }
+                
+                self.length = BodyLength::Chunked { remaining: size, finished: false };
+                
+                return self.read_piece();
+            }
+            BodyLength::Chunked { remaining, .. } => {
+                let piece = self.read_some(remaining.min(BODY_CHUNK_SIZE))?;
+                
+                // Every chunk is terminated by a CRLF.
+                if piece.len() == remaining && !self.read_line()?.is_empty() {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "A chunk is longer than its size!"));
+                }
+                
+                self.length = BodyLength::Chunked { remaining: remaining - piece.len(), finished: false };
+                
+                piece
+            }
+        };
+        
+        self.read += piece.len();
+        
+        Ok(Some(piece))
+    }
+    
+    /// Reads the rest of the body, e.g. to buffer it for whoever doesn't read it as it arrives.
+    pub fn read_all(&mut self) -> io::Result<Vec<u8>> {
+        let mut body = Vec::new();
+        
+        while let Some(piece) = self.read_chunk()? {
+            body.extend(piece);
+        }
+        
+        Ok(body)
+    }
+    
+    /// Returns the buffered bytes that weren't part of the body, which belong to the next request on the connection.
+    pub fn into_leftover(self) -> Vec<u8> {
+        self.buffered[self.position..].to_vec()
+    }
+    
+    fn read_some(&mut self, max: usize) -> io::Result<Vec<u8>> {
+        // Serve already buffered bytes before touching the stream.
+        if self.position < self.buffered.len() {
+            let end = self.buffered.len().min(self.position + max);
+            let bytes = self.buffered[self.position..end].to_vec();
+            
+            self.position = end;
+            
+            return Ok(bytes);
+        }
+        
+        let mut buffer = vec![0; max];
+        let bytes_read = self.stream.read(&mut buffer)?;
+        
+        if bytes_read == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Connection closed before the body was read!"));
+        }
+        
+        buffer.truncate(bytes_read);
+        
+        Ok(buffer)
+    }
+    
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = Vec::new();
+        
+        // Read byte by byte until the line terminator is found, so nothing after the line is consumed.
+        while !line.ends_with(b"\r\n") {
+            if line.len() >= MAX_CHUNK_LINE_LENGTH {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("A chunk line is longer than {} bytes!", MAX_CHUNK_LINE_LENGTH)));
+            }
+            
+            line.extend(self.read_some(1)?);
+        }
+        
+        line.truncate(line.len() - 2);
+        
+        Ok(String::from_utf8_lossy(&line).to_string())
+    }
+}
+
+/// A response body that's read while it's being sent, rather than held in memory.
+struct BodyStream {
+    reader: Box<dyn Read + Send>,
+    length: Option<u64>,
 }
 
 pub struct Response {
-    version: String,
+    version: HttpVersion,
     status_code: u16,
     status_message: String,
-    headers: Vec<String>,
-    body: String,
+    headers: Headers,
+    body: Vec<u8>,
+    stream: Option<BodyStream>,
+    error: Option<String>,
 }
 
 impl Response {
-    pub fn new(version: &str, status_code: u16, status_message: &str) -> Response {
+    pub fn new(version: HttpVersion, status_code: u16, status_message: &str) -> Response {
         // Create a new response instance.
         Response {
-            version: version.to_string(),
+            version,
             status_code,
             status_message: status_message.to_string(),
-            headers: Vec::new(),
-            body: "".to_string(),
+            headers: Headers::new(),
+            body: Vec::new(),
+            stream: None,
+            error: None,
         }
     }
     
-    pub fn get_version(&self) -> &str {
-        &self.version
+    /// Creates an HTTP/1.1 response whose reason phrase matches its status code, e.g. `Response::with_status(StatusCode::Found)`.
+    pub fn with_status(status: StatusCode) -> Response {
+        Response::new(HttpVersion::Http11, status.code(), status.reason())
+    }
+    
+    pub fn ok() -> Response {
+        Response::with_status(StatusCode::Ok)
+    }
+    
+    pub fn not_found() -> Response {
+        Response::with_status(StatusCode::NotFound)
+    }
+    
+    /// Starts a Server-Sent Events response. The events sent with the returned sender are written to the client as
+    /// they come, with a keep-alive comment whenever it's been quiet for a while, see `sse::event_stream`.
+    pub fn stream() -> (Response, EventSender) {
+        sse::event_stream(sse::DEFAULT_KEEP_ALIVE)
+    }
+    
+    pub fn get_version(&self) -> HttpVersion {
+        self.version
     }
     
     pub fn get_status_code(&self) -> u16 {
@@ -105,42 +784,362 @@ impl Response {
         &self.status_message
     }
     
-    pub fn get_headers(&self) -> &Vec<String> {
+    pub fn get_headers(&self) -> &Headers {
         &self.headers
     }
     
-    pub fn get_body(&self) -> &str {
+    pub fn headers_mut(&mut self) -> &mut Headers {
+        &mut self.headers
+    }
+    
+    pub fn get_header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+    
+    /// Returns the body held in memory, which is empty if the body is streamed.
+    pub fn get_body(&self) -> &[u8] {
         &self.body
     }
     
+    /// Returns the length of the body, or `None` for a streamed body whose length isn't known up front.
+    pub fn get_body_length(&self) -> Option<u64> {
+        match &self.stream {
+            Some(stream) => stream.length,
+            None => Some(self.body.len() as u64),
+        }
+    }
+    
+    pub fn is_streamed(&self) -> bool {
+        self.stream.is_some()
+    }
+    
     pub fn set_status_code(&mut self, status_code: u16) {
         self.status_code = status_code;
     }
     
+    /// Sets the status code along with its reason phrase.
+    pub fn set_status(&mut self, status: StatusCode) {
+        self.status_code = status.code();
+        self.status_message = status.reason().to_string();
+    }
+    
     pub fn set_status_message(&mut self, status_message: &str) {
         self.status_message = status_message.to_string();
     }
     
     pub fn set_body(&mut self, body: &str) {
-        self.body = body.to_string();
+        self.set_body_bytes(body.as_bytes());
+    }
+    
+    pub fn set_body_bytes(&mut self, body: &[u8]) {
+        self.body = body.to_vec();
+        self.stream = None;
     }
     
-    pub fn add_header(&mut self, header: &str) {
-        self.headers.push(header.to_string());
+    /// Streams the body from a reader while the response is sent, e.g. for large files or generated output.
+    ///
+    /// Without a length the body is sent with chunked transfer encoding, or until the connection closes for HTTP/1.0.
+    pub fn set_body_stream(&mut self, reader: impl Read + Send + 'static, length: Option<u64>) {
+        self.body.clear();
+        self.stream = Some(BodyStream {
+            reader: Box::new(reader),
+            length,
+        });
+    }
+    
+    /// Adds a header, keeping any earlier values of it.
+    pub fn add_header(&mut self, name: &str, value: &str) {
+        self.headers.append(name, value);
+    }
+    
+    /// Adds a header and returns the response, so headers can be chained onto a new response.
+    /// Notes what went wrong for the error log, e.g. which upstream failed and how. The client never sees it.
+    pub fn with_error(mut self, error: &str) -> Response {
+        self.error = Some(error.to_string());
+        
+        self
+    }
+    
+    pub fn get_error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+    
+    pub fn with_header(mut self, name: &str, value: &str) -> Response {
+        self.headers.append(name, value);
+        
+        self
+    }
+    
+    /// Sets a header, replacing any earlier value unless the header may appear more than once, like `Set-Cookie`.
+    pub fn set_header(&mut self, name: &str, value: &str) {
+        if name.eq_ignore_ascii_case("Set-Cookie") {
+            self.headers.append(name, value);
+        } else {
+            self.headers.insert(name, value);
+        }
+    }
+    
+    /// Writes the response, reading a streamed body as it goes and chunking it if `Transfer-Encoding` says so.
+    pub fn write_to(&mut self, writer: &mut dyn Write) -> io::Result<()> {
+        writer.write_all(&self.to_bytes())?;
+        
+        let chunked = self.get_header("Transfer-Encoding").is_some_and(|encoding| encoding.eq_ignore_ascii_case("chunked"));
+        
+        let stream = match &mut self.stream {
+            Some(stream) => stream,
+            None => return Ok(()),
+        };
+        
+        let written = if chunked {
+            let mut writer = ChunkedWriter::new(writer);
+            let written = io::copy(&mut stream.reader, &mut writer)?;
+            writer.finish()?;
+            
+            written
+        } else {
+            io::copy(&mut stream.reader, writer)?
+        };
+        
+        // Remember how much was sent, so hooks like the access log can report it.
+        stream.length = Some(written);
+        
+        Ok(())
+    }
+    
+    /// Serializes the response, including a body that may not be valid UTF-8, but not a streamed body.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut response = format!("{} {} {}\r\n", self.version, self.status_code, self.status_message);
+        
+        for (name, value) in self.headers.iter() {
+            response += &format!("{}: {}\r\n", name, value);
+        }
+        
+        response += "\r\n";
+        
+        let mut bytes = response.into_bytes();
+        bytes.extend_from_slice(&self.body);
+        
+        bytes
     }
 }
 
-impl fmt::Display for Response {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut response = format!("HTTP/{} {} {}\r\n", self.version, self.status_code, self.status_message);
+/// Returns the canonical reason phrase for a status code.
+pub fn reason_phrase(status_code: u16) -> &'static str {
+    StatusCode::from_code(status_code).map_or("Unknown", |status| status.reason())
+}
+
+/// An RFC 7807 problem details object, describing an error in a machine-readable way.
+pub struct ProblemDetail {
+    problem_type: String,
+    title: String,
+    status: u16,
+    detail: String,
+    instance: String,
+}
+
+impl ProblemDetail {
+    /// Creates a problem without a specific type, which RFC 7807 represents as `about:blank`.
+    pub fn new(status: u16, detail: &str, instance: &str) -> ProblemDetail {
+        ProblemDetail {
+            problem_type: "about:blank".to_string(),
+            title: reason_phrase(status).to_string(),
+            status,
+            detail: detail.to_string(),
+            instance: instance.to_string(),
+        }
+    }
+    
+    pub fn set_type(&mut self, problem_type: &str) {
+        self.problem_type = problem_type.to_string();
+    }
+    
+    pub fn get_type(&self) -> &str {
+        &self.problem_type
+    }
+    
+    pub fn get_title(&self) -> &str {
+        &self.title
+    }
+    
+    pub fn get_status(&self) -> u16 {
+        self.status
+    }
+    
+    pub fn get_detail(&self) -> &str {
+        &self.detail
+    }
+    
+    pub fn get_instance(&self) -> &str {
+        &self.instance
+    }
+    
+    pub fn to_response(&self) -> Response {
+        let body = json::object! {
+            type: self.problem_type.as_str(),
+            title: self.title.as_str(),
+            status: self.status,
+            detail: self.detail.as_str(),
+            instance: self.instance.as_str(),
+        };
         
-        for header in &self.headers {
-            response += &format!("{}\r\n", header);
+        let mut response = Response::new(HttpVersion::Http11, self.status, &self.title);
+        response.add_header("Content-Type", "application/problem+json");
+        response.set_body(&body.dump());
+        
+        response
+    }
+}
+
+/// Builds an error response in the format the client asked for.
+///
+/// Clients that accept JSON get RFC 7807 problem details, using `problem_type` as the type URI if given. Everyone else
+/// gets an HTML page.
+pub fn error_response(status_code: u16, request: &Request, message: &str, request_id: &str, problem_type: Option<&str>) -> Response {
+    let wants_json = request
+        .preferred_mime(&["text/html", "application/problem+json", "application/json"])
+        .is_some_and(|mime_type| mime_type != "text/html");
+    
+    if wants_json {
+        let mut problem = ProblemDetail::new(status_code, message, request.get_path());
+        
+        if let Some(problem_type) = problem_type {
+            problem.set_type(problem_type);
         }
         
-        response += "\r\n";
-        response += &self.body;
+        return problem.to_response();
+    }
+    
+    let mut response = Response::new(HttpVersion::Http11, status_code, reason_phrase(status_code));
+    response.add_header("Content-Type", "text/html; charset=utf-8");
+    response.set_body(&default_error_page(status_code, message, request_id));
+    
+    response
+}
+
+/// Renders a self-contained HTML5 error page, styled inline so it doesn't need any further requests.
+///
+/// The request ID is shown so users can refer to it when reporting a problem.
+pub fn default_error_page(status: u16, message: &str, request_id: &str) -> String {
+    let reason = reason_phrase(status);
+    
+    format!(
+        concat!(
+            "<!doctype html>\n",
+            "<html lang=\"en\">\n",
+            "<head>\n",
+            "<meta charset=\"utf-8\">\n",
+            "<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n",
+            "<title>{status} {reason}</title>\n",
+            "<style>",
+            "body{{font-family:system-ui,sans-serif;max-width:40rem;margin:4rem auto;padding:0 1rem;color:#222}}",
+            "h1{{font-size:1.75rem;border-bottom:1px solid #ddd;padding-bottom:.5rem}}",
+            "small{{color:#666}}",
+            "</style>\n",
+            "</head>\n",
+            "<body>\n",
+            "<h1>{status} {reason}</h1>\n",
+            "<p>{message}</p>\n",
+            "<p><small>Request ID: {request_id}</small></p>\n",
+            "<p><a href=\"/\">Back to the home page</a></p>\n",
+            "</body>\n",
+            "</html>\n",
+        ),
+        status = status,
+        reason = reason,
+        message = escape_html(message),
+        request_id = escape_html(request_id),
+    )
+}
+
+/// Escapes the characters that have a special meaning in HTML.
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    
+    for character in text.chars() {
+        match character {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(character),
+        }
+    }
+    
+    escaped
+}
+
+/// Formats a time as an HTTP date, e.g. `Sun, 06 Nov 1994 08:49:37 GMT` (RFC 9110 §5.6.7).
+pub fn format_http_date(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0);
+    let days = seconds / 86_400;
+    let (year, month, day) = logging::civil_from_days(days as i64);
+    let seconds_of_day = seconds % 86_400;
+    
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[(days % 7) as usize], day, MONTHS[month as usize - 1], year,
+        seconds_of_day / 3_600, seconds_of_day % 3_600 / 60, seconds_of_day % 60,
+    )
+}
+
+/// Parses an HTTP date in the preferred format, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`, ignoring the day name.
+pub fn parse_http_date(date: &str) -> Option<SystemTime> {
+    let (_, date) = date.trim().split_once(", ")?;
+    let parts: Vec<&str> = date.split(' ').collect();
+    
+    let [day, month, year, time, "GMT"] = parts[..] else {
+        return None;
+    };
+    
+    let day = day.parse::<u32>().ok().filter(|day| (1..=31).contains(day))?;
+    let month = MONTHS.iter().position(|name| *name == month)? as u32 + 1;
+    let year = year.parse::<i64>().ok().filter(|year| *year >= 1970)?;
+    let time: Vec<u64> = time.split(':').map(|part| part.parse().ok()).collect::<Option<_>>()?;
+    
+    let [hours, minutes, seconds] = time[..] else {
+        return None;
+    };
+    
+    if hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+    
+    let days = logging::days_from_civil(year, month, day) as u64;
+    
+    Some(UNIX_EPOCH + Duration::from_secs(days * 86_400 + hours * 3_600 + minutes * 60 + seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn known_methods_convert() {
+        let names = ["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE", "OPTIONS", "CONNECT"];
         
-        response.fmt(f)
+        for (name, method) in names.iter().zip(Method::ALL) {
+            assert_eq!(Method::try_from(*name), Ok(method));
+            assert_eq!(method.to_string(), *name);
+        }
+    }
+    
+    #[test]
+    fn unknown_methods_are_errors() {
+        for name in ["BREW", "PROPFIND", "TRACE", "get", ""] {
+            assert_eq!(Method::try_from(name), Err(UnknownMethod(name.to_string())));
+        }
+    }
+    
+    #[test]
+    fn unknown_method_is_a_parse_error() {
+        match Request::parse("BREW /pot-0 HTTP/1.1\r\nHost: localhost\r\n\r\n") {
+            Err(error @ HttpParseError::UnknownMethod(_)) => {
+                assert_eq!(error.to_string(), "Invalid method: BREW");
+                assert_eq!(error.status_code(), 501);
+            }
+            Err(error) => panic!("expected an unknown method, got {}", error),
+            Ok(_) => panic!("expected BREW to be refused"),
+        }
     }
 }
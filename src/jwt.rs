@@ -0,0 +1,829 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64URL;
+use base64::Engine;
+use json::JsonValue;
+use log::{info, warn};
+use ring::{hmac, signature};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{ServerName, SubjectPublicKeyInfoDer};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+use crate::auth;
+use crate::http::Request;
+
+/// How far `exp` and `nbf` may be off, to make up for clocks that aren't quite in sync.
+pub const DEFAULT_LEEWAY: Duration = Duration::from_secs(60);
+
+/// How long keys fetched from a JWKS URL are used before they're fetched again.
+pub const DEFAULT_JWKS_REFRESH: Duration = Duration::from_secs(300);
+
+/// How long to wait between fetches when a token names a key that isn't known yet, so made-up key IDs can't make the
+/// server fetch the keys on every request.
+const MIN_JWKS_REFETCH: Duration = Duration::from_secs(10);
+
+/// How long fetching the keys may take, connecting and each read and write.
+const JWKS_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The largest key set that's read, which is far more than any real one needs.
+const MAX_JWKS_BYTES: u64 = 1_048_576;
+
+/// The object identifiers of the public key types and curves, as DER-encoded in a SubjectPublicKeyInfo.
+const RSA_ENCRYPTION_OID: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+const EC_PUBLIC_KEY_OID: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+const P256_OID: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+const P384_OID: &[u8] = &[0x2b, 0x81, 0x04, 0x00, 0x22];
+
+/// The signature algorithms tokens may be signed with, see RFC 7518 §3.1. `none` is never accepted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    Hs256,
+    Hs384,
+    Hs512,
+    Rs256,
+    Rs384,
+    Rs512,
+    Es256,
+    Es384,
+}
+
+impl Algorithm {
+    pub fn parse(name: &str) -> Option<Algorithm> {
+        match name {
+            "HS256" => Some(Algorithm::Hs256),
+            "HS384" => Some(Algorithm::Hs384),
+            "HS512" => Some(Algorithm::Hs512),
+            "RS256" => Some(Algorithm::Rs256),
+            "RS384" => Some(Algorithm::Rs384),
+            "RS512" => Some(Algorithm::Rs512),
+            "ES256" => Some(Algorithm::Es256),
+            "ES384" => Some(Algorithm::Es384),
+            _ => None,
+        }
+    }
+}
+
+/// Why a request's token was refused.
+#[derive(Debug)]
+pub enum JwtError {
+    /// There's no `Authorization: Bearer` header.
+    Missing,
+    /// The token isn't three base64url-encoded parts with JSON in the first two.
+    Malformed,
+    UnsupportedAlgorithm(String),
+    /// No key matches the token, e.g. because the JWKS URL couldn't be fetched or doesn't have the token's key ID.
+    UnknownKey,
+    InvalidSignature,
+    Expired,
+    NotYetValid,
+    WrongIssuer,
+    WrongAudience,
+}
+
+impl JwtError {
+    /// Returns the `WWW-Authenticate` header value sent with the 401 (RFC 6750 §3).
+    pub fn challenge(&self) -> String {
+        match self {
+            // A client that didn't try to authenticate only learns which scheme to use.
+            JwtError::Missing => "Bearer".to_string(),
+            error => format!("Bearer error=\"invalid_token\", error_description=\"{}\"", error),
+        }
+    }
+}
+
+impl fmt::Display for JwtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JwtError::Missing => write!(f, "The request has no bearer token."),
+            JwtError::Malformed => write!(f, "The token is malformed."),
+            JwtError::UnsupportedAlgorithm(algorithm) => write!(f, "The token is signed with the unsupported algorithm {}.", algorithm.replace(['"', '\\'], "")),
+            JwtError::UnknownKey => write!(f, "The token is signed with an unknown key."),
+            JwtError::InvalidSignature => write!(f, "The token's signature is invalid."),
+            JwtError::Expired => write!(f, "The token has expired."),
+            JwtError::NotYetValid => write!(f, "The token isn't valid yet."),
+            JwtError::WrongIssuer => write!(f, "The token was issued by someone else."),
+            JwtError::WrongAudience => write!(f, "The token is meant for someone else."),
+        }
+    }
+}
+
+impl Error for JwtError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Curve {
+    P256,
+    P384,
+}
+
+/// A public key tokens are verified with.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum PublicKey {
+    /// The big-endian modulus and exponent, without leading zeros.
+    Rsa { modulus: Vec<u8>, exponent: Vec<u8> },
+    /// The uncompressed point, `04 || x || y`.
+    Ec { curve: Curve, point: Vec<u8> },
+}
+
+impl PublicKey {
+    fn verify(&self, algorithm: Algorithm, message: &[u8], signature: &[u8]) -> bool {
+        let rsa_parameters = match algorithm {
+            Algorithm::Rs256 => &signature::RSA_PKCS1_2048_8192_SHA256,
+            Algorithm::Rs384 => &signature::RSA_PKCS1_2048_8192_SHA384,
+            _ => &signature::RSA_PKCS1_2048_8192_SHA512,
+        };
+        
+        // The algorithm has to fit the key, so a token can't have a public key used as an HMAC secret.
+        match (self, algorithm) {
+            (PublicKey::Rsa { modulus, exponent }, Algorithm::Rs256 | Algorithm::Rs384 | Algorithm::Rs512) => {
+                signature::RsaPublicKeyComponents { n: modulus, e: exponent }.verify(rsa_parameters, message, signature).is_ok()
+            }
+            (PublicKey::Ec { curve: Curve::P256, point }, Algorithm::Es256) => {
+                signature::UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_FIXED, point).verify(message, signature).is_ok()
+            }
+            (PublicKey::Ec { curve: Curve::P384, point }, Algorithm::Es384) => {
+                signature::UnparsedPublicKey::new(&signature::ECDSA_P384_SHA384_FIXED, point).verify(message, signature).is_ok()
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A key of a key set, which tokens pick by its ID.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct JsonWebKey {
+    id: Option<String>,
+    /// The only algorithm the key may be used with, if the key set says so.
+    algorithm: Option<Algorithm>,
+    key: PublicKey,
+}
+
+/// The keys last fetched from a JWKS URL.
+#[derive(Default)]
+struct JwksCache {
+    keys: Vec<JsonWebKey>,
+    fetched_at: Option<Instant>,
+}
+
+/// Keys fetched from a JWKS URL, e.g. an identity provider's `/.well-known/jwks.json`.
+///
+/// The keys are fetched when the first token arrives and again once they're older than the refresh interval, or when a
+/// token names a key that isn't known yet, since providers rotate their keys.
+struct Jwks {
+    url: String,
+    tls: bool,
+    host: String,
+    address: String,
+    path: String,
+    refresh: Duration,
+    cache: RwLock<JwksCache>,
+    fetching: AtomicBool,
+}
+
+impl Jwks {
+    fn new(url: &str, refresh: Duration) -> Result<Jwks, String> {
+        let (tls, rest) = match (url.strip_prefix("https://"), url.strip_prefix("http://")) {
+            (Some(rest), _) => (true, rest),
+            (_, Some(rest)) => (false, rest),
+            _ => return Err("must be an http:// or https:// URL".to_string()),
+        };
+        
+        let (authority, path) = rest.find('/').map_or((rest, "/"), |index| rest.split_at(index));
+        
+        // IPv6 addresses are bracketed, so their colons aren't mistaken for the port separator.
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => (host, port.parse::<u16>().map_err(|_| "must have a valid port".to_string())?),
+            _ => (authority, if tls { 443 } else { 80 }),
+        };
+        
+        if host.is_empty() {
+            return Err("must have a host".to_string());
+        }
+        
+        Ok(Jwks {
+            url: url.to_string(),
+            tls,
+            host: host.trim_start_matches('[').trim_end_matches(']').to_string(),
+            address: format!("{}:{}", host, port),
+            path: path.to_string(),
+            refresh,
+            cache: RwLock::new(JwksCache::default()),
+            fetching: AtomicBool::new(false),
+        })
+    }
+    
+    /// Returns the key a token with the given key ID is signed with, fetching the keys first if needed.
+    fn find(&self, id: Option<&str>) -> Option<JsonWebKey> {
+        {
+            let cache = self.cache.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+            let fresh = cache.fetched_at.is_some_and(|fetched_at| fetched_at.elapsed() < self.refresh);
+            
+            if let (Some(key), true) = (select_key(&cache.keys, id), fresh) {
+                return Some(key.clone());
+            }
+        }
+        
+        self.refresh();
+        
+        let cache = self.cache.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+        
+        select_key(&cache.keys, id).cloned()
+    }
+    
+    /// Fetches the keys again, unless that happened moments ago. A failed fetch keeps the old keys.
+    fn refresh(&self) {
+        // Only one request fetches, the others go on with the keys they have rather than waiting for a slow provider.
+        if self.fetching.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        
+        let fetched_at = self.cache.read().unwrap_or_else(|poisoned| poisoned.into_inner()).fetched_at;
+        
+        if fetched_at.is_none_or(|fetched_at| fetched_at.elapsed() >= MIN_JWKS_REFETCH) {
+            // No lock is held while fetching, so requests with known keys aren't held up by it.
+            let result = self.fetch();
+            
+            let mut cache = self.cache.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+            cache.fetched_at = Some(Instant::now());
+            
+            match result {
+                Ok(keys) => {
+                    info!("Fetched {} keys from {}.", keys.len(), self.url);
+                    
+                    cache.keys = keys;
+                }
+                Err(error) => warn!("Failed to fetch the keys from {}: {}", self.url, error),
+            }
+        }
+        
+        self.fetching.store(false, Ordering::Release);
+    }
+    
+    fn fetch(&self) -> Result<Vec<JsonWebKey>, String> {
+        let mut last_error = format!("{} didn't resolve to any address", self.address);
+        let mut connection = None;
+        
+        for address in self.address.to_socket_addrs().map_err(|error| error.to_string())? {
+            match TcpStream::connect_timeout(&address, JWKS_TIMEOUT) {
+                Ok(stream) => {
+                    connection = Some(stream);
+                    
+                    break;
+                }
+                Err(error) => last_error = error.to_string(),
+            }
+        }
+        
+        let stream = connection.ok_or(last_error)?;
+        stream.set_read_timeout(Some(JWKS_TIMEOUT)).map_err(|error| error.to_string())?;
+        stream.set_write_timeout(Some(JWKS_TIMEOUT)).map_err(|error| error.to_string())?;
+        
+        // HTTP/1.0 keeps the response from being chunked, it simply ends when the connection is closed.
+        let request = format!("GET {} HTTP/1.0\r\nHost: {}\r\nAccept: application/json\r\nUser-Agent: web_server/{}\r\n\r\n", self.path, self.host, env!("CARGO_PKG_VERSION"));
+        
+        let response = if self.tls {
+            let server_name = ServerName::try_from(self.host.clone()).map_err(|error| error.to_string())?;
+            let connection = ClientConnection::new(client_config()?, server_name).map_err(|error| error.to_string())?;
+            
+            exchange(StreamOwned::new(connection, stream), &request)
+        } else {
+            exchange(stream, &request)
+        };
+        
+        let response = response.map_err(|error| error.to_string())?;
+        let header_end = response.windows(4).position(|window| window == b"\r\n\r\n").ok_or("the response has no end of headers")?;
+        let head = String::from_utf8_lossy(&response[..header_end]);
+        
+        match head.split(' ').nth(1) {
+            Some("200") => {}
+            status => return Err(format!("the response has status {}", status.unwrap_or("?"))),
+        }
+        
+        let body = std::str::from_utf8(&response[header_end + 4..]).map_err(|_| "the key set isn't UTF-8")?;
+        let key_set = json::parse(body).map_err(|error| format!("the key set isn't JSON: {}", error))?;
+        
+        parse_key_set(&key_set)
+    }
+}
+
+/// Sends a request and reads the whole response, which ends when the connection is closed.
+fn exchange(mut stream: impl Read + Write, request: &str) -> io::Result<Vec<u8>> {
+    stream.write_all(request.as_bytes())?;
+    stream.flush()?;
+    
+    let mut response = Vec::new();
+    
+    // Servers may close the connection without ending the TLS session properly, which doesn't lose anything here.
+    match stream.take(MAX_JWKS_BYTES).read_to_end(&mut response) {
+        Ok(_) => Ok(response),
+        Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => Ok(response),
+        Err(error) => Err(error),
+    }
+}
+
+/// Returns the TLS configuration for fetching keys, trusting the Mozilla root certificates.
+fn client_config() -> Result<Arc<ClientConfig>, String> {
+    let roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    
+    let config = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|error| error.to_string())?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    
+    Ok(Arc::new(config))
+}
+
+/// Reads the RSA and EC keys of a JSON Web Key Set (RFC 7517 §5), skipping encryption keys and unsupported types.
+fn parse_key_set(key_set: &JsonValue) -> Result<Vec<JsonWebKey>, String> {
+    if !key_set["keys"].is_array() {
+        return Err("the key set has no keys array".to_string());
+    }
+    
+    let decode = |value: &JsonValue| value.as_str().and_then(|value| BASE64URL.decode(value).ok());
+    let mut keys = Vec::new();
+    
+    for key in key_set["keys"].members() {
+        if key["use"].as_str().is_some_and(|usage| usage != "sig") {
+            continue;
+        }
+        
+        let public_key = match (key["kty"].as_str(), key["crv"].as_str()) {
+            (Some("RSA"), _) => match (decode(&key["n"]), decode(&key["e"])) {
+                (Some(modulus), Some(exponent)) => PublicKey::Rsa {
+                    modulus: strip_leading_zeros(&modulus).to_vec(),
+                    exponent: strip_leading_zeros(&exponent).to_vec(),
+                },
+                _ => continue,
+            },
+            (Some("EC"), Some(curve @ ("P-256" | "P-384"))) => match (decode(&key["x"]), decode(&key["y"])) {
+                (Some(x), Some(y)) => PublicKey::Ec {
+                    curve: if curve == "P-256" { Curve::P256 } else { Curve::P384 },
+                    point: [&[0x04], x.as_slice(), y.as_slice()].concat(),
+                },
+                _ => continue,
+            },
+            _ => continue,
+        };
+        
+        keys.push(JsonWebKey {
+            id: key["kid"].as_str().map(str::to_string),
+            algorithm: key["alg"].as_str().and_then(Algorithm::parse),
+            key: public_key,
+        });
+    }
+    
+    Ok(keys)
+}
+
+/// Picks the key with the token's key ID, or the only key if the token doesn't name one.
+fn select_key<'a>(keys: &'a [JsonWebKey], id: Option<&str>) -> Option<&'a JsonWebKey> {
+    match id {
+        Some(id) => keys.iter().find(|key| key.id.as_deref() == Some(id)),
+        None if keys.len() == 1 => keys.first(),
+        None => None,
+    }
+}
+
+enum KeySource {
+    Secret(Vec<u8>),
+    PublicKey(PublicKey),
+    Jwks(Jwks),
+}
+
+/// What tokens are verified with: an HMAC secret, a public key or the keys published at a JWKS URL.
+pub struct JwtKey {
+    source: KeySource,
+}
+
+impl JwtKey {
+    /// Verifies tokens signed with HS256, HS384 or HS512.
+    pub fn secret(secret: &[u8]) -> JwtKey {
+        JwtKey {
+            source: KeySource::Secret(secret.to_vec()),
+        }
+    }
+    
+    /// Reads an RSA or EC (P-256 or P-384) public key from a PEM file, e.g. one written by
+    /// `openssl pkey -pubout`, which verifies tokens signed with RS256, RS384, RS512, ES256 or ES384.
+    pub fn from_pem_file(path: &str) -> Result<JwtKey, String> {
+        let der = SubjectPublicKeyInfoDer::from_pem_file(path).map_err(|error| format!("must be a PEM public key: {}", error))?;
+        let key = parse_public_key(der.as_ref()).ok_or("must be an RSA, P-256 or P-384 public key")?;
+        
+        Ok(JwtKey {
+            source: KeySource::PublicKey(key),
+        })
+    }
+    
+    /// Verifies tokens with the keys published at a URL, which are fetched again every `refresh`.
+    pub fn jwks(url: &str, refresh: Duration) -> Result<JwtKey, String> {
+        Ok(JwtKey {
+            source: KeySource::Jwks(Jwks::new(url, refresh)?),
+        })
+    }
+    
+    fn verify(&self, algorithm: Algorithm, key_id: Option<&str>, message: &[u8], signature: &[u8]) -> Result<(), JwtError> {
+        let verified = match &self.source {
+            KeySource::Secret(secret) => {
+                let hash = match algorithm {
+                    Algorithm::Hs256 => hmac::HMAC_SHA256,
+                    Algorithm::Hs384 => hmac::HMAC_SHA384,
+                    Algorithm::Hs512 => hmac::HMAC_SHA512,
+                    _ => return Err(JwtError::InvalidSignature),
+                };
+                
+                hmac::verify(&hmac::Key::new(hash, secret), message, signature).is_ok()
+            }
+            KeySource::PublicKey(key) => key.verify(algorithm, message, signature),
+            KeySource::Jwks(jwks) => {
+                let key = jwks.find(key_id).ok_or(JwtError::UnknownKey)?;
+                
+                key.algorithm.is_none_or(|expected| expected == algorithm) && key.key.verify(algorithm, message, signature)
+            }
+        };
+        
+        match verified {
+            true => Ok(()),
+            false => Err(JwtError::InvalidSignature),
+        }
+    }
+}
+
+/// Requires a valid JSON Web Token (RFC 7519) as `Authorization: Bearer` on every request for a path prefix.
+pub struct JwtValidator {
+    prefix: String,
+    key: JwtKey,
+    issuer: Option<String>,
+    audience: Option<String>,
+    leeway: Duration,
+}
+
+impl JwtValidator {
+    pub fn new(prefix: &str, key: JwtKey) -> JwtValidator {
+        JwtValidator {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            key,
+            issuer: None,
+            audience: None,
+            leeway: DEFAULT_LEEWAY,
+        }
+    }
+    
+    /// Only accepts tokens whose `iss` claim is the given issuer.
+    pub fn with_issuer(mut self, issuer: &str) -> JwtValidator {
+        self.issuer = Some(issuer.to_string());
+        
+        self
+    }
+    
+    /// Only accepts tokens whose `aud` claim is or contains the given audience.
+    pub fn with_audience(mut self, audience: &str) -> JwtValidator {
+        self.audience = Some(audience.to_string());
+        
+        self
+    }
+    
+    pub fn with_leeway(mut self, leeway: Duration) -> JwtValidator {
+        self.leeway = leeway;
+        
+        self
+    }
+    
+    pub fn get_prefix(&self) -> &str {
+        &self.prefix
+    }
+    
+    pub fn get_issuer(&self) -> Option<&str> {
+        self.issuer.as_deref()
+    }
+    
+    pub fn get_audience(&self) -> Option<&str> {
+        self.audience.as_deref()
+    }
+    
+    pub fn get_leeway(&self) -> Duration {
+        self.leeway
+    }
+    
+    /// Checks if a path requires a token, see `auth::covers`.
+    pub fn covers(&self, path: &str) -> bool {
+        auth::covers(&self.prefix, path)
+    }
+    
+    /// Checks the request's token and returns its claims if it's signed with the key, current, and for the configured
+    /// issuer and audience.
+    pub fn validate(&self, request: &Request) -> Result<JsonValue, JwtError> {
+        let token = request.get_header("Authorization")
+            .and_then(|header| header.trim().split_once(' '))
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("Bearer"))
+            .map(|(_, token)| token.trim())
+            .ok_or(JwtError::Missing)?;
+        
+        // The signature covers the encoded header and payload, exactly as they were sent.
+        let (signed, signature) = token.rsplit_once('.').ok_or(JwtError::Malformed)?;
+        let (header, payload) = signed.split_once('.').ok_or(JwtError::Malformed)?;
+        
+        let header = decode_json(header)?;
+        let signature = BASE64URL.decode(signature).map_err(|_| JwtError::Malformed)?;
+        
+        let algorithm = header["alg"].as_str().ok_or(JwtError::Malformed)?;
+        let algorithm = Algorithm::parse(algorithm).ok_or_else(|| JwtError::UnsupportedAlgorithm(algorithm.to_string()))?;
+        
+        self.key.verify(algorithm, header["kid"].as_str(), signed.as_bytes(), &signature)?;
+        
+        // Only look at the claims once it's certain they weren't tampered with.
+        let claims = decode_json(payload)?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+        let leeway = self.leeway.as_secs_f64();
+        
+        match (&claims["exp"], claims["exp"].as_f64()) {
+            (JsonValue::Null, _) => {}
+            (_, Some(expires_at)) if now < expires_at + leeway => {}
+            (_, Some(_)) => return Err(JwtError::Expired),
+            (_, None) => return Err(JwtError::Malformed),
+        }
+        
+        match (&claims["nbf"], claims["nbf"].as_f64()) {
+            (JsonValue::Null, _) => {}
+            (_, Some(not_before)) if now + leeway >= not_before => {}
+            (_, Some(_)) => return Err(JwtError::NotYetValid),
+            (_, None) => return Err(JwtError::Malformed),
+        }
+        
+        if let Some(issuer) = &self.issuer {
+            if claims["iss"].as_str() != Some(issuer.as_str()) {
+                return Err(JwtError::WrongIssuer);
+            }
+        }
+        
+        // The audience is either a single string or an array of them (RFC 7519 §4.1.3).
+        if let Some(audience) = &self.audience {
+            let audience = audience.as_str();
+            let matches = claims["aud"].as_str() == Some(audience) || claims["aud"].members().any(|member| member.as_str() == Some(audience));
+            
+            if !matches {
+                return Err(JwtError::WrongAudience);
+            }
+        }
+        
+        Ok(claims)
+    }
+}
+
+/// Decodes a part of a token that holds a JSON object.
+fn decode_json(part: &str) -> Result<JsonValue, JwtError> {
+    let bytes = BASE64URL.decode(part).map_err(|_| JwtError::Malformed)?;
+    let text = String::from_utf8(bytes).map_err(|_| JwtError::Malformed)?;
+    
+    match json::parse(&text) {
+        Ok(value) if value.is_object() => Ok(value),
+        _ => Err(JwtError::Malformed),
+    }
+}
+
+/// Reads the key out of a DER-encoded SubjectPublicKeyInfo (RFC 5280 §4.1), the format of PEM `PUBLIC KEY` blocks.
+fn parse_public_key(der: &[u8]) -> Option<PublicKey> {
+    let (info, _) = read_der(der, 0x30)?;
+    let (algorithm, rest) = read_der(info, 0x30)?;
+    let (bits, _) = read_der(rest, 0x03)?;
+    let (oid, parameters) = read_der(algorithm, 0x06)?;
+    
+    // The bit string starts with the number of unused bits, which is always 0 for keys.
+    let key = bits.strip_prefix(&[0])?;
+    
+    match oid {
+        RSA_ENCRYPTION_OID => {
+            let (rsa_key, _) = read_der(key, 0x30)?;
+            let (modulus, rest) = read_der(rsa_key, 0x02)?;
+            let (exponent, _) = read_der(rest, 0x02)?;
+            
+            Some(PublicKey::Rsa {
+                modulus: strip_leading_zeros(modulus).to_vec(),
+                exponent: strip_leading_zeros(exponent).to_vec(),
+            })
+        }
+        EC_PUBLIC_KEY_OID => {
+            let curve = match read_der(parameters, 0x06)?.0 {
+                P256_OID => Curve::P256,
+                P384_OID => Curve::P384,
+                _ => return None,
+            };
+            
+            Some(PublicKey::Ec { curve, point: key.to_vec() })
+        }
+        _ => None,
+    }
+}
+
+/// Reads a DER element with the given tag, returning its contents and whatever follows it.
+fn read_der(input: &[u8], tag: u8) -> Option<(&[u8], &[u8])> {
+    let (&actual_tag, rest) = input.split_first()?;
+    let (&length, rest) = rest.split_first()?;
+    
+    if actual_tag != tag {
+        return None;
+    }
+    
+    // Lengths from 128 on are written in as many bytes as the low bits of the first one say.
+    let (length, rest) = match length {
+        0..=0x7f => (usize::from(length), rest),
+        0x81..=0x84 => {
+            let count = usize::from(length & 0x7f);
+            let bytes = rest.get(..count)?;
+            
+            (bytes.iter().fold(0, |length, byte| length << 8 | usize::from(*byte)), &rest[count..])
+        }
+        _ => return None,
+    };
+    
+    (rest.len() >= length).then(|| rest.split_at(length))
+}
+
+fn strip_leading_zeros(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter().position(|byte| *byte != 0).unwrap_or(bytes.len());
+    
+    &bytes[start..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const SECRET: &[u8] = b"a secret that is long enough for HS256";
+    
+    fn now() -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+    }
+    
+    /// Builds a token from a header and claims, signed with HMAC-SHA256 over `key`.
+    fn token(header: &JsonValue, claims: &JsonValue, key: &[u8]) -> String {
+        let signed = format!("{}.{}", BASE64URL.encode(header.dump()), BASE64URL.encode(claims.dump()));
+        let signature = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, key), signed.as_bytes());
+        
+        format!("{}.{}", signed, BASE64URL.encode(signature.as_ref()))
+    }
+    
+    fn request(token: &str) -> Request {
+        Request::parse(&format!("GET /api HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer {}\r\n\r\n", token)).unwrap()
+    }
+    
+    fn validator() -> JwtValidator {
+        JwtValidator::new("/api", JwtKey::secret(SECRET)).with_leeway(Duration::ZERO)
+    }
+    
+    /// Wraps contents in a DER element, only short lengths are needed here.
+    fn der(tag: u8, contents: &[u8]) -> Vec<u8> {
+        [&[tag, contents.len() as u8], contents].concat()
+    }
+    
+    #[test]
+    fn valid_token_is_accepted() {
+        let token = token(&json::object! { "alg": "HS256" }, &json::object! { "sub": "alice", "exp": now() + 60 }, SECRET);
+        
+        assert_eq!(validator().validate(&request(&token)).unwrap()["sub"], "alice");
+    }
+    
+    #[test]
+    fn bad_signature_is_refused() {
+        let forged = token(&json::object! { "alg": "HS256" }, &json::object! { "sub": "alice" }, b"someone else's secret");
+        
+        assert!(matches!(validator().validate(&request(&forged)), Err(JwtError::InvalidSignature)));
+        
+        // Swapping the claims of a valid token breaks its signature as well.
+        let valid = token(&json::object! { "alg": "HS256" }, &json::object! { "sub": "alice" }, SECRET);
+        let (header, rest) = valid.split_once('.').unwrap();
+        let signature = rest.split_once('.').unwrap().1;
+        let tampered = format!("{}.{}.{}", header, BASE64URL.encode(json::object! { "sub": "admin" }.dump()), signature);
+        
+        assert!(matches!(validator().validate(&request(&tampered)), Err(JwtError::InvalidSignature)));
+    }
+    
+    #[test]
+    fn alg_none_is_refused() {
+        let header = BASE64URL.encode(json::object! { "alg": "none" }.dump());
+        let claims = BASE64URL.encode(json::object! { "sub": "alice" }.dump());
+        
+        match validator().validate(&request(&format!("{}.{}.", header, claims))) {
+            Err(JwtError::UnsupportedAlgorithm(algorithm)) => assert_eq!(algorithm, "none"),
+            result => panic!("expected alg none to be refused, got {:?}", result),
+        }
+    }
+    
+    #[test]
+    fn public_key_is_not_used_as_an_hmac_secret() {
+        let modulus = vec![0xc5; 256];
+        let key = JwtKey { source: KeySource::PublicKey(PublicKey::Rsa { modulus: modulus.clone(), exponent: vec![1, 0, 1] }) };
+        let validator = JwtValidator::new("/api", key);
+        
+        // Someone who knows the public key signs an HS256 token with it, hoping it's taken as the secret.
+        let token = token(&json::object! { "alg": "HS256" }, &json::object! { "sub": "alice" }, &modulus);
+        
+        assert!(matches!(validator.validate(&request(&token)), Err(JwtError::InvalidSignature)));
+    }
+    
+    #[test]
+    fn rsa_token_is_refused_by_an_hmac_secret() {
+        let token = token(&json::object! { "alg": "RS256" }, &json::object! { "sub": "alice" }, SECRET);
+        
+        assert!(matches!(validator().validate(&request(&token)), Err(JwtError::InvalidSignature)));
+    }
+    
+    #[test]
+    fn expired_token_is_refused() {
+        let token = token(&json::object! { "alg": "HS256" }, &json::object! { "exp": now() - 30 }, SECRET);
+        
+        assert!(matches!(validator().validate(&request(&token)), Err(JwtError::Expired)));
+        assert!(validator().with_leeway(Duration::from_secs(60)).validate(&request(&token)).is_ok());
+    }
+    
+    #[test]
+    fn token_before_nbf_is_refused() {
+        let token = token(&json::object! { "alg": "HS256" }, &json::object! { "nbf": now() + 30 }, SECRET);
+        
+        assert!(matches!(validator().validate(&request(&token)), Err(JwtError::NotYetValid)));
+        assert!(validator().with_leeway(Duration::from_secs(60)).validate(&request(&token)).is_ok());
+    }
+    
+    #[test]
+    fn non_numeric_exp_is_malformed() {
+        let token = token(&json::object! { "alg": "HS256" }, &json::object! { "exp": "tomorrow" }, SECRET);
+        
+        assert!(matches!(validator().validate(&request(&token)), Err(JwtError::Malformed)));
+    }
+    
+    #[test]
+    fn slow_key_fetch_does_not_hold_up_other_requests() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let jwks = Arc::new(Jwks::new(&format!("http://{}/jwks.json", listener.local_addr().unwrap()), DEFAULT_JWKS_REFRESH).unwrap());
+        
+        let fetching = Arc::clone(&jwks);
+        let fetch = std::thread::spawn(move || fetching.refresh());
+        
+        // The provider accepts the connection but doesn't answer, while another request needs a key.
+        let (connection, _) = listener.accept().unwrap();
+        let started = Instant::now();
+        
+        assert_eq!(jwks.find(Some("key")), None);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        
+        drop(connection);
+        fetch.join().unwrap();
+    }
+    
+    #[test]
+    fn rsa_public_key_is_parsed() {
+        let algorithm = [der(0x06, RSA_ENCRYPTION_OID), der(0x05, &[])].concat();
+        let rsa_key = der(0x30, &[der(0x02, &[0x00, 0xc5, 0x01]), der(0x02, &[0x01, 0x00, 0x01])].concat());
+        let spki = der(0x30, &[der(0x30, &algorithm), der(0x03, &[&[0x00], rsa_key.as_slice()].concat())].concat());
+        
+        assert_eq!(parse_public_key(&spki), Some(PublicKey::Rsa { modulus: vec![0xc5, 0x01], exponent: vec![0x01, 0x00, 0x01] }));
+    }
+    
+    #[test]
+    fn malformed_der_key_is_refused() {
+        let rsa_key = der(0x30, &[der(0x02, &[0xc5]), der(0x02, &[0x03])].concat());
+        let rsa_algorithm = der(0x30, &[der(0x06, RSA_ENCRYPTION_OID), der(0x05, &[])].concat());
+        let unknown_algorithm = der(0x30, &der(0x06, &[0x2a, 0x03]));
+        let ec_algorithm = der(0x30, &[der(0x06, EC_PUBLIC_KEY_OID), der(0x06, &[0x2b, 0x81, 0x04, 0x00, 0x23])].concat());
+        let bits = der(0x03, &[&[0x00], rsa_key.as_slice()].concat());
+        
+        let keys = [
+            Vec::new(),
+            b"not a key at all".to_vec(),
+            // The outer length claims more bytes than there are.
+            vec![0x30, 0x84, 0xff, 0xff, 0xff, 0xff, 0x30],
+            // The outer element is cut short.
+            der(0x30, &[rsa_algorithm.clone(), bits.clone()].concat())[..10].to_vec(),
+            // Lengths written in more than four bytes aren't supported.
+            vec![0x30, 0x85, 0x00, 0x00, 0x00, 0x00, 0x05],
+            // The bit string has unused bits.
+            der(0x30, &[rsa_algorithm.clone(), der(0x03, &[&[0x01], rsa_key.as_slice()].concat())].concat()),
+            // Neither RSA nor EC.
+            der(0x30, &[unknown_algorithm, bits.clone()].concat()),
+            // EC on P-521, which isn't supported.
+            der(0x30, &[ec_algorithm, der(0x03, &[0x00, 0x04, 0x01, 0x02])].concat()),
+        ];
+        
+        for key in keys {
+            assert_eq!(parse_public_key(&key), None, "{:02x?}", key);
+        }
+    }
+    
+    #[test]
+    fn pem_file_without_a_key_is_refused() {
+        let path = std::env::temp_dir().join(format!("web_server-jwt-{}.pem", std::process::id()));
+        std::fs::write(&path, "-----BEGIN PUBLIC KEY-----\nbm90IGEga2V5\n-----END PUBLIC KEY-----\n").unwrap();
+        
+        let result = JwtKey::from_pem_file(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        
+        assert!(result.is_err());
+    }
+}
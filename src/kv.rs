@@ -0,0 +1,39 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use json::JsonValue;
+
+/// The path the store can be updated under with `POST /_kv/<key>`, if signing in is required for it.
+pub const ADMIN_PATH: &str = "/_kv/";
+
+/// A small in-memory key-value store for sharing state between requests, e.g. a visit counter or a feature flag.
+#[derive(Default)]
+pub struct KvStore {
+    values: RwLock<HashMap<String, JsonValue>>,
+}
+
+impl KvStore {
+    pub fn new() -> KvStore {
+        KvStore::default()
+    }
+    
+    pub fn get(&self, key: &str) -> Option<JsonValue> {
+        self.values.read().unwrap_or_else(|poisoned| poisoned.into_inner()).get(key).cloned()
+    }
+    
+    pub fn set(&self, key: &str, value: JsonValue) {
+        self.values.write().unwrap_or_else(|poisoned| poisoned.into_inner()).insert(key.to_string(), value);
+    }
+    
+    pub fn remove(&self, key: &str) -> Option<JsonValue> {
+        self.values.write().unwrap_or_else(|poisoned| poisoned.into_inner()).remove(key)
+    }
+    
+    /// Returns a copy of every entry, so they can be used without holding the lock.
+    pub fn entries(&self) -> Vec<(String, JsonValue)> {
+        self.values.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+}
@@ -0,0 +1,41 @@
+pub mod access_log;
+pub mod auth;
+pub mod autoindex;
+pub mod compression;
+pub mod conditional;
+pub mod config;
+pub mod context;
+pub mod error;
+pub mod fastcgi;
+pub mod file_cache;
+pub mod filter;
+pub mod head_cache;
+pub mod hook;
+pub mod http;
+pub mod jwt;
+pub mod kv;
+pub mod listener;
+pub mod logging;
+pub mod middleware;
+pub mod mime;
+pub mod network;
+pub mod openapi;
+pub mod proxy;
+pub mod range;
+pub mod rate_limit;
+pub mod robots;
+pub mod router;
+pub mod server;
+pub mod shutdown;
+pub mod sse;
+pub mod status;
+pub mod template;
+pub mod tls;
+pub mod tunnel;
+pub mod upgrade;
+pub mod websocket;
+
+pub use http::{Request, Response};
+pub use router::{Handler, RouteParams};
+pub use server::{Server, ServerBuilder};
+pub use status::StatusCode;
@@ -0,0 +1,54 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use rustls::ServerConfig;
+
+/// An address the server accepts connections on, along with whether they're encrypted with TLS.
+///
+/// Every connection remembers the listener it arrived on, see `ConnectionContext::get_listener`, so middleware can
+/// tell e.g. the plain and the TLS port apart.
+pub struct Listener {
+    name: String,
+    bind_address: IpAddr,
+    port: u16,
+    force_dual_stack: bool,
+    tls_config: Option<Arc<ServerConfig>>,
+}
+
+impl Listener {
+    pub fn new(name: &str, bind_address: IpAddr, port: u16, force_dual_stack: bool, tls_config: Option<Arc<ServerConfig>>) -> Listener {
+        Listener {
+            name: name.to_string(),
+            bind_address,
+            port,
+            force_dual_stack,
+            tls_config,
+        }
+    }
+    
+    /// Returns the name connections are tagged with, which defaults to the configured port.
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+    
+    pub fn get_bind_address(&self) -> IpAddr {
+        self.bind_address
+    }
+    
+    /// Returns the configured port, which is 0 if the OS picks one when binding.
+    pub fn get_port(&self) -> u16 {
+        self.port
+    }
+    
+    pub fn is_force_dual_stack(&self) -> bool {
+        self.force_dual_stack
+    }
+    
+    pub fn get_tls_config(&self) -> Option<&Arc<ServerConfig>> {
+        self.tls_config.as_ref()
+    }
+    
+    pub fn is_tls(&self) -> bool {
+        self.tls_config.is_some()
+    }
+}
@@ -0,0 +1,492 @@
+use std::backtrace::Backtrace;
+use std::cmp::Reverse;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+use crate::context::ConnectionContext;
+
+/// How long buffered entries may wait for another write before they're flushed along with it.
+const BUFFERED_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When a log file is moved aside for a fresh one, and how many of the old files are kept.
+///
+/// Old files get a number appended, `access.log.1` being the most recent one, and the oldest is deleted once there are
+/// more than `max_files` of them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogRotation {
+    max_size_bytes: Option<u64>,
+    daily: bool,
+    max_files: usize,
+}
+
+impl LogRotation {
+    /// Rotates once the file would grow past `max_size_bytes`, if given, and at midnight UTC if `daily` is set.
+    pub fn new(max_size_bytes: Option<u64>, daily: bool, max_files: usize) -> LogRotation {
+        LogRotation {
+            max_size_bytes,
+            daily,
+            max_files,
+        }
+    }
+    
+    pub fn get_max_size_bytes(&self) -> Option<u64> {
+        self.max_size_bytes
+    }
+    
+    pub fn is_daily(&self) -> bool {
+        self.daily
+    }
+    
+    pub fn get_max_files(&self) -> usize {
+        self.max_files
+    }
+}
+
+/// The open log along with what's needed to decide when to flush and rotate it.
+struct LogTarget {
+    writer: Box<dyn Write + Send>,
+    /// The file being written, stdout and stderr have none and are never rotated.
+    path: Option<PathBuf>,
+    size_bytes: u64,
+    day: u64,
+    last_flush: Instant,
+}
+
+/// A thread-safe destination for log entries.
+pub struct LogWriter {
+    target: Mutex<LogTarget>,
+    buffer_bytes: usize,
+    rotation: Option<LogRotation>,
+}
+
+impl LogWriter {
+    /// Opens the file at the given path in append mode, `-` stands for stdout.
+    pub fn open(path: &str) -> io::Result<LogWriter> {
+        LogWriter::open_buffered(path, 0)
+    }
+    
+    /// Like `open`, but collects entries in a buffer of the given size rather than writing each one straight away.
+    ///
+    /// The buffer is written out when it's full, when an entry arrives a second or more after the last flush, and on
+    /// `flush`. A size of 0 opens the log unbuffered.
+    pub fn open_buffered(path: &str, buffer_bytes: usize) -> io::Result<LogWriter> {
+        let target = match path {
+            "-" => LogTarget {
+                writer: buffer(io::stdout(), buffer_bytes),
+                path: None,
+                size_bytes: 0,
+                day: current_day(),
+                last_flush: Instant::now(),
+            },
+            path => {
+                let file = open_append(path)?;
+                let metadata = file.metadata()?;
+                
+                // A file left over from an earlier run counts towards the size and belongs to the day it was written.
+                LogTarget {
+                    writer: buffer(file, buffer_bytes),
+                    path: Some(PathBuf::from(path)),
+                    size_bytes: metadata.len(),
+                    day: metadata.modified().map(day_of).unwrap_or_else(|_| current_day()),
+                    last_flush: Instant::now(),
+                }
+            }
+        };
+        
+        Ok(LogWriter {
+            target: Mutex::new(target),
+            buffer_bytes,
+            rotation: None,
+        })
+    }
+    
+    pub fn stderr() -> LogWriter {
+        LogWriter {
+            target: Mutex::new(LogTarget {
+                writer: Box::new(io::stderr()),
+                path: None,
+                size_bytes: 0,
+                day: current_day(),
+                last_flush: Instant::now(),
+            }),
+            buffer_bytes: 0,
+            rotation: None,
+        }
+    }
+    
+    /// Rotates the log file according to the given policy, logs written to stdout or stderr are never rotated.
+    pub fn with_rotation(mut self, rotation: LogRotation) -> LogWriter {
+        self.rotation = Some(rotation);
+        self
+    }
+    
+    pub fn get_rotation(&self) -> Option<&LogRotation> {
+        self.rotation.as_ref()
+    }
+    
+    /// Writes a complete entry at once, so entries from different threads never interleave.
+    pub fn write(&self, entry: &str) {
+        // A poisoned lock only means another thread panicked mid-write, the writer itself is still usable.
+        let mut target = self.target.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        
+        // Rotate while holding the lock, so no other thread writes to the file as it's moved aside.
+        if let Some(rotation) = &self.rotation {
+            if self.is_rotation_due(&target, rotation, entry.len() as u64) {
+                // If the file can't be moved aside, keep appending to it rather than losing the entry.
+                let _ = self.rotate(&mut target, rotation);
+            }
+        }
+        
+        // Failing to log shouldn't fail the request.
+        let _ = target.writer.write_all(entry.as_bytes());
+        target.size_bytes += entry.len() as u64;
+        
+        if self.buffer_bytes == 0 {
+            let _ = target.writer.flush();
+            
+            return;
+        }
+        
+        // Flush now and then, so a quiet server's entries don't sit in the buffer indefinitely.
+        if target.last_flush.elapsed() >= BUFFERED_FLUSH_INTERVAL {
+            let _ = target.writer.flush();
+            target.last_flush = Instant::now();
+        }
+    }
+    
+    /// Writes out any buffered entries.
+    pub fn flush(&self) {
+        let mut target = self.target.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        
+        let _ = target.writer.flush();
+        target.last_flush = Instant::now();
+    }
+    
+    fn is_rotation_due(&self, target: &LogTarget, rotation: &LogRotation, entry_bytes: u64) -> bool {
+        if target.path.is_none() || target.size_bytes == 0 {
+            return false;
+        }
+        
+        let too_large = rotation.max_size_bytes.is_some_and(|max_size_bytes| target.size_bytes + entry_bytes > max_size_bytes);
+        let new_day = rotation.daily && current_day() != target.day;
+        
+        too_large || new_day
+    }
+    
+    /// Moves the current file aside, shifting the older ones up by one, and starts a new one in its place.
+    fn rotate(&self, target: &mut LogTarget, rotation: &LogRotation) -> io::Result<()> {
+        let path = match &target.path {
+            Some(path) => path.clone(),
+            None => return Ok(()),
+        };
+        
+        target.writer.flush()?;
+        
+        if rotation.max_files == 0 {
+            fs::remove_file(&path)?;
+        } else {
+            // Renaming over an existing file fails on some platforms, so make room for the oldest file first.
+            let oldest = numbered_path(&path, rotation.max_files);
+            
+            if oldest.exists() {
+                fs::remove_file(&oldest)?;
+            }
+            
+            for number in (1..rotation.max_files).rev() {
+                let from = numbered_path(&path, number);
+                
+                if from.exists() {
+                    fs::rename(&from, numbered_path(&path, number + 1))?;
+                }
+            }
+            
+            fs::rename(&path, numbered_path(&path, 1))?;
+        }
+        
+        target.writer = buffer(open_append(&path)?, self.buffer_bytes);
+        target.size_bytes = 0;
+        target.day = current_day();
+        target.last_flush = Instant::now();
+        
+        Ok(())
+    }
+}
+
+/// A log that only records failed requests, separate from any per-request logging.
+pub struct ErrorLog {
+    writer: LogWriter,
+}
+
+impl ErrorLog {
+    /// Opens the error log at the given path in append mode, or logs to stderr if no path is given.
+    pub fn new(path: Option<&str>) -> io::Result<ErrorLog> {
+        let writer = match path {
+            Some(path) => LogWriter::open(path)?,
+            None => LogWriter::stderr(),
+        };
+        
+        Ok(ErrorLog::with_writer(writer))
+    }
+    
+    /// Logs to an already opened writer, e.g. one that rotates its file.
+    pub fn with_writer(writer: LogWriter) -> ErrorLog {
+        ErrorLog {
+            writer,
+        }
+    }
+    
+    /// Records a failed request, with what went wrong as far as it's known.
+    pub fn log(&self, context: &ConnectionContext, status_code: u16, message: &str) {
+        self.writer.write(&format!("{} {} {} {}\n", format_timestamp(SystemTime::now()), context, status_code, message));
+    }
+    
+    /// Records a server error that isn't tied to a request, like failing to accept a connection.
+    pub fn log_error(&self, message: &str) {
+        self.writer.write(&format!("{} Error: {}\n", format_timestamp(SystemTime::now()), message));
+    }
+    
+    /// Records a panic that happened outside of the normal error handling.
+    pub fn log_panic(&self, message: &str, backtrace: Option<&Backtrace>) {
+        let mut entry = format!("{} Panic: {}\n", format_timestamp(SystemTime::now()), message);
+        
+        if let Some(backtrace) = backtrace {
+            entry += &format!("{}\n", backtrace);
+        }
+        
+        self.writer.write(&entry);
+    }
+}
+
+/// Which messages are logged, as a default level followed by per-module overrides, e.g.
+/// `info,web_server::tunnel=debug`.
+///
+/// A module's most specific override applies to it and the modules inside it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogFilter {
+    default: LevelFilter,
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl LogFilter {
+    pub fn new(default: LevelFilter) -> LogFilter {
+        LogFilter {
+            default,
+            targets: Vec::new(),
+        }
+    }
+    
+    /// Parses a comma-separated list of levels, where a bare level sets the default and `target=level` overrides it.
+    pub fn parse(spec: &str) -> Option<LogFilter> {
+        let mut filter = LogFilter::new(LevelFilter::Info);
+        
+        for directive in spec.split(',').map(str::trim).filter(|directive| !directive.is_empty()) {
+            match directive.split_once('=') {
+                Some((target, level)) if !target.trim().is_empty() => {
+                    filter.targets.push((target.trim().to_string(), level.trim().parse().ok()?));
+                }
+                Some(_) => return None,
+                None => filter.default = directive.parse().ok()?,
+            }
+        }
+        
+        // Check the longest targets first, so the most specific override wins.
+        filter.targets.sort_by_key(|(target, _)| Reverse(target.len()));
+        
+        Some(filter)
+    }
+    
+    /// Returns the most verbose level a message from the given module is logged at.
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        self.targets.iter()
+            .find(|(prefix, _)| target == prefix || target.strip_prefix(prefix.as_str()).is_some_and(|rest| rest.starts_with("::")))
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default)
+    }
+    
+    /// Returns the most verbose level any module is logged at.
+    pub fn max_level(&self) -> LevelFilter {
+        self.targets.iter().map(|(_, level)| *level).fold(self.default, Ord::max)
+    }
+}
+
+impl fmt::Display for LogFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.default.as_str().to_lowercase())?;
+        
+        for (target, level) in self.targets.iter().rev() {
+            write!(f, ",{}={}", target, level.as_str().to_lowercase())?;
+        }
+        
+        Ok(())
+    }
+}
+
+/// Where the server's own messages go, see `init`.
+struct LoggerOutputs {
+    filter: LogFilter,
+    stderr: Option<LogWriter>,
+    file: Option<LogWriter>,
+}
+
+/// The logger behind the `log` macros, whose outputs can be replaced after it's been installed.
+struct Logger {
+    outputs: RwLock<LoggerOutputs>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let outputs = self.outputs.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+        
+        metadata.level() <= outputs.filter.level_for(metadata.target())
+    }
+    
+    fn log(&self, record: &Record) {
+        let outputs = self.outputs.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+        
+        if record.level() > outputs.filter.level_for(record.target()) {
+            return;
+        }
+        
+        let entry = format!(
+            "{} {:<5} {}: {}\n",
+            format_timestamp(SystemTime::now()), record.level(), record.target(), record.args(),
+        );
+        
+        for writer in outputs.stderr.iter().chain(outputs.file.iter()) {
+            writer.write(&entry);
+        }
+    }
+    
+    fn flush(&self) {
+        let outputs = self.outputs.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+        
+        for writer in outputs.stderr.iter().chain(outputs.file.iter()) {
+            writer.flush();
+        }
+    }
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/// Whether `LOGGER` is the one the `log` macros use.
+static INSTALLED: OnceLock<bool> = OnceLock::new();
+
+/// Sends the messages of the `log` macros that pass the filter to stderr and, if given, a file.
+///
+/// The logger is installed the first time this is called, later calls replace its filter and outputs. Returns false if
+/// the application installed a logger of its own, which then receives the messages instead.
+pub fn init(filter: LogFilter, stderr: bool, file: Option<LogWriter>) -> bool {
+    let max_level = filter.max_level();
+    let outputs = LoggerOutputs {
+        filter,
+        stderr: stderr.then(LogWriter::stderr),
+        file,
+    };
+    
+    let logger = LOGGER.get_or_init(|| Logger {
+        outputs: RwLock::new(LoggerOutputs {
+            filter: LogFilter::new(LevelFilter::Off),
+            stderr: None,
+            file: None,
+        }),
+    });
+    
+    // Leave the level alone if the application installed its own logger, it has settings of its own.
+    if !*INSTALLED.get_or_init(|| log::set_logger(logger).is_ok()) {
+        return false;
+    }
+    
+    *logger.outputs.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = outputs;
+    log::set_max_level(max_level);
+    
+    true
+}
+
+fn open_append<P: AsRef<Path>>(path: P) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Wraps a writer in a buffer of the given size, or leaves it as it is if the size is 0.
+fn buffer<W: Write + Send + 'static>(writer: W, buffer_bytes: usize) -> Box<dyn Write + Send> {
+    if buffer_bytes == 0 {
+        Box::new(writer)
+    } else {
+        Box::new(BufWriter::with_capacity(buffer_bytes, writer))
+    }
+}
+
+/// Returns the path an old log file is kept at, e.g. `access.log.2`.
+fn numbered_path(path: &Path, number: usize) -> PathBuf {
+    let mut numbered = path.as_os_str().to_owned();
+    numbered.push(format!(".{}", number));
+    
+    PathBuf::from(numbered)
+}
+
+/// Returns the number of days between the Unix epoch and the given time, in UTC.
+fn day_of(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|duration| duration.as_secs() / 86_400).unwrap_or(0)
+}
+
+fn current_day() -> u64 {
+    day_of(SystemTime::now())
+}
+
+/// Formats a time as an ISO 8601 UTC timestamp, e.g. `2023-06-01T12:34:56Z`.
+pub fn format_timestamp(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0);
+    let (year, month, day) = civil_from_days((seconds / 86_400) as i64);
+    let seconds_of_day = seconds % 86_400;
+    
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, seconds_of_day / 3_600, seconds_of_day % 3_600 / 60, seconds_of_day % 60,
+    )
+}
+
+/// Formats a time as a Common Log Format timestamp, e.g. `01/Jun/2023:12:34:56 +0000`.
+pub fn format_clf_timestamp(time: SystemTime) -> String {
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    
+    let seconds = time.duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0);
+    let (year, month, day) = civil_from_days((seconds / 86_400) as i64);
+    let seconds_of_day = seconds % 86_400;
+    
+    format!(
+        "{:02}/{}/{:04}:{:02}:{:02}:{:02} +0000",
+        day, MONTHS[month as usize - 1], year, seconds_of_day / 3_600, seconds_of_day % 3_600 / 60, seconds_of_day % 60,
+    )
+}
+
+/// Converts days since the Unix epoch to a (year, month, day) date in the proleptic Gregorian calendar.
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    
+    (year, month, day)
+}
+
+/// Converts a (year, month, day) date in the proleptic Gregorian calendar to days since the Unix epoch.
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_index = if month > 2 { month - 3 } else { month + 9 } as i64;
+    let day_of_year = (153 * month_index + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    
+    era * 146_097 + day_of_era - 719_468
+}
@@ -1,51 +1,234 @@
+use std::env;
 use std::fs;
-use std::path::Path;
+use std::io;
+use std::path::PathBuf;
+use std::process;
+#[cfg(unix)]
+use std::sync::Arc;
+#[cfg(unix)]
+use std::thread;
 
-use crate::server::Server;
+#[cfg(unix)]
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+#[cfg(unix)]
+use signal_hook::iterator::Signals;
+use web_server::config::ConfigFormat;
+use web_server::server::Server;
 
-mod server;
-mod http;
+use crate::cli::Command;
 
-const CONFIG_PATH: &str = "config.json";
+mod cli;
+
+/// The configuration file created when there's none in any of the supported formats.
+const DEFAULT_CONFIG_PATH: &str = "config.json";
 
 fn main() {
-    // Check if the config.json file exists in current directory.
-    if !Path::new(CONFIG_PATH).exists() {
+    let args = match cli::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(error) => {
+            eprintln!("{}, run with --help to see the available options.", error);
+            
+            process::exit(2);
+        }
+    };
+    
+    // Handle the commands that don't need a configuration file.
+    match args.command {
+        Command::Help => {
+            println!("{}", cli::USAGE);
+            
+            process::exit(0);
+        }
+        Command::Version => {
+            println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+            
+            process::exit(0);
+        }
+        Command::GenerateConfig => {
+            let mut config = default_config();
+            
+            for (key, value) in args.overrides.entries() {
+                config[key] = value.clone();
+            }
+            
+            println!("{}", config.pretty(2));
+            
+            process::exit(0);
+        }
+        Command::Run | Command::Check => {}
+    }
+    
+    let config_path = args.config_path.clone().unwrap_or_else(find_config_file);
+    
+    // Validate the configuration and exit without starting the server, like `nginx -t`.
+    if args.command == Command::Check {
+        match Server::check_config_file(&config_path, &args.overrides) {
+            Ok(()) => {
+                println!("The configuration in {} is valid.", config_path.display());
+                
+                process::exit(0);
+            }
+            Err(error) => {
+                eprintln!("{}", error);
+                
+                process::exit(1);
+            }
+        }
+    }
+    
+    // Create a configuration file if none exists in the current directory, a file given with --config must exist.
+    if args.config_path.is_none() && !config_path.exists() {
         println!("Configuration file not found, creating a new one...");
         
-        init_cfg();
+        if let Err(error) = init_cfg() {
+            eprintln!("Failed to create {}: {}", DEFAULT_CONFIG_PATH, error);
+            
+            process::exit(1);
+        }
     }
     
-    // Read the config.json file.
-    let config = fs::read_to_string(CONFIG_PATH).unwrap();
+    // Create a new server instance from the configuration file, with the command-line flags taking precedence.
+    let server = match Server::from_config_file_with_overrides(&config_path, args.overrides) {
+        Ok(server) => server,
+        Err(error) => {
+            eprintln!("{}", error);
+            
+            process::exit(1);
+        }
+    };
+    
+    // Log panics to the error log, the server itself leaves the process-wide hook alone.
+    server.install_panic_hook();
+    
+    // Write the resolved configuration for auditing, if asked to.
+    if let Some(path) = server.get_dump_resolved_config_to() {
+        if let Err(error) = server.write_resolved_config(path) {
+            eprintln!("{}", error);
+            
+            process::exit(1);
+        }
+    }
     
-    // Parse the config.json file.
-    let config = json::parse(&config).unwrap();
+    // Bind the socket, so the actual port is known even if the OS picked it.
+    let server = match server.bind() {
+        Ok(server) => server,
+        Err(error) => {
+            eprintln!("{}", error);
+            
+            process::exit(1);
+        }
+    };
     
-    // Create a new server instance.
-    let server = Server::new(&config);
+    let site = server.get_server().get_site();
     
     // Print the server configuration.
     println!("================ CONFIG ================");
-    println!("Verbose Output:\t{}", server.is_verbose());
-    println!("Thread Count:\t{}", server.get_thread_count());
-    println!("Port:\t\t\t{}", server.get_port());
-    println!("Web Root:\t\t{}", server.get_web_root());
-    println!("Page Count:\t\t{}", server.get_pages().len());
+    println!("Verbose Output:\t{}", server.get_server().is_verbose());
+    println!("Thread Count:\t{}", server.get_server().get_thread_count());
+    println!("Listeners:\t\t{}", server.bound_addrs().len());
+    
+    for (address, listener) in server.bound_addrs() {
+        println!("\t{} ({}{})", address, listener.get_name(), if listener.is_tls() { ", TLS" } else { "" });
+    }
+    
+    println!("Web Root:\t\t{}", site.get_web_root());
+    println!("Page Count:\t\t{}", site.get_pages().len());
+    
+    for page in site.get_pages() {
+        println!("\t{}", page);
+    }
+    
+    let vhost_names = server.get_server().get_vhost_names();
+    
+    if !vhost_names.is_empty() {
+        println!("Virtual Hosts:\t{}", vhost_names.len());
+        
+        for hostname in vhost_names {
+            if let Some(vhost) = server.get_server().get_vhost(&hostname) {
+                println!("\t{} ({}, {} pages)", hostname, vhost.get_web_root(), vhost.get_pages().len());
+            }
+        }
+    }
+    
     println!("========================================");
     println!();
     
-    // Start listening for incoming connections on the specified port.
-    server.listen();
+    // Without TLS nothing hides which files are requested, so serving everything in the web root deserves a warning.
+    if !server.get_server().is_deny_unlisted() && !server.get_server().is_tls_enabled() {
+        log::warn!("deny_unlisted is disabled and TLS is not enabled, every file in the web root is served, not just the configured pages and routes.");
+    }
+    
+    // Start accepting incoming connections.
+    let handle = server.start();
+    
+    // Shut down gracefully on SIGINT and SIGTERM, and reload the configuration on SIGHUP.
+    #[cfg(unix)]
+    if let Err(error) = handle_signals(Arc::clone(handle.get_server())) {
+        log::warn!("Failed to install the signal handlers, the server can't shut down gracefully: {}", error);
+    }
+    
+    // Wait until the server stops, then write out whatever the logs still buffer.
+    let result = handle.join();
+    log::logger().flush();
+    
+    if result.is_err() {
+        process::exit(1);
+    }
+}
+
+/// Shuts the server down on the first SIGINT or SIGTERM, a second one exits straight away without waiting for the
+/// in-flight connections. SIGHUP reloads the configuration file.
+#[cfg(unix)]
+fn handle_signals(server: Arc<Server>) -> io::Result<()> {
+    let mut signals = Signals::new([SIGINT, SIGTERM, SIGHUP])?;
+    
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            if signal == SIGHUP {
+                if let Err(error) = server.reload() {
+                    log::error!("Failed to reload the configuration, keeping the current one: {}", error);
+                }
+                
+                continue;
+            }
+            
+            if server.is_shutting_down() {
+                log::warn!("Received signal {} again, exiting without waiting for the open connections.", signal);
+                log::logger().flush();
+                
+                process::exit(1);
+            }
+            
+            log::info!(
+                "Received signal {}, shutting down once the open connections finish (at most {} seconds)...",
+                signal, server.get_shutdown_grace_period().as_secs(),
+            );
+            
+            server.shutdown();
+        }
+    });
+    
+    Ok(())
 }
 
-fn init_cfg() {
-    // Create the config.json file.
-    let default_config = json::parse(r#"
+/// Returns the first configuration file that exists in the current directory, or the one to create if there's none.
+fn find_config_file() -> PathBuf {
+    ConfigFormat::DEFAULT_FILE_NAMES.iter()
+        .map(PathBuf::from)
+        .find(|path| path.exists())
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_PATH))
+}
+
+/// Returns the configuration written when there's none, which is also what `generate-config` prints.
+fn default_config() -> json::JsonValue {
+    json::parse(r#"
     {
       "thread_count": 1,
       "verbose": true,
       "port": 8080,
+      "bind_address": "0.0.0.0",
+      "force_dual_stack": false,
+      "tcp_backlog": 1024,
       "web_root": "web",
       "pages": [
         {
@@ -54,8 +237,10 @@ fn init_cfg() {
         }
       ]
     }
-    "#).unwrap();
-    
+    "#).unwrap()
+}
+
+fn init_cfg() -> io::Result<()> {
     // Write the config.json file.
-    fs::write(CONFIG_PATH, default_config.dump()).unwrap();
+    fs::write(DEFAULT_CONFIG_PATH, default_config().dump())
 }
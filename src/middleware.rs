@@ -0,0 +1,51 @@
+use crate::context::ConnectionContext;
+use crate::http::{Request, Response};
+
+/// Code that wraps request handling, able to act before and after the rest of the chain runs.
+///
+/// A middleware either produces a response itself or calls `next` to hand the request down the chain.
+pub trait Middleware {
+    fn handle(&self, context: &ConnectionContext, request: &Request, next: &dyn Fn(&Request) -> Response) -> Response;
+}
+
+/// Checks a path against a pattern, where a trailing `*` matches any suffix, e.g. `/api/*`.
+pub fn path_matches(pattern: &str, path: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => path.starts_with(prefix),
+        None => pattern == path,
+    }
+}
+
+/// Delays matching requests by a random duration to simulate a slow server.
+#[cfg(feature = "dev")]
+pub struct DelayMiddleware {
+    min_ms: u64,
+    max_ms: u64,
+    paths: Vec<String>,
+}
+
+#[cfg(feature = "dev")]
+impl DelayMiddleware {
+    pub fn new(min_ms: u64, max_ms: u64, paths: Vec<String>) -> DelayMiddleware {
+        DelayMiddleware {
+            min_ms,
+            max_ms,
+            paths,
+        }
+    }
+}
+
+#[cfg(feature = "dev")]
+impl Middleware for DelayMiddleware {
+    fn handle(&self, _context: &ConnectionContext, request: &Request, next: &dyn Fn(&Request) -> Response) -> Response {
+        use rand::Rng;
+        
+        if self.paths.iter().any(|pattern| path_matches(pattern, request.path())) {
+            let delay = rand::thread_rng().gen_range(self.min_ms..=self.max_ms);
+            
+            std::thread::sleep(std::time::Duration::from_millis(delay));
+        }
+        
+        next(request)
+    }
+}
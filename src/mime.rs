@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+/// The content type of files whose extension isn't known, which makes browsers download rather than guess.
+pub const DEFAULT_MIME_TYPE: &str = "application/octet-stream";
+
+/// Returns the built-in content type for a file extension, without the leading dot.
+pub fn from_extension(extension: &str) -> Option<&'static str> {
+    let mime_type = match extension.to_ascii_lowercase().as_str() {
+        // Text.
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "txt" => "text/plain; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "md" => "text/markdown; charset=utf-8",
+        "xml" => "application/xml",
+        "json" | "map" => "application/json",
+        "webmanifest" => "application/manifest+json",
+        // Images.
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "svg" => "image/svg+xml",
+        "ico" => "image/x-icon",
+        "bmp" => "image/bmp",
+        // Fonts.
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        // Audio and video.
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "wav" => "audio/wav",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        // Everything else.
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "wasm" => "application/wasm",
+        _ => return None,
+    };
+    
+    Some(mime_type)
+}
+
+/// Resolves content types from file extensions, with overrides from the configuration taking precedence.
+#[derive(Default)]
+pub struct MimeTypes {
+    overrides: HashMap<String, String>,
+}
+
+impl MimeTypes {
+    pub fn new() -> MimeTypes {
+        MimeTypes::default()
+    }
+    
+    /// Maps an extension to a content type, the extension is matched case-insensitively with or without a dot.
+    pub fn add_override(&mut self, extension: &str, mime_type: &str) {
+        self.overrides.insert(normalize(extension), mime_type.to_string());
+    }
+    
+    pub fn get_overrides(&self) -> &HashMap<String, String> {
+        &self.overrides
+    }
+    
+    /// Returns the content type for a path, falling back to `DEFAULT_MIME_TYPE` if the extension is unknown.
+    pub fn resolve(&self, path: &str) -> &str {
+        let extension = match Path::new(path).extension().and_then(|extension| extension.to_str()) {
+            Some(extension) => normalize(extension),
+            None => return DEFAULT_MIME_TYPE,
+        };
+        
+        match self.overrides.get(&extension) {
+            Some(mime_type) => mime_type,
+            None => from_extension(&extension).unwrap_or(DEFAULT_MIME_TYPE),
+        }
+    }
+}
+
+fn normalize(extension: &str) -> String {
+    extension.trim_start_matches('.').to_ascii_lowercase()
+}
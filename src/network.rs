@@ -0,0 +1,84 @@
+use std::net::IpAddr;
+
+use ipnet::{AddrParseError, IpNet};
+
+/// Checks whether an address falls within any of the ranges.
+///
+/// IPv4 clients on a dual-stack socket show up as IPv4-mapped IPv6 addresses, so those are matched as IPv4.
+pub fn ip_in_range(ip: IpAddr, ranges: &[IpNet]) -> bool {
+    let ip = ip.to_canonical();
+    
+    ranges.iter().any(|range| range.contains(&ip))
+}
+
+/// Parses a list of ranges in CIDR notation, where a plain address is a range of one, e.g. `10.0.0.0/8` or `::1`.
+pub fn parse_cidr_list(strs: &[&str]) -> Result<Vec<IpNet>, AddrParseError> {
+    strs.iter()
+        .map(|range| match range.trim().parse::<IpAddr>() {
+            Ok(ip) => Ok(IpNet::from(ip)),
+            Err(_) => range.trim().parse::<IpNet>(),
+        })
+        .collect()
+}
+
+/// Which clients may make requests, by address range.
+///
+/// Denied ranges win over allowed ones, and an empty allow list lets in every client that isn't denied.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AccessList {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+}
+
+impl AccessList {
+    pub fn new(allow: Vec<IpNet>, deny: Vec<IpNet>) -> AccessList {
+        AccessList {
+            allow,
+            deny,
+        }
+    }
+    
+    pub fn get_allow(&self) -> &Vec<IpNet> {
+        &self.allow
+    }
+    
+    pub fn get_deny(&self) -> &Vec<IpNet> {
+        &self.deny
+    }
+    
+    /// Checks whether the list restricts anything, an empty one allows every client.
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+    
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        !ip_in_range(ip, &self.deny) && (self.allow.is_empty() || ip_in_range(ip, &self.allow))
+    }
+}
+
+/// Returns the address of the client a request comes from, which is the peer unless the peer is a trusted proxy.
+///
+/// Requests from trusted proxies are traced back through `X-Forwarded-For` from the right, since each proxy appends the
+/// address it got the request from. The first address that isn't a trusted proxy is the client, anything to the left
+/// of it could have been made up by the client.
+pub fn client_address(peer: IpAddr, forwarded_for: &[&str], trusted_proxies: &[IpNet]) -> IpAddr {
+    if !ip_in_range(peer, trusted_proxies) {
+        return peer;
+    }
+    
+    let mut client = peer;
+    
+    for address in forwarded_for.iter().rev().flat_map(|header| header.rsplit(',')) {
+        // An entry that isn't an address can't be traced any further, so the last proxy before it is the client.
+        match address.trim().parse::<IpAddr>() {
+            Ok(address) => client = address,
+            Err(_) => break,
+        }
+        
+        if !ip_in_range(client, trusted_proxies) {
+            break;
+        }
+    }
+    
+    client
+}
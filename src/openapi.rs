@@ -0,0 +1,204 @@
+use json::JsonValue;
+
+use crate::http::Method;
+
+/// The path Swagger UI is served under.
+pub const SWAGGER_UI_PATH: &str = "/swagger-ui/";
+
+/// The files of Swagger UI 5.17.14, from its `dist` directory. The page and the script pointing it at the document are
+/// generated, since they depend on the configuration.
+const SWAGGER_UI_ASSETS: [(&str, &[u8], &str); 6] = [
+    ("swagger-ui.css", include_bytes!("../assets/swagger-ui/swagger-ui.css"), "text/css; charset=utf-8"),
+    ("swagger-ui-bundle.js", include_bytes!("../assets/swagger-ui/swagger-ui-bundle.js"), "text/javascript; charset=utf-8"),
+    ("swagger-ui-standalone-preset.js", include_bytes!("../assets/swagger-ui/swagger-ui-standalone-preset.js"), "text/javascript; charset=utf-8"),
+    ("index.css", include_bytes!("../assets/swagger-ui/index.css"), "text/css; charset=utf-8"),
+    ("favicon-32x32.png", include_bytes!("../assets/swagger-ui/favicon-32x32.png"), "image/png"),
+    ("favicon-16x16.png", include_bytes!("../assets/swagger-ui/favicon-16x16.png"), "image/png"),
+];
+
+/// The page Swagger UI runs in, which loads everything else relative to `SWAGGER_UI_PATH`.
+const SWAGGER_UI_PAGE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>Swagger UI</title>
+<link rel="stylesheet" href="swagger-ui.css">
+<link rel="stylesheet" href="index.css">
+<link rel="icon" type="image/png" href="favicon-32x32.png" sizes="32x32">
+<link rel="icon" type="image/png" href="favicon-16x16.png" sizes="16x16">
+</head>
+<body>
+<div id="swagger-ui"></div>
+<script src="swagger-ui-bundle.js"></script>
+<script src="swagger-ui-standalone-preset.js"></script>
+<script src="swagger-initializer.js"></script>
+</body>
+</html>
+"#;
+
+/// Where the OpenAPI document is served and what it calls the API.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OpenApiConfig {
+    title: String,
+    version: String,
+    json_path: String,
+}
+
+impl OpenApiConfig {
+    pub fn new(title: &str, version: &str, json_path: &str) -> OpenApiConfig {
+        OpenApiConfig {
+            title: title.to_string(),
+            version: version.to_string(),
+            json_path: json_path.to_string(),
+        }
+    }
+    
+    pub fn get_title(&self) -> &str {
+        &self.title
+    }
+    
+    pub fn get_version(&self) -> &str {
+        &self.version
+    }
+    
+    /// Returns the path the document is served at, e.g. `/openapi.json`.
+    pub fn get_json_path(&self) -> &str {
+        &self.json_path
+    }
+}
+
+/// Describes a route in the OpenAPI document, with JSON schemas for the bodies it takes and returns.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RouteMetadata {
+    description: String,
+    request_schema: Option<JsonValue>,
+    response_schema: Option<JsonValue>,
+}
+
+impl RouteMetadata {
+    pub fn new(description: &str) -> RouteMetadata {
+        RouteMetadata {
+            description: description.to_string(),
+            request_schema: None,
+            response_schema: None,
+        }
+    }
+    
+    /// Sets the schema of the JSON body the route takes, e.g. `json::object! { "type": "object" }`.
+    pub fn with_request_schema(mut self, schema: JsonValue) -> RouteMetadata {
+        self.request_schema = Some(schema);
+        
+        self
+    }
+    
+    /// Sets the schema of the JSON body the route answers with.
+    pub fn with_response_schema(mut self, schema: JsonValue) -> RouteMetadata {
+        self.response_schema = Some(schema);
+        
+        self
+    }
+    
+    pub fn get_description(&self) -> &str {
+        &self.description
+    }
+    
+    pub fn get_request_schema(&self) -> Option<&JsonValue> {
+        self.request_schema.as_ref()
+    }
+    
+    pub fn get_response_schema(&self) -> Option<&JsonValue> {
+        self.response_schema.as_ref()
+    }
+}
+
+/// A route as it's listed in the document, with the methods it answers.
+pub struct ApiRoute<'a> {
+    pub pattern: &'a str,
+    pub methods: Vec<Method>,
+    pub metadata: Option<&'a RouteMetadata>,
+}
+
+/// Generates an OpenAPI 3.0 document listing the routes, where HEAD and OPTIONS are left out since every route answers
+/// them.
+pub fn document(config: &OpenApiConfig, routes: &[ApiRoute]) -> JsonValue {
+    let mut paths = JsonValue::new_object();
+    
+    for route in routes {
+        let (path, parameters) = openapi_path(route.pattern);
+        let mut item = JsonValue::new_object();
+        
+        for method in route.methods.iter().filter(|method| !matches!(method, Method::Head | Method::Options | Method::Connect)) {
+            let mut operation = json::object! {
+                "parameters": parameters.clone(),
+                "responses": { "200": { "description": "OK" } },
+            };
+            
+            if let Some(metadata) = route.metadata {
+                operation["description"] = metadata.get_description().into();
+                
+                // Only methods that are sent with a body take one.
+                let takes_body = matches!(method, Method::Post | Method::Put | Method::Patch);
+                
+                if let (Some(schema), true) = (metadata.get_request_schema(), takes_body) {
+                    operation["requestBody"] = json::object! { "content": { "application/json": { "schema": schema.clone() } } };
+                }
+                
+                if let Some(schema) = metadata.get_response_schema() {
+                    operation["responses"]["200"]["content"] = json::object! { "application/json": { "schema": schema.clone() } };
+                }
+            }
+            
+            item[method.to_string().to_ascii_lowercase()] = operation;
+        }
+        
+        paths[path] = item;
+    }
+    
+    json::object! {
+        "openapi": "3.0.3",
+        "info": { "title": config.get_title(), "version": config.get_version() },
+        "paths": paths,
+    }
+}
+
+/// Returns a Swagger UI file by its name under `SWAGGER_UI_PATH`, with its content type.
+pub fn swagger_ui_asset(config: &OpenApiConfig, name: &str) -> Option<(Vec<u8>, &'static str)> {
+    match name {
+        "" | "index.html" => Some((SWAGGER_UI_PAGE.as_bytes().to_vec(), "text/html; charset=utf-8")),
+        "swagger-initializer.js" => Some((initializer(config).into_bytes(), "text/javascript; charset=utf-8")),
+        _ => SWAGGER_UI_ASSETS.iter()
+            .find(|(asset, _, _)| *asset == name)
+            .map(|(_, contents, content_type)| (contents.to_vec(), *content_type)),
+    }
+}
+
+/// Returns the script that starts Swagger UI on the configured document.
+fn initializer(config: &OpenApiConfig) -> String {
+    format!(
+        "window.onload = function() {{\n  window.ui = SwaggerUIBundle({{\n    url: {},\n    dom_id: '#swagger-ui',\n    deepLinking: true,\n    presets: [SwaggerUIBundle.presets.apis, SwaggerUIStandalonePreset],\n    layout: 'StandaloneLayout',\n  }});\n}};\n",
+        json::stringify(config.get_json_path()),
+    )
+}
+
+/// Turns a route pattern into an OpenAPI path and its parameters, e.g. `/users/:id` into `/users/{id}`. A wildcard
+/// becomes a `path` parameter, since OpenAPI has no way to describe one.
+fn openapi_path(pattern: &str) -> (String, JsonValue) {
+    let mut parameters = Vec::new();
+    
+    let path = pattern.split('/')
+        .map(|segment| {
+            let name = match (segment, segment.strip_prefix(':')) {
+                ("*", _) => "path",
+                (_, Some(name)) => name,
+                _ => return segment.to_string(),
+            };
+            
+            parameters.push(json::object! { "name": name, "in": "path", "required": true, "schema": { "type": "string" } });
+            
+            format!("{{{}}}", name)
+        })
+        .collect::<Vec<_>>()
+        .join("/");
+    
+    (path, parameters.into())
+}
@@ -0,0 +1,1067 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+
+use crate::context::ConnectionContext;
+use crate::http::{HttpVersion, Method, Request, Response};
+use crate::tls::ClientStream;
+use crate::tunnel;
+
+/// The path the health and circuit state of the upstreams is reported under, if signing in is required for it.
+pub const STATUS_PATH: &str = "/status";
+
+/// How long an upstream gets to accept the connection and to send each part of its response.
+pub const DEFAULT_UPSTREAM_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often upstreams are checked by default, if a proxy has health checks.
+pub const DEFAULT_HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long a health check may take by default, before the upstream counts as unhealthy.
+pub const DEFAULT_HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How many requests in a row may fail before an upstream's circuit opens.
+pub const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+
+/// How long an open circuit keeps requests away from an upstream, before a single request may probe it again.
+pub const DEFAULT_OPEN_DURATION: Duration = Duration::from_secs(30);
+
+/// The largest response head an upstream may send, so a broken one can't make the server buffer without end.
+const MAX_RESPONSE_HEAD_BYTES: usize = 65_536;
+
+/// Headers that only apply to a single connection (RFC 9110 §7.6.1), or to this server like `Proxy-Authorization`,
+/// which are never forwarded in either direction.
+const HOP_BY_HOP_HEADERS: [&str; 8] = [
+    "Connection", "Keep-Alive", "Proxy-Connection", "Proxy-Authorization", "TE", "Trailer", "Transfer-Encoding", "Upgrade",
+];
+
+#[derive(Debug)]
+pub enum ProxyError {
+    /// The upstream couldn't be resolved or refused the connection.
+    Connect(io::Error),
+    /// The upstream didn't accept the connection or answer in time.
+    Timeout,
+    /// The connection failed while the request was sent or the response was read.
+    Io(io::Error),
+    /// The upstream answered with something that isn't an HTTP/1.x response.
+    InvalidResponse(String),
+    /// The client's body couldn't be read while it was passed on, which is the client's fault rather than the upstream's.
+    RequestBody(io::Error),
+}
+
+impl fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProxyError::Connect(error) => write!(f, "Failed to connect to the upstream: {}", error),
+            ProxyError::Timeout => write!(f, "The upstream didn't answer in time"),
+            ProxyError::Io(error) => write!(f, "The connection to the upstream failed: {}", error),
+            ProxyError::InvalidResponse(message) => write!(f, "The upstream sent an invalid response: {}", message),
+            ProxyError::RequestBody(error) => write!(f, "Failed to read the request body: {}", error),
+        }
+    }
+}
+
+impl Error for ProxyError {}
+
+impl From<io::Error> for ProxyError {
+    fn from(error: io::Error) -> ProxyError {
+        match error.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => ProxyError::Timeout,
+            _ => ProxyError::Io(error),
+        }
+    }
+}
+
+/// How a proxy picks the upstream for each request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BalanceStrategy {
+    /// Takes turns, so every upstream gets the same share of requests.
+    RoundRobin,
+    /// Picks the upstream with the fewest requests in flight, which suits requests that take very different times.
+    LeastConnections,
+}
+
+impl BalanceStrategy {
+    pub fn parse(strategy: &str) -> Option<BalanceStrategy> {
+        match strategy {
+            "round_robin" => Some(BalanceStrategy::RoundRobin),
+            "least_connections" => Some(BalanceStrategy::LeastConnections),
+            _ => None,
+        }
+    }
+    
+    pub fn name(&self) -> &'static str {
+        match self {
+            BalanceStrategy::RoundRobin => "round_robin",
+            BalanceStrategy::LeastConnections => "least_connections",
+        }
+    }
+}
+
+/// Whether an upstream passed its last health check.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UpstreamStatus {
+    Healthy,
+    Unhealthy { since: Instant },
+}
+
+/// Periodically requests a path from every upstream of a proxy, taking the ones that don't answer with the expected
+/// status out of rotation until they do again.
+#[derive(Clone, Debug)]
+pub struct HealthCheck {
+    path: String,
+    interval: Duration,
+    timeout: Duration,
+    expected_status: u16,
+}
+
+impl HealthCheck {
+    pub fn new(path: &str, interval: Duration, timeout: Duration, expected_status: u16) -> HealthCheck {
+        HealthCheck {
+            path: path.to_string(),
+            interval,
+            timeout,
+            expected_status,
+        }
+    }
+    
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+    
+    pub fn get_interval(&self) -> Duration {
+        self.interval
+    }
+    
+    pub fn get_timeout(&self) -> Duration {
+        self.timeout
+    }
+    
+    pub fn get_expected_status(&self) -> u16 {
+        self.expected_status
+    }
+}
+
+/// The state of a circuit breaker, as reported to whoever monitors the upstreams.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Requests go through.
+    Closed,
+    /// Too many requests failed, so requests are refused without trying the upstream.
+    Open,
+    /// The circuit has been open long enough, the next request probes whether the upstream is back.
+    HalfOpen,
+}
+
+impl CircuitState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CircuitState::Closed => "closed",
+            CircuitState::Open => "open",
+            CircuitState::HalfOpen => "half_open",
+        }
+    }
+}
+
+/// Stops sending requests to an upstream that keeps failing, so they don't pile up waiting for it.
+///
+/// The circuit opens after `failure_threshold` failed requests in a row, counting timeouts and 5xx responses. Once it's been open for
+/// `open_duration`, a single request is let through: if it succeeds the circuit closes, otherwise it stays open for
+/// another `open_duration`.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    open_duration: Duration,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Debug, Default)]
+struct BreakerState {
+    failures: u32,
+    opened_at: Option<Instant>,
+    probing: bool,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, open_duration: Duration) -> CircuitBreaker {
+        CircuitBreaker {
+            failure_threshold,
+            open_duration,
+            state: Mutex::new(BreakerState::default()),
+        }
+    }
+    
+    pub fn get_failure_threshold(&self) -> u32 {
+        self.failure_threshold
+    }
+    
+    pub fn get_open_duration(&self) -> Duration {
+        self.open_duration
+    }
+    
+    pub fn get_state(&self) -> CircuitState {
+        match self.lock().opened_at {
+            None => CircuitState::Closed,
+            Some(opened_at) if opened_at.elapsed() < self.open_duration => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+    
+    /// Returns whether a request may be sent, claiming the probe if the circuit is half-open.
+    fn try_acquire(&self) -> bool {
+        let mut state = self.lock();
+        
+        match state.opened_at {
+            None => true,
+            Some(opened_at) if opened_at.elapsed() < self.open_duration || state.probing => false,
+            Some(_) => {
+                state.probing = true;
+                
+                true
+            }
+        }
+    }
+    
+    fn record_success(&self) {
+        *self.lock() = BreakerState::default();
+    }
+    
+    fn record_failure(&self) {
+        let mut state = self.lock();
+        
+        // A failed probe opens the circuit again straight away.
+        if state.opened_at.is_some() {
+            state.opened_at = Some(Instant::now());
+            state.probing = false;
+            
+            return;
+        }
+        
+        state.failures += 1;
+        
+        if state.failures >= self.failure_threshold {
+            state.failures = 0;
+            state.opened_at = Some(Instant::now());
+        }
+    }
+    
+    /// Returns how long the circuit stays open, if it is.
+    fn get_retry_after(&self) -> Option<Duration> {
+        self.lock().opened_at.map(|opened_at| self.open_duration.saturating_sub(opened_at.elapsed()))
+    }
+    
+    fn lock(&self) -> std::sync::MutexGuard<'_, BreakerState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// One of the upstream HTTP servers of a proxy, e.g. an application server on `http://127.0.0.1:3000`.
+///
+/// Every request gets a connection of its own, which is closed once the response has been relayed.
+#[derive(Debug)]
+pub struct Upstream {
+    url: String,
+    host: String,
+    port: u16,
+    path: String,
+    status: Mutex<UpstreamStatus>,
+    active_requests: AtomicUsize,
+    circuit_breaker: CircuitBreaker,
+}
+
+impl Upstream {
+    /// Parses an upstream URL like `http://127.0.0.1:3000` or `http://app.internal/api`, returning why it's invalid
+    /// otherwise. The request path is appended to the path of the URL.
+    pub fn new(url: &str, circuit_breaker: CircuitBreaker) -> Result<Upstream, String> {
+        let rest = match url.strip_prefix("http://") {
+            Some(rest) => rest,
+            None if url.starts_with("https://") => return Err("must be an http:// URL, TLS to upstreams isn't supported".to_string()),
+            None => return Err("must be an http:// URL".to_string()),
+        };
+        
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, ""),
+        };
+        
+        // IPv6 addresses are bracketed, so their colons aren't mistaken for the port separator.
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => match port.parse::<u16>() {
+                Ok(port) if port > 0 => (host, port),
+                _ => return Err("must have a port between 1 and 65535".to_string()),
+            },
+            _ => (authority, 80),
+        };
+        
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        
+        if host.is_empty() {
+            return Err("must have a host".to_string());
+        }
+        
+        Ok(Upstream {
+            url: url.to_string(),
+            host: host.to_string(),
+            port,
+            path: path.trim_end_matches('/').to_string(),
+            status: Mutex::new(UpstreamStatus::Healthy),
+            active_requests: AtomicUsize::new(0),
+            circuit_breaker,
+        })
+    }
+    
+    /// Returns the URL the upstream was configured with.
+    pub fn get_url(&self) -> &str {
+        &self.url
+    }
+    
+    /// Returns whether the upstream passed its last health check, upstreams without health checks are always healthy.
+    pub fn get_status(&self) -> UpstreamStatus {
+        *self.status.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+    
+    /// Returns the number of requests currently forwarded to the upstream, including responses still being relayed.
+    pub fn get_active_requests(&self) -> usize {
+        self.active_requests.load(Ordering::Relaxed)
+    }
+    
+    pub fn get_circuit_breaker(&self) -> &CircuitBreaker {
+        &self.circuit_breaker
+    }
+    
+    fn is_healthy(&self) -> bool {
+        self.get_status() == UpstreamStatus::Healthy
+    }
+    
+    /// Sends a request and reads the head of the response, the body is relayed by the returned response.
+    fn send(self: &Arc<Self>, request: &Request, context: &ConnectionContext, timeout: Duration) -> Result<Response, ProxyError> {
+        let mut upstream = self.open(context, timeout)?;
+        
+        upstream.write_all(&self.request_head(request, context.get_client_ip(), context.is_tls(), None))?;
+        upstream.write_all(request.get_body())?;
+        upstream.flush()?;
+        
+        // Count the request as active until its body has been relayed, which is after this returns.
+        let mut reader = ActiveRequest::new(Arc::clone(self), BufReader::new(upstream));
+        
+        // Skip informational responses, the client only gets the final one.
+        let head = loop {
+            let head = read_response_head(&mut reader)?;
+            
+            if head.status_code >= 200 {
+                break head;
+            }
+        };
+        
+        into_response(request, head, reader)
+    }
+    
+    /// Sends a request asking to switch protocols, e.g. to WebSocket, and returns the upstream's answer. If it agreed,
+    /// the connection is returned as well, to be relayed once the answer has been sent to the client.
+    fn send_upgrade(self: &Arc<Self>, request: &Request, context: &ConnectionContext, timeout: Duration) -> Result<(Response, Option<UpgradedConnection>), ProxyError> {
+        let protocol = request.get_header("Upgrade").unwrap_or_default();
+        
+        let mut upstream = self.open(context, timeout)?;
+        
+        upstream.write_all(&self.request_head(request, context.get_client_ip(), context.is_tls(), Some(protocol)))?;
+        upstream.flush()?;
+        
+        let mut reader = ActiveRequest::new(Arc::clone(self), BufReader::new(upstream));
+        
+        // Skip informational responses other than the one switching protocols.
+        let head = loop {
+            let head = read_response_head(&mut reader)?;
+            
+            if head.status_code >= 200 || head.status_code == 101 {
+                break head;
+            }
+        };
+        
+        if head.status_code != 101 {
+            return Ok((into_response(request, head, reader)?, None));
+        }
+        
+        let mut response = Response::new(HttpVersion::Http11, 101, &head.status_message);
+        
+        // The handshake headers, like Sec-WebSocket-Accept, are meant for the client.
+        for (name, value) in &head.headers {
+            if !is_hop_by_hop(name, &[]) {
+                response.add_header(name, value);
+            }
+        }
+        
+        let upgrade = head.headers.iter().find(|(name, _)| name.eq_ignore_ascii_case("Upgrade")).map_or(protocol, |(_, value)| value.as_str());
+        
+        response.add_header("Connection", "Upgrade");
+        response.add_header("Upgrade", upgrade);
+        
+        Ok((response, Some(UpgradedConnection { reader })))
+    }
+    
+    /// Requests the health check path and updates the status, logging when the upstream is taken out of rotation or
+    /// put back.
+    fn check_health(&self, health_check: &HealthCheck) {
+        let result = self.connect(health_check.timeout).and_then(|mut upstream| {
+            upstream.set_read_timeout(Some(health_check.timeout))?;
+            upstream.set_write_timeout(Some(health_check.timeout))?;
+            
+            let head = format!("GET {}{} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", self.path, health_check.path, self.authority());
+            upstream.write_all(head.as_bytes())?;
+            
+            Ok(read_response_head(&mut BufReader::new(upstream))?.status_code)
+        });
+        
+        let mut status = self.status.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        
+        match (result, *status) {
+            (Ok(status_code), UpstreamStatus::Healthy) if status_code != health_check.expected_status => {
+                warn!("Upstream {} failed its health check with status {}, taking it out of rotation.", self.url, status_code);
+                
+                *status = UpstreamStatus::Unhealthy { since: Instant::now() };
+            }
+            (Err(error), UpstreamStatus::Healthy) => {
+                warn!("Upstream {} failed its health check, taking it out of rotation: {}", self.url, error);
+                
+                *status = UpstreamStatus::Unhealthy { since: Instant::now() };
+            }
+            (Ok(status_code), UpstreamStatus::Unhealthy { since }) if status_code == health_check.expected_status => {
+                info!("Upstream {} passed its health check after {}s, putting it back in rotation.", self.url, since.elapsed().as_secs());
+                
+                *status = UpstreamStatus::Healthy;
+            }
+            _ => {}
+        }
+    }
+    
+    /// Connects for a request, recording the address the upstream resolved to and the timeout that applies to it.
+    fn open(&self, context: &ConnectionContext, timeout: Duration) -> Result<TcpStream, ProxyError> {
+        context.set_upstream_timeout(timeout);
+        
+        let upstream = self.connect(timeout)?;
+        upstream.set_read_timeout(Some(timeout))?;
+        upstream.set_write_timeout(Some(timeout))?;
+        
+        if let Ok(address) = upstream.peer_addr() {
+            context.set_upstream_address(address);
+        }
+        
+        Ok(upstream)
+    }
+    
+    fn connect(&self, timeout: Duration) -> Result<TcpStream, ProxyError> {
+        let addresses = (self.host.as_str(), self.port).to_socket_addrs().map_err(ProxyError::Connect)?;
+        let mut last_error = io::Error::new(io::ErrorKind::NotFound, format!("{} didn't resolve to any address", self.host));
+        
+        // Try every address the host resolves to, the way a browser would.
+        for address in addresses {
+            match TcpStream::connect_timeout(&address, timeout) {
+                Ok(stream) => return Ok(stream),
+                Err(error) if error.kind() == io::ErrorKind::TimedOut => return Err(ProxyError::Timeout),
+                Err(error) => last_error = error,
+            }
+        }
+        
+        Err(ProxyError::Connect(last_error))
+    }
+    
+    /// Writes the request line and headers forwarded to the upstream, asking it to switch to `upgrade` if it's given.
+    fn request_head(&self, request: &Request, client_ip: IpAddr, is_tls: bool, upgrade: Option<&str>) -> Vec<u8> {
+        let mut head = format!("{} {}{} HTTP/1.1\r\n", request.get_method(), self.path, request.get_path());
+        
+        let connection_headers = request.get_headers().get_all("Connection")
+            .flat_map(|value| value.split(',').map(|name| name.trim().to_string()))
+            .collect::<Vec<_>>();
+        
+        // The body has already been read, so it's sent with a length rather than the way the client sent it.
+        for (name, value) in request.get_headers().iter() {
+            let skipped = ["Content-Length", "Expect", "X-Forwarded-For"].iter().any(|skipped| name.eq_ignore_ascii_case(skipped));
+            
+            if !skipped && !is_hop_by_hop(name, &connection_headers) {
+                head += &format!("{}: {}\r\n", name, value);
+            }
+        }
+        
+        if request.get_header("Host").is_none() {
+            head += &format!("Host: {}\r\n", self.authority());
+        }
+        
+        let forwarded_for = match request.get_header("X-Forwarded-For") {
+            Some(forwarded_for) => format!("{}, {}", forwarded_for, client_ip),
+            None => client_ip.to_string(),
+        };
+        
+        head += &format!("X-Forwarded-For: {}\r\n", forwarded_for);
+        
+        if request.get_header("X-Forwarded-Proto").is_none() {
+            head += &format!("X-Forwarded-Proto: {}\r\n", if is_tls { "https" } else { "http" });
+        }
+        
+        if !request.get_body().is_empty() || request.get_header("Content-Length").is_some() || request.get_header("Transfer-Encoding").is_some() {
+            head += &format!("Content-Length: {}\r\n", request.get_body().len());
+        }
+        
+        match upgrade {
+            // The connection is handed over to the new protocol, so it has to stay open.
+            Some(protocol) => head += &format!("Upgrade: {}\r\nConnection: Upgrade\r\n\r\n", protocol),
+            // Connections aren't reused, so the upstream can close it once it's answered.
+            None => head += "Connection: close\r\n\r\n",
+        }
+        
+        head.into_bytes()
+    }
+    
+    fn authority(&self) -> String {
+        let host = if self.host.contains(':') { format!("[{}]", self.host) } else { self.host.clone() };
+        
+        if self.port == 80 {
+            host
+        } else {
+            format!("{}:{}", host, self.port)
+        }
+    }
+}
+
+/// Forwards requests to one of several upstream servers, skipping the ones that are unhealthy or failing.
+#[derive(Debug)]
+pub struct Proxy {
+    upstreams: Vec<Arc<Upstream>>,
+    strategy: BalanceStrategy,
+    timeout: Duration,
+    health_check: Option<HealthCheck>,
+    next: AtomicUsize,
+    health_checks_started: AtomicBool,
+}
+
+impl Proxy {
+    /// Creates a proxy, `timeout` limits how long each upstream gets to accept the connection and to send each part
+    /// of its response.
+    pub fn new(upstreams: Vec<Upstream>, strategy: BalanceStrategy, timeout: Duration) -> Proxy {
+        Proxy {
+            upstreams: upstreams.into_iter().map(Arc::new).collect(),
+            strategy,
+            timeout,
+            health_check: None,
+            next: AtomicUsize::new(0),
+            health_checks_started: AtomicBool::new(false),
+        }
+    }
+    
+    /// Checks the health of the upstreams once `start_health_checks` is called.
+    pub fn with_health_check(mut self, health_check: HealthCheck) -> Proxy {
+        self.health_check = Some(health_check);
+        
+        self
+    }
+    
+    pub fn get_upstreams(&self) -> &Vec<Arc<Upstream>> {
+        &self.upstreams
+    }
+    
+    pub fn get_strategy(&self) -> BalanceStrategy {
+        self.strategy
+    }
+    
+    pub fn get_timeout(&self) -> Duration {
+        self.timeout
+    }
+    
+    pub fn get_health_check(&self) -> Option<&HealthCheck> {
+        self.health_check.as_ref()
+    }
+    
+    /// Starts checking the health of the upstreams on a background thread, if a health check is configured. Calling
+    /// it again does nothing.
+    ///
+    /// The thread stops once the proxy is dropped, e.g. when the configuration is reloaded.
+    pub fn start_health_checks(&self) {
+        let health_check = match &self.health_check {
+            Some(health_check) if !self.health_checks_started.swap(true, Ordering::Relaxed) => health_check.clone(),
+            _ => return,
+        };
+        
+        let upstreams: Vec<Weak<Upstream>> = self.upstreams.iter().map(Arc::downgrade).collect();
+        
+        let spawned = thread::Builder::new().name("health-check".to_string()).spawn(move || loop {
+            for upstream in &upstreams {
+                match upstream.upgrade() {
+                    Some(upstream) => upstream.check_health(&health_check),
+                    None => return,
+                }
+            }
+            
+            thread::sleep(health_check.interval);
+        });
+        
+        if let Err(error) = spawned {
+            warn!("Failed to start the upstream health checks: {}", error);
+        }
+    }
+    
+    /// Picks the upstream for the next request. If every upstream is unhealthy or has its circuit open, returns how
+    /// long it may take until one is back instead.
+    pub fn select(&self) -> Result<Arc<Upstream>, Duration> {
+        // Start where the last request left off, so the upstreams take turns, also between equally loaded ones.
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let mut candidates = (0..self.upstreams.len())
+            .map(|offset| &self.upstreams[(start + offset) % self.upstreams.len()])
+            .collect::<Vec<_>>();
+        
+        if self.strategy == BalanceStrategy::LeastConnections {
+            candidates.sort_by_key(|upstream| upstream.get_active_requests());
+        }
+        
+        // Claiming the circuit comes last, since a half-open one lets only the request that claims it through.
+        if let Some(upstream) = candidates.into_iter().find(|upstream| upstream.is_healthy() && upstream.circuit_breaker.try_acquire()) {
+            return Ok(Arc::clone(upstream));
+        }
+        
+        // Tell the client when it's worth trying again, which is when the first circuit closes or the next health check
+        // runs.
+        let retry_after = self.upstreams.iter()
+            .filter_map(|upstream| upstream.circuit_breaker.get_retry_after())
+            .chain(self.health_check.as_ref().map(|health_check| health_check.interval))
+            .min()
+            .unwrap_or(Duration::from_secs(1));
+        
+        Err(retry_after)
+    }
+    
+    /// Forwards a request to an upstream picked by `select` and returns its response, whose body is relayed while
+    /// it's sent to the client.
+    ///
+    /// The method, headers and body are kept, apart from the hop-by-hop headers. `X-Forwarded-For` gets the client's
+    /// address appended, and `X-Forwarded-Proto` tells the upstream whether the client used TLS unless an earlier
+    /// proxy already did. Failures, timeouts and 5xx responses included, count towards opening the upstream's circuit.
+    pub fn forward(&self, upstream: &Arc<Upstream>, request: &Request, context: &ConnectionContext) -> Result<Response, ProxyError> {
+        let result = upstream.send(request, context, self.timeout);
+        
+        // An upstream that answers with server errors is as broken as one that doesn't answer at all.
+        match &result {
+            Ok(response) if response.get_status_code() < 500 => upstream.circuit_breaker.record_success(),
+            _ => upstream.circuit_breaker.record_failure(),
+        }
+        
+        result
+    }
+    
+    /// Like `forward`, but for a request asking to switch protocols, e.g. a WebSocket handshake. If the upstream agrees,
+    /// its `101 Switching Protocols` is returned along with the connection, which is relayed once the response has been
+    /// sent to the client. Otherwise its response is relayed as usual.
+    pub fn upgrade(&self, upstream: &Arc<Upstream>, request: &Request, context: &ConnectionContext) -> Result<(Response, Option<UpgradedConnection>), ProxyError> {
+        let result = upstream.send_upgrade(request, context, self.timeout);
+        
+        match &result {
+            Ok((response, _)) if response.get_status_code() < 500 => upstream.circuit_breaker.record_success(),
+            _ => upstream.circuit_breaker.record_failure(),
+        }
+        
+        result
+    }
+}
+
+/// A connection to an upstream that switched protocols, which counts as an active request until it's relayed.
+pub struct UpgradedConnection {
+    reader: ActiveRequest<BufReader<TcpStream>>,
+}
+
+impl UpgradedConnection {
+    /// Relays bytes between the client and the upstream until either side hangs up. `client_buffered` are the bytes the
+    /// client already sent after its request, which go first.
+    pub fn relay(self, client: ClientStream, client_buffered: &[u8]) -> io::Result<()> {
+        let buffered = self.reader.reader.buffer().to_vec();
+        let mut upstream = self.reader.reader.get_ref().try_clone()?;
+        upstream.write_all(client_buffered)?;
+        
+        // The upstream may have sent its first frames along with the response head.
+        let mut client = client;
+        client.write_all(&buffered)?;
+        client.flush()?;
+        
+        tunnel::relay_client(client, upstream)
+    }
+}
+
+/// Wraps the body of a proxied response, counting the request as active until the body has been relayed.
+struct ActiveRequest<R: Read> {
+    upstream: Arc<Upstream>,
+    reader: R,
+}
+
+impl<R: Read> ActiveRequest<R> {
+    fn new(upstream: Arc<Upstream>, reader: R) -> ActiveRequest<R> {
+        upstream.active_requests.fetch_add(1, Ordering::Relaxed);
+        
+        ActiveRequest {
+            upstream,
+            reader,
+        }
+    }
+}
+
+impl<R: Read> Read for ActiveRequest<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buffer)
+    }
+}
+
+impl<R: BufRead> BufRead for ActiveRequest<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.reader.fill_buf()
+    }
+    
+    fn consume(&mut self, amount: usize) {
+        self.reader.consume(amount)
+    }
+}
+
+impl<R: Read> Drop for ActiveRequest<R> {
+    fn drop(&mut self) {
+        self.upstream.active_requests.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn is_hop_by_hop(name: &str, connection_headers: &[String]) -> bool {
+    HOP_BY_HOP_HEADERS.iter().any(|header| header.eq_ignore_ascii_case(name))
+        || connection_headers.iter().any(|header| header.eq_ignore_ascii_case(name))
+}
+
+/// Builds the response relayed to the client from the head of the upstream's response, the body is read from `reader`
+/// while it's sent.
+fn into_response(request: &Request, head: ResponseHead, reader: ActiveRequest<BufReader<TcpStream>>) -> Result<Response, ProxyError> {
+    let ResponseHead { status_code, status_message, headers } = head;
+    
+    let mut response = Response::new(HttpVersion::Http11, status_code, &status_message);
+    
+    let connection_headers = headers.iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("Connection"))
+        .flat_map(|(_, value)| value.split(',').map(|name| name.trim().to_string()))
+        .collect::<Vec<_>>();
+    
+    let mut content_length = None;
+    let mut chunked = false;
+    
+    for (name, value) in &headers {
+        if name.eq_ignore_ascii_case("Content-Length") {
+            content_length = Some(value.parse::<u64>().map_err(|_| ProxyError::InvalidResponse(format!("invalid Content-Length {}", value)))?);
+        }
+        
+        if name.eq_ignore_ascii_case("Transfer-Encoding") {
+            chunked = value.to_ascii_lowercase().contains("chunked");
+        }
+        
+        if !is_hop_by_hop(name, &connection_headers) {
+            response.add_header(name, value);
+        }
+    }
+    
+    // Answers to HEAD, 204 and 304 never have a body, whatever their headers say (RFC 9112 §6.3).
+    let has_body = *request.get_method() != Method::Head && status_code != 204 && status_code != 304;
+    
+    if has_body {
+        if chunked {
+            response.headers_mut().remove("Content-Length");
+            response.set_body_stream(ChunkedReader::new(reader), None);
+        } else if let Some(content_length) = content_length {
+            response.set_body_stream(reader.take(content_length), Some(content_length));
+        } else {
+            // Without a length the body ends when the upstream closes the connection.
+            response.set_body_stream(reader, None);
+        }
+    }
+    
+    Ok(response)
+}
+
+/// The status line and headers of an upstream response.
+struct ResponseHead {
+    status_code: u16,
+    status_message: String,
+    headers: Vec<(String, String)>,
+}
+
+/// Reads the status line and headers of a response.
+fn read_response_head(reader: &mut impl BufRead) -> Result<ResponseHead, ProxyError> {
+    let mut head_bytes = 0;
+    let mut read_line = |reader: &mut dyn BufRead| -> Result<String, ProxyError> {
+        let mut line = String::new();
+        let bytes_read = reader.read_line(&mut line)?;
+        
+        head_bytes += bytes_read;
+        
+        if bytes_read == 0 {
+            return Err(ProxyError::InvalidResponse("the connection closed before the response was complete".to_string()));
+        }
+        
+        if head_bytes > MAX_RESPONSE_HEAD_BYTES {
+            return Err(ProxyError::InvalidResponse(format!("the headers are larger than {} bytes", MAX_RESPONSE_HEAD_BYTES)));
+        }
+        
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    };
+    
+    let status_line = read_line(reader)?;
+    let mut parts = status_line.splitn(3, ' ');
+    
+    let status_code = match (parts.next(), parts.next().and_then(|code| code.parse::<u16>().ok())) {
+        (Some(version), Some(status_code)) if version.starts_with("HTTP/1.") && (100..600).contains(&status_code) => status_code,
+        _ => return Err(ProxyError::InvalidResponse(format!("invalid status line {}", status_line))),
+    };
+    
+    let status_message = parts.next().unwrap_or_default().to_string();
+    let mut headers = Vec::new();
+    
+    loop {
+        let line = read_line(reader)?;
+        
+        if line.is_empty() {
+            break;
+        }
+        
+        match line.split_once(':') {
+            Some((name, value)) if !name.is_empty() && !name.contains(char::is_whitespace) => headers.push((name.to_string(), value.trim().to_string())),
+            _ => return Err(ProxyError::InvalidResponse(format!("invalid header {}", line))),
+        }
+    }
+    
+    Ok(ResponseHead {
+        status_code,
+        status_message,
+        headers,
+    })
+}
+
+/// Decodes a chunked response body (RFC 9112 §7.1) while it's read, so it can be sent on in whichever way the
+/// client needs.
+struct ChunkedReader<R: BufRead> {
+    reader: R,
+    remaining: u64,
+    started: bool,
+    finished: bool,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    fn new(reader: R) -> ChunkedReader<R> {
+        ChunkedReader {
+            reader,
+            remaining: 0,
+            started: false,
+            finished: false,
+        }
+    }
+    
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "The upstream closed the connection in the middle of the body!"));
+        }
+        
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        if self.finished || buffer.is_empty() {
+            return Ok(0);
+        }
+        
+        if self.remaining == 0 {
+            // Every chunk after the first follows the CRLF that ends the one before it.
+            if self.started {
+                self.read_line()?;
+            }
+            
+            // Parse the chunk size, ignoring any chunk extensions.
+            let line = self.read_line()?;
+            let size = line.split(';').next().unwrap_or_default().trim();
+            
+            self.remaining = u64::from_str_radix(size, 16)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid chunk size: {}", size)))?;
+            self.started = true;
+            
+            // The last chunk is followed by optional trailers and an empty line.
+            if self.remaining == 0 {
+                while !self.read_line()?.is_empty() {}
+                
+                self.finished = true;
+                
+                return Ok(0);
+            }
+        }
+        
+        let max = buffer.len().min(usize::try_from(self.remaining).unwrap_or(usize::MAX));
+        let bytes_read = self.reader.read(&mut buffer[..max])?;
+        
+        if bytes_read == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "The upstream closed the connection in the middle of the body!"));
+        }
+        
+        self.remaining -= bytes_read as u64;
+        
+        Ok(bytes_read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    use std::net::Ipv4Addr;
+    
+    fn upstream(url: &str) -> Upstream {
+        Upstream::new(url, CircuitBreaker::new(2, Duration::from_millis(50))).unwrap()
+    }
+    
+    fn fail(breaker: &CircuitBreaker, times: u32) {
+        for _ in 0..times {
+            assert!(breaker.try_acquire());
+            breaker.record_failure();
+        }
+    }
+    
+    #[test]
+    fn circuits_open_after_failures_in_a_row() {
+        let breaker = CircuitBreaker::new(3, Duration::from_secs(60));
+        
+        fail(&breaker, 2);
+        breaker.record_success();
+        fail(&breaker, 2);
+        
+        // A success in between starts the count over.
+        assert_eq!(breaker.get_state(), CircuitState::Closed);
+        
+        fail(&breaker, 1);
+        
+        assert_eq!(breaker.get_state(), CircuitState::Open);
+        assert!(!breaker.try_acquire());
+        assert!(breaker.get_retry_after().is_some_and(|retry_after| retry_after > Duration::from_secs(59)));
+    }
+    
+    #[test]
+    fn half_open_circuits_let_a_single_probe_through() {
+        let breaker = CircuitBreaker::new(1, Duration::from_millis(50));
+        
+        fail(&breaker, 1);
+        thread::sleep(Duration::from_millis(60));
+        
+        assert_eq!(breaker.get_state(), CircuitState::HalfOpen);
+        assert!(breaker.try_acquire());
+        assert!(!breaker.try_acquire());
+        
+        breaker.record_success();
+        
+        assert_eq!(breaker.get_state(), CircuitState::Closed);
+        assert!(breaker.try_acquire());
+    }
+    
+    #[test]
+    fn failed_probes_open_the_circuit_again() {
+        let breaker = CircuitBreaker::new(1, Duration::from_millis(50));
+        
+        fail(&breaker, 1);
+        thread::sleep(Duration::from_millis(60));
+        fail(&breaker, 1);
+        
+        assert_eq!(breaker.get_state(), CircuitState::Open);
+        assert!(!breaker.try_acquire());
+    }
+    
+    #[test]
+    fn upstream_urls_are_parsed() {
+        let parsed = upstream("http://[::1]:3000/api/");
+        
+        assert_eq!((parsed.host.as_str(), parsed.port, parsed.path.as_str()), ("::1", 3000, "/api"));
+        assert_eq!(parsed.authority(), "[::1]:3000");
+        assert_eq!(upstream("http://app.internal").authority(), "app.internal");
+        
+        for url in ["https://app.internal", "ftp://app.internal", "http://:3000", "http://app.internal:0", "http://app.internal:port"] {
+            assert!(Upstream::new(url, CircuitBreaker::new(1, Duration::from_secs(1))).is_err(), "{}", url);
+        }
+    }
+    
+    #[test]
+    fn round_robin_takes_turns() {
+        let proxy = Proxy::new(vec![upstream("http://a:1"), upstream("http://b:1")], BalanceStrategy::RoundRobin, DEFAULT_UPSTREAM_TIMEOUT);
+        
+        let picked = (0..4).map(|_| proxy.select().unwrap().get_url().to_string()).collect::<Vec<_>>();
+        
+        assert_eq!(picked, ["http://a:1", "http://b:1", "http://a:1", "http://b:1"]);
+    }
+    
+    #[test]
+    fn least_connections_picks_the_least_busy() {
+        let proxy = Proxy::new(vec![upstream("http://a:1"), upstream("http://b:1")], BalanceStrategy::LeastConnections, DEFAULT_UPSTREAM_TIMEOUT);
+        proxy.upstreams[0].active_requests.store(3, Ordering::Relaxed);
+        
+        for _ in 0..3 {
+            assert_eq!(proxy.select().unwrap().get_url(), "http://b:1");
+        }
+    }
+    
+    #[test]
+    fn failing_upstreams_are_skipped_until_none_is_left() {
+        let proxy = Proxy::new(vec![upstream("http://a:1"), upstream("http://b:1")], BalanceStrategy::RoundRobin, DEFAULT_UPSTREAM_TIMEOUT);
+        fail(&proxy.upstreams[0].circuit_breaker, 2);
+        
+        for _ in 0..3 {
+            assert_eq!(proxy.select().unwrap().get_url(), "http://b:1");
+        }
+        
+        *proxy.upstreams[1].status.lock().unwrap() = UpstreamStatus::Unhealthy { since: Instant::now() };
+        
+        // The client is told to come back once the open circuit may close.
+        assert!(proxy.select().is_err_and(|retry_after| retry_after <= Duration::from_millis(50)));
+    }
+    
+    #[test]
+    fn hop_by_hop_headers_are_not_forwarded() {
+        let request = Request::parse(
+            "GET /users HTTP/1.1\r\nHost: example.com\r\nConnection: keep-alive, X-Secret\r\nX-Secret: 1\r\nKeep-Alive: timeout=5\r\nX-Forwarded-For: 198.51.100.7\r\nAccept: */*\r\n\r\n",
+        ).unwrap();
+        
+        let head = String::from_utf8(upstream("http://app:3000/api").request_head(&request, IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), true, None)).unwrap();
+        
+        assert!(head.starts_with("GET /api/users HTTP/1.1\r\n"), "{}", head);
+        assert!(head.contains("Host: example.com\r\n"), "{}", head);
+        assert!(head.contains("Accept: */*\r\n"), "{}", head);
+        assert!(head.contains("X-Forwarded-For: 198.51.100.7, 192.0.2.1\r\n"), "{}", head);
+        assert!(head.contains("X-Forwarded-Proto: https\r\n"), "{}", head);
+        assert!(head.ends_with("Connection: close\r\n\r\n"), "{}", head);
+        assert!(!head.contains("X-Secret") && !head.contains("Keep-Alive") && !head.contains("keep-alive"), "{}", head);
+    }
+    
+    #[test]
+    fn response_heads_are_parsed() {
+        let mut reader = io::Cursor::new(b"HTTP/1.1 404 Not Found\r\nContent-Type: text/plain\r\nX-Empty:\r\n\r\nbody".to_vec());
+        let head = read_response_head(&mut reader).unwrap();
+        
+        assert_eq!((head.status_code, head.status_message.as_str()), (404, "Not Found"));
+        assert_eq!(head.headers, [("Content-Type".to_string(), "text/plain".to_string()), ("X-Empty".to_string(), String::new())]);
+        
+        for invalid in ["HTTP/2 200 OK\r\n\r\n", "HTTP/1.1 99 Low\r\n\r\n", "HTTP/1.1 200 OK\r\nBad Header: 1\r\n\r\n", "HTTP/1.1 200 OK\r\n"] {
+            let result = read_response_head(&mut io::Cursor::new(invalid.as_bytes().to_vec()));
+            
+            assert!(matches!(result, Err(ProxyError::InvalidResponse(_))), "{:?}", invalid);
+        }
+    }
+    
+    #[test]
+    fn chunked_bodies_are_decoded() {
+        let body = b"5;name=value\r\nhello\r\n7\r\n, world\r\n0\r\nTrailer: 1\r\n\r\n";
+        let mut decoded = String::new();
+        ChunkedReader::new(io::Cursor::new(body.to_vec())).read_to_string(&mut decoded).unwrap();
+        
+        assert_eq!(decoded, "hello, world");
+        
+        let truncated = ChunkedReader::new(io::Cursor::new(b"5\r\nhel".to_vec())).read_to_string(&mut String::new());
+        
+        assert!(truncated.is_err());
+    }
+}
@@ -0,0 +1,231 @@
+use uuid::Uuid;
+
+use crate::http::{self, Response};
+use crate::status::StatusCode;
+
+/// The most ranges a single request may ask for, more than that is usually an attempt to waste resources.
+const MAX_RANGES: usize = 16;
+
+/// An inclusive range of byte positions in a body.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ByteRange {
+    start: usize,
+    end: usize,
+}
+
+impl ByteRange {
+    pub fn get_start(&self) -> usize {
+        self.start
+    }
+    
+    /// Returns the position of the last byte in the range, which is included.
+    pub fn get_end(&self) -> usize {
+        self.end
+    }
+    
+    fn content_range(&self, length: usize) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, length)
+    }
+}
+
+/// What a `Range` header asks for, once it's checked against the length of the body.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RangeRequest {
+    /// The header isn't a byte range the server understands, so the whole body is sent.
+    Ignored,
+    /// None of the ranges overlap the body, which is answered with 416.
+    Unsatisfiable,
+    /// The ranges to send, in the order they were asked for.
+    Satisfiable(Vec<ByteRange>),
+}
+
+/// Parses a `Range` header like `bytes=0-99, -500` for a body of the given length (RFC 9110 §14.2).
+pub fn parse(range: &str, length: usize) -> RangeRequest {
+    let specs = match range.trim().strip_prefix("bytes=") {
+        Some(specs) => specs,
+        None => return RangeRequest::Ignored,
+    };
+    
+    let mut ranges = Vec::new();
+    
+    for spec in specs.split(',').map(str::trim).filter(|spec| !spec.is_empty()) {
+        let (start, end) = match spec.split_once('-') {
+            Some(bounds) => bounds,
+            None => return RangeRequest::Ignored,
+        };
+        
+        let range = match (start.parse::<usize>(), end.parse::<usize>()) {
+            // A suffix range asks for the last bytes, e.g. -500 for the final 500.
+            (Err(_), Ok(suffix)) if start.is_empty() => match suffix {
+                0 => None,
+                _ => length.checked_sub(1).map(|last| ByteRange { start: length.saturating_sub(suffix), end: last }),
+            },
+            (Ok(start), Err(_)) if end.is_empty() => (start < length).then(|| ByteRange { start, end: length - 1 }),
+            (Ok(start), Ok(end)) if start <= end => (start < length).then(|| ByteRange { start, end: end.min(length - 1) }),
+            _ => return RangeRequest::Ignored,
+        };
+        
+        ranges.extend(range);
+    }
+    
+    if ranges.len() > MAX_RANGES {
+        return RangeRequest::Ignored;
+    }
+    
+    if ranges.is_empty() {
+        RangeRequest::Unsatisfiable
+    } else {
+        RangeRequest::Satisfiable(ranges)
+    }
+}
+
+/// Checks whether an `If-Range` validator still matches the response, so the ranges can be sent (RFC 9110 §13.1.5).
+///
+/// Entity tags have to match exactly and weak ones never do, dates match if the response hasn't changed since.
+pub fn if_range_matches(if_range: &str, response: &Response) -> bool {
+    let if_range = if_range.trim();
+    
+    if if_range.starts_with('"') || if_range.starts_with("W/") {
+        return !if_range.starts_with("W/") && response.get_header("ETag") == Some(if_range);
+    }
+    
+    match (http::parse_http_date(if_range), response.get_header("Last-Modified").and_then(http::parse_http_date)) {
+        (Some(if_range), Some(last_modified)) => last_modified <= if_range,
+        _ => false,
+    }
+}
+
+/// Turns a complete response into a 206 with only the requested ranges.
+///
+/// A single range is sent as is, several are sent as `multipart/byteranges` with each part labelled.
+pub fn partial_content(response: &Response, ranges: &[ByteRange]) -> Response {
+    let body = response.get_body();
+    let mut partial = Response::with_status(StatusCode::PartialContent);
+    
+    for (name, value) in response.get_headers().iter() {
+        if !name.eq_ignore_ascii_case("Content-Type") && !name.eq_ignore_ascii_case("Content-Length") {
+            partial.add_header(name, value);
+        }
+    }
+    
+    if let [range] = ranges {
+        if let Some(content_type) = response.get_header("Content-Type") {
+            partial.add_header("Content-Type", content_type);
+        }
+        
+        partial.add_header("Content-Range", &range.content_range(body.len()));
+        partial.set_body_bytes(&body[range.start..=range.end]);
+        
+        return partial;
+    }
+    
+    let boundary = Uuid::new_v4().simple().to_string();
+    let mut multipart = Vec::new();
+    
+    for range in ranges {
+        multipart.extend_from_slice(format!("--{}\r\n", boundary).as_bytes());
+        
+        if let Some(content_type) = response.get_header("Content-Type") {
+            multipart.extend_from_slice(format!("Content-Type: {}\r\n", content_type).as_bytes());
+        }
+        
+        multipart.extend_from_slice(format!("Content-Range: {}\r\n\r\n", range.content_range(body.len())).as_bytes());
+        multipart.extend_from_slice(&body[range.start..=range.end]);
+        multipart.extend_from_slice(b"\r\n");
+    }
+    
+    multipart.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+    
+    partial.add_header("Content-Type", &format!("multipart/byteranges; boundary={}", boundary));
+    partial.set_body_bytes(&multipart);
+    
+    partial
+}
+
+/// Answers a request whose ranges all lie past the end of the body.
+pub fn range_not_satisfiable(length: usize) -> Response {
+    Response::with_status(StatusCode::RangeNotSatisfiable)
+        .with_header("Content-Range", &format!("bytes */{}", length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn range(start: usize, end: usize) -> ByteRange {
+        ByteRange { start, end }
+    }
+    
+    #[test]
+    fn single_ranges_are_clamped_to_the_body() {
+        assert_eq!(parse("bytes=0-9", 36), RangeRequest::Satisfiable(vec![range(0, 9)]));
+        assert_eq!(parse("bytes=10-", 36), RangeRequest::Satisfiable(vec![range(10, 35)]));
+        assert_eq!(parse("bytes=30-99", 36), RangeRequest::Satisfiable(vec![range(30, 35)]));
+        assert_eq!(parse("bytes=-6", 36), RangeRequest::Satisfiable(vec![range(30, 35)]));
+        assert_eq!(parse("bytes=-100", 36), RangeRequest::Satisfiable(vec![range(0, 35)]));
+    }
+    
+    #[test]
+    fn several_ranges_keep_their_order() {
+        assert_eq!(parse("bytes=20-29, 0-9", 36), RangeRequest::Satisfiable(vec![range(20, 29), range(0, 9)]));
+    }
+    
+    #[test]
+    fn ranges_past_the_end_are_unsatisfiable() {
+        assert_eq!(parse("bytes=36-", 36), RangeRequest::Unsatisfiable);
+        assert_eq!(parse("bytes=-0", 36), RangeRequest::Unsatisfiable);
+        assert_eq!(parse("bytes=0-", 0), RangeRequest::Unsatisfiable);
+    }
+    
+    #[test]
+    fn malformed_headers_are_ignored() {
+        for header in ["items=0-9", "bytes=9-0", "bytes=a-b", "bytes=5", "bytes=-"] {
+            assert_eq!(parse(header, 36), RangeRequest::Ignored, "{}", header);
+        }
+        
+        let too_many = format!("bytes={}", vec!["0-0"; MAX_RANGES + 1].join(","));
+        
+        assert_eq!(parse(&too_many, 36), RangeRequest::Ignored);
+    }
+    
+    #[test]
+    fn a_single_range_is_sent_as_is() {
+        let mut response = Response::ok().with_header("Content-Type", "text/plain");
+        response.set_body("0123456789");
+        
+        let partial = partial_content(&response, &[range(2, 4)]);
+        
+        assert_eq!(partial.get_status_code(), 206);
+        assert_eq!(partial.get_header("Content-Type"), Some("text/plain"));
+        assert_eq!(partial.get_header("Content-Range"), Some("bytes 2-4/10"));
+        assert_eq!(partial.get_body(), b"234");
+    }
+    
+    #[test]
+    fn several_ranges_are_sent_as_labelled_parts() {
+        let mut response = Response::ok().with_header("Content-Type", "text/plain");
+        response.set_body("0123456789");
+        
+        let partial = partial_content(&response, &[range(0, 1), range(8, 9)]);
+        let content_type = partial.get_header("Content-Type").unwrap();
+        let boundary = content_type.strip_prefix("multipart/byteranges; boundary=").unwrap();
+        
+        let expected = format!(
+            "--{0}\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/10\r\n\r\n01\r\n\
+             --{0}\r\nContent-Type: text/plain\r\nContent-Range: bytes 8-9/10\r\n\r\n89\r\n\
+             --{0}--\r\n",
+            boundary,
+        );
+        
+        assert_eq!(partial.get_status_code(), 206);
+        assert_eq!(String::from_utf8_lossy(partial.get_body()), expected);
+    }
+    
+    #[test]
+    fn unsatisfiable_ranges_name_the_length() {
+        let response = range_not_satisfiable(36);
+        
+        assert_eq!(response.get_status_code(), 416);
+        assert_eq!(response.get_header("Content-Range"), Some("bytes */36"));
+    }
+}
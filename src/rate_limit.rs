@@ -0,0 +1,326 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Decides whether a client may make another request.
+pub trait RateLimiter {
+    /// Records a request from the client, returning how long it has to wait if it's over the limit.
+    fn check(&self, client_ip: IpAddr) -> Result<(), Duration>;
+}
+
+/// The algorithms a rate limiter can be configured with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RateLimiterAlgorithm {
+    TokenBucket,
+    SlidingWindow,
+}
+
+impl RateLimiterAlgorithm {
+    pub fn parse(algorithm: &str) -> Option<RateLimiterAlgorithm> {
+        match algorithm {
+            "token_bucket" => Some(RateLimiterAlgorithm::TokenBucket),
+            "sliding_window" => Some(RateLimiterAlgorithm::SlidingWindow),
+            _ => None,
+        }
+    }
+}
+
+/// The clients a rate limiter keeps track of, where the idle ones are forgotten every so often.
+struct Clients<T> {
+    entries: HashMap<IpAddr, T>,
+    pruned: Instant,
+}
+
+impl<T> Clients<T> {
+    fn new() -> Clients<T> {
+        Clients {
+            entries: HashMap::new(),
+            pruned: Instant::now(),
+        }
+    }
+    
+    /// Forgets the clients that aren't active anymore, unless that was already done within the interval, so the cost
+    /// of going over every client is spread out rather than paid on every request.
+    fn prune(&mut self, now: Instant, interval: Duration, is_active: impl Fn(&T) -> bool) {
+        if now.duration_since(self.pruned) < interval {
+            return;
+        }
+        
+        self.entries.retain(|_, entry| is_active(entry));
+        self.pruned = now;
+    }
+}
+
+/// Allows bursts of up to `max_requests`, refilling the bucket evenly over the window.
+pub struct TokenBucketRateLimiter {
+    max_requests: u32,
+    window: Duration,
+    buckets: Mutex<Clients<(f64, Instant)>>,
+}
+
+impl TokenBucketRateLimiter {
+    pub fn new(window_secs: u64, max_requests: u32) -> TokenBucketRateLimiter {
+        TokenBucketRateLimiter {
+            max_requests,
+            window: Duration::from_secs(window_secs),
+            buckets: Mutex::new(Clients::new()),
+        }
+    }
+}
+
+impl RateLimiter for TokenBucketRateLimiter {
+    fn check(&self, client_ip: IpAddr) -> Result<(), Duration> {
+        let now = Instant::now();
+        let capacity = self.max_requests as f64;
+        let refill_per_sec = capacity / self.window.as_secs_f64();
+        
+        let mut buckets = self.buckets.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        
+        // Forget the clients whose buckets have refilled completely, once per window.
+        buckets.prune(now, self.window, |(_, updated)| now.duration_since(*updated) < self.window);
+        
+        let (tokens, updated) = buckets.entries.entry(client_ip).or_insert((capacity, now));
+        
+        // Refill the tokens earned since the last request.
+        *tokens = (*tokens + now.duration_since(*updated).as_secs_f64() * refill_per_sec).min(capacity);
+        *updated = now;
+        
+        if *tokens < 1.0 {
+            return Err(Duration::from_secs_f64((1.0 - *tokens) / refill_per_sec));
+        }
+        
+        *tokens -= 1.0;
+        
+        Ok(())
+    }
+}
+
+/// Allows at most `max_requests` within any window, which is stricter than a token bucket near the limit.
+///
+/// Every request timestamp within the window is kept, so it uses more memory than a token bucket.
+pub struct SlidingWindowRateLimiter {
+    max_requests: u32,
+    window: Duration,
+    requests: Mutex<Clients<VecDeque<Instant>>>,
+}
+
+impl SlidingWindowRateLimiter {
+    pub fn new(window_secs: u64, max_requests: u32) -> SlidingWindowRateLimiter {
+        SlidingWindowRateLimiter {
+            max_requests,
+            window: Duration::from_secs(window_secs),
+            requests: Mutex::new(Clients::new()),
+        }
+    }
+}
+
+impl RateLimiter for SlidingWindowRateLimiter {
+    fn check(&self, client_ip: IpAddr) -> Result<(), Duration> {
+        let now = Instant::now();
+        
+        let mut requests = self.requests.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        
+        // Forget the clients without any requests in the current window, once per window.
+        requests.prune(now, self.window, |timestamps| timestamps.back().is_some_and(|last| now.duration_since(*last) < self.window));
+        
+        let timestamps = requests.entries.entry(client_ip).or_default();
+        
+        // Evict the requests that have left the window.
+        while timestamps.front().is_some_and(|first| now.duration_since(*first) >= self.window) {
+            timestamps.pop_front();
+        }
+        
+        if timestamps.len() >= self.max_requests as usize {
+            // The client may try again once the oldest request leaves the window.
+            let oldest = timestamps.front().copied().unwrap_or(now);
+            
+            return Err(self.window.saturating_sub(now.duration_since(oldest)));
+        }
+        
+        timestamps.push_back(now);
+        
+        Ok(())
+    }
+}
+
+/// Limits how many connections a single client may have open at the same time.
+pub struct ConnectionLimiter {
+    max_connections: usize,
+    open_connections: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl ConnectionLimiter {
+    pub fn new(max_connections: usize) -> ConnectionLimiter {
+        ConnectionLimiter {
+            max_connections,
+            open_connections: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+    
+    pub fn get_max_connections(&self) -> usize {
+        self.max_connections
+    }
+    
+    /// Records a new connection from the client, returning `None` if it already has as many open as it may.
+    ///
+    /// The connection counts as open until the returned guard is dropped.
+    pub fn acquire(&self, client_ip: IpAddr) -> Option<ConnectionGuard> {
+        let mut open_connections = self.open_connections.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let count = open_connections.entry(client_ip).or_insert(0);
+        
+        if *count >= self.max_connections {
+            return None;
+        }
+        
+        *count += 1;
+        
+        Some(ConnectionGuard {
+            client_ip,
+            open_connections: Arc::clone(&self.open_connections),
+        })
+    }
+}
+
+/// An open connection counted by a `ConnectionLimiter`, released when dropped, even while unwinding from a panic.
+pub struct ConnectionGuard {
+    client_ip: IpAddr,
+    open_connections: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut open_connections = self.open_connections.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        
+        // Forget the client once its last connection is closed, so the map only holds connected clients.
+        if let Some(count) = open_connections.get_mut(&self.client_ip) {
+            *count -= 1;
+            
+            if *count == 0 {
+                open_connections.remove(&self.client_ip);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    use std::thread;
+    
+    const CLIENT: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 1));
+    const OTHER_CLIENT: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 2));
+    
+    #[test]
+    fn algorithms_are_parsed_by_name() {
+        assert_eq!(RateLimiterAlgorithm::parse("token_bucket"), Some(RateLimiterAlgorithm::TokenBucket));
+        assert_eq!(RateLimiterAlgorithm::parse("sliding_window"), Some(RateLimiterAlgorithm::SlidingWindow));
+        assert_eq!(RateLimiterAlgorithm::parse("leaky_bucket"), None);
+    }
+    
+    #[test]
+    fn token_buckets_allow_a_burst_and_then_refill() {
+        let limiter = TokenBucketRateLimiter::new(1, 2);
+        
+        assert!(limiter.check(CLIENT).is_ok());
+        assert!(limiter.check(CLIENT).is_ok());
+        
+        // A token is earned every half second.
+        let retry_after = limiter.check(CLIENT).unwrap_err();
+        
+        assert!(retry_after > Duration::ZERO && retry_after <= Duration::from_millis(500), "{:?}", retry_after);
+        
+        thread::sleep(Duration::from_millis(550));
+        
+        assert!(limiter.check(CLIENT).is_ok());
+        assert!(limiter.check(CLIENT).is_err());
+    }
+    
+    #[test]
+    fn sliding_windows_wait_for_the_oldest_request_to_leave() {
+        let limiter = SlidingWindowRateLimiter::new(1, 2);
+        
+        assert!(limiter.check(CLIENT).is_ok());
+        assert!(limiter.check(CLIENT).is_ok());
+        
+        let retry_after = limiter.check(CLIENT).unwrap_err();
+        
+        assert!(retry_after > Duration::from_millis(500) && retry_after <= Duration::from_secs(1), "{:?}", retry_after);
+        
+        // Half the window later a token bucket would have let a request through, but both are still in the window.
+        thread::sleep(Duration::from_millis(550));
+        
+        assert!(limiter.check(CLIENT).is_err());
+        
+        thread::sleep(Duration::from_millis(500));
+        
+        assert!(limiter.check(CLIENT).is_ok());
+    }
+    
+    #[test]
+    fn clients_are_limited_separately() {
+        let limiters: [Box<dyn RateLimiter>; 2] = [Box::new(TokenBucketRateLimiter::new(60, 1)), Box::new(SlidingWindowRateLimiter::new(60, 1))];
+        
+        for limiter in limiters {
+            assert!(limiter.check(CLIENT).is_ok());
+            assert!(limiter.check(CLIENT).is_err());
+            assert!(limiter.check(OTHER_CLIENT).is_ok());
+        }
+    }
+    
+    #[test]
+    fn refused_requests_do_not_count_against_the_window() {
+        let limiter = SlidingWindowRateLimiter::new(60, 1);
+        
+        assert!(limiter.check(CLIENT).is_ok());
+        
+        for _ in 0..10 {
+            assert!(limiter.check(CLIENT).is_err());
+        }
+        
+        assert_eq!(limiter.requests.lock().unwrap().entries[&CLIENT].len(), 1);
+    }
+    
+    #[test]
+    fn connections_are_capped_per_client() {
+        let limiter = ConnectionLimiter::new(2);
+        
+        let first = limiter.acquire(CLIENT);
+        let second = limiter.acquire(CLIENT);
+        
+        assert!(first.is_some() && second.is_some());
+        assert!(limiter.acquire(CLIENT).is_none());
+        assert!(limiter.acquire(OTHER_CLIENT).is_some());
+    }
+    
+    #[test]
+    fn dropping_a_guard_frees_its_slot() {
+        let limiter = ConnectionLimiter::new(1);
+        
+        let guard = limiter.acquire(CLIENT);
+        
+        assert!(limiter.acquire(CLIENT).is_none());
+        
+        drop(guard);
+        
+        assert!(limiter.acquire(CLIENT).is_some());
+        
+        // The client is forgotten once its last connection is closed.
+        assert!(limiter.open_connections.lock().unwrap().is_empty());
+    }
+    
+    #[test]
+    fn slots_are_freed_when_a_connection_panics() {
+        let limiter = ConnectionLimiter::new(1);
+        
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _guard = limiter.acquire(CLIENT);
+            
+            panic!("the connection handler failed");
+        }));
+        
+        assert!(result.is_err());
+        assert!(limiter.acquire(CLIENT).is_some());
+    }
+}
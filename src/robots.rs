@@ -0,0 +1,100 @@
+use std::sync::OnceLock;
+
+use json::JsonValue;
+
+use crate::config::ConfigError;
+
+/// The rules for a single user agent in a robots.txt file.
+pub struct RobotsGroup {
+    user_agent: String,
+    allow: Vec<String>,
+    disallow: Vec<String>,
+}
+
+/// Configuration for a generated robots.txt, following the Robots Exclusion Protocol (RFC 9309).
+pub struct RobotsConfig {
+    groups: Vec<RobotsGroup>,
+    sitemap: Option<String>,
+    rendered: OnceLock<String>,
+}
+
+impl RobotsConfig {
+    /// Parses the `robots_txt` config section.
+    pub fn from_json(config: &JsonValue) -> Result<RobotsConfig, ConfigError> {
+        let mut groups = Vec::new();
+        
+        for rule in config["rules"].members() {
+            let user_agent = match rule["user_agent"].as_str() {
+                Some(user_agent) => ascii_value(user_agent)?,
+                None => return Err(ConfigError::invalid("robots_txt", "every rule needs a user_agent string")),
+            };
+            
+            groups.push(RobotsGroup {
+                user_agent,
+                allow: string_list(&rule["allow"])?,
+                disallow: string_list(&rule["disallow"])?,
+            });
+        }
+        
+        let sitemap = match config["sitemap"].as_str() {
+            Some(sitemap) => Some(ascii_value(sitemap)?),
+            None if config["sitemap"].is_null() => None,
+            None => return Err(ConfigError::invalid("robots_txt", "sitemap must be a URL string")),
+        };
+        
+        Ok(RobotsConfig {
+            groups,
+            sitemap,
+            rendered: OnceLock::new(),
+        })
+    }
+    
+    /// Returns the robots.txt contents, generating them on first use.
+    pub fn render(&self) -> &str {
+        self.rendered.get_or_init(|| {
+            let mut robots = String::new();
+            
+            for group in &self.groups {
+                robots += &format!("User-agent: {}\n", group.user_agent);
+                
+                for path in &group.allow {
+                    robots += &format!("Allow: {}\n", path);
+                }
+                
+                for path in &group.disallow {
+                    robots += &format!("Disallow: {}\n", path);
+                }
+                
+                robots += "\n";
+            }
+            
+            if let Some(sitemap) = &self.sitemap {
+                robots += &format!("Sitemap: {}\n", sitemap);
+            }
+            
+            robots
+        })
+    }
+}
+
+fn string_list(config: &JsonValue) -> Result<Vec<String>, ConfigError> {
+    let mut values = Vec::new();
+    
+    for value in config.members() {
+        match value.as_str() {
+            Some(value) => values.push(ascii_value(value)?),
+            None => return Err(ConfigError::invalid("robots_txt", "allow and disallow must be lists of paths")),
+        }
+    }
+    
+    Ok(values)
+}
+
+/// Makes sure a value is printable ASCII, so it can't break the line-based format.
+fn ascii_value(value: &str) -> Result<String, ConfigError> {
+    if !value.chars().all(|character| character.is_ascii_graphic() || character == ' ') {
+        return Err(ConfigError::invalid("robots_txt", &format!("{:?} must only contain printable ASCII characters", value)));
+    }
+    
+    Ok(value.to_string())
+}
@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::http::{BodyReader, Method, Request, Response};
+use crate::network::AccessList;
+
+/// Produces the response for a route that's handled in code rather than by serving a file.
+pub trait Handler {
+    fn handle(&self, request: &Request, params: &RouteParams) -> Response;
+    
+    /// Produces the response while reading the body from `body` as the client sends it, so uploads don't have to fit in
+    /// memory. The request itself comes without a body, and whatever isn't read is skipped once the response is made.
+    ///
+    /// By default the whole body is read into the request, which is then passed to `handle`. Reading errors are answered
+    /// by the server, with 413 if the body is too large and 400 otherwise.
+    fn handle_body(&self, request: &Request, params: &RouteParams, body: &mut BodyReader) -> io::Result<Response> {
+        let mut request = request.clone();
+        request.set_body_bytes(&body.read_all()?);
+        
+        Ok(self.handle(&request, params))
+    }
+}
+
+/// Lets a closure like `|request| Response::ok()` be used as a handler, implement `Handler` to use the parameters.
+impl<F> Handler for F
+where
+    F: Fn(&Request) -> Response,
+{
+    fn handle(&self, request: &Request, _params: &RouteParams) -> Response {
+        self(request)
+    }
+}
+
+/// An error in a route pattern.
+#[derive(Debug)]
+pub enum RouteError {
+    /// The pattern doesn't start with a slash.
+    NotAbsolute(String),
+    /// A `*` is used anywhere but as the last segment.
+    MisplacedWildcard(String),
+    /// A `:` segment doesn't have a parameter name.
+    UnnamedParameter(String),
+    /// The exact same pattern is already routed.
+    Duplicate(String),
+}
+
+impl fmt::Display for RouteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RouteError::NotAbsolute(pattern) => write!(f, "The route {} must start with a slash", pattern),
+            RouteError::MisplacedWildcard(pattern) => write!(f, "The route {} may only use * as its last segment", pattern),
+            RouteError::UnnamedParameter(pattern) => write!(f, "The route {} has a parameter without a name", pattern),
+            RouteError::Duplicate(pattern) => write!(f, "The route {} is already defined", pattern),
+        }
+    }
+}
+
+impl Error for RouteError {}
+
+/// What a matched route is answered with.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RouteTarget {
+    /// One of the configured pages, by its index in the server's pages.
+    Page(usize),
+    /// A file relative to the web root, or a directory if the route ends with a wildcard.
+    File(String),
+    /// A handler registered under the given name.
+    Handler(String),
+    /// An upstream server the request is forwarded to, by its index in the site's proxies.
+    Proxy(usize),
+    /// A FastCGI backend the request is passed to, by its index in the site's FastCGI backends.
+    FastCgi(usize),
+    /// A WebSocket handler registered under the given name, requests that don't open a WebSocket get 426.
+    WebSocket(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Parameter(String),
+    Wildcard,
+}
+
+impl Segment {
+    /// Ranks segments from most to least specific, which is the order routes are tried in.
+    fn rank(&self) -> u8 {
+        match self {
+            Segment::Literal(_) => 0,
+            Segment::Parameter(_) => 1,
+            Segment::Wildcard => 2,
+        }
+    }
+}
+
+/// A path pattern, e.g. `/about`, `/users/:id` or `/static/*`, and what it's answered with.
+pub struct Route {
+    pattern: String,
+    segments: Vec<Segment>,
+    target: RouteTarget,
+    headers: Vec<(String, String)>,
+    compress: Option<bool>,
+    autoindex: bool,
+    methods: Option<Vec<Method>>,
+    access: AccessList,
+}
+
+impl Route {
+    pub fn new(pattern: &str, target: RouteTarget) -> Result<Route, RouteError> {
+        let path = pattern.strip_prefix('/').ok_or_else(|| RouteError::NotAbsolute(pattern.to_string()))?;
+        let parts: Vec<&str> = if path.is_empty() { Vec::new() } else { path.split('/').collect() };
+        let mut segments = Vec::new();
+        
+        for (index, part) in parts.iter().enumerate() {
+            let segment = match (*part, part.strip_prefix(':')) {
+                ("*", _) if index == parts.len() - 1 => Segment::Wildcard,
+                ("*", _) => return Err(RouteError::MisplacedWildcard(pattern.to_string())),
+                (_, Some("")) => return Err(RouteError::UnnamedParameter(pattern.to_string())),
+                (_, Some(name)) => Segment::Parameter(name.to_string()),
+                (literal, None) => Segment::Literal(literal.to_string()),
+            };
+            
+            segments.push(segment);
+        }
+        
+        Ok(Route {
+            pattern: pattern.to_string(),
+            segments,
+            target,
+            headers: Vec::new(),
+            compress: None,
+            autoindex: false,
+            methods: None,
+            access: AccessList::default(),
+        })
+    }
+    
+    pub fn get_pattern(&self) -> &str {
+        &self.pattern
+    }
+    
+    pub fn get_target(&self) -> &RouteTarget {
+        &self.target
+    }
+    
+    /// Returns the headers set on every response for this route, overriding the ones set by pages and the server.
+    pub fn get_headers(&self) -> &Vec<(String, String)> {
+        &self.headers
+    }
+    
+    pub fn add_header(&mut self, name: &str, value: &str) {
+        self.headers.push((name.to_string(), value.to_string()));
+    }
+    
+    /// Returns whether responses for this route are compressed, or `None` to follow the server's settings.
+    pub fn get_compress(&self) -> Option<bool> {
+        self.compress
+    }
+    
+    pub fn set_compress(&mut self, compress: Option<bool>) {
+        self.compress = compress;
+    }
+    
+    /// Returns whether directories without an index file are answered with a listing of their contents.
+    pub fn is_autoindex(&self) -> bool {
+        self.autoindex
+    }
+    
+    pub fn set_autoindex(&mut self, autoindex: bool) {
+        self.autoindex = autoindex;
+    }
+    
+    /// Returns the methods the route answers, or `None` to allow whatever its target supports.
+    pub fn get_methods(&self) -> Option<&Vec<Method>> {
+        self.methods.as_ref()
+    }
+    
+    pub fn set_methods(&mut self, methods: Option<Vec<Method>>) {
+        self.methods = methods;
+    }
+    
+    /// Returns the clients that may request this route, on top of the ones the server lets in.
+    pub fn get_access(&self) -> &AccessList {
+        &self.access
+    }
+    
+    pub fn set_access(&mut self, access: AccessList) {
+        self.access = access;
+    }
+    
+    fn is_exact(&self) -> bool {
+        self.segments.iter().all(|segment| matches!(segment, Segment::Literal(_)))
+    }
+    
+    fn matches(&self, path: &str) -> Option<RouteParams> {
+        let path = path.strip_prefix('/').unwrap_or(path);
+        let mut parts = if path.is_empty() { Vec::new() } else { path.split('/').collect::<Vec<_>>() };
+        let mut params = RouteParams::default();
+        
+        for (index, segment) in self.segments.iter().enumerate() {
+            match segment {
+                // The wildcard takes everything that's left, including nothing at all.
+                Segment::Wildcard => {
+                    params.values.insert("*".to_string(), parts.split_off(index.min(parts.len())).join("/"));
+                    
+                    return Some(params);
+                }
+                Segment::Literal(literal) => {
+                    if parts.get(index) != Some(&literal.as_str()) {
+                        return None;
+                    }
+                }
+                Segment::Parameter(name) => match parts.get(index) {
+                    Some(value) if !value.is_empty() => {
+                        params.values.insert(name.clone(), value.to_string());
+                    }
+                    _ => return None,
+                },
+            }
+        }
+        
+        if parts.len() == self.segments.len() {
+            Some(params)
+        } else {
+            None
+        }
+    }
+}
+
+/// The values a matched route captured from the path, e.g. `id` for `/users/:id` and `*` for a wildcard.
+#[derive(Default, Debug)]
+pub struct RouteParams {
+    values: HashMap<String, String>,
+}
+
+impl RouteParams {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(String::as_str)
+    }
+}
+
+/// Maps request paths to routes.
+///
+/// Exact paths are looked up directly, patterns are kept sorted from most to least specific so the first one that
+/// matches wins, e.g. `/users/me` before `/users/:id` before `/users/*`.
+#[derive(Default)]
+pub struct Router {
+    exact: HashMap<String, Route>,
+    patterns: Vec<Route>,
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router::default()
+    }
+    
+    pub fn add(&mut self, route: Route) -> Result<(), RouteError> {
+        if self.exact.contains_key(&route.pattern) || self.patterns.iter().any(|existing| existing.segments == route.segments) {
+            return Err(RouteError::Duplicate(route.pattern));
+        }
+        
+        if route.is_exact() {
+            self.exact.insert(route.pattern.clone(), route);
+        } else {
+            let ranks = |route: &Route| route.segments.iter().map(Segment::rank).collect::<Vec<_>>();
+            let position = self.patterns.partition_point(|existing| ranks(existing) <= ranks(&route));
+            
+            self.patterns.insert(position, route);
+        }
+        
+        Ok(())
+    }
+    
+    /// Finds the route for a path, which shouldn't include the query string.
+    pub fn find(&self, path: &str) -> Option<(&Route, RouteParams)> {
+        if let Some(route) = self.exact.get(path) {
+            return Some((route, RouteParams::default()));
+        }
+        
+        self.patterns.iter().find_map(|route| route.matches(path).map(|params| (route, params)))
+    }
+    
+    /// Returns every route, exact ones first.
+    pub fn routes(&self) -> impl Iterator<Item = &Route> {
+        self.exact.values().chain(self.patterns.iter())
+    }
+}
@@ -1,332 +1,5237 @@
-use std::fs;
-use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream};
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Write};
+use std::iter;
+use std::net::{IpAddr, Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
 
+use ipnet::IpNet;
 use json::JsonValue;
+use log::{debug, error, info, LevelFilter};
 use rayon::{ThreadPool, ThreadPoolBuilder};
+use socket2::{Domain, Protocol, Socket, Type};
 
-use crate::http::{Request, Response};
+use crate::access_log::{AccessLogFormat, CombinedLogger, NdjsonLogger, SampledLogger};
+use crate::auth::{self, BasicAuth};
+use crate::autoindex;
+use crate::compression::{self, CompressionConfig, CompressionRule, Encoding};
+use crate::conditional;
+use crate::config::{ConfigError, ConfigFormat};
+use crate::context::ConnectionContext;
+use crate::error::ServerError;
+use crate::fastcgi::{self, FastCgi, FastCgiAddress};
+use crate::file_cache::{self, FileCache};
+use crate::filter::{BodyFilter, HtmlRewritingFilter};
+use crate::head_cache::{CacheStatus, HeadCache};
+use crate::hook::ResponseHook;
+use crate::http::{self, BodyReader, HttpParseError, HttpVersion, Method, Request, Response};
+use crate::jwt::{self, JwtKey, JwtValidator};
+use crate::kv::{self, KvStore};
+use crate::listener::Listener;
+use crate::logging::{self, ErrorLog, LogFilter, LogRotation, LogWriter};
+use crate::middleware::Middleware;
+use crate::mime::MimeTypes;
+use crate::network::{self, AccessList};
+use crate::openapi::{self, ApiRoute, OpenApiConfig, RouteMetadata};
+use crate::proxy::{self, BalanceStrategy, CircuitBreaker, HealthCheck, Proxy, ProxyError, Upstream, UpstreamStatus};
+use crate::range::{self, RangeRequest};
+use crate::rate_limit::{ConnectionLimiter, RateLimiter, RateLimiterAlgorithm, SlidingWindowRateLimiter, TokenBucketRateLimiter};
+use crate::robots::RobotsConfig;
+use crate::router::{Handler, Route, RouteError, RouteParams, RouteTarget, Router};
+use crate::shutdown::{self, ConnectionTracker, ShutdownSignal};
+use crate::status::StatusCode;
+use crate::template::{CompiledTemplate, RenderError, TemplateContext};
+use crate::tls::{self, ClientStream};
+use crate::tunnel;
+use crate::upgrade::{self, UpgradeHandler};
+use crate::websocket::{self, HandshakeError, WebSocket, WebSocketHandler};
+
+/// The default maximum request body size, in bytes.
+const DEFAULT_MAX_BODY_SIZE: usize = 1_048_576;
+
+/// A 1x1 fully transparent icon, served for /favicon.ico when the site doesn't provide one.
+const TRANSPARENT_FAVICON: [u8; 70] = [
+    // Icon directory with a single entry.
+    0x00, 0x00, 0x01, 0x00, 0x01, 0x00,
+    0x01, 0x01, 0x00, 0x00, 0x01, 0x00, 0x20, 0x00, 0x30, 0x00, 0x00, 0x00, 0x16, 0x00, 0x00, 0x00,
+    // Bitmap header, 1x1 pixels at 32 bits per pixel (the height counts the XOR and AND masks).
+    0x28, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01, 0x00, 0x20, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // A single transparent pixel followed by the AND mask, padded to 4 bytes.
+    0x00, 0x00, 0x00, 0x00,
+    0x80, 0x00, 0x00, 0x00,
+];
+
+/// The configuration values left out of `Server::dump_config`, by their path like in configuration errors, where `[*]`
+/// stands for every item of an array.
+const SENSITIVE_CONFIG_FIELDS: [&str; 4] = ["auth", "jwt", "tls.key_path", "listeners[*].tls.key_path"];
+
+/// The default maximum length of the request URL, including the query string, in bytes.
+const DEFAULT_MAX_URL_LENGTH: usize = 8_192;
+
+/// The default maximum size of the request line and headers, in bytes.
+const DEFAULT_MAX_HEADER_BYTES: usize = 8_192;
+
+/// The default number of seconds an idle connection is kept open, waiting for the next request.
+const DEFAULT_KEEP_ALIVE_TIMEOUT_SECS: u64 = 5;
+
+/// The default number of requests served on a single connection before it's closed.
+const DEFAULT_MAX_KEEP_ALIVE_REQUESTS: usize = 100;
+
+/// The files a request for a directory is answered with by default, the first one that exists wins.
+const DEFAULT_INDEX_FILES: [&str; 2] = ["index.html", "index.htm"];
+
+/// The path the server's metrics are exported under in the Prometheus text format, if signing in is required for it.
+pub const METRICS_PATH: &str = "/metrics";
+
+/// The default value of the Server header, the crate name and version.
+const DEFAULT_SERVER_BANNER: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// The default size of the access log's write buffer, in bytes.
+const DEFAULT_ACCESS_LOG_BUFFER_BYTES: usize = 65_536;
+
+/// The default number of rotated log files kept around.
+const DEFAULT_LOG_ROTATION_MAX_FILES: usize = 7;
+
+/// The methods static files and pages answer, anything else has no meaning for them.
+const STATIC_METHODS: [Method; 3] = [Method::Get, Method::Head, Method::Options];
+
+/// The environment variable that overrides the `log_level` configuration, e.g. `WEB_SERVER_LOG=debug`.
+pub const LOG_LEVEL_ENV_VAR: &str = "WEB_SERVER_LOG";
+
+/// How often a watched configuration file is checked for changes.
+const CONFIG_WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long a refused client gets to finish the TLS handshake and read the refusal, before it's disconnected.
+const REFUSAL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How much of a refused client's request is read and discarded, so closing the connection doesn't reset it before the
+/// client has read the refusal.
+const MAX_REFUSAL_DRAIN_BYTES: u64 = 64 * 1_024;
+
+/// The default number of connections the OS may queue before they're accepted.
+const DEFAULT_TCP_BACKLOG: u32 = 1_024;
+
+/// The top-level configuration keys the server reads, in the order they're loaded.
+///
+/// Other keys are left alone, since applications can read their own settings through `Server::get_config`, unless
+/// they're a likely typo of one of these.
+const CONFIG_KEYS: [&str; 59] = [
+    "verbose", "log_level", "log_file", "log_stderr", "shutdown_grace_period_secs", "watch_config", "thread_count",
+    "port", "bind_address", "force_dual_stack", "tcp_backlog", "tcp_recv_buffer_bytes", "tcp_send_buffer_bytes",
+    "web_root", "max_body_size", "max_url_length", "max_header_bytes", "keep_alive_timeout_secs",
+    "max_keep_alive_requests", "max_connections_per_ip", "deny_unlisted", "connect_allowlist", "connect_allow",
+    "connect_deny", "error_log", "log_rotation", "problem_types", "error_pages", "tls", "enable_cache_busting",
+    "access_log", "access_log_format", "access_log_buffer_bytes", "log_sample_rate", "simulate_latency", "robots_txt",
+    "favicon", "mime_types", "compression", "serve_precompressed", "file_cache", "head_cache_ttl_secs", "server_banner",
+    "index_files", "disabled_methods", "well_known_dir", "dump_resolved_config_to", "rate_limit", "routes", "pages",
+    "listeners", "vhosts", "vhost_fallback", "allow", "deny", "trusted_proxies", "auth", "jwt", "openapi",
+];
+
+/// The keys of the objects nested in the configuration, where any other key is reported.
+const PAGE_KEYS: [&str; 4] = ["name", "path", "template", "headers"];
+const ROUTE_KEYS: [&str; 12] = [
+    "path", "file", "handler", "proxy_pass", "proxy", "fastcgi", "headers", "compress", "methods", "autoindex", "allow", "deny",
+];
+const PROXY_KEYS: [&str; 6] = ["upstreams", "strategy", "timeout_secs", "failure_threshold", "open_duration_secs", "health_check"];
+const AUTH_KEYS: [&str; 3] = ["realm", "credentials", "htpasswd"];
+const JWT_KEYS: [&str; 7] = ["issuer", "audience", "secret", "public_key_path", "jwks_url", "jwks_refresh_secs", "leeway_secs"];
+const FASTCGI_KEYS: [&str; 4] = ["address", "script", "timeout_secs", "params"];
+const OPENAPI_KEYS: [&str; 3] = ["title", "version", "openapi_json_path"];
+const HEALTH_CHECK_KEYS: [&str; 4] = ["path", "interval_secs", "timeout_secs", "expected_status"];
+const LISTENER_KEYS: [&str; 5] = ["name", "port", "bind_address", "force_dual_stack", "tls"];
+const VHOST_KEYS: [&str; 4] = ["web_root", "error_pages", "routes", "pages"];
+const TLS_KEYS: [&str; 3] = ["enabled", "cert_path", "key_path"];
+const COMPRESSION_KEYS: [&str; 3] = ["enabled", "min_size_bytes", "rules"];
+const COMPRESSION_RULE_KEYS: [&str; 3] = ["content_type_prefix", "min_size_bytes", "never"];
+const FILE_CACHE_KEYS: [&str; 2] = ["max_file_size_bytes", "max_size_bytes"];
+const LOG_ROTATION_KEYS: [&str; 3] = ["max_size_mb", "daily", "max_files"];
+const RATE_LIMIT_KEYS: [&str; 3] = ["algorithm", "window_secs", "max_requests"];
+const SIMULATE_LATENCY_KEYS: [&str; 3] = ["min_ms", "max_ms", "paths"];
 
 pub struct Server {
     verbose: bool,
+    log_filter: LogFilter,
+    log_file: Option<String>,
+    log_stderr: bool,
+    shutdown_signal: Arc<ShutdownSignal>,
     thread_count: u16,
     thread_pool: ThreadPool,
-    port: u16,
-    web_root: String,
-    pages: Vec<Page>,
+    listeners: Vec<Arc<Listener>>,
+    tcp_backlog: u32,
+    tcp_recv_buffer_bytes: Option<usize>,
+    tcp_send_buffer_bytes: Option<usize>,
+    max_body_size: usize,
+    max_url_length: usize,
+    max_header_bytes: usize,
+    keep_alive_timeout_secs: u64,
+    max_keep_alive_requests: usize,
+    connection_limiter: Option<ConnectionLimiter>,
+    deny_unlisted: bool,
+    connect_allowlist: Option<Vec<String>>,
+    connect_access: AccessList,
+    access: AccessList,
+    trusted_proxies: Vec<IpNet>,
+    error_log: Arc<ErrorLog>,
+    panics_total: Arc<AtomicU64>,
+    problem_types: HashMap<u16, String>,
+    robots_txt: Option<RobotsConfig>,
+    favicon: Option<String>,
+    mime_types: MimeTypes,
+    compression: CompressionConfig,
+    serve_precompressed: bool,
+    file_cache: FileCache,
+    index_files: Vec<String>,
+    server_banner: Option<String>,
+    disabled_methods: HashSet<Method>,
+    rate_limiter: Option<Box<dyn RateLimiter + Send + Sync>>,
+    auth: Vec<BasicAuth>,
+    jwt: Vec<JwtValidator>,
+    openapi: Option<OpenApiConfig>,
+    well_known_dir: Option<String>,
+    dump_resolved_config_to: Option<String>,
+    sites: RwLock<Arc<Sites>>,
     config: JsonValue,
+    config_path: Option<PathBuf>,
+    config_overrides: JsonValue,
+    watch_config: bool,
+    response_hooks: Vec<Box<dyn ResponseHook + Send + Sync>>,
+    body_filters: Vec<Box<dyn BodyFilter + Send + Sync>>,
+    middleware: Vec<Box<dyn Middleware + Send + Sync>>,
+    upgrade_handlers: Vec<Box<dyn UpgradeHandler + Send + Sync>>,
+    code_routes: Vec<(String, RouteTarget)>,
+    route_metadata: HashMap<String, RouteMetadata>,
+    handlers: HashMap<String, Box<dyn Handler + Send + Sync>>,
+    websocket_handlers: HashMap<String, Box<dyn WebSocketHandler + Send + Sync>>,
+    kv_store: Arc<KvStore>,
+    head_cache: Arc<RwLock<HeadCache>>,
 }
 
 impl Server {
-    pub(crate) fn new(config: &JsonValue) -> Server {
+    pub fn new(config: &JsonValue) -> Result<Server, ConfigError> {
         
         // Load the config and return a new server instance.
-        Self::load_cfg(config)
+        Self::load_cfg(config, true)
+    }
+    
+    /// Starts building a server in code, e.g. `Server::builder().route("/api/time", |request| ...).build()`.
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::new()
+    }
+    
+    /// Reads, parses and validates a configuration file and creates a server from it.
+    pub fn from_config_file(path: &Path) -> Result<Server, ConfigError> {
+        Self::from_config_file_with_overrides(path, JsonValue::new_object())
     }
     
-    fn load_cfg(config: &JsonValue) -> Server {
+    /// Like `from_config_file`, but the top-level keys of `overrides` replace the ones in the file, e.g. to let
+    /// command-line flags win over the configuration. Listener settings like `port` replace the ones of the listener
+    /// when the file has a single one. The overrides are applied again whenever the file is reloaded.
+    pub fn from_config_file_with_overrides(path: &Path, overrides: JsonValue) -> Result<Server, ConfigError> {
+        let mut config = read_config_file(path)?;
+        apply_overrides(&mut config, &overrides)?;
         
-        // Get the verbose flag.
-        let verbose = config["verbose"].as_bool();
+        let mut server = Self::new(&config)?;
         
-        // Check if the verbose flag is valid.
-        if verbose.is_none() {
-            panic!("Invalid verbose flag, must be a boolean!");
-        }
+        // Remember where the configuration came from, so it can be reloaded.
+        server.config_path = Some(path.to_path_buf());
+        server.config_overrides = overrides;
         
-        let verbose = verbose.unwrap();
+        Ok(server)
+    }
+    
+    /// Checks that a configuration file, with the given overrides applied, would start a server, without binding any
+    /// sockets.
+    ///
+    /// Besides the values themselves, the web root, page and error page files, log files and TLS certificate and key
+    /// are opened, and every problem is reported together. Unlike starting the server, a missing web root or page file
+    /// is an error instead of being created.
+    pub fn check_config_file(path: &Path, overrides: &JsonValue) -> Result<(), ConfigError> {
+        let mut config = read_config_file(path)?;
+        apply_overrides(&mut config, overrides)?;
         
-        // Get the thread count.
-        let thread_count = config["thread_count"].as_u16();
+        Self::load_cfg(&config, false).map(|_| ())
+    }
+    
+    /// Checks every value of a configuration, reporting all the invalid ones at once.
+    fn parse_config(config: &JsonValue) -> Result<Config<'_>, ConfigError> {
+        // Every invalid value is collected, so all of them can be reported at once.
+        let mut errors = Vec::new();
         
-        // Check if the thread count is valid.
-        if thread_count.is_none() || thread_count.unwrap() < 1 {
-            panic!("Invalid thread count, must be a number greater than 0!");
+        // Report keys that are a typo of a setting, which would otherwise silently fall back to its default.
+        for (key, _) in config.entries() {
+            if !CONFIG_KEYS.contains(&key) {
+                if let error @ ConfigError::UnknownField { suggestion: Some(_), .. } = ConfigError::unknown("", key, &CONFIG_KEYS) {
+                    errors.push(error);
+                }
+            }
         }
         
-        let thread_count = thread_count.unwrap();
+        // Get the verbose flag.
+        let verbose = match config["verbose"].as_bool() {
+            Some(verbose) => verbose,
+            None => {
+                errors.push(ConfigError::invalid("verbose", "must be a boolean").with_value(&config["verbose"]));
+                
+                false
+            }
+        };
+        
+        // Get which messages are logged, the environment variable wins over the configuration so a running setup can be
+        // debugged without editing it. Verbose configurations log the details of every request by default.
+        let log_filter = match env::var(LOG_LEVEL_ENV_VAR) {
+            Ok(spec) => match LogFilter::parse(&spec) {
+                Some(log_filter) => log_filter,
+                None => {
+                    errors.push(ConfigError::invalid(LOG_LEVEL_ENV_VAR, "must be a level or a list of target=level pairs, e.g. info,web_server::tunnel=debug").with_value(&spec.as_str().into()));
+                    
+                    LogFilter::new(LevelFilter::Info)
+                }
+            },
+            Err(_) if config["log_level"].is_null() => LogFilter::new(if verbose { LevelFilter::Debug } else { LevelFilter::Warn }),
+            Err(_) => match config["log_level"].as_str().and_then(LogFilter::parse) {
+                Some(log_filter) => log_filter,
+                None => {
+                    errors.push(ConfigError::invalid("log_level", "must be a level or a list of target=level pairs, e.g. info,web_server::tunnel=debug").with_value(&config["log_level"]));
+                    
+                    LogFilter::new(LevelFilter::Info)
+                }
+            },
+        };
+        
+        // Get the file log messages are written to, besides stderr.
+        let log_file = if config["log_file"].is_null() {
+            None
+        } else {
+            match config["log_file"].as_str() {
+                Some(path) => Some(path),
+                None => {
+                    errors.push(ConfigError::invalid("log_file", "must be a string").with_value(&config["log_file"]));
+                    
+                    None
+                }
+            }
+        };
+        
+        // Get whether log messages are written to stderr.
+        let log_stderr = if config["log_stderr"].is_null() {
+            true
+        } else {
+            match config["log_stderr"].as_bool() {
+                Some(log_stderr) => log_stderr,
+                None => {
+                    errors.push(ConfigError::invalid("log_stderr", "must be a boolean").with_value(&config["log_stderr"]));
+                    
+                    true
+                }
+            }
+        };
+        
+        // Get how long in-flight connections may take to finish once a shutdown is requested.
+        let shutdown_grace_period_secs = if config["shutdown_grace_period_secs"].is_null() {
+            shutdown::DEFAULT_GRACE_PERIOD_SECS
+        } else {
+            match config["shutdown_grace_period_secs"].as_u64() {
+                Some(shutdown_grace_period_secs) => shutdown_grace_period_secs,
+                None => {
+                    errors.push(ConfigError::invalid("shutdown_grace_period_secs", "must be a non-negative number").with_value(&config["shutdown_grace_period_secs"]));
+                    
+                    shutdown::DEFAULT_GRACE_PERIOD_SECS
+                }
+            }
+        };
+        
+        // Get whether the configuration file is reloaded when it changes.
+        let watch_config = if config["watch_config"].is_null() {
+            false
+        } else {
+            match config["watch_config"].as_bool() {
+                Some(watch_config) => watch_config,
+                None => {
+                    errors.push(ConfigError::invalid("watch_config", "must be a boolean").with_value(&config["watch_config"]));
+                    
+                    false
+                }
+            }
+        };
+        
+        // Get the thread count.
+        let thread_count = match config["thread_count"].as_u16() {
+            Some(thread_count) if thread_count >= 1 => thread_count,
+            _ => {
+                errors.push(ConfigError::invalid("thread_count", "must be a number greater than 0").with_value(&config["thread_count"]));
+                
+                1
+            }
+        };
         
-        // Create a new thread pool.
-        let thread_pool = ThreadPoolBuilder::new().num_threads(thread_count as usize).build().unwrap();
+        // Get the addresses to listen on, either from the listeners array or a single one from the top-level settings.
+        let listener_settings = if config["listeners"].is_null() {
+            vec![load_listener(config, "", &mut errors)]
+        } else if !config["listeners"].is_array() || config["listeners"].is_empty() {
+            errors.push(ConfigError::invalid("listeners", "must be a non-empty array of listener objects").with_value(&config["listeners"]));
+            
+            Vec::new()
+        } else {
+            // The top-level settings would be silently ignored, so they may only be set on each listener.
+            for key in LISTENER_KEYS.iter().filter(|key| **key != "name") {
+                if !config[*key].is_null() {
+                    errors.push(ConfigError::invalid(key, "must be set on each listener when listeners is used").with_value(&config[*key]));
+                }
+            }
+            
+            config["listeners"].members().enumerate()
+                .map(|(index, listener)| load_listener(listener, &format!("listeners[{}]", index), &mut errors))
+                .collect()
+        };
         
-        // Get the port number.
-        let port = config["port"].as_u16();
+        // Connections are tagged with the name of their listener, so the names have to tell them apart.
+        let mut listener_names = HashSet::new();
         
-        // Check if the port is valid.
-        if port.is_none() || port.unwrap() < 1_024 || port.unwrap() == 65_535 {
-            panic!("Invalid port, must be a number between 1.024 and 65.535!");
+        for settings in &listener_settings {
+            if !listener_names.insert(settings.name.as_str()) {
+                errors.push(ConfigError::invalid("listeners", "must have unique names").with_value(&settings.name.as_str().into()));
+            }
         }
         
-        let port = port.unwrap();
+        // Get the TCP backlog size.
+        let tcp_backlog = if config["tcp_backlog"].is_null() {
+            DEFAULT_TCP_BACKLOG
+        } else {
+            match config["tcp_backlog"].as_u32() {
+                Some(tcp_backlog) if tcp_backlog > 0 && tcp_backlog <= i32::MAX as u32 => tcp_backlog,
+                _ => {
+                    errors.push(ConfigError::invalid("tcp_backlog", "must be a number greater than 0").with_value(&config["tcp_backlog"]));
+                    
+                    DEFAULT_TCP_BACKLOG
+                }
+            }
+        };
         
-        // If the web_root is not specified, use the default value.
-        let web_root = config["web_root"].as_str();
+        // Get the socket buffer sizes, the OS defaults are used for the ones that aren't specified.
+        let mut buffer_sizes = Vec::new();
         
-        if web_root.is_none() {
-            panic!("Invalid web_root, must be a string!");
+        for field in ["tcp_recv_buffer_bytes", "tcp_send_buffer_bytes"] {
+            if config[field].is_null() {
+                buffer_sizes.push(None);
+                
+                continue;
+            }
+            
+            match config[field].as_usize() {
+                Some(size) if size > 0 => buffer_sizes.push(Some(size)),
+                _ => {
+                    errors.push(ConfigError::invalid(field, "must be a number greater than 0").with_value(&config[field]));
+                    buffer_sizes.push(None);
+                }
+            }
         }
         
-        let web_root = web_root.unwrap();
+        let (tcp_recv_buffer_bytes, tcp_send_buffer_bytes) = (buffer_sizes[0], buffer_sizes[1]);
         
-        // Check if the web_root directory exists.
-        if !fs::metadata(web_root).is_ok() {
-            // Create the web_root directory.
-            match fs::create_dir(web_root) {
-                Ok(_) => {
-                    if verbose {
-                        println!("Created web root directory: {}", web_root)
+        // Get the maximum body size, falling back to the default if it's not specified.
+        let max_body_size = if config["max_body_size"].is_null() {
+            DEFAULT_MAX_BODY_SIZE
+        } else {
+            match config["max_body_size"].as_usize() {
+                Some(max_body_size) => max_body_size,
+                None => {
+                    errors.push(ConfigError::invalid("max_body_size", "must be a positive number").with_value(&config["max_body_size"]));
+                    
+                    DEFAULT_MAX_BODY_SIZE
+                }
+            }
+        };
+        
+        // Get the maximum URL length, falling back to the default if it's not specified.
+        let max_url_length = if config["max_url_length"].is_null() {
+            DEFAULT_MAX_URL_LENGTH
+        } else {
+            match config["max_url_length"].as_usize() {
+                Some(max_url_length) if max_url_length > 0 => max_url_length,
+                _ => {
+                    errors.push(ConfigError::invalid("max_url_length", "must be a number greater than 0").with_value(&config["max_url_length"]));
+                    
+                    DEFAULT_MAX_URL_LENGTH
+                }
+            }
+        };
+        
+        // Get the maximum size of the request line and headers, falling back to the default if it's not specified.
+        let max_header_bytes = if config["max_header_bytes"].is_null() {
+            DEFAULT_MAX_HEADER_BYTES
+        } else {
+            match config["max_header_bytes"].as_usize() {
+                Some(max_header_bytes) if max_header_bytes > 0 => max_header_bytes,
+                _ => {
+                    errors.push(ConfigError::invalid("max_header_bytes", "must be a number greater than 0").with_value(&config["max_header_bytes"]));
+                    
+                    DEFAULT_MAX_HEADER_BYTES
+                }
+            }
+        };
+        
+        // Get how long an idle connection is kept open, falling back to the default if it's not specified.
+        let keep_alive_timeout_secs = if config["keep_alive_timeout_secs"].is_null() {
+            DEFAULT_KEEP_ALIVE_TIMEOUT_SECS
+        } else {
+            match config["keep_alive_timeout_secs"].as_u64() {
+                Some(keep_alive_timeout_secs) if keep_alive_timeout_secs > 0 => keep_alive_timeout_secs,
+                _ => {
+                    errors.push(ConfigError::invalid("keep_alive_timeout_secs", "must be a number greater than 0").with_value(&config["keep_alive_timeout_secs"]));
+                    
+                    DEFAULT_KEEP_ALIVE_TIMEOUT_SECS
+                }
+            }
+        };
+        
+        // Get how many requests a connection may serve, 1 turns keep-alive off.
+        let max_keep_alive_requests = if config["max_keep_alive_requests"].is_null() {
+            DEFAULT_MAX_KEEP_ALIVE_REQUESTS
+        } else {
+            match config["max_keep_alive_requests"].as_usize() {
+                Some(max_keep_alive_requests) if max_keep_alive_requests > 0 => max_keep_alive_requests,
+                _ => {
+                    errors.push(ConfigError::invalid("max_keep_alive_requests", "must be a number greater than 0").with_value(&config["max_keep_alive_requests"]));
+                    
+                    DEFAULT_MAX_KEEP_ALIVE_REQUESTS
+                }
+            }
+        };
+        
+        // Get the maximum number of open connections per client, which is unlimited if it's not specified.
+        let connection_limiter = if config["max_connections_per_ip"].is_null() {
+            None
+        } else {
+            match config["max_connections_per_ip"].as_usize() {
+                Some(max_connections) if max_connections > 0 => Some(ConnectionLimiter::new(max_connections)),
+                _ => {
+                    errors.push(ConfigError::invalid("max_connections_per_ip", "must be a number greater than 0").with_value(&config["max_connections_per_ip"]));
+                    
+                    None
+                }
+            }
+        };
+        
+        // Get the deny-unlisted flag, files in the web root that aren't a page or route are answered with 404 if it's set.
+        let deny_unlisted = if config["deny_unlisted"].is_null() {
+            false
+        } else {
+            match config["deny_unlisted"].as_bool() {
+                Some(deny_unlisted) => deny_unlisted,
+                None => {
+                    errors.push(ConfigError::invalid("deny_unlisted", "must be a boolean").with_value(&config["deny_unlisted"]));
+                    
+                    false
+                }
+            }
+        };
+        
+        // Get the CONNECT allowlist, tunneling stays disabled if it's not specified.
+        let connect_allowlist = if config["connect_allowlist"].is_null() {
+            None
+        } else if !config["connect_allowlist"].is_array() {
+            errors.push(ConfigError::invalid("connect_allowlist", "must be an array of host:port strings").with_value(&config["connect_allowlist"]));
+            
+            None
+        } else {
+            let mut connect_allowlist = Vec::new();
+            
+            for (index, target) in config["connect_allowlist"].members().enumerate() {
+                match target.as_str() {
+                    Some(target) => connect_allowlist.push(target.to_string()),
+                    None => errors.push(ConfigError::invalid(&format!("connect_allowlist[{}]", index), "must be a host:port string").with_value(target)),
+                }
+            }
+            
+            Some(connect_allowlist)
+        };
+        
+        // Get the address ranges CONNECT targets may resolve to, only public addresses are reachable if neither is specified.
+        let connect_allow = load_cidr_list(config, "connect_allow", "connect_allow", &mut errors);
+        let connect_deny = load_cidr_list(config, "connect_deny", "connect_deny", &mut errors);
+        let connect_access = AccessList::new(connect_allow, connect_deny);
+        
+        // Get the address ranges clients may connect from, every client is let in if neither list is specified.
+        let allow = load_cidr_list(config, "allow", "allow", &mut errors);
+        let deny = load_cidr_list(config, "deny", "deny", &mut errors);
+        let access = AccessList::new(allow, deny);
+        
+        // Get the proxies whose X-Forwarded-For header is trusted to name the client, the peer is the client otherwise.
+        let trusted_proxies = load_cidr_list(config, "trusted_proxies", "trusted_proxies", &mut errors);
+        
+        // Get the error log path, errors are written to stderr if it's not specified.
+        let error_log_path = if config["error_log"].is_null() {
+            None
+        } else {
+            match config["error_log"].as_str() {
+                Some(path) => Some(path),
+                None => {
+                    errors.push(ConfigError::invalid("error_log", "must be a string").with_value(&config["error_log"]));
+                    
+                    None
+                }
+            }
+        };
+        
+        // Get the rotation policy shared by the access and error logs, log files grow forever if it's not specified.
+        let log_rotation = if config["log_rotation"].is_null() {
+            None
+        } else {
+            check_keys(&config["log_rotation"], "log_rotation", &LOG_ROTATION_KEYS, &mut errors);
+            
+            match load_log_rotation(&config["log_rotation"]) {
+                Ok(log_rotation) => Some(log_rotation),
+                Err(error) => {
+                    errors.push(error);
+                    
+                    None
+                }
+            }
+        };
+        
+        // Get the problem type URIs used in JSON error responses, keyed by status code.
+        let mut problem_types = HashMap::new();
+        
+        if !config["problem_types"].is_null() && !config["problem_types"].is_object() {
+            errors.push(ConfigError::invalid("problem_types", "must be an object mapping status codes to URIs").with_value(&config["problem_types"]));
+        } else {
+            for (status_code, uri) in config["problem_types"].entries() {
+                let status_code = match status_code.parse::<u16>() {
+                    Ok(status_code) if (400..600).contains(&status_code) => status_code,
+                    _ => {
+                        errors.push(ConfigError::invalid(&format!("problem_types.{}", status_code), "must be keyed by an error status code between 400 and 599"));
+                        
+                        continue;
+                    }
+                };
+                
+                match uri.as_str() {
+                    Some(uri) => {
+                        problem_types.insert(status_code, uri.to_string());
                     }
+                    None => errors.push(ConfigError::invalid(&format!("problem_types.{}", status_code), "must be a URI string").with_value(uri)),
                 }
-                Err(_) => panic!("Failed to create web root directory: {}", web_root),
             }
         }
         
-        // Get the pages array.
-        let pages_from_file = config["pages"].members();
+        // Get the cache busting flag.
+        let enable_cache_busting = if config["enable_cache_busting"].is_null() {
+            false
+        } else {
+            match config["enable_cache_busting"].as_bool() {
+                Some(enable_cache_busting) => enable_cache_busting,
+                None => {
+                    errors.push(ConfigError::invalid("enable_cache_busting", "must be a boolean").with_value(&config["enable_cache_busting"]));
+                    
+                    false
+                }
+            }
+        };
+        
+        // Get the access log path, requests aren't logged if it's not specified.
+        let access_log = if config["access_log"].is_null() {
+            None
+        } else {
+            match config["access_log"].as_str() {
+                Some(path) => Some(path),
+                None => {
+                    errors.push(ConfigError::invalid("access_log", "must be a file path or - for stdout").with_value(&config["access_log"]));
+                    
+                    None
+                }
+            }
+        };
+        
+        // Get the access log format.
+        let access_log_format = if config["access_log_format"].is_null() {
+            AccessLogFormat::Combined
+        } else {
+            match config["access_log_format"].as_str().and_then(AccessLogFormat::parse) {
+                Some(format) => format,
+                None => {
+                    errors.push(ConfigError::invalid("access_log_format", "must be one of common, combined or ndjson").with_value(&config["access_log_format"]));
+                    
+                    AccessLogFormat::Combined
+                }
+            }
+        };
         
-        // Make sure the pages array is not empty.
-        if pages_from_file.len() == 0 {
-            if verbose {
-                println!("No pages found, creating an index.html file...");
+        // Get the size of the access log's write buffer, 0 writes every entry straight away.
+        let access_log_buffer_bytes = if config["access_log_buffer_bytes"].is_null() {
+            DEFAULT_ACCESS_LOG_BUFFER_BYTES
+        } else {
+            match config["access_log_buffer_bytes"].as_usize() {
+                Some(access_log_buffer_bytes) => access_log_buffer_bytes,
+                None => {
+                    errors.push(ConfigError::invalid("access_log_buffer_bytes", "must be a non-negative number").with_value(&config["access_log_buffer_bytes"]));
+                    
+                    DEFAULT_ACCESS_LOG_BUFFER_BYTES
+                }
+            }
+        };
+        
+        // Get the fraction of requests written to the access log, errors are always logged.
+        let log_sample_rate = if config["log_sample_rate"].is_null() {
+            1.0
+        } else {
+            match config["log_sample_rate"].as_f64() {
+                Some(log_sample_rate) if (0.0..=1.0).contains(&log_sample_rate) => log_sample_rate,
+                _ => {
+                    errors.push(ConfigError::invalid("log_sample_rate", "must be a number between 0.0 and 1.0").with_value(&config["log_sample_rate"]));
+                    
+                    1.0
+                }
             }
+        };
+        
+        let mut middleware: Vec<Box<dyn Middleware + Send + Sync>> = Vec::new();
+        
+        // Simulated latency is only available in development builds, so it can't be enabled in production by accident.
+        if !config["simulate_latency"].is_null() {
+            check_keys(&config["simulate_latency"], "simulate_latency", &SIMULATE_LATENCY_KEYS, &mut errors);
             
-            // Create the file.
-            let page = create_file(format!("{}/{}", web_root, "index.html"), verbose);
-            
-            // Return a new server instance.
-            return Server {
-                verbose,
-                thread_count,
-                thread_pool,
-                port,
-                web_root: web_root.to_string(),
-                pages: vec!(page),
-                config: config.clone(),
-            };
+            match load_delay_middleware(&config["simulate_latency"]) {
+                Ok(delay) => middleware.push(delay),
+                Err(error) => errors.push(error),
+            }
         }
         
-        let mut pages: Vec<Page> = Vec::new();
+        // Get the robots.txt configuration.
+        let robots_txt = if config["robots_txt"].is_null() {
+            None
+        } else {
+            match RobotsConfig::from_json(&config["robots_txt"]) {
+                Ok(robots_txt) => Some(robots_txt),
+                Err(error) => {
+                    errors.push(error);
+                    
+                    None
+                }
+            }
+        };
+        
+        // Get the favicon path, relative to the web root.
+        let favicon = if config["favicon"].is_null() {
+            None
+        } else {
+            match config["favicon"].as_str() {
+                Some(favicon) => Some(favicon.to_string()),
+                None => {
+                    errors.push(ConfigError::invalid("favicon", "must be a path relative to the web root").with_value(&config["favicon"]));
+                    
+                    None
+                }
+            }
+        };
+        
+        // Get the content types that override the built-in ones, keyed by file extension.
+        let mut mime_types = MimeTypes::new();
+        
+        if !config["mime_types"].is_null() && !config["mime_types"].is_object() {
+            errors.push(ConfigError::invalid("mime_types", "must be an object mapping file extensions to content types").with_value(&config["mime_types"]));
+        } else {
+            for (extension, mime_type) in config["mime_types"].entries() {
+                match mime_type.as_str() {
+                    Some(mime_type) => mime_types.add_override(extension, mime_type),
+                    None => errors.push(ConfigError::invalid(&format!("mime_types.{}", extension), "must be a content type string").with_value(mime_type)),
+                }
+            }
+        }
         
-        // Iterate over the pages from the config file.
-        for page in pages_from_file {
+        // Get the compression settings, text responses above the size threshold are compressed by default.
+        let compression = if config["compression"].is_null() {
+            CompressionConfig::default()
+        } else if !config["compression"].is_object() {
+            errors.push(ConfigError::invalid("compression", "must be an object with enabled, min_size_bytes and rules").with_value(&config["compression"]));
             
-            // Get the page name.
-            let name = page["name"].as_str();
+            CompressionConfig::default()
+        } else {
+            let compression = &config["compression"];
+            check_keys(compression, "compression", &COMPRESSION_KEYS, &mut errors);
             
-            // Check if the page name is valid.
-            if name.is_none() {
-                panic!("Invalid page name, must be a string!");
-            }
+            let enabled = if compression["enabled"].is_null() {
+                true
+            } else {
+                match compression["enabled"].as_bool() {
+                    Some(enabled) => enabled,
+                    None => {
+                        errors.push(ConfigError::invalid("compression.enabled", "must be a boolean").with_value(&compression["enabled"]));
+                        
+                        false
+                    }
+                }
+            };
             
-            let name = name.unwrap();
+            let min_size_bytes = if compression["min_size_bytes"].is_null() {
+                compression::DEFAULT_MIN_SIZE_BYTES
+            } else {
+                match compression["min_size_bytes"].as_usize() {
+                    Some(min_size_bytes) => min_size_bytes,
+                    None => {
+                        errors.push(ConfigError::invalid("compression.min_size_bytes", "must be a number").with_value(&compression["min_size_bytes"]));
+                        
+                        compression::DEFAULT_MIN_SIZE_BYTES
+                    }
+                }
+            };
             
-            // Get the page path.
-            let path = page["path"].as_str();
+            // Rules refine the decision by content type, the first one whose prefix matches wins.
+            let mut rules = Vec::new();
             
-            // Check if the page path is valid.
-            if path.is_none() {
-                panic!("Invalid page path, must be a string!");
+            if !compression["rules"].is_null() && !compression["rules"].is_array() {
+                errors.push(ConfigError::invalid("compression.rules", "must be an array of rule objects").with_value(&compression["rules"]));
             }
             
-            let path = path.unwrap();
-            
-            // Make sure the file exists.
-            if fs::metadata(format!("{}/{}", web_root, path)).is_err() {
-                // Create the file.
-                let page = create_file(format!("{}/{}", web_root, path), verbose);
+            for (index, rule) in compression["rules"].members().enumerate() {
+                check_keys(rule, &format!("compression.rules[{}]", index), &COMPRESSION_RULE_KEYS, &mut errors);
+                
+                let content_type_prefix = match rule["content_type_prefix"].as_str() {
+                    Some(content_type_prefix) => content_type_prefix,
+                    None => {
+                        errors.push(ConfigError::invalid(&format!("compression.rules[{}].content_type_prefix", index), "must be a string").with_value(&rule["content_type_prefix"]));
+                        
+                        continue;
+                    }
+                };
                 
-                // Add the page to the pages vector.
-                pages.push(page);
+                let min_size_bytes = if rule["min_size_bytes"].is_null() {
+                    None
+                } else {
+                    match rule["min_size_bytes"].as_usize() {
+                        Some(min_size_bytes) => Some(min_size_bytes),
+                        None => {
+                            errors.push(ConfigError::invalid(&format!("compression.rules[{}].min_size_bytes", index), "must be a number").with_value(&rule["min_size_bytes"]));
+                            
+                            None
+                        }
+                    }
+                };
                 
-                continue;
+                let never = if rule["never"].is_null() {
+                    false
+                } else {
+                    match rule["never"].as_bool() {
+                        Some(never) => never,
+                        None => {
+                            errors.push(ConfigError::invalid(&format!("compression.rules[{}].never", index), "must be a boolean").with_value(&rule["never"]));
+                            
+                            false
+                        }
+                    }
+                };
+                
+                rules.push(CompressionRule::new(content_type_prefix, min_size_bytes, never));
             }
             
-            // Get the page contents from the file.
-            let contents = fs::read_to_string(format!("{}/{}", web_root, path));
-            
-            // Check if the page contents are valid.
-            if contents.is_err() {
-                panic!("Invalid page contents, must be a string!");
+            CompressionConfig::new(enabled, min_size_bytes, rules)
+        };
+        
+        // Get whether .gz and .br files are served as the file they contain, decompressed if the client can't read them.
+        let serve_precompressed = if config["serve_precompressed"].is_null() {
+            true
+        } else {
+            match config["serve_precompressed"].as_bool() {
+                Some(serve_precompressed) => serve_precompressed,
+                None => {
+                    errors.push(ConfigError::invalid("serve_precompressed", "must be a boolean").with_value(&config["serve_precompressed"]));
+                    
+                    true
+                }
             }
+        };
+        
+        // Get the file cache settings, files above the size limit are streamed from disk instead of being cached.
+        let file_cache = if config["file_cache"].is_null() {
+            FileCache::default()
+        } else if !config["file_cache"].is_object() {
+            errors.push(ConfigError::invalid("file_cache", "must be an object with max_file_size_bytes and max_size_bytes").with_value(&config["file_cache"]));
             
-            let contents = contents.unwrap();
+            FileCache::default()
+        } else {
+            let file_cache = &config["file_cache"];
+            check_keys(file_cache, "file_cache", &FILE_CACHE_KEYS, &mut errors);
+            
+            let max_file_size_bytes = if file_cache["max_file_size_bytes"].is_null() {
+                file_cache::DEFAULT_MAX_FILE_SIZE_BYTES
+            } else {
+                match file_cache["max_file_size_bytes"].as_u64() {
+                    Some(max_file_size_bytes) => max_file_size_bytes,
+                    None => {
+                        errors.push(ConfigError::invalid("file_cache.max_file_size_bytes", "must be a number").with_value(&file_cache["max_file_size_bytes"]));
+                        
+                        file_cache::DEFAULT_MAX_FILE_SIZE_BYTES
+                    }
+                }
+            };
             
-            // Create a new page instance.
-            let page = Page::new(name, path, &contents);
+            let max_size_bytes = if file_cache["max_size_bytes"].is_null() {
+                file_cache::DEFAULT_MAX_SIZE_BYTES
+            } else {
+                match file_cache["max_size_bytes"].as_u64() {
+                    Some(max_size_bytes) => max_size_bytes,
+                    None => {
+                        errors.push(ConfigError::invalid("file_cache.max_size_bytes", "must be a number").with_value(&file_cache["max_size_bytes"]));
+                        
+                        file_cache::DEFAULT_MAX_SIZE_BYTES
+                    }
+                }
+            };
             
-            // Add the page to the pages vector.
-            pages.push(page);
-        }
+            FileCache::new(max_file_size_bytes, max_size_bytes)
+        };
         
-        // Return a new server instance.
-        Server {
-            verbose,
-            thread_count,
-            thread_pool,
-            port,
-            web_root: web_root.to_string(),
-            pages,
-            config: config.clone(),
-        }
-    }
-    
-    pub fn is_verbose(&self) -> bool {
-        self.verbose
-    }
-    
-    pub fn get_thread_count(&self) -> u16 {
-        self.thread_count
-    }
-    
-    pub fn get_thread_pool(&self) -> &ThreadPool {
-        &self.thread_pool
-    }
-    
-    pub fn get_port(&self) -> u16 {
-        self.port
-    }
-    
-    pub fn get_web_root(&self) -> &str {
-        &self.web_root
-    }
-    
-    pub fn get_pages(&self) -> &Vec<Page> {
-        &self.pages
-    }
-    
-    pub fn get_config(&self) -> &JsonValue {
-        &self.config
-    }
-    
-    pub fn listen(&self) {
-        if self.verbose {
-            println!("Listening on port {}...", self.port);
-        }
+        // Get how long the metadata of HEAD responses is reused, so polling clients don't cause the body to be hashed every time.
+        let head_cache = if config["head_cache_ttl_secs"].is_null() {
+            HeadCache::default()
+        } else {
+            match config["head_cache_ttl_secs"].as_u64() {
+                Some(ttl_secs) => HeadCache::new(Duration::from_secs(ttl_secs)),
+                None => {
+                    errors.push(ConfigError::invalid("head_cache_ttl_secs", "must be a number").with_value(&config["head_cache_ttl_secs"]));
+                    
+                    HeadCache::default()
+                }
+            }
+        };
         
-        // Create a new TcpListener instance on a random IP address.
-        let listener = TcpListener::bind(format!("0.0.0.0:{}", self.port));
+        // Get the Server header sent with every response, an empty banner leaves the header out.
+        let server_banner = if config["server_banner"].is_null() {
+            Some(DEFAULT_SERVER_BANNER.to_string())
+        } else {
+            match config["server_banner"].as_str() {
+                Some("") => None,
+                Some(server_banner) => Some(server_banner.to_string()),
+                None => {
+                    errors.push(ConfigError::invalid("server_banner", "must be a string").with_value(&config["server_banner"]));
+                    
+                    None
+                }
+            }
+        };
         
-        // Check if the listener is valid.
-        if listener.is_err() {
-            panic!("Failed to bind to port {}!", self.port);
-        }
+        // Get the files that are looked for, in order, when a request names a directory.
+        let index_files = if config["index_files"].is_null() {
+            DEFAULT_INDEX_FILES.iter().map(|index_file| index_file.to_string()).collect()
+        } else if !config["index_files"].is_array() {
+            errors.push(ConfigError::invalid("index_files", "must be an array of file names").with_value(&config["index_files"]));
+            
+            Vec::new()
+        } else {
+            let mut index_files = Vec::new();
+            
+            for (index, index_file) in config["index_files"].members().enumerate() {
+                match index_file.as_str() {
+                    Some(name) if !name.is_empty() && !name.contains(['/', '\\']) && name != ".." => index_files.push(name.to_string()),
+                    _ => errors.push(ConfigError::invalid(&format!("index_files[{}]", index), "must be a file name without a directory").with_value(index_file)),
+                }
+            }
+            
+            index_files
+        };
         
-        let listener = listener.unwrap();
+        // Get the methods that are refused server-wide.
+        let mut disabled_methods = HashSet::new();
         
-        // Accept incoming connections.
-        for stream in listener.incoming() {
-            // Check if the stream is valid.
-            if stream.is_err() {
-                panic!("Failed to accept incoming connection!");
+        if !config["disabled_methods"].is_null() && !config["disabled_methods"].is_array() {
+            errors.push(ConfigError::invalid("disabled_methods", "must be an array of method names").with_value(&config["disabled_methods"]));
+        } else {
+            for (index, method) in config["disabled_methods"].members().enumerate() {
+                match method.as_str().and_then(|method| Method::try_from(method.to_ascii_uppercase().as_str()).ok()) {
+                    Some(method) => {
+                        disabled_methods.insert(method);
+                    }
+                    None => errors.push(ConfigError::invalid(&format!("disabled_methods[{}]", index), "must be a method name like GET or POST").with_value(method)),
+                }
             }
-            
-            let stream = stream.unwrap();
-            
-            // Use a thread from the thread pool to handle the connection.
-            self.thread_pool.install(|| {
-                self.handle_connection(stream);
-            });
         }
-    }
-    
-    fn handle_connection(&self, mut stream: TcpStream) {
-        let mut buffer = [0; 1024];
         
-        // Read the request from the stream.
-        let bytes_read = stream.read(&mut buffer);
+        // Get the rate limiter, clients aren't limited if it's not specified.
+        let rate_limiter = if config["rate_limit"].is_null() {
+            None
+        } else {
+            check_keys(&config["rate_limit"], "rate_limit", &RATE_LIMIT_KEYS, &mut errors);
+            
+            match load_rate_limiter(&config["rate_limit"]) {
+                Ok(rate_limiter) => Some(rate_limiter),
+                Err(error) => {
+                    errors.push(error);
+                    
+                    None
+                }
+            }
+        };
         
-        // Check if the bytes_read is valid.
-        if bytes_read.is_err() {
-            panic!("Failed to read from stream!");
-        }
+        // Get the path prefixes that ask for credentials, nothing is protected if it's not specified.
+        let auth = load_auth(&config["auth"], &mut errors);
         
-        let bytes_read = bytes_read.unwrap();
+        // Get the path prefixes that require a JSON Web Token, nothing requires one if it's not specified.
+        let jwt = load_jwt(&config["jwt"], &mut errors);
         
-        // Convert the buffer to a string.
-        let request = String::from_utf8_lossy(&buffer[..bytes_read]);
-        // Create a new Request instance.
-        let request = Request::new(&request);
+        // Get where the OpenAPI document is served, there's no document or Swagger UI if it's not specified.
+        let openapi = load_openapi(&config["openapi"], &mut errors);
         
-        // Find the page.
-        let page = self.find_page(&request);
+        // Get the directory .well-known URIs are served from, falling back to web_root/.well-known.
+        let well_known_dir = if config["well_known_dir"].is_null() {
+            None
+        } else {
+            match config["well_known_dir"].as_str() {
+                Some(well_known_dir) => Some(well_known_dir.to_string()),
+                None => {
+                    errors.push(ConfigError::invalid("well_known_dir", "must be a directory path").with_value(&config["well_known_dir"]));
+                    
+                    None
+                }
+            }
+        };
         
-        // Send the response.
-        let mut response = Response::new("1.1", 200, "OK");
-        response.set_body(page.get_contents());
+        // Get the path the resolved configuration is written to at startup, for auditing.
+        let dump_resolved_config_to = if config["dump_resolved_config_to"].is_null() {
+            None
+        } else {
+            match config["dump_resolved_config_to"].as_str() {
+                Some(path) => Some(path.to_string()),
+                None => {
+                    errors.push(ConfigError::invalid("dump_resolved_config_to", "must be a file path").with_value(&config["dump_resolved_config_to"]));
+                    
+                    None
+                }
+            }
+        };
         
-        // Write the response to the stream.
-        stream
-            .write_all(response.to_string().as_bytes())
-            .expect("An error occurred while writing to the stream!");
+        // Get the web root, error pages, routes and pages served when no virtual host matches.
+        let site_settings = load_site_settings(config, "", &mut errors);
         
-        // Flush the stream.
-        stream.flush().unwrap();
+        // Get the virtual hosts, which serve their own site to requests for their hostname.
+        let mut vhost_settings = Vec::new();
         
-        if self.verbose {
-            println!("Served request to {}!", stream.peer_addr().unwrap());
+        if !config["vhosts"].is_null() && !config["vhosts"].is_object() {
+            errors.push(ConfigError::invalid("vhosts", "must be an object mapping hostnames to sites").with_value(&config["vhosts"]));
         }
-    }
-    
-    fn find_page(&self, request: &Request) -> &Page {
-        // Iterate over the pages.
-        for page in &self.pages {
-            // Check if the page name matches the request path.
-            if page.get_name() == request.get_path() {
-                return page;
+        
+        for (hostname, vhost) in config["vhosts"].entries() {
+            let field = format!("vhosts.{}", hostname);
+            
+            // Hostnames are case-insensitive, so they're matched in lowercase.
+            let hostname = hostname.to_ascii_lowercase();
+            
+            if !is_vhost_hostname(&hostname) {
+                errors.push(ConfigError::invalid(&field, "must be keyed by a hostname like example.com or *.example.com"));
+                
+                continue;
+            }
+            
+            if vhost_settings.iter().any(|(existing, _)| *existing == hostname) {
+                errors.push(ConfigError::invalid(&field, "is the same hostname as another virtual host"));
+                
+                continue;
+            }
+            
+            if !vhost.is_object() {
+                errors.push(ConfigError::invalid(&field, "must be an object with a web_root and pages").with_value(vhost));
+                
+                continue;
             }
+            
+            check_keys(vhost, &field, &VHOST_KEYS, &mut errors);
+            vhost_settings.push((hostname, load_site_settings(vhost, &field, &mut errors)));
         }
         
-        // Return the index page if no page was found.
-        &self.pages[0]
-    }
-}
-
-fn create_file(path: String, verbose: bool) -> Page {
-    
-    // Make sure all the directories exist before creating the file.
-    if fs::metadata(path.replace(path.split('/').last().unwrap(), "")).is_err() {
-        // Create the directories.
-        match fs::create_dir_all(path.replace(path.split('/').last().unwrap(), "")) {
-            Ok(_) => {
-                if verbose {
-                    println!("Created directories: {}", path.clone().replace(path.split('/').last().unwrap(), ""));
+        // Get the virtual host fallback flag, requests for other hosts are refused with 421 if it's off.
+        let vhost_fallback = if config["vhost_fallback"].is_null() {
+            true
+        } else {
+            match config["vhost_fallback"].as_bool() {
+                Some(vhost_fallback) => vhost_fallback,
+                None => {
+                    errors.push(ConfigError::invalid("vhost_fallback", "must be a boolean").with_value(&config["vhost_fallback"]));
+                    
+                    true
                 }
             }
-            Err(_) => panic!("Failed to create directories: {}", path.clone().replace(path.split('/').last().unwrap(), "")),
-        }
+        };
+        
+        // Stop here if anything is invalid, before any files are created or opened.
+        ConfigError::check_all(errors)?;
+        
+        Ok(Config {
+            verbose,
+            log_filter,
+            log_file,
+            log_stderr,
+            shutdown_grace_period_secs,
+            watch_config,
+            thread_count,
+            listener_settings,
+            tcp_backlog,
+            tcp_recv_buffer_bytes,
+            tcp_send_buffer_bytes,
+            max_body_size,
+            max_url_length,
+            max_header_bytes,
+            keep_alive_timeout_secs,
+            max_keep_alive_requests,
+            connection_limiter,
+            deny_unlisted,
+            connect_allowlist,
+            connect_access,
+            access,
+            trusted_proxies,
+            error_log_path,
+            log_rotation,
+            problem_types,
+            enable_cache_busting,
+            access_log,
+            access_log_format,
+            access_log_buffer_bytes,
+            log_sample_rate,
+            middleware,
+            robots_txt,
+            favicon,
+            mime_types,
+            compression,
+            serve_precompressed,
+            file_cache,
+            head_cache,
+            server_banner,
+            index_files,
+            disabled_methods,
+            rate_limiter,
+            auth,
+            jwt,
+            openapi,
+            well_known_dir,
+            dump_resolved_config_to,
+            site_settings,
+            vhost_settings,
+            vhost_fallback,
+        })
     }
     
-    // Create an empty file.
-    match fs::write(&path, "") {
-        Ok(_) => {
-            if verbose {
-                println!("Created file: {}", path);
+    fn load_cfg(config: &JsonValue, create_missing: bool) -> Result<Server, ConfigError> {
+        // Check every value first, nothing below reads the configuration again.
+        let Config {
+            verbose,
+            log_filter,
+            log_file,
+            log_stderr,
+            shutdown_grace_period_secs,
+            watch_config,
+            thread_count,
+            listener_settings,
+            tcp_backlog,
+            tcp_recv_buffer_bytes,
+            tcp_send_buffer_bytes,
+            max_body_size,
+            max_url_length,
+            max_header_bytes,
+            keep_alive_timeout_secs,
+            max_keep_alive_requests,
+            connection_limiter,
+            deny_unlisted,
+            connect_allowlist,
+            connect_access,
+            access,
+            trusted_proxies,
+            error_log_path,
+            log_rotation,
+            problem_types,
+            enable_cache_busting,
+            access_log,
+            access_log_format,
+            access_log_buffer_bytes,
+            log_sample_rate,
+            middleware,
+            robots_txt,
+            favicon,
+            mime_types,
+            compression,
+            serve_precompressed,
+            file_cache,
+            head_cache,
+            server_banner,
+            index_files,
+            disabled_methods,
+            rate_limiter,
+            auth,
+            jwt,
+            openapi,
+            well_known_dir,
+            dump_resolved_config_to,
+            site_settings,
+            vhost_settings,
+            vhost_fallback,
+        } = Self::parse_config(config)?;
+        
+        // Set up logging before anything worth logging happens, checking a configuration leaves the running one alone.
+        if create_missing {
+            let log_file_writer = match log_file {
+                Some(path) => {
+                    let writer = LogWriter::open(path).map_err(|error| ConfigError::io(path, error))?;
+                    
+                    match &log_rotation {
+                        Some(log_rotation) => Some(writer.with_rotation(log_rotation.clone())),
+                        None => Some(writer),
+                    }
+                }
+                None => None,
+            };
+            
+            logging::init(log_filter.clone(), log_stderr, log_file_writer);
+        }
+        
+        // Files that are missing or can't be used are collected as well, so checking a configuration reports all of them.
+        let mut file_errors = Vec::new();
+        
+        // Open the error log.
+        let error_log = match error_log_path.map(|path| (path, LogWriter::open(path))) {
+            Some((_, Ok(writer))) => match &log_rotation {
+                Some(log_rotation) => ErrorLog::with_writer(writer.with_rotation(log_rotation.clone())),
+                None => ErrorLog::with_writer(writer),
+            },
+            Some((path, Err(error))) => {
+                file_errors.push(ConfigError::io(path, error));
+                
+                ErrorLog::with_writer(LogWriter::stderr())
+            }
+            None => ErrorLog::with_writer(LogWriter::stderr()),
+        };
+        
+        let error_log = Arc::new(error_log);
+        let panics_total = Arc::new(AtomicU64::new(0));
+        
+        // Create a new thread pool, logging any panic that escapes a task instead of aborting the process.
+        let panic_log = Arc::clone(&error_log);
+        let thread_pool = ThreadPoolBuilder::new()
+            .num_threads(thread_count as usize)
+            .panic_handler(move |payload| panic_log.log_panic(&panic_message(payload.as_ref()), None))
+            .build()
+            .map_err(|error| ConfigError::invalid("thread_count", &format!("failed to create the thread pool: {}", error)))?;
+        
+        // Load the certificates and keys, so a bad one is reported before any connection is accepted.
+        let mut listeners = Vec::new();
+        
+        for settings in listener_settings {
+            let tls_config = match settings.tls_paths.map(|(cert_path, key_path)| tls::load_server_config(cert_path, key_path)) {
+                Some(Ok(tls_config)) => Some(tls_config),
+                Some(Err(error)) => {
+                    file_errors.push(error);
+                    
+                    None
+                }
+                None => None,
+            };
+            
+            listeners.push(Arc::new(Listener::new(&settings.name, settings.bind_address, settings.port, settings.force_dual_stack, tls_config)));
+        }
+        
+        let mut body_filters: Vec<Box<dyn BodyFilter + Send + Sync>> = Vec::new();
+        
+        if enable_cache_busting {
+            body_filters.push(Box::new(HtmlRewritingFilter::new(site_settings.web_root)));
+        }
+        
+        // The access log is written by a response hook once each response has been sent.
+        let mut response_hooks: Vec<Box<dyn ResponseHook + Send + Sync>> = Vec::new();
+        
+        let access_log_writer = access_log.and_then(|path| match LogWriter::open_buffered(path, access_log_buffer_bytes) {
+            Ok(writer) => Some(writer),
+            Err(error) => {
+                file_errors.push(ConfigError::io(path, error));
+                
+                None
+            }
+        });
+        
+        if let Some(mut writer) = access_log_writer {
+            if let Some(log_rotation) = log_rotation {
+                writer = writer.with_rotation(log_rotation);
+            }
+            
+            let logger: Box<dyn ResponseHook + Send + Sync> = match access_log_format {
+                AccessLogFormat::Common => Box::new(CombinedLogger::common(writer)),
+                AccessLogFormat::Combined => Box::new(CombinedLogger::new(writer)),
+                AccessLogFormat::Ndjson => Box::new(NdjsonLogger::new(writer)),
+            };
+            
+            // Only wrap the logger when sampling, so logging everything stays as cheap as before.
+            if log_sample_rate < 1.0 {
+                response_hooks.push(Box::new(SampledLogger::new(logger, log_sample_rate)));
+            } else {
+                response_hooks.push(logger);
+            }
+        }
+        
+        // Load the files of every site, so a missing one is noticed at startup rather than when it's needed.
+        let default_site = Arc::new(load_site(site_settings, create_missing, &mut file_errors)?);
+        let mut vhosts = HashMap::new();
+        
+        for (hostname, settings) in vhost_settings {
+            vhosts.insert(hostname, Arc::new(load_site(settings, create_missing, &mut file_errors)?));
+        }
+        
+        ConfigError::check_all(file_errors)?;
+        
+        // Return a new server instance.
+        Ok(Server {
+            verbose,
+            log_filter,
+            log_file: log_file.map(str::to_string),
+            log_stderr,
+            shutdown_signal: Arc::new(ShutdownSignal::new(Duration::from_secs(shutdown_grace_period_secs))),
+            thread_count,
+            thread_pool,
+            listeners,
+            tcp_backlog,
+            tcp_recv_buffer_bytes,
+            tcp_send_buffer_bytes,
+            max_body_size,
+            max_url_length,
+            max_header_bytes,
+            keep_alive_timeout_secs,
+            max_keep_alive_requests,
+            connection_limiter,
+            deny_unlisted,
+            connect_allowlist,
+            connect_access,
+            access,
+            trusted_proxies,
+            error_log,
+            panics_total,
+            problem_types,
+            robots_txt,
+            favicon,
+            mime_types,
+            compression,
+            serve_precompressed,
+            file_cache,
+            index_files,
+            server_banner,
+            disabled_methods,
+            rate_limiter,
+            auth,
+            jwt,
+            openapi,
+            well_known_dir,
+            dump_resolved_config_to,
+            sites: RwLock::new(Arc::new(Sites { default: default_site, vhosts, fallback: vhost_fallback })),
+            config_path: None,
+            config_overrides: JsonValue::new_object(),
+            watch_config,
+            config: config.clone(),
+            response_hooks,
+            body_filters,
+            middleware,
+            upgrade_handlers: Vec::new(),
+            code_routes: Vec::new(),
+            route_metadata: HashMap::new(),
+            handlers: HashMap::new(),
+            websocket_handlers: HashMap::new(),
+            kv_store: Arc::new(KvStore::new()),
+            head_cache: Arc::new(RwLock::new(head_cache)),
+        })
+    }
+    
+    pub fn is_verbose(&self) -> bool {
+        self.verbose
+    }
+    
+    /// Returns which messages are logged, from `WEB_SERVER_LOG` if it's set and from `log_level` otherwise.
+    pub fn get_log_filter(&self) -> &LogFilter {
+        &self.log_filter
+    }
+    
+    pub fn get_log_file(&self) -> Option<&str> {
+        self.log_file.as_deref()
+    }
+    
+    pub fn is_log_stderr(&self) -> bool {
+        self.log_stderr
+    }
+    
+    pub fn get_shutdown_grace_period(&self) -> Duration {
+        self.shutdown_signal.get_grace_period()
+    }
+    
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutdown_signal.is_requested()
+    }
+    
+    /// Stops the server gracefully, returning straight away.
+    ///
+    /// The accept loops stop and idle kept-alive connections are closed. Requests that are being handled get the grace
+    /// period to finish, after which their connections are closed too. Once every connection is done, the buffered logs
+    /// are flushed and `listen`, or the thread started by `start`, returns.
+    pub fn shutdown(&self) {
+        self.shutdown_signal.request();
+    }
+    
+    pub fn get_thread_count(&self) -> u16 {
+        self.thread_count
+    }
+    
+    pub fn get_thread_pool(&self) -> &ThreadPool {
+        &self.thread_pool
+    }
+    
+    /// Returns the configured port of the first listener, see `get_listeners` for the others.
+    pub fn get_port(&self) -> u16 {
+        self.listeners[0].get_port()
+    }
+    
+    /// Returns the bind address of the first listener, see `get_listeners` for the others.
+    pub fn get_bind_address(&self) -> IpAddr {
+        self.listeners[0].get_bind_address()
+    }
+    
+    pub fn is_force_dual_stack(&self) -> bool {
+        self.listeners[0].is_force_dual_stack()
+    }
+    
+    pub fn get_listeners(&self) -> &[Arc<Listener>] {
+        &self.listeners
+    }
+    
+    pub fn get_tcp_backlog(&self) -> u32 {
+        self.tcp_backlog
+    }
+    
+    pub fn get_tcp_recv_buffer_bytes(&self) -> Option<usize> {
+        self.tcp_recv_buffer_bytes
+    }
+    
+    pub fn get_tcp_send_buffer_bytes(&self) -> Option<usize> {
+        self.tcp_send_buffer_bytes
+    }
+    
+    /// Returns the web root of the default site, virtual hosts have their own.
+    pub fn get_web_root(&self) -> String {
+        self.get_site().web_root.clone()
+    }
+    
+    pub fn get_max_body_size(&self) -> usize {
+        self.max_body_size
+    }
+    
+    /// Returns the maximum length of the request URL, which includes the query string.
+    pub fn get_max_url_length(&self) -> usize {
+        self.max_url_length
+    }
+    
+    pub fn get_connect_allowlist(&self) -> Option<&Vec<String>> {
+        self.connect_allowlist.as_ref()
+    }
+    
+    /// Returns the address ranges CONNECT targets may resolve to.
+    pub fn get_connect_access(&self) -> &AccessList {
+        &self.connect_access
+    }
+    
+    /// Returns the clients that may connect to the server.
+    pub fn get_access(&self) -> &AccessList {
+        &self.access
+    }
+    
+    /// Returns the proxies that are trusted to name the client in `X-Forwarded-For`.
+    pub fn get_trusted_proxies(&self) -> &Vec<IpNet> {
+        &self.trusted_proxies
+    }
+    
+    pub fn get_error_log(&self) -> &ErrorLog {
+        &self.error_log
+    }
+    
+    /// Returns how many connections panicked while they were handled, whether or not the panic hook is installed.
+    pub fn get_panics_total(&self) -> u64 {
+        self.panics_total.load(Ordering::Relaxed)
+    }
+    
+    pub fn get_problem_types(&self) -> &HashMap<u16, String> {
+        &self.problem_types
+    }
+    
+    pub fn get_robots_txt(&self) -> Option<&RobotsConfig> {
+        self.robots_txt.as_ref()
+    }
+    
+    pub fn get_favicon(&self) -> Option<&str> {
+        self.favicon.as_deref()
+    }
+    
+    pub fn get_compression(&self) -> &CompressionConfig {
+        &self.compression
+    }
+    
+    pub fn is_serve_precompressed(&self) -> bool {
+        self.serve_precompressed
+    }
+    
+    pub fn get_file_cache(&self) -> &FileCache {
+        &self.file_cache
+    }
+    
+    pub fn get_head_cache_ttl(&self) -> Duration {
+        self.head_cache.read().unwrap_or_else(|poisoned| poisoned.into_inner()).get_ttl()
+    }
+    
+    pub fn get_server_banner(&self) -> Option<&str> {
+        self.server_banner.as_deref()
+    }
+    
+    pub fn get_index_files(&self) -> &Vec<String> {
+        &self.index_files
+    }
+    
+    pub fn get_mime_types(&self) -> &MimeTypes {
+        &self.mime_types
+    }
+    
+    /// Returns the maximum size of the request line and headers together.
+    pub fn get_max_header_bytes(&self) -> usize {
+        self.max_header_bytes
+    }
+    
+    pub fn get_keep_alive_timeout_secs(&self) -> u64 {
+        self.keep_alive_timeout_secs
+    }
+    
+    pub fn get_max_keep_alive_requests(&self) -> usize {
+        self.max_keep_alive_requests
+    }
+    
+    /// Returns how many connections a single client may have open at once, `None` means unlimited.
+    pub fn get_max_connections_per_ip(&self) -> Option<usize> {
+        self.connection_limiter.as_ref().map(ConnectionLimiter::get_max_connections)
+    }
+    
+    /// Returns whether any of the listeners uses TLS.
+    pub fn is_tls_enabled(&self) -> bool {
+        self.listeners.iter().any(|listener| listener.is_tls())
+    }
+    
+    pub fn is_deny_unlisted(&self) -> bool {
+        self.deny_unlisted
+    }
+    
+    pub fn get_disabled_methods(&self) -> &HashSet<Method> {
+        &self.disabled_methods
+    }
+    
+    /// Returns the path prefixes that ask for credentials.
+    pub fn get_auth(&self) -> &Vec<BasicAuth> {
+        &self.auth
+    }
+    
+    /// Returns the path prefixes that require a JSON Web Token.
+    pub fn get_jwt(&self) -> &Vec<JwtValidator> {
+        &self.jwt
+    }
+    
+    pub fn get_openapi(&self) -> Option<&OpenApiConfig> {
+        self.openapi.as_ref()
+    }
+    
+    pub fn get_well_known_dir(&self) -> Option<&str> {
+        self.well_known_dir.as_deref()
+    }
+    
+    pub fn get_dump_resolved_config_to(&self) -> Option<&str> {
+        self.dump_resolved_config_to.as_deref()
+    }
+    
+    /// Returns the pages and routes currently served to requests without a virtual host, which stay the same for the
+    /// caller even if they're reloaded.
+    pub fn get_site(&self) -> Arc<Site> {
+        Arc::clone(&self.get_sites().default)
+    }
+    
+    /// Returns the site of a virtual host by the hostname it's configured with, e.g. `*.example.com`.
+    pub fn get_vhost(&self, hostname: &str) -> Option<Arc<Site>> {
+        self.get_sites().vhosts.get(&hostname.to_ascii_lowercase()).cloned()
+    }
+    
+    /// Returns the hostnames of the virtual hosts, sorted.
+    pub fn get_vhost_names(&self) -> Vec<String> {
+        let mut names = self.get_sites().vhosts.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        
+        names
+    }
+    
+    /// Returns whether requests for hosts without a virtual host are served the default site, rather than refused.
+    pub fn is_vhost_fallback(&self) -> bool {
+        self.get_sites().fallback
+    }
+    
+    /// Starts checking the health of every proxy's upstreams, the checks that already run are left alone.
+    fn start_health_checks(&self) {
+        let sites = self.get_sites();
+        
+        for site in iter::once(&sites.default).chain(sites.vhosts.values()) {
+            for proxy in &site.proxies {
+                proxy.start_health_checks();
+            }
+        }
+    }
+    
+    fn get_sites(&self) -> Arc<Sites> {
+        Arc::clone(&self.sites.read().unwrap_or_else(|poisoned| poisoned.into_inner()))
+    }
+    
+    /// Checks whether the client behind a request may make it, by the server's and the matching route's allow and deny
+    /// lists. Requests from trusted proxies are checked against the client they're forwarding for.
+    fn is_client_allowed(&self, peer: IpAddr, request: &Request) -> bool {
+        let client = self.client_address(peer, request);
+        
+        if !self.access.is_allowed(client) {
+            return false;
+        }
+        
+        match self.site_for(request) {
+            Some(site) => site.router.find(request.path()).is_none_or(|(route, _)| route.get_access().is_allowed(client)),
+            None => true,
+        }
+    }
+    
+    /// Returns the client a request comes from, which is the peer unless it's a trusted proxy forwarding the request.
+    fn client_address(&self, peer: IpAddr, request: &Request) -> IpAddr {
+        let forwarded_for = request.get_headers().get_all("X-Forwarded-For").collect::<Vec<_>>();
+        
+        network::client_address(peer, &forwarded_for, &self.trusted_proxies)
+    }
+    
+    /// Picks the site a request is for by its Host header, the most specific virtual host wins.
+    ///
+    /// Requests for other hosts, or without one, get the default site unless falling back is turned off.
+    fn site_for(&self, request: &Request) -> Option<Arc<Site>> {
+        let sites = self.get_sites();
+        
+        if sites.vhosts.is_empty() {
+            return Some(Arc::clone(&sites.default));
+        }
+        
+        if let Some(hostname) = request_hostname(request) {
+            if let Some(site) = sites.vhosts.get(&hostname) {
+                return Some(Arc::clone(site));
+            }
+            
+            // Try the wildcards from the longest suffix to the shortest, e.g. *.a.example.com before *.example.com.
+            let mut suffix = hostname.as_str();
+            
+            while let Some((_, rest)) = suffix.split_once('.') {
+                if let Some(site) = sites.vhosts.get(&format!("*.{}", rest)) {
+                    return Some(Arc::clone(site));
+                }
+                
+                suffix = rest;
+            }
+        }
+        
+        if sites.fallback {
+            Some(Arc::clone(&sites.default))
+        } else {
+            None
+        }
+    }
+    
+    pub fn pages_mut(&mut self) -> &mut Vec<Page> {
+        &mut self.site_mut().pages
+    }
+    
+    /// Returns the site for changes made while setting the server up, before any request shares it.
+    fn site_mut(&mut self) -> &mut Site {
+        let sites = Arc::get_mut(self.sites.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner()))
+            .expect("the sites can't be changed while a snapshot of them is held");
+        
+        Arc::get_mut(&mut sites.default).expect("the site can't be changed while a snapshot of it is held")
+    }
+    
+    /// Returns the configuration file the server was created from, if any.
+    pub fn get_config_path(&self) -> Option<&Path> {
+        self.config_path.as_deref()
+    }
+    
+    pub fn get_config_overrides(&self) -> &JsonValue {
+        &self.config_overrides
+    }
+    
+    pub fn is_watch_config(&self) -> bool {
+        self.watch_config
+    }
+    
+    pub fn get_config(&self) -> &JsonValue {
+        &self.config
+    }
+    
+    /// Returns the effective configuration as pretty-printed JSON, with defaults filled in and secrets redacted.
+    pub fn dump_config(&self) -> String {
+        let mut config = self.config.clone();
+        
+        // Replace the values that have defaults with the ones actually in use.
+        config["verbose"] = self.verbose.into();
+        config["log_level"] = self.log_filter.to_string().into();
+        config["log_file"] = self.log_file.as_deref().into();
+        config["log_stderr"] = self.log_stderr.into();
+        config["shutdown_grace_period_secs"] = self.shutdown_signal.get_grace_period().as_secs().into();
+        config["watch_config"] = self.watch_config.into();
+        config["thread_count"] = self.thread_count.into();
+        
+        // The resolved addresses go wherever they were configured.
+        if self.config["listeners"].is_null() {
+            config["port"] = self.get_port().into();
+            config["bind_address"] = self.get_bind_address().to_string().into();
+            config["force_dual_stack"] = self.is_force_dual_stack().into();
+        } else {
+            for (index, listener) in self.listeners.iter().enumerate() {
+                config["listeners"][index]["name"] = listener.get_name().into();
+                config["listeners"][index]["bind_address"] = listener.get_bind_address().to_string().into();
+                config["listeners"][index]["force_dual_stack"] = listener.is_force_dual_stack().into();
+            }
+        }
+        
+        config["tcp_backlog"] = self.tcp_backlog.into();
+        config["tcp_recv_buffer_bytes"] = self.tcp_recv_buffer_bytes.into();
+        config["tcp_send_buffer_bytes"] = self.tcp_send_buffer_bytes.into();
+        config["max_body_size"] = self.max_body_size.into();
+        config["max_url_length"] = self.max_url_length.into();
+        config["max_header_bytes"] = self.max_header_bytes.into();
+        config["keep_alive_timeout_secs"] = self.keep_alive_timeout_secs.into();
+        config["max_keep_alive_requests"] = self.max_keep_alive_requests.into();
+        config["max_connections_per_ip"] = self.get_max_connections_per_ip().into();
+        config["deny_unlisted"] = self.deny_unlisted.into();
+        config["allow"] = dump_cidr_list(self.access.get_allow());
+        config["deny"] = dump_cidr_list(self.access.get_deny());
+        config["connect_allow"] = dump_cidr_list(self.connect_access.get_allow());
+        config["connect_deny"] = dump_cidr_list(self.connect_access.get_deny());
+        config["trusted_proxies"] = dump_cidr_list(&self.trusted_proxies);
+        config["favicon"] = self.favicon.as_deref().into();
+        config["well_known_dir"] = self.well_known_dir.as_deref().into();
+        config["openapi"] = match &self.openapi {
+            Some(openapi) => json::object! {
+                "title": openapi.get_title(),
+                "version": openapi.get_version(),
+                "openapi_json_path": openapi.get_json_path(),
+            },
+            None => JsonValue::Null,
+        };
+        
+        let mut problem_types = JsonValue::new_object();
+        
+        for (status_code, uri) in &self.problem_types {
+            problem_types[status_code.to_string()] = uri.as_str().into();
+        }
+        
+        config["problem_types"] = problem_types;
+        
+        let mut mime_types = JsonValue::new_object();
+        
+        for (extension, mime_type) in self.mime_types.get_overrides() {
+            mime_types[extension.as_str()] = mime_type.as_str().into();
+        }
+        
+        config["mime_types"] = mime_types;
+        config["compression"] = json::object! {
+            "enabled": self.compression.is_enabled(),
+            "min_size_bytes": self.compression.get_min_size_bytes(),
+            "rules": self.compression.get_rules().iter()
+                .map(|rule| json::object! {
+                    "content_type_prefix": rule.get_content_type_prefix(),
+                    "min_size_bytes": rule.get_min_size_bytes(),
+                    "never": rule.is_never(),
+                })
+                .collect::<Vec<_>>(),
+        };
+        config["serve_precompressed"] = self.serve_precompressed.into();
+        config["index_files"] = self.index_files.clone().into();
+        config["server_banner"] = self.server_banner.clone().unwrap_or_default().into();
+        config["head_cache_ttl_secs"] = self.get_head_cache_ttl().as_secs().into();
+        config["file_cache"] = json::object! {
+            "max_file_size_bytes": self.file_cache.get_max_file_size_bytes(),
+            "max_size_bytes": self.file_cache.get_max_size_bytes(),
+        };
+        
+        // The default site is configured at the top level, virtual hosts the same way under their hostname.
+        let sites = self.get_sites();
+        dump_site(&sites.default, &mut config);
+        
+        let mut vhosts = JsonValue::new_object();
+        
+        for hostname in self.get_vhost_names() {
+            let mut vhost = JsonValue::new_object();
+            dump_site(&sites.vhosts[&hostname], &mut vhost);
+            
+            vhosts[hostname.as_str()] = vhost;
+        }
+        
+        config["vhosts"] = vhosts;
+        config["vhost_fallback"] = sites.fallback.into();
+        
+        // Never print credentials or where to find them, even when debugging.
+        for field in SENSITIVE_CONFIG_FIELDS {
+            redact(&mut config, &field.split('.').collect::<Vec<_>>());
+        }
+        
+        config.pretty(2)
+    }
+    
+    /// Writes the output of `dump_config` to a file, replacing it atomically so readers never see a partial file.
+    ///
+    /// Secrets are redacted, but the file still describes the whole deployment, so only its owner may read it.
+    pub fn write_resolved_config(&self, path: &str) -> Result<(), ConfigError> {
+        let temporary_path = format!("{}.tmp", path);
+        
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        
+        #[cfg(unix)]
+        options.mode(0o600);
+        
+        options.open(&temporary_path)
+            .and_then(|mut file| file.write_all(self.dump_config().as_bytes()))
+            .map_err(|error| ConfigError::io(&temporary_path, error))?;
+        fs::rename(&temporary_path, path).map_err(|error| ConfigError::io(path, error))
+    }
+    
+    pub fn kv_store(&self) -> Arc<KvStore> {
+        Arc::clone(&self.kv_store)
+    }
+    
+    pub fn add_response_hook(&mut self, hook: impl ResponseHook + Send + Sync + 'static) {
+        self.response_hooks.push(Box::new(hook));
+    }
+    
+    pub fn add_middleware(&mut self, middleware: impl Middleware + Send + Sync + 'static) {
+        self.middleware.push(Box::new(middleware));
+    }
+    
+    /// Adds middleware and returns the server, so it can be configured inline, e.g. `server.with_middleware(a).listen()`.
+    pub fn with_middleware(mut self, middleware: impl Middleware + Send + Sync + 'static) -> Server {
+        self.add_middleware(middleware);
+        
+        self
+    }
+    
+    pub fn add_body_filter(&mut self, filter: impl BodyFilter + Send + Sync + 'static) {
+        self.body_filters.push(Box::new(filter));
+    }
+    
+    /// Registers a handler under the name routes refer to it by in the configuration.
+    pub fn add_handler(&mut self, name: &str, handler: impl Handler + Send + Sync + 'static) {
+        self.handlers.insert(name.to_string(), Box::new(handler));
+    }
+    
+    /// Answers requests matching a path pattern with a handler, alongside the routes and pages from the configuration.
+    ///
+    /// Only the default site gets the route, virtual hosts serve just the routes configured for them.
+    pub fn route(&mut self, pattern: &str, handler: impl Handler + Send + Sync + 'static) -> Result<(), RouteError> {
+        // Routes registered in code use their pattern as the handler name.
+        self.site_mut().router.add(Route::new(pattern, RouteTarget::Handler(pattern.to_string()))?)?;
+        self.code_routes.push((pattern.to_string(), RouteTarget::Handler(pattern.to_string())));
+        self.add_handler(pattern, handler);
+        
+        Ok(())
+    }
+    
+    /// Describes the route with a path pattern in the OpenAPI document, whether it's registered in code or configured.
+    pub fn describe_route(&mut self, pattern: &str, metadata: RouteMetadata) {
+        self.route_metadata.insert(pattern.to_string(), metadata);
+    }
+    
+    /// Accepts WebSocket connections for requests matching a path pattern and hands them to a handler. Other requests
+    /// for the path are answered with 426.
+    ///
+    /// Like `route`, only the default site gets the route.
+    pub fn websocket(&mut self, pattern: &str, handler: impl WebSocketHandler + Send + Sync + 'static) -> Result<(), RouteError> {
+        self.site_mut().router.add(Route::new(pattern, RouteTarget::WebSocket(pattern.to_string()))?)?;
+        self.code_routes.push((pattern.to_string(), RouteTarget::WebSocket(pattern.to_string())));
+        self.websocket_handlers.insert(pattern.to_string(), Box::new(handler));
+        
+        Ok(())
+    }
+    
+    pub fn add_upgrade_handler(&mut self, handler: impl UpgradeHandler + Send + Sync + 'static) {
+        self.upgrade_handlers.push(Box::new(handler));
+    }
+    
+    /// Re-reads the configuration file the server was created from and starts serving its pages and routes.
+    ///
+    /// Requests keep being served during the reload, the ones already being handled finish with the old pages and
+    /// routes. An invalid configuration is rejected, leaving the current one in place. Other settings, such as the port
+    /// or the thread count, only take effect after a restart.
+    pub fn reload(&self) -> Result<(), ConfigError> {
+        let path = self.config_path.as_deref()
+            .ok_or_else(|| ConfigError::invalid("reload", "the server wasn't created from a configuration file"))?;
+        let mut config = read_config_file(path)?;
+        apply_overrides(&mut config, &self.config_overrides)?;
+        
+        self.reload_from(&config)
+    }
+    
+    /// Like `reload`, but with a configuration that's already been read.
+    pub fn reload_from(&self, config: &JsonValue) -> Result<(), ConfigError> {
+        // Validate the whole configuration the way a check does, which neither creates files nor touches the logger.
+        let mut reloaded = Server::load_cfg(config, false)?;
+        
+        // Routes registered in code aren't part of the configuration, so carry them over.
+        for (pattern, target) in &self.code_routes {
+            Route::new(pattern, target.clone())
+                .and_then(|route| reloaded.site_mut().router.add(route))
+                .map_err(|error| route_error("route", &error, &pattern.as_str().into()))?;
+        }
+        
+        let sites = reloaded.get_sites();
+        
+        info!(
+            "Reloaded the configuration, serving {} pages and {} routes, and {} virtual hosts.",
+            sites.default.pages.len(),
+            sites.default.router.routes().count(),
+            sites.vhosts.len(),
+        );
+        
+        // Swap every site at once, so no request sees the new pages with the old routes or the other way around.
+        *self.sites.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = sites;
+        self.start_health_checks();
+        
+        // The cached metadata may describe pages that just changed.
+        let mut head_cache = self.head_cache.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        *head_cache = HeadCache::new(head_cache.get_ttl());
+        
+        Ok(())
+    }
+    
+    /// Binds the listening sockets and accepts connections until `shutdown` is called, e.g. from another thread.
+    pub fn listen(&self) -> Result<(), ServerError> {
+        let listeners = self.bind_listeners()?;
+        
+        self.serve(&listeners);
+        
+        Ok(())
+    }
+    
+    /// Binds the listening sockets without accepting connections yet, so the bound address can be inspected first.
+    ///
+    /// This is useful with port 0, where the OS picks a free port.
+    pub fn bind(self) -> Result<BoundServer, ServerError> {
+        let listeners = self.bind_listeners()?;
+        
+        Ok(BoundServer {
+            server: self,
+            listeners,
+        })
+    }
+    
+    /// Binds the listening sockets and accepts connections on a background thread, so it doesn't block the caller.
+    pub fn start(self) -> Result<ServerHandle, ServerError> {
+        Ok(self.bind()?.start())
+    }
+    
+    fn serve(&self, listeners: &[(TcpListener, Arc<Listener>)]) {
+        let listener_addrs: Vec<SocketAddr> = listeners.iter()
+            .filter_map(|(socket, _)| socket.local_addr().ok())
+            .collect();
+        
+        for address in &listener_addrs {
+            info!("Listening on {}...", address);
+        }
+        
+        self.shutdown_signal.set_listener_addrs(listener_addrs);
+        self.start_health_checks();
+        
+        // Run an accept loop per listener, all of them sharing the same thread pool.
+        thread::scope(|scope| {
+            for (socket, listener) in listeners {
+                scope.spawn(move || self.accept_connections(socket, listener));
+            }
+            
+            if let (true, Some(path)) = (self.watch_config, &self.config_path) {
+                scope.spawn(move || self.watch_config_file(path));
+            }
+        });
+        
+        // Every connection has been handled by now, so write out what the hooks, like the access log, still buffer.
+        for hook in &self.response_hooks {
+            hook.flush();
+        }
+    }
+    
+    /// Reloads the configuration whenever the file changes, until the server shuts down.
+    fn watch_config_file(&self, path: &Path) {
+        let fingerprint = |path: &Path| fs::metadata(path).ok().map(|metadata| (metadata.modified().ok(), metadata.len()));
+        let mut last_seen = fingerprint(path);
+        
+        while !self.shutdown_signal.is_requested() {
+            thread::sleep(CONFIG_WATCH_INTERVAL);
+            
+            let current = fingerprint(path);
+            
+            if current == last_seen {
+                continue;
+            }
+            
+            last_seen = current;
+            
+            // An editor may still be writing the file, a rejected reload is retried with its next change.
+            if let Err(error) = self.reload() {
+                error!("Failed to reload {}, keeping the current configuration: {}", path.display(), error);
+            }
+        }
+    }
+    
+    /// Records panics with their location and a backtrace in the error log, then hands them to the hook that was
+    /// installed before, e.g. the default one printing to stderr.
+    ///
+    /// The hook is process-wide, so it's up to the application to install it, usually once from `main`.
+    pub fn install_panic_hook(&self) {
+        let error_log = Arc::clone(&self.error_log);
+        let previous = panic::take_hook();
+        
+        panic::set_hook(Box::new(move |info| {
+            let message = match info.location() {
+                Some(location) => format!("{} at {}", panic_message(info.payload()), location),
+                None => panic_message(info.payload()),
+            };
+            
+            error_log.log_panic(&message, Some(&Backtrace::force_capture()));
+            
+            previous(info);
+        }));
+    }
+    
+    /// Binds the sockets of every listener, each paired with the listener its connections are tagged with.
+    fn bind_listeners(&self) -> Result<Vec<(TcpListener, Arc<Listener>)>, ServerError> {
+        let mut sockets = Vec::new();
+        
+        for listener in &self.listeners {
+            let address = SocketAddr::new(listener.get_bind_address(), listener.get_port());
+            
+            // Binding to "::" covers IPv4 as well on Linux and macOS, while the BSDs and Windows only accept IPv6 on
+            // such a socket. On those platforms, or when asked to, bind a separate IPv4 listener alongside an
+            // IPv6-only one. The IPv6 loopback never accepts IPv4, so "::1" only gets a 127.0.0.1 listener next to it
+            // when asked to.
+            let dual_stack_by_default = cfg!(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios"));
+            let ipv4_address = match listener.get_bind_address() {
+                IpAddr::V6(ip) if ip.is_unspecified() && (listener.is_force_dual_stack() || !dual_stack_by_default) => Some(Ipv4Addr::UNSPECIFIED),
+                IpAddr::V6(ip) if ip.is_loopback() && listener.is_force_dual_stack() => Some(Ipv4Addr::LOCALHOST),
+                _ => None,
+            };
+            let split_stacks = ipv4_address.is_some();
+            
+            let bind_error = |address: SocketAddr| move |source| ServerError::Bind { address, source };
+            let socket = self.bind_listener(address, split_stacks).map_err(bind_error(address))?;
+            
+            // With port 0 the OS picks the port, the IPv4 listener has to use the same one.
+            let port = socket.local_addr().map_err(bind_error(address))?.port();
+            
+            sockets.push((socket, Arc::clone(listener)));
+            
+            if let Some(ipv4_address) = ipv4_address {
+                let address = SocketAddr::new(IpAddr::V4(ipv4_address), port);
+                
+                sockets.push((self.bind_listener(address, split_stacks).map_err(bind_error(address))?, Arc::clone(listener)));
+            }
+        }
+        
+        Ok(sockets)
+    }
+    
+    fn bind_listener(&self, address: SocketAddr, only_v6: bool) -> io::Result<TcpListener> {
+        let socket = Socket::new(Domain::for_address(address), Type::STREAM, Some(Protocol::TCP))?;
+        
+        // Only IPv6 sockets have a dual-stack mode to configure.
+        if address.is_ipv6() {
+            socket.set_only_v6(only_v6)?;
+        }
+        
+        socket.set_reuse_address(true)?;
+        
+        // Size the socket buffers before binding, accepted connections inherit them from the listening socket.
+        if let Some(size) = self.tcp_recv_buffer_bytes {
+            socket.set_recv_buffer_size(size)?;
+        }
+        
+        if let Some(size) = self.tcp_send_buffer_bytes {
+            socket.set_send_buffer_size(size)?;
+        }
+        
+        // The kernel may adjust the requested sizes (Linux doubles them), so report what was actually applied.
+        debug!(
+            "Socket buffers for {}: receive {} bytes, send {} bytes",
+            address, socket.recv_buffer_size()?, socket.send_buffer_size()?,
+        );
+        
+        socket.bind(&address.into())?;
+        
+        // The backlog is the queue of connections the OS has completed the handshake for but that haven't been
+        // accepted yet. When it's full, new connections are dropped or reset before the server ever sees them, which
+        // shows up as clients getting "connection refused" or timing out during traffic spikes while the server itself
+        // looks idle. TcpListener::bind always uses 128, so the socket is built by hand to make the queue configurable.
+        // Note that the OS may cap the value, e.g. at net.core.somaxconn on Linux.
+        socket.listen(self.tcp_backlog as i32)?;
+        
+        Ok(socket.into())
+    }
+    
+    fn accept_connections(&self, socket: &TcpListener, listener: &Listener) {
+        // Hand every connection to the thread pool, so a kept-alive connection doesn't hold up the ones behind it. The
+        // scope waits for the connections still being handled once the loop stops.
+        self.thread_pool.in_place_scope(|scope| {
+            // Accept incoming connections.
+            for stream in socket.incoming() {
+                // A shutdown wakes the loop up with a connection of its own, which is dropped without an answer.
+                if self.shutdown_signal.is_requested() {
+                    break;
+                }
+                
+                // Accepting fails for reasons like the client resetting the connection or running out of file
+                // descriptors, neither of which should take the server down.
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(error) => {
+                        self.error_log.log_error(&format!("Failed to accept a connection: {}", error));
+                        
+                        continue;
+                    }
+                };
+                
+                // Terminate TLS if it's enabled, the handshake only happens once the connection is read from.
+                let stream = match ClientStream::new(stream, listener.get_tls_config()) {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                
+                // Refuse clients that aren't allowed to connect, unless they're a proxy that may be forwarding for
+                // someone who is, which is decided once the request names the client.
+                if let Ok(address) = stream.peer_addr() {
+                    if !network::ip_in_range(address.ip(), &self.trusted_proxies) && !self.access.is_allowed(address.ip()) {
+                        scope.spawn(move |_| self.refuse_connection(stream, address.ip(), 403, "address not allowed"));
+                        
+                        continue;
+                    }
+                }
+                
+                // Refuse clients that already have as many connections open as they may, the guard releases the slot.
+                let connection_guard = match (&self.connection_limiter, stream.peer_addr()) {
+                    (Some(limiter), Ok(address)) => match limiter.acquire(address.ip()) {
+                        Some(guard) => Some(guard),
+                        None => {
+                            scope.spawn(move |_| self.refuse_connection(stream, address.ip(), 429, "too many open connections"));
+                            
+                            continue;
+                        }
+                    },
+                    _ => None,
+                };
+                
+                // Let a shutdown wait for the connection to finish, or close it once the grace period is over.
+                let tracker = self.shutdown_signal.track(stream.get_tcp_stream());
+                
+                // Keep a handle to the stream so the client can still be answered if handling the connection panics. A
+                // TLS connection can't be written to without its session, which is lost along with the panicking handler.
+                let fallback = match &stream {
+                    ClientStream::Plain(stream) => stream.try_clone().ok(),
+                    ClientStream::Tls(_) => None,
+                };
+                
+                // Use a thread from the thread pool to handle the connection.
+                scope.spawn(move |_| {
+                    let _connection_guard = connection_guard;
+                    match panic::catch_unwind(AssertUnwindSafe(|| self.handle_connection(stream, listener, tracker.as_ref()))) {
+                        Ok(Ok(())) => {}
+                        // Connection errors only affect the one client, which has most likely gone away already.
+                        Ok(Err(error)) => debug!("{}", error),
+                        // The panic hook, if it's installed, has logged where the panic happened, all that's left is
+                        // to count it and tell the client.
+                        Err(_) => {
+                            self.panics_total.fetch_add(1, Ordering::Relaxed);
+                            
+                            if let Some(mut stream) = fallback {
+                                let mut response = Response::with_status(StatusCode::InternalServerError).with_header("Connection", "close");
+                                self.finalize_response(&mut response);
+                                
+                                let _ = stream.write_all(&response.to_bytes());
+                            }
+                        }
+                    }
+                });
+            }
+        });
+    }
+    
+    fn handle_connection(&self, mut stream: ClientStream, listener: &Listener, tracker: Option<&ConnectionTracker>) -> Result<(), ServerError> {
+        // Get the client's address for logging.
+        let client_ip = stream.peer_addr()?.ip();
+        
+        // Don't let an idle client hold on to the connection, and a thread with it, for longer than the timeout.
+        let _ = stream.get_tcp_stream().set_read_timeout(Some(Duration::from_secs(self.keep_alive_timeout_secs)));
+        
+        // Bytes read past the end of a request are the start of the next one.
+        let mut buffer = Vec::new();
+        
+        // Serve requests until the client or the server closes the connection.
+        for request_count in 1..=self.max_keep_alive_requests {
+            let allow_keep_alive = request_count < self.max_keep_alive_requests;
+            
+            // A connection waiting for its next request is closed straight away when the server shuts down.
+            if buffer.is_empty() && !tracker.is_none_or(|tracker| tracker.set_idle(true)) {
+                return Ok(());
+            }
+            
+            stream = match self.handle_request(stream, listener, client_ip, &mut buffer, allow_keep_alive, tracker)? {
+                Some(stream) => stream,
+                None => return Ok(()),
+            };
+        }
+        
+        Ok(())
+    }
+    
+    /// Handles a single request on the connection, returning the stream if it's kept alive for another one.
+    fn handle_request(&self, mut stream: ClientStream, listener: &Listener, client_ip: IpAddr, buffer: &mut Vec<u8>, allow_keep_alive: bool, tracker: Option<&ConnectionTracker>) -> Result<Option<ClientStream>, ServerError> {
+        let mut chunk = [0; 1024];
+        
+        // Read until the end of the headers, anything read after that is the start of the body.
+        let header_end = loop {
+            // Check the URL as soon as it's complete or known to be too long, before parsing anything.
+            let request_line_end = buffer.windows(2).position(|window| window == b"\r\n").unwrap_or(buffer.len());
+            let url_length = buffer[..request_line_end].split(|byte| *byte == b' ').nth(1).map_or(0, |url| url.len());
+            
+            if url_length > self.max_url_length {
+                self.refuse(&mut stream, client_ip, 414, &format!("URL longer than {} bytes", self.max_url_length));
+                
+                return Ok(None);
+            }
+            
+            // Stop reading as soon as the headers are known to be too large, the body doesn't count towards the limit.
+            let header_end = buffer.windows(4).position(|window| window == b"\r\n\r\n").map(|position| position + 4);
+            
+            if header_end.unwrap_or(buffer.len()) > self.max_header_bytes {
+                self.refuse(&mut stream, client_ip, 431, &format!("headers larger than {} bytes", self.max_header_bytes));
+                
+                return Ok(None);
+            }
+            
+            // The bytes left over from the previous request may already hold all of the headers.
+            if let Some(header_end) = header_end {
+                break header_end;
+            }
+            
+            // A read error usually means the keep-alive timeout passed, either way the connection is done.
+            let bytes_read = match stream.read(&mut chunk) {
+                Ok(bytes_read) => bytes_read,
+                Err(_) => return Ok(None),
+            };
+            
+            if bytes_read == 0 {
+                break buffer.len();
+            }
+            
+            // Once a request starts arriving, a shutdown lets it finish.
+            if buffer.is_empty() {
+                if let Some(tracker) = tracker {
+                    tracker.set_idle(false);
+                }
+            }
+            
+            buffer.extend_from_slice(&chunk[..bytes_read]);
+        };
+        
+        // The request starts being handled once its headers are in, not while the connection waits for it.
+        let start = Instant::now();
+        let bytes_read = buffer.len();
+        
+        // Convert the headers to a string.
+        let request = String::from_utf8_lossy(&buffer[..header_end]);
+        
+        // Parse the request, a client that closes the connection without sending anything doesn't need an answer.
+        let mut request = match Request::parse(&request) {
+            Ok(request) => request,
+            Err(HttpParseError::MissingRequestLine) => return Ok(None),
+            Err(error) => {
+                self.refuse(&mut stream, client_ip, error.status_code(), &error.to_string());
+                
+                return Ok(None);
+            }
+        };
+        
+        // Create the context that ties together everything logged for this request.
+        let context = ConnectionContext::new(client_ip, &request, start, stream.is_tls(), listener.get_name());
+        
+        // Turn away clients that may not make requests, to the server or to the route, before anything is read from disk.
+        if !self.is_client_allowed(client_ip, &request) {
+            let response = self.error_response(&context, 403, &request, "Your address is not allowed to access this resource.");
+            
+            self.send_response(&mut stream, &context, &request, response)?;
+            
+            return Ok(None);
+        }
+        
+        // Turn away clients that are making too many requests, counting the requests forwarded by trusted proxies
+        // against the clients they're forwarded for.
+        if let Some(rate_limiter) = &self.rate_limiter {
+            if let Err(retry_after) = rate_limiter.check(self.client_address(client_ip, &request)) {
+                let mut response = self.error_response(&context, 429, &request, "Too many requests, please try again later.");
+                response.add_header("Retry-After", &(retry_after.as_secs_f64().ceil() as u64).to_string());
+                
+                self.send_response(&mut stream, &context, &request, response)?;
+                
+                return Ok(None);
+            }
+        }
+        
+        // Refuse disabled methods before anything else gets to see the request.
+        if self.disabled_methods.contains(request.get_method()) {
+            let mut response = self.error_response(&context, 405, &request, "The request method is disabled on this server.");
+            response.add_header("Allow", &allow_header(&self.allowed_methods(&self.get_site(), None, true)));
+            
+            self.send_response(&mut stream, &context, &request, response)?;
+            
+            return Ok(None);
+        }
+        
+        // Open a tunnel for CONNECT requests instead of serving a page, which needs the raw TCP connection.
+        if matches!(request.get_method(), Method::Connect) {
+            match stream.into_plain() {
+                Ok(stream) => self.handle_connect(stream, &context, &request, &buffer[header_end..bytes_read])?,
+                Err(mut stream) => {
+                    let response = self.error_response(&context, 501, &request, "CONNECT is not supported over TLS.");
+                    
+                    self.send_response(&mut stream, &context, &request, response)?;
+                }
+            }
+            
+            return Ok(None);
+        }
+        
+        // Ask for credentials on protected paths, the longest prefix that covers the path decides which ones.
+        let auth = self.auth.iter()
+            .filter(|auth| auth.covers(request.path()))
+            .max_by_key(|auth| auth.get_prefix().len());
+        
+        if let Some(auth) = auth {
+            match auth.authenticate(&request) {
+                Some(user) => context.set_user(&user),
+                None => {
+                    let mut response = self.error_response(&context, 401, &request, "Signing in is required to access this resource.");
+                    response.add_header("WWW-Authenticate", &auth.challenge());
+                    
+                    self.send_response(&mut stream, &context, &request, response)?;
+                    
+                    return Ok(None);
+                }
+            }
+        }
+        
+        // Require a valid token on the paths that need one, and hand its claims to whatever serves the request.
+        let validator = self.jwt.iter()
+            .filter(|validator| validator.covers(request.path()))
+            .max_by_key(|validator| validator.get_prefix().len());
+        
+        if let Some(validator) = validator {
+            match validator.validate(&request) {
+                Ok(claims) => {
+                    if let Some(subject) = claims["sub"].as_str() {
+                        context.set_user(subject);
+                    }
+                    
+                    request.set_claims(claims);
+                }
+                Err(error) => {
+                    let mut response = self.error_response(&context, 401, &request, &error.to_string());
+                    response.add_header("WWW-Authenticate", &error.challenge());
+                    
+                    self.send_response(&mut stream, &context, &request, response)?;
+                    
+                    return Ok(None);
+                }
+            }
+        }
+        
+        // Hand the connection over to a registered handler if the client asks to switch to its protocol. Handlers take
+        // over the raw TCP connection, so they're only available without TLS.
+        let upgrade_handler = upgrade::requested_protocols(&request).into_iter().find_map(|protocol| {
+            self.upgrade_handlers.iter().find(|handler| handler.protocol().eq_ignore_ascii_case(protocol))
+        });
+        
+        if let (Some(handler), false) = (upgrade_handler, stream.is_tls()) {
+            let response = Response::with_status(StatusCode::SwitchingProtocols)
+                .with_header("Connection", "Upgrade")
+                .with_header("Upgrade", handler.protocol());
+            
+            self.send_response(&mut stream, &context, &request, response)?;
+            
+            let stream = match stream.into_plain() {
+                Ok(stream) => stream,
+                Err(_) => return Ok(None),
+            };
+            
+            if let Err(error) = handler.handle(stream, request) {
+                self.error_log.log(&context, 101, &format!("The {} connection failed: {}", handler.protocol(), error));
+            }
+            
+            return Ok(None);
+        }
+        
+        // Open WebSockets for WebSocket routes and proxied routes, before the body of the handshake would be read. Other
+        // routes serve the request as if it didn't ask for an upgrade.
+        if websocket::is_upgrade(&request) {
+            let site = self.site_for(&request);
+            let target = site.as_ref().and_then(|site| site.router.find(request.path()).map(|(route, _)| route.get_target().clone()));
+            let buffered = &buffer[header_end..bytes_read];
+            
+            match (site, target) {
+                (_, Some(RouteTarget::WebSocket(name))) => {
+                    self.handle_websocket(stream, &context, &request, &name, buffered)?;
+                    
+                    return Ok(None);
+                }
+                (Some(site), Some(RouteTarget::Proxy(index))) => {
+                    self.proxy_websocket(stream, &context, &request, &site.proxies[index], buffered)?;
+                    
+                    return Ok(None);
+                }
+                _ => {}
+            }
+        }
+        
+        // A body whose length can't be read can't be told apart from the next request, so it's refused.
+        let content_length = match request.get_header("Content-Length").map(|length| length.trim().parse::<usize>()) {
+            Some(Ok(length)) => Some(length),
+            Some(Err(_)) => {
+                let response = self.error_response(&context, 400, &request, "The Content-Length header must be a number.");
+                
+                self.send_response(&mut stream, &context, &request, response)?;
+                
+                return Ok(None);
+            }
+            None => None,
+        };
+        
+        // Reject bodies that are announced to be too large before reading any of them.
+        if content_length.is_some_and(|length| length > self.max_body_size) {
+            let response = self.error_response(&context, 413, &request, "The request body is too large.");
+            
+            self.send_response(&mut stream, &context, &request, response)?;
+            
+            return Ok(None);
+        }
+        
+        // Let clients that wait for permission know they can send the body now.
+        if let Some(expectation) = request.get_header("Expect") {
+            if !expectation.eq_ignore_ascii_case("100-continue") {
+                let response = self.error_response(&context, 417, &request, "Only 100-continue expectations are supported.");
+                
+                self.send_response(&mut stream, &context, &request, response)?;
+                
+                return Ok(None);
+            }
+            
+            // Only a client that's about to send a body waits for permission, without one there's nothing to continue.
+            let chunked = request.get_header("Transfer-Encoding").is_some_and(|encoding| encoding.to_ascii_lowercase().contains("chunked"));
+            let expects_body = chunked || content_length.is_some_and(|length| length > 0);
+            
+            if expects_body && stream.write_all(&Response::with_status(StatusCode::Continue).to_bytes()).is_err() {
+                return Ok(None);
+            }
+        }
+        
+        // Read the body chunk by chunk, refusing to take more than the configured limit.
+        let mut body_reader = BodyReader::new(&mut stream, &buffer[header_end..bytes_read], &request).with_limit(self.max_body_size);
+        let streams_body = self.streams_body(&request);
+        
+        // Routes that read the body as it arrives get it left unread, everything else gets it buffered in the request.
+        if !streams_body {
+            match body_reader.read_all() {
+                // Keep the body as it was sent, so binary uploads survive.
+                Ok(body) => request.set_body_bytes(&body),
+                // A malformed body gets an answer, a client that went away doesn't need one.
+                Err(error) if body_reader.is_over_limit() || error.kind() == io::ErrorKind::InvalidData => {
+                    let response = self.body_error_response(&context, &request, &error, body_reader.is_over_limit());
+                    
+                    self.send_response(&mut stream, &context, &request, response)?;
+                    
+                    return Ok(None);
+                }
+                Err(error) => {
+                    self.error_log.log(&context, 400, &format!("Failed to read the request body: {}", error));
+                    
+                    return Ok(None);
+                }
+            }
+        }
+        
+        let body_reader = RefCell::new(body_reader);
+        
+        // Run the request through the middleware chain, ending with the page lookup.
+        let mut response = self.dispatch(&context, &request, &self.middleware, streams_body.then_some(&body_reader));
+        
+        // A response from the HEAD cache is already complete, it only lacks what's unique to each response.
+        let is_head = *request.get_method() == Method::Head;
+        let head_cached = context.get_head_cache_status() == Some(CacheStatus::Hit);
+        
+        // Skip whatever the route didn't read of the body, the next request on the connection starts after it. If that
+        // fails, there's no telling where the next request starts.
+        let mut body_reader = body_reader.into_inner();
+        
+        if body_reader.read_all().is_err() {
+            response.set_header("Connection", "close");
+        }
+        
+        // Keep whatever the client already sent of its next request.
+        *buffer = body_reader.into_leftover();
+        
+        // Make sure every body has a content type, so browsers don't have to guess.
+        if response.get_header("Content-Type").is_none() && (!response.get_body().is_empty() || response.is_streamed()) {
+            response.set_header("Content-Type", self.mime_types.resolve(request.path()));
+        }
+        
+        // Tag complete responses by their contents, so clients can revalidate their cached copy.
+        let cacheable = response.get_status_code() == 200 && (*request.get_method() == Method::Get || is_head);
+        
+        // Streamed bodies aren't in memory to be hashed, so they're only tagged if whoever streams them does it. An empty
+        // answer to a HEAD request most likely left the body out, so hashing it would tag a body that doesn't exist.
+        let has_body = !response.is_streamed() && (!is_head || !response.get_body().is_empty());
+        
+        if cacheable && !head_cached && has_body && response.get_header("ETag").is_none() {
+            response.set_header("ETag", &conditional::etag(response.get_body()));
+        }
+        
+        // Compress the body if the client accepts it and it's worth the effort.
+        if !head_cached {
+            self.compress_response(&request, &mut response);
+        }
+        
+        // Answer with 304 and no body if the client's copy is still current.
+        if cacheable {
+            let not_modified = match (request.get_header("If-None-Match"), request.get_header("If-Modified-Since")) {
+                // Entity tags are more precise than dates, so If-Modified-Since is ignored next to If-None-Match.
+                (Some(if_none_match), _) => conditional::etag_matches(if_none_match, response.get_header("ETag").unwrap_or_default()),
+                (None, Some(if_modified_since)) => response.get_header("Last-Modified")
+                    .is_some_and(|last_modified| conditional::not_modified_since(if_modified_since, last_modified)),
+                (None, None) => false,
+            };
+            
+            if not_modified {
+                response = conditional::not_modified(&response);
+            }
+        }
+        
+        // Send only the requested ranges, unless If-Range says the client's partial copy is outdated.
+        if cacheable && !head_cached && response.get_status_code() == 200 && !response.is_streamed() {
+            response.set_header("Accept-Ranges", "bytes");
+            
+            if let Some(range_header) = request.get_header("Range") {
+                let current = request.get_header("If-Range").is_none_or(|if_range| range::if_range_matches(if_range, &response));
+                
+                if current {
+                    match range::parse(range_header, response.get_body().len()) {
+                        RangeRequest::Ignored => {}
+                        RangeRequest::Unsatisfiable => response = range::range_not_satisfiable(response.get_body().len()),
+                        RangeRequest::Satisfiable(ranges) => response = range::partial_content(&response, &ranges),
+                    }
+                }
+            }
+        }
+        
+        // A streamed body of unknown length is chunked, except for HTTP/1.0 clients, for which closing the connection ends it.
+        let close_delimited = response.is_streamed() && response.get_body_length().is_none() && request.get_version() == HttpVersion::Http10;
+        
+        if response.is_streamed() && response.get_body_length().is_none() && !close_delimited {
+            response.set_header("Transfer-Encoding", "chunked");
+        }
+        
+        // HTTP/1.1 keeps connections open unless asked not to, while HTTP/1.0 clients have to ask for it. A server
+        // that's shutting down closes every connection once its response is sent.
+        let connection = request.get_header("Connection").unwrap_or_default().to_ascii_lowercase();
+        let keep_alive = allow_keep_alive
+            && !self.shutdown_signal.is_requested()
+            && !close_delimited
+            && !response.get_header("Connection").is_some_and(|connection| connection.eq_ignore_ascii_case("close"))
+            && match request.get_version() {
+                HttpVersion::Http10 => connection.contains("keep-alive"),
+                _ => !connection.contains("close"),
+            };
+        
+        // The client can only tell where the response ends on a kept-alive connection if it knows the length.
+        self.finalize_response(&mut response);
+        
+        // HEAD is answered like GET, headers and all, just without the body.
+        if is_head {
+            let head_cache_key = HeadCache::key(&request).filter(|_| context.get_head_cache_status() == Some(CacheStatus::Miss));
+            
+            if let Some(head_cache_key) = head_cache_key.filter(|_| cacheable && response.get_status_code() == 200) {
+                self.head_cache.write().unwrap_or_else(|poisoned| poisoned.into_inner()).insert(head_cache_key, &response);
+            }
+            
+            response.set_body_bytes(&[]);
+        }
+        
+        // A response offering to switch protocols, like a 426, has to list Upgrade as a connection option too.
+        let upgrade = if response.get_header("Upgrade").is_some() { "Upgrade, " } else { "" };
+        
+        if keep_alive {
+            response.set_header("Connection", &format!("{}keep-alive", upgrade));
+            response.set_header("Keep-Alive", &format!("timeout={}", self.keep_alive_timeout_secs));
+        } else {
+            response.set_header("Connection", &format!("{}close", upgrade));
+        }
+        
+        self.send_response(&mut stream, &context, &request, response)?;
+        
+        debug!("{} Served request!", context);
+        
+        if keep_alive {
+            Ok(Some(stream))
+        } else {
+            Ok(None)
+        }
+    }
+    
+    fn compress_response(&self, request: &Request, response: &mut Response) {
+        // A route's own setting takes precedence over the server-wide one.
+        let enabled = self.site_for(request).unwrap_or_else(|| self.get_site()).router.find(request.path())
+            .and_then(|(route, _)| route.get_compress())
+            .unwrap_or(self.compression.is_enabled());
+        
+        let content_type = match response.get_header("Content-Type") {
+            Some(content_type) => content_type.to_string(),
+            None => return,
+        };
+        
+        // Streamed bodies aren't in memory, so they're sent as they are.
+        if !enabled || response.is_streamed() || response.get_header("Content-Encoding").is_some() || !self.compression.is_eligible(&content_type) {
+            return;
+        }
+        
+        // From here on the response depends on Accept-Encoding, even for clients that get it uncompressed.
+        match response.get_header("Vary").map(str::to_string) {
+            Some(vary) if vary.split(',').any(|name| name.trim().eq_ignore_ascii_case("Accept-Encoding") || name.trim() == "*") => {}
+            Some(vary) => response.set_header("Vary", &format!("{}, Accept-Encoding", vary)),
+            None => response.set_header("Vary", "Accept-Encoding"),
+        }
+        
+        // Only complete, successful bodies are compressed, errors and bodies that ranges are taken from are left alone.
+        if response.get_status_code() != 200
+            || request.get_header("Range").is_some()
+            || !self.compression.should_compress(&content_type, response.get_body().len())
+        {
+            return;
+        }
+        
+        let encoding = match request.get_header("Accept-Encoding").and_then(compression::negotiate) {
+            Some(encoding) => encoding,
+            None => return,
+        };
+        
+        // Fall back to the uncompressed body rather than failing the request.
+        if let Ok(compressed) = encoding.compress(response.get_body()) {
+            response.set_body_bytes(&compressed);
+            response.set_header("Content-Encoding", encoding.name());
+            
+            // The compressed body is a different representation, so it needs its own tag.
+            if let Some(etag) = response.get_header("ETag").map(|etag| conditional::encoded_etag(etag, encoding)) {
+                response.set_header("ETag", &etag);
+            }
+            response.set_header("Content-Length", &compressed.len().to_string());
+        }
+    }
+    
+    /// Checks if the request's route reads the body as the client sends it, rather than getting it buffered up front.
+    fn streams_body(&self, request: &Request) -> bool {
+        let site = match self.site_for(request) {
+            Some(site) => site,
+            None => return false,
+        };
+        
+        match site.router.find(request.path()).map(|(route, _)| route.get_target()) {
+            Some(RouteTarget::Handler(_)) => true,
+            // Backends are told the length of the body before it's sent, which a chunked body only reveals at its end.
+            Some(RouteTarget::FastCgi(_)) => request.get_header("Transfer-Encoding").is_none(),
+            _ => false,
+        }
+    }
+    
+    /// `body` is the unread body of a request whose route streams it, and `None` if the body is buffered in the request.
+    fn dispatch(&self, context: &ConnectionContext, request: &Request, middleware: &[Box<dyn Middleware + Send + Sync>], body: Option<&RefCell<BodyReader>>) -> Response {
+        match middleware.split_first() {
+            Some((first, rest)) => first.handle(context, request, &|request| self.dispatch(context, request, rest, body)),
+            None => self.serve_page(context, request, body),
+        }
+    }
+    
+    fn serve_page(&self, context: &ConnectionContext, request: &Request, body: Option<&RefCell<BodyReader>>) -> Response {
+        // The path is already decoded, so escapes can't be used to sneak past the checks.
+        let path = request.path();
+        
+        // Refuse paths that try to leave the web root, whether written plainly, percent-encoded or with backslashes.
+        if path.split('/').any(|segment| segment == "..") || path.contains('\\') {
+            return self.error_response(context, 403, request, "The requested path is outside the web root.");
+        }
+        
+        // Hold on to the site for the whole request, so a reload can't swap its pages and routes out halfway.
+        let site = match self.site_for(request) {
+            Some(site) => site,
+            None => return self.error_response(context, 421, request, "This server doesn't serve the requested host."),
+        };
+        let route = site.router.find(path);
+        
+        // Let the key-value store be updated, but only by clients that had to sign in to get here.
+        if let Some(key) = path.strip_prefix(kv::ADMIN_PATH).filter(|_| self.requires_sign_in(kv::ADMIN_PATH)) {
+            return self.serve_kv_update(context, request, key);
+        }
+        
+        // Report the state of the upstreams, also only to clients that had to sign in.
+        if path == proxy::STATUS_PATH && self.requires_sign_in(proxy::STATUS_PATH) {
+            return self.serve_upstream_status(context, request, &site);
+        }
+        
+        // Export the metrics to scrapers that sign in the same way.
+        if path == METRICS_PATH && self.requires_sign_in(METRICS_PATH) {
+            return self.serve_metrics(context, request);
+        }
+        
+        // Answer OPTIONS and refuse the methods the target doesn't support, before it's served. OPTIONS * asks about
+        // the server as a whole.
+        let allowed = self.allowed_methods(&site, route.as_ref().map(|(route, _)| *route), path == "*");
+        let handles_options = route.as_ref().is_some_and(|(route, _)| match route.get_methods() {
+            Some(methods) => methods.contains(&Method::Options),
+            // Upstreams and FastCGI backends answer OPTIONS themselves, e.g. for CORS preflight requests.
+            None => matches!(route.get_target(), RouteTarget::Proxy(_) | RouteTarget::FastCgi(_)),
+        });
+        
+        if *request.get_method() == Method::Options && !handles_options {
+            return Response::with_status(StatusCode::NoContent).with_header("Allow", &allow_header(&allowed));
+        }
+        
+        if !allowed.contains(request.get_method()) {
+            let mut response = self.error_response(context, 405, request, "The request method is not allowed for this resource.");
+            response.add_header("Allow", &allow_header(&allowed));
+            
+            return response;
+        }
+        
+        // Serve the generated robots.txt, unless there's a physical one in the web root.
+        if let Some(robots_txt) = &self.robots_txt {
+            if path == "/robots.txt" {
+                let contents = fs::read_to_string(Path::new(&site.web_root).join("robots.txt"))
+                    .unwrap_or_else(|_| robots_txt.render().to_string());
+                
+                let mut response = Response::ok();
+                response.add_header("Content-Type", "text/plain; charset=us-ascii");
+                response.set_body(&contents);
+                
+                return response;
+            }
+        }
+        
+        // Serve the OpenAPI document and Swagger UI, which are generated rather than read from the web root.
+        if let Some(openapi) = &self.openapi {
+            if path == openapi.get_json_path() {
+                return self.serve_openapi(&site, openapi);
+            }
+            
+            if path == openapi::SWAGGER_UI_PATH.trim_end_matches('/') {
+                return Response::with_status(StatusCode::MovedPermanently).with_header("Location", openapi::SWAGGER_UI_PATH);
+            }
+            
+            if let Some(name) = path.strip_prefix(openapi::SWAGGER_UI_PATH) {
+                return match openapi::swagger_ui_asset(openapi, name) {
+                    Some((contents, content_type)) => {
+                        let mut response = Response::ok()
+                            .with_header("Content-Type", content_type)
+                            .with_header("Cache-Control", "public, max-age=86400");
+                        response.set_body_bytes(&contents);
+                        
+                        response
+                    }
+                    None => self.error_response(context, 404, request, "The requested resource was not found."),
+                };
+            }
+        }
+        
+        // Serve site verification files from the .well-known directory (RFC 8615).
+        if let Some(name) = path.strip_prefix("/.well-known/") {
+            return self.serve_well_known(context, request, &site, name);
+        }
+        
+        // Routes, including the configured pages, take precedence over the files in the web root.
+        if let Some((route, params)) = route {
+            return self.serve_route(context, request, &site, route, &params, body);
+        }
+        
+        // Answer the browser's automatic favicon request unless it's routed elsewhere.
+        if path == "/favicon.ico" {
+            return self.serve_favicon(&site);
+        }
+        
+        // Only serve the configured pages and routes if unlisted files are denied, the web root may hold files that
+        // were never meant to be public. The first page still answers for the root, as the index page.
+        if self.deny_unlisted {
+            return match path {
+                "/" => self.render_page(context, request, &site.pages[0]),
+                _ => self.error_response(context, 404, request, "The requested resource was not found."),
+            };
+        }
+        
+        if let Some(redirect) = path.strip_prefix('/').and_then(|path| self.redirect_to_directory(request, &site, path)) {
+            return redirect;
+        }
+        
+        if let Some(file) = path.strip_prefix('/').and_then(|path| self.resolve_file(&site, path)) {
+            return self.serve_file(context, request, &site, &file);
+        }
+        
+        self.error_response(context, 404, request, "The requested resource was not found.")
+    }
+    
+    /// Returns the methods a route answers, or the web root's files if there's no route, less the disabled ones.
+    ///
+    /// Handlers and templates get every method, since they can tell them apart, static content only gets GET and HEAD.
+    fn allowed_methods(&self, site: &Site, route: Option<&Route>, server_wide: bool) -> Vec<Method> {
+        let mut methods = match (route.and_then(Route::get_methods), route.map(Route::get_target)) {
+            _ if server_wide => Method::ALL.to_vec(),
+            (Some(methods), _) => methods.clone(),
+            (None, Some(RouteTarget::Handler(_) | RouteTarget::Proxy(_) | RouteTarget::FastCgi(_))) => Method::ALL.to_vec(),
+            (None, Some(RouteTarget::Page(index))) if site.pages[*index].is_template() => Method::ALL.to_vec(),
+            _ => STATIC_METHODS.to_vec(),
+        };
+        
+        // Whatever answers GET answers HEAD, and OPTIONS is always answered.
+        if methods.contains(&Method::Get) && !methods.contains(&Method::Head) {
+            methods.push(Method::Head);
+        }
+        
+        if !methods.contains(&Method::Options) {
+            methods.push(Method::Options);
+        }
+        
+        // CONNECT is never routed, it opens a tunnel before routes are looked at.
+        methods.retain(|method| !self.disabled_methods.contains(method) && (server_wide || *method != Method::Connect));
+        methods.sort_by_key(|method| Method::ALL.iter().position(|candidate| candidate == method));
+        
+        methods
+    }
+    
+    fn serve_route(&self, context: &ConnectionContext, request: &Request, site: &Site, route: &Route, params: &RouteParams, body: Option<&RefCell<BodyReader>>) -> Response {
+        let mut response = match route.get_target() {
+            RouteTarget::Page(index) => self.render_page(context, request, &site.pages[*index]),
+            // A wildcard route serves the rest of the path from the directory it points to.
+            RouteTarget::File(file) => {
+                let file = match params.get("*") {
+                    Some(rest) => [file.trim_end_matches('/'), rest].iter()
+                        .filter(|part| !part.is_empty())
+                        .copied()
+                        .collect::<Vec<_>>()
+                        .join("/"),
+                    None => file.clone(),
+                };
+                
+                // Only wildcard routes map onto directories by the request path, an exact route serves the index as is.
+                let redirect = params.get("*").and_then(|_| self.redirect_to_directory(request, site, &file));
+                
+                if let Some(redirect) = redirect {
+                    redirect
+                } else {
+                    match self.resolve_file(site, &file) {
+                        Some(file) => self.serve_file(context, request, site, &file),
+                        None => match self.resolve_path(site, &file).filter(|path| route.is_autoindex() && path.is_dir()) {
+                            Some(directory) => self.serve_directory(context, request, site, &directory),
+                            None => self.error_response(context, 404, request, "The requested resource was not found."),
+                        },
+                    }
+                }
+            }
+            RouteTarget::Handler(name) => match (self.handlers.get(name), body) {
+                (Some(handler), Some(body)) => {
+                    let mut body = body.borrow_mut();
+                    
+                    match handler.handle_body(request, params, &mut body) {
+                        Ok(response) => response,
+                        Err(error) => self.body_error_response(context, request, &error, body.is_over_limit()),
+                    }
+                }
+                (Some(handler), None) => handler.handle(request, params),
+                (None, _) => self.error_response(context, 500, request, &format!("No handler named {} is registered.", name)),
+            },
+            RouteTarget::Proxy(index) => self.serve_proxy(context, request, &site.proxies[*index]),
+            RouteTarget::FastCgi(index) => self.serve_fastcgi(context, request, body, site, &site.fastcgi[*index]),
+            RouteTarget::WebSocket(_) => {
+                let mut response = self.error_response(context, 426, request, "This resource can only be used over a WebSocket connection.");
+                response.add_header("Upgrade", "websocket");
+                
+                response
+            }
+        };
+        
+        // Route headers take precedence over the ones set by pages and the server.
+        for (name, value) in route.get_headers() {
+            response.set_header(name, value);
+        }
+        
+        response
+    }
+    
+    fn serve_proxy(&self, context: &ConnectionContext, request: &Request, proxy: &Proxy) -> Response {
+        // Refuse the request straight away if no upstream can take it, rather than letting it wait for a failing one.
+        let upstream = match proxy.select() {
+            Ok(upstream) => upstream,
+            Err(retry_after) => return self.unavailable_response(context, request, retry_after),
+        };
+        
+        context.set_upstream(upstream.get_url());
+        
+        match proxy.forward(&upstream, request, context) {
+            Ok(response) => response,
+            Err(error) => self.proxy_error_response(context, request, proxy, &upstream, &error),
+        }
+    }
+    
+    fn serve_fastcgi(&self, context: &ConnectionContext, request: &Request, body: Option<&RefCell<BodyReader>>, site: &Site, backend: &FastCgi) -> Response {
+        let address = backend.get_address().to_string();
+        context.set_upstream(&address);
+        context.set_upstream_timeout(backend.get_timeout());
+        
+        // Backends look scripts up by absolute path, and the web root may be relative to the working directory.
+        let document_root = fs::canonicalize(site.get_web_root()).unwrap_or_else(|_| PathBuf::from(site.get_web_root()));
+        
+        let mut body = body.map(RefCell::borrow_mut);
+        
+        match backend.send(request, body.as_deref_mut(), &document_root, context.get_client_ip(), context.is_tls()) {
+            Ok(response) => response,
+            Err(ProxyError::RequestBody(error)) => {
+                let over_limit = body.is_some_and(|body| body.is_over_limit());
+                
+                self.body_error_response(context, request, &error, over_limit)
+            }
+            Err(ProxyError::Timeout) => self.error_response(context, 504, request, "The upstream server didn't answer in time.")
+                .with_error(&format!("The FastCGI backend {} didn't answer within {}s.", address, backend.get_timeout().as_secs())),
+            Err(error) => self.error_response(context, 502, request, "The upstream server couldn't be reached.")
+                .with_error(&format!("Failed to pass the request to the FastCGI backend {}: {}", address, error)),
+        }
+    }
+    
+    /// Answers with 413 if the body went over the limit and with 400 if it's malformed or couldn't be read.
+    fn body_error_response(&self, context: &ConnectionContext, request: &Request, error: &io::Error, over_limit: bool) -> Response {
+        if over_limit {
+            info!("{} Rejected a request body larger than {} bytes!", context, self.max_body_size);
+            
+            return self.error_response(context, 413, request, "The request body is too large.");
+        }
+        
+        self.error_response(context, 400, request, &format!("The request body is malformed: {}", error))
+    }
+    
+    fn unavailable_response(&self, context: &ConnectionContext, request: &Request, retry_after: Duration) -> Response {
+        let mut response = self.error_response(context, 503, request, "No upstream server is available, please try again later.");
+        response.add_header("Retry-After", &(retry_after.as_secs_f64().ceil() as u64).max(1).to_string());
+        
+        response
+    }
+    
+    /// Answers with 504 if the upstream timed out and 502 for anything else that went wrong talking to it.
+    fn proxy_error_response(&self, context: &ConnectionContext, request: &Request, proxy: &Proxy, upstream: &Upstream, error: &ProxyError) -> Response {
+        match error {
+            ProxyError::Timeout => self.error_response(context, 504, request, "The upstream server didn't answer in time.")
+                .with_error(&format!("The upstream {} didn't answer within {}s.", upstream.get_url(), proxy.get_timeout().as_secs())),
+            error => self.error_response(context, 502, request, "The upstream server couldn't be reached.")
+                .with_error(&format!("Failed to proxy to {}: {}", upstream.get_url(), error)),
+        }
+    }
+    
+    fn render_page(&self, context: &ConnectionContext, request: &Request, page: &Page) -> Response {
+        let mut response = Response::ok();
+        
+        // Render templates with what's known about the request, static pages are served as is.
+        if page.is_template() {
+            let mut template_context = TemplateContext::new();
+            template_context.set("path", request.path());
+            template_context.set("query", request.get_path().split_once('?').map(|(_, query)| query).unwrap_or_default());
+            template_context.set("method", &request.get_method().to_string());
+            template_context.set("client_ip", &context.get_client_ip().to_string());
+            template_context.set("request_id", &context.get_request_id().to_string());
+            
+            // Look up the key-value entries the page uses as {{ kv.name }}, strings as they are and anything else as JSON.
+            // Keys that aren't set render empty, like any value that hasn't been stored yet.
+            for name in page.get_template_variables() {
+                if let Some(key) = name.strip_prefix("kv.") {
+                    match self.kv_store.get(key) {
+                        Some(value) => match value.as_str() {
+                            Some(value) => template_context.set(name, value),
+                            None => template_context.set(name, &value.dump()),
+                        },
+                        None => template_context.set(name, ""),
+                    }
+                }
+            }
+            
+            // What went wrong is only for the error log, the client can't do anything about a broken template.
+            match page.render_template(&template_context) {
+                Ok(contents) => response.set_body(&contents),
+                Err(error) => {
+                    return self.error_response(context, 500, request, "The page could not be rendered.")
+                        .with_error(&format!("Failed to render {}: {}", page.get_path(), error));
+                }
+            }
+        } else {
+            response.set_body_bytes(page.get_contents());
+            
+            // Only static pages have a modification time, templates change with every request.
+            if let Some(modified) = page.get_modified() {
+                response.set_header("Last-Modified", &conditional::last_modified(modified));
+            }
+        }
+        
+        response.set_header("Content-Type", self.mime_types.resolve(page.get_path()));
+        
+        // Page headers take precedence over the ones set by the server.
+        for (name, value) in page.get_headers() {
+            response.set_header(name, value);
+        }
+        
+        // Let the body filters post-process the response.
+        for filter in &self.body_filters {
+            filter.filter(request, &mut response);
+        }
+        
+        response
+    }
+    
+    fn serve_file(&self, context: &ConnectionContext, request: &Request, site: &Site, path: &Path) -> Response {
+        if !self.is_inside_web_root(site, path) {
+            return self.error_response(context, 403, request, "The requested path is outside the web root.");
+        }
+        
+        // Answer HEAD requests from the cache while a recent answer is known, without reading the file again.
+        if let Some(key) = HeadCache::key(request) {
+            let cached_headers = self.head_cache.read().unwrap_or_else(|poisoned| poisoned.into_inner()).get(&key).cloned();
+            
+            match cached_headers {
+                Some(headers) => {
+                    context.set_head_cache_status(CacheStatus::Hit);
+                    
+                    let mut response = Response::ok();
+                    *response.headers_mut() = headers;
+                    
+                    return response;
+                }
+                None => context.set_head_cache_status(CacheStatus::Miss),
+            }
+        }
+        
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) if metadata.is_file() => metadata,
+            _ => return self.error_response(context, 404, request, "The requested resource was not found."),
+        };
+        
+        // Serve precompressed files as the file they contain, e.g. app.js.gz as app.js.
+        let encoding = path.extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(Encoding::from_extension)
+            .filter(|_| self.serve_precompressed);
+        
+        // Large files are copied from disk while they're sent, rather than read into memory.
+        if !self.file_cache.is_cacheable(metadata.len()) {
+            return self.stream_file(context, request, path, encoding, &metadata);
+        }
+        
+        let contents = match self.file_cache.read(path, &metadata) {
+            Ok(contents) => contents,
+            Err(_) => return self.error_response(context, 404, request, "The requested resource was not found."),
+        };
+        
+        let mut response = match encoding {
+            Some(encoding) => self.serve_precompressed(context, request, path, encoding, &contents),
+            None => {
+                let mut response = Response::ok()
+                    .with_header("Content-Type", self.mime_types.resolve(&path.to_string_lossy()));
+                response.set_body_bytes(&contents);
+                
+                // Let the body filters post-process the response.
+                for filter in &self.body_filters {
+                    filter.filter(request, &mut response);
+                }
+                
+                response
+            }
+        };
+        
+        if let Ok(modified) = metadata.modified() {
+            if response.get_status_code() == 200 {
+                response.set_header("Last-Modified", &conditional::last_modified(modified));
+            }
+        }
+        
+        response
+    }
+    
+    fn stream_file(&self, context: &ConnectionContext, request: &Request, path: &Path, encoding: Option<Encoding>, metadata: &fs::Metadata) -> Response {
+        let file = match File::open(path) {
+            Ok(file) => BufReader::with_capacity(file_cache::STREAM_BUFFER_BYTES, file),
+            Err(_) => return self.error_response(context, 404, request, "The requested resource was not found."),
+        };
+        
+        let mut response = Response::ok();
+        
+        // Body filters are skipped, since the body is never in memory to be rewritten.
+        match encoding {
+            Some(encoding) => {
+                response.set_header("Content-Type", self.mime_types.resolve(&path.with_extension("").to_string_lossy()));
+                response.set_header("Vary", "Accept-Encoding");
+                
+                // Clients that can't read the encoding get the file decompressed while it's sent, its length unknown.
+                if request.get_header("Accept-Encoding").is_some_and(|accept_encoding| compression::accepts(accept_encoding, encoding)) {
+                    response.set_header("Content-Encoding", encoding.name());
+                    response.set_body_stream(file, Some(metadata.len()));
+                } else {
+                    response.set_body_stream(encoding.decoder(file), None);
+                }
+            }
+            None => {
+                response.set_header("Content-Type", self.mime_types.resolve(&path.to_string_lossy()));
+                response.set_body_stream(file, Some(metadata.len()));
+            }
+        }
+        
+        // Tag the file by its size and modification time, so clients can still revalidate it without it being hashed.
+        if let Ok(modified) = metadata.modified() {
+            let etag = conditional::file_etag(metadata.len(), modified);
+            
+            match encoding.filter(|_| response.get_header("Content-Encoding").is_some()) {
+                Some(encoding) => response.set_header("ETag", &conditional::encoded_etag(&etag, encoding)),
+                None => response.set_header("ETag", &etag),
+            }
+            
+            response.set_header("Last-Modified", &conditional::last_modified(modified));
+        }
+        
+        response
+    }
+    
+    fn serve_precompressed(&self, context: &ConnectionContext, request: &Request, path: &Path, encoding: Encoding, contents: &[u8]) -> Response {
+        // The content type comes from the extension underneath the compression one.
+        let mut response = Response::ok()
+            .with_header("Content-Type", self.mime_types.resolve(&path.with_extension("").to_string_lossy()))
+            .with_header("Vary", "Accept-Encoding");
+        
+        // Clients that can't read the encoding get the file decompressed on the fly.
+        if request.get_header("Accept-Encoding").is_some_and(|accept_encoding| compression::accepts(accept_encoding, encoding)) {
+            response.set_header("Content-Encoding", encoding.name());
+            response.set_body_bytes(contents);
+        } else {
+            match encoding.decompress(contents) {
+                Ok(contents) => response.set_body_bytes(&contents),
+                Err(error) => {
+                    return self.error_response(context, 500, request, "The requested resource could not be decompressed.")
+                        .with_error(&format!("Failed to decompress {}: {}", path.display(), error));
+                }
+            }
+        }
+        
+        // Body filters are skipped, since they can't rewrite a compressed body.
+        response
+    }
+    
+    fn serve_directory(&self, context: &ConnectionContext, request: &Request, site: &Site, directory: &Path) -> Response {
+        if !self.is_inside_web_root(site, directory) {
+            return self.error_response(context, 403, request, "The requested path is outside the web root.");
+        }
+        
+        let path = request.get_path().split('?').next().unwrap_or_default();
+        
+        match autoindex::render(path, directory) {
+            Ok(listing) => {
+                let mut response = Response::ok()
+                    .with_header("Content-Type", "text/html; charset=utf-8");
+                response.set_body(&listing);
+                
+                response
+            }
+            Err(error) => self.error_response(context, 500, request, "The directory could not be listed.")
+                .with_error(&format!("Failed to list {}: {}", directory.display(), error)),
+        }
+    }
+    
+    /// Checks if signing in is required for a path, which is what enables `POST /_kv/<key>`, `GET /status` and
+    /// `GET /metrics`.
+    fn requires_sign_in(&self, path: &str) -> bool {
+        self.auth.iter().any(|auth| auth.covers(path)) || self.jwt.iter().any(|validator| validator.covers(path))
+    }
+    
+    /// Sets a key in the key-value store to the JSON body of the request.
+    fn serve_kv_update(&self, context: &ConnectionContext, request: &Request, key: &str) -> Response {
+        if *request.get_method() != Method::Post {
+            let mut response = self.error_response(context, 405, request, "The key-value store can only be updated with POST.");
+            response.add_header("Allow", "POST");
+            
+            return response;
+        }
+        
+        if key.is_empty() {
+            return self.error_response(context, 404, request, "The requested resource was not found.");
+        }
+        
+        match request.get_body_text().map(json::parse) {
+            Some(Ok(value)) => {
+                self.kv_store.set(key, value);
+                
+                Response::with_status(StatusCode::NoContent)
+            }
+            _ => self.error_response(context, 400, request, "The request body must be a JSON value."),
+        }
+    }
+    
+    /// Lists the upstreams of every proxy route, with whether they're healthy, the state of their circuit and how many
+    /// requests they're handling.
+    fn serve_upstream_status(&self, context: &ConnectionContext, request: &Request, site: &Site) -> Response {
+        if !matches!(request.get_method(), Method::Get | Method::Head) {
+            let mut response = self.error_response(context, 405, request, "The upstream status can only be read with GET.");
+            response.add_header("Allow", "GET, HEAD");
+            
+            return response;
+        }
+        
+        let proxies = site.router.routes()
+            .filter_map(|route| match route.get_target() {
+                RouteTarget::Proxy(index) => Some(json::object! {
+                    "path": route.get_pattern(),
+                    "upstreams": site.proxies[*index].get_upstreams().iter().map(|upstream| json::object! {
+                        "url": upstream.get_url(),
+                        "healthy": upstream.get_status() == UpstreamStatus::Healthy,
+                        "circuit": upstream.get_circuit_breaker().get_state().name(),
+                        "active_requests": upstream.get_active_requests(),
+                    }).collect::<Vec<_>>(),
+                }),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        
+        let mut response = Response::ok()
+            .with_header("Content-Type", "application/json")
+            .with_header("Cache-Control", "no-store");
+        response.set_body(&json::object! { "proxies": proxies }.dump());
+        
+        response
+    }
+    
+    /// Exports the server's counters in the Prometheus text format.
+    fn serve_metrics(&self, context: &ConnectionContext, request: &Request) -> Response {
+        if !matches!(request.get_method(), Method::Get | Method::Head) {
+            let mut response = self.error_response(context, 405, request, "The metrics can only be read with GET.");
+            response.add_header("Allow", "GET, HEAD");
+            
+            return response;
+        }
+        
+        let mut response = Response::ok()
+            .with_header("Content-Type", "text/plain; version=0.0.4; charset=utf-8")
+            .with_header("Cache-Control", "no-store");
+        response.set_body(&format!(
+            "# HELP web_server_panics_total Connections that panicked while they were handled.\n\
+             # TYPE web_server_panics_total counter\n\
+             web_server_panics_total {}\n",
+            self.get_panics_total(),
+        ));
+        
+        response
+    }
+    
+    /// Lists the routes answered by handlers in the OpenAPI document, with the metadata they were described with.
+    fn serve_openapi(&self, site: &Site, openapi: &OpenApiConfig) -> Response {
+        let mut routes = site.router.routes()
+            .filter(|route| matches!(route.get_target(), RouteTarget::Handler(_)))
+            .map(|route| ApiRoute {
+                pattern: route.get_pattern(),
+                methods: self.allowed_methods(site, Some(route), false),
+                metadata: self.route_metadata.get(route.get_pattern()),
+            })
+            .collect::<Vec<_>>();
+        routes.sort_by_key(|route| route.pattern);
+        
+        let mut response = Response::ok().with_header("Content-Type", "application/json");
+        response.set_body(&openapi::document(openapi, &routes).dump());
+        
+        response
+    }
+    
+    fn serve_favicon(&self, site: &Site) -> Response {
+        let path = Path::new(&site.web_root).join(self.favicon.as_deref().unwrap_or("favicon.ico"));
+        
+        // Fall back to a transparent icon rather than filling the logs with 404s.
+        let icon = fs::read(path).unwrap_or_else(|_| TRANSPARENT_FAVICON.to_vec());
+        
+        let mut response = Response::ok()
+            .with_header("Content-Type", "image/x-icon")
+            .with_header("Cache-Control", "public, max-age=86400");
+        response.set_body_bytes(&icon);
+        
+        response
+    }
+    
+    fn serve_well_known(&self, context: &ConnectionContext, request: &Request, site: &Site, name: &str) -> Response {
+        // Refuse anything that could escape the directory, as well as directories themselves since they're never listed.
+        if name.split('/').any(|segment| segment.is_empty() || segment == "." || segment == "..") || name.contains('\\') {
+            return self.error_response(context, 404, request, "The requested resource was not found.");
+        }
+        
+        let directory = match &self.well_known_dir {
+            Some(well_known_dir) => Path::new(well_known_dir).to_path_buf(),
+            None => Path::new(&site.web_root).join(".well-known"),
+        };
+        
+        let path = directory.join(name);
+        
+        if !path.is_file() {
+            return self.error_response(context, 404, request, "The requested resource was not found.");
+        }
+        
+        let contents = match fs::read(&path) {
+            Ok(contents) => contents,
+            Err(_) => return self.error_response(context, 404, request, "The requested resource was not found."),
+        };
+        
+        // ACME tokens and security.txt are plain text, WebFinger and similar protocols use JSON.
+        let content_type = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => "application/json",
+            Some("txt") | None => "text/plain; charset=utf-8",
+            Some(_) => "application/octet-stream",
+        };
+        
+        let mut response = Response::ok();
+        response.add_header("Content-Type", content_type);
+        response.set_body_bytes(&contents);
+        
+        response
+    }
+    
+    /// Answers a request that couldn't be parsed, so there's no request to build a regular error response from, and
+    /// closes the connection.
+    fn refuse(&self, stream: &mut ClientStream, client_ip: IpAddr, status_code: u16, reason: &str) {
+        let mut response = Response::new(HttpVersion::Http11, status_code, http::reason_phrase(status_code));
+        response.add_header("Connection", "close");
+        self.finalize_response(&mut response);
+        
+        let _ = stream.write_all(&response.to_bytes());
+        
+        info!("{} Refused a request: {}", client_ip, reason);
+        
+        // Closing a socket with unread data resets it, which can discard the refusal before the client reads it, so
+        // stop writing and wait for the client to close its end instead.
+        let tcp_stream = stream.get_tcp_stream();
+        let _ = tcp_stream.set_read_timeout(Some(REFUSAL_TIMEOUT));
+        let _ = tcp_stream.shutdown(Shutdown::Write);
+        let _ = io::copy(&mut tcp_stream.take(MAX_REFUSAL_DRAIN_BYTES), &mut io::sink());
+    }
+    
+    /// Refuses a connection before reading a request from it. It's called on the thread pool, since a TLS client has to
+    /// finish its handshake first, and the timeouts keep a client that never does from holding on to the thread.
+    fn refuse_connection(&self, mut stream: ClientStream, client_ip: IpAddr, status_code: u16, reason: &str) {
+        let _ = stream.get_tcp_stream().set_read_timeout(Some(REFUSAL_TIMEOUT));
+        let _ = stream.get_tcp_stream().set_write_timeout(Some(REFUSAL_TIMEOUT));
+        
+        self.refuse(&mut stream, client_ip, status_code, reason);
+    }
+    
+    fn handle_connect(&self, mut stream: TcpStream, context: &ConnectionContext, request: &Request, buffered: &[u8]) -> Result<(), ServerError> {
+        let target = request.get_path();
+        
+        // Tunneling is disabled unless an allowlist is configured.
+        let allowlist = match &self.connect_allowlist {
+            Some(allowlist) => allowlist,
+            None => {
+                let response = self.error_response(context, 405, request, "CONNECT is disabled on this server.");
+                
+                self.send_response(&mut stream, context, request, response)?;
+                
+                return Ok(());
+            }
+        };
+        
+        // Validate the target before resolving it.
+        if !tunnel::is_allowed(target, allowlist) {
+            let response = self.error_response(context, 403, request, "The CONNECT target is not allowed.");
+            
+            self.send_response(&mut stream, context, request, response)?;
+            
+            return Ok(());
+        }
+        
+        // Forward anything the client sent along with the CONNECT request.
+        let upstream = match tunnel::open(target, &self.connect_access).and_then(|mut upstream| upstream.write_all(buffered).map(|_| upstream)) {
+            Ok(upstream) => upstream,
+            Err(error) => {
+                let response = self.error_response(context, 502, request, "Failed to connect to the CONNECT target.")
+                    .with_error(&format!("Failed to open the tunnel: {}", error));
+                
+                self.send_response(&mut stream, context, request, response)?;
+                
+                return Ok(());
+            }
+        };
+        
+        let response = Response::new(HttpVersion::Http11, 200, "Connection Established");
+        
+        self.send_response(&mut stream, context, request, response)?;
+        
+        debug!("{} Opened tunnel!", context);
+        
+        // Relay bytes until either side hangs up.
+        if let Err(error) = tunnel::relay(stream, upstream) {
+            debug!("{} Tunnel closed with an error: {}", context, error);
+        }
+        
+        Ok(())
+    }
+    
+    /// Answers a WebSocket handshake and hands the connection to the route's handler.
+    fn handle_websocket(&self, mut stream: ClientStream, context: &ConnectionContext, request: &Request, name: &str, buffered: &[u8]) -> Result<(), ServerError> {
+        let handler = match self.websocket_handlers.get(name) {
+            Some(handler) => handler,
+            None => {
+                let response = self.error_response(context, 500, request, &format!("No WebSocket handler named {} is registered.", name));
+                
+                self.send_response(&mut stream, context, request, response)?;
+                
+                return Ok(());
+            }
+        };
+        
+        let accept = match websocket::accept_handshake(request) {
+            Ok(accept) => accept,
+            Err(error) => {
+                self.send_response(&mut stream, context, request, self.handshake_error_response(context, request, &error))?;
+                
+                return Ok(());
+            }
+        };
+        
+        let response = Response::with_status(StatusCode::SwitchingProtocols)
+            .with_header("Connection", "Upgrade")
+            .with_header("Upgrade", "websocket")
+            .with_header("Sec-WebSocket-Accept", &accept);
+        
+        self.send_response(&mut stream, context, request, response)?;
+        
+        // Messages can arrive long after each other, so the keep-alive timeout doesn't apply anymore.
+        stream.get_tcp_stream().set_read_timeout(None)?;
+        
+        let mut socket = WebSocket::new(stream, buffered);
+        
+        if let Err(error) = handler.handle(&mut socket, request) {
+            debug!("{} The WebSocket connection failed: {}", context, error);
+        }
+        
+        // Let the client know the server is done, if the handler didn't.
+        let _ = socket.close(websocket::CLOSE_NORMAL, "");
+        
+        Ok(())
+    }
+    
+    /// Forwards a WebSocket handshake to an upstream and relays the connection if it accepts.
+    fn proxy_websocket(&self, mut stream: ClientStream, context: &ConnectionContext, request: &Request, proxy: &Proxy, buffered: &[u8]) -> Result<(), ServerError> {
+        // Refuse invalid handshakes here, rather than tying up an upstream with them.
+        if let Err(error) = websocket::accept_handshake(request) {
+            self.send_response(&mut stream, context, request, self.handshake_error_response(context, request, &error))?;
+            
+            return Ok(());
+        }
+        
+        let upstream = match proxy.select() {
+            Ok(upstream) => upstream,
+            Err(retry_after) => {
+                self.send_response(&mut stream, context, request, self.unavailable_response(context, request, retry_after))?;
+                
+                return Ok(());
+            }
+        };
+        
+        context.set_upstream(upstream.get_url());
+        
+        let (response, connection) = match proxy.upgrade(&upstream, request, context) {
+            Ok(upgraded) => upgraded,
+            Err(error) => {
+                let response = self.proxy_error_response(context, request, proxy, &upstream, &error);
+                
+                self.send_response(&mut stream, context, request, response)?;
+                
+                return Ok(());
+            }
+        };
+        
+        self.send_response(&mut stream, context, request, response)?;
+        
+        // Relay frames in both directions, the upstream refusing the upgrade ends the exchange with its response.
+        if let Some(connection) = connection {
+            debug!("{} Relaying a WebSocket connection to {}.", context, upstream.get_url());
+            
+            if let Err(error) = connection.relay(stream, buffered) {
+                debug!("{} The WebSocket relay closed with an error: {}", context, error);
+            }
+        }
+        
+        Ok(())
+    }
+    
+    fn handshake_error_response(&self, context: &ConnectionContext, request: &Request, error: &HandshakeError) -> Response {
+        match error {
+            HandshakeError::UnsupportedVersion => {
+                // The connection is closed after a refused handshake, the client can try again on a new one.
+                let mut response = self.error_response(context, 426, request, &error.to_string());
+                response.add_header("Sec-WebSocket-Version", websocket::VERSION);
+                response.add_header("Upgrade", "websocket");
+                response.add_header("Connection", "Upgrade, close");
+                
+                response
+            }
+            HandshakeError::InvalidKey => self.error_response(context, 400, request, &error.to_string()),
+        }
+    }
+    
+    fn error_response(&self, context: &ConnectionContext, status_code: u16, request: &Request, message: &str) -> Response {
+        let problem_type = self.problem_types.get(&status_code).map(String::as_str);
+        
+        let mut response = http::error_response(status_code, request, message, &context.get_request_id().to_string(), problem_type);
+        
+        // Swap in the site's custom error page, unless the client asked for a JSON problem instead of HTML. Requests for
+        // hosts that aren't served get the default site's.
+        let site = self.site_for(request).unwrap_or_else(|| self.get_site());
+        
+        if let Some(page) = site.error_pages.get(&status_code) {
+            if response.get_header("Content-Type").is_some_and(|content_type| content_type.starts_with("text/html")) {
+                response.set_body_bytes(page.get_contents());
+            }
+        }
+        
+        response
+    }
+    
+    /// Adds the headers every response carries, unless the response already has them: `Date`, `Server` and
+    /// `Content-Length` for bodies that aren't chunked.
+    fn finalize_response(&self, response: &mut Response) {
+        // Origin servers with a clock have to date their responses (RFC 9110 §6.6.1).
+        if response.get_header("Date").is_none() {
+            response.set_header("Date", &http::format_http_date(SystemTime::now()));
+        }
+        
+        if let Some(server_banner) = &self.server_banner {
+            if response.get_header("Server").is_none() {
+                response.set_header("Server", server_banner);
+            }
+        }
+        
+        // Informational, 204 and 304 responses never have a body, so they don't get a length either.
+        let status_code = response.get_status_code();
+        let has_body = status_code >= 200 && status_code != 204 && status_code != 304;
+        
+        if has_body && response.get_header("Content-Length").is_none() && response.get_header("Transfer-Encoding").is_none() {
+            if let Some(length) = response.get_body_length() {
+                response.set_header("Content-Length", &length.to_string());
+            }
+        }
+    }
+    
+    fn send_response(&self, stream: &mut impl Write, context: &ConnectionContext, request: &Request, mut response: Response) -> io::Result<()> {
+        self.finalize_response(&mut response);
+        
+        // Write the response to the stream, a streamed body is read while it's sent.
+        response.write_to(stream)?;
+        
+        // Flush the stream.
+        stream.flush()?;
+        
+        // Record failed requests in the error log, with what was noted about the error where it happened.
+        let status_code = response.get_status_code();
+        
+        if status_code >= 400 {
+            self.error_log.log(context, status_code, response.get_error().unwrap_or(response.get_status_message()));
+        }
+        
+        // Run the response hooks now that the response has been fully sent.
+        for hook in &self.response_hooks {
+            hook.after_send(context, request, &response, context.get_start_time().elapsed());
+        }
+        
+        Ok(())
+    }
+    
+    /// Joins a path to the site's web root, refusing anything that could escape it. The result may not exist.
+    fn resolve_path(&self, site: &Site, path: &str) -> Option<PathBuf> {
+        let relative = Path::new(path);
+        
+        // Refuse anything that could escape the web root, like "..", absolute paths or Windows separators.
+        if path.contains('\\') || !relative.components().all(|component| matches!(component, Component::Normal(_))) {
+            return None;
+        }
+        
+        Some(Path::new(&site.web_root).join(relative))
+    }
+    
+    /// Checks where a path really leads once symlinks are followed, which has to be inside the site's web root.
+    fn is_inside_web_root(&self, site: &Site, path: &Path) -> bool {
+        match (fs::canonicalize(&site.web_root), fs::canonicalize(path)) {
+            (Ok(web_root), Ok(path)) => path.starts_with(web_root),
+            _ => false,
+        }
+    }
+    
+    /// Maps a path relative to the web root onto a file, using the first index file that exists if it names a directory.
+    fn resolve_file(&self, site: &Site, path: &str) -> Option<PathBuf> {
+        let file = self.resolve_path(site, path)?;
+        
+        if file.is_dir() {
+            return self.index_files.iter()
+                .map(|index_file| file.join(index_file))
+                .find(|file| file.is_file());
+        }
+        
+        if file.is_file() {
+            Some(file)
+        } else {
+            None
+        }
+    }
+    
+    /// Redirects a request for a directory to the same path with a trailing slash, so relative links resolve inside it.
+    fn redirect_to_directory(&self, request: &Request, site: &Site, path: &str) -> Option<Response> {
+        let (request_path, query) = match request.get_path().split_once('?') {
+            Some((request_path, query)) => (request_path, Some(query)),
+            None => (request.get_path(), None),
+        };
+        
+        if request_path.ends_with('/') || !self.resolve_path(site, path)?.is_dir() {
+            return None;
+        }
+        
+        let location = match query {
+            Some(query) => format!("{}/?{}", request_path, query),
+            None => format!("{}/", request_path),
+        };
+        
+        Some(Response::with_status(StatusCode::MovedPermanently).with_header("Location", &location))
+    }
+}
+
+/// Builds a server in code, see `Server::builder`.
+///
+/// The configuration defaults to the one `config.json` is created with, but quiet, and any of it can be replaced.
+pub struct ServerBuilder {
+    config: JsonValue,
+    routes: Vec<(String, Box<dyn Handler + Send + Sync>)>,
+    websockets: Vec<(String, Box<dyn WebSocketHandler + Send + Sync>)>,
+    middleware: Vec<Box<dyn Middleware + Send + Sync>>,
+    route_metadata: HashMap<String, RouteMetadata>,
+}
+
+impl ServerBuilder {
+    pub fn new() -> ServerBuilder {
+        ServerBuilder {
+            config: json::object! {
+                "verbose": false,
+                "thread_count": 1,
+                "port": 8080,
+                "web_root": "web",
+                "pages": [{ "name": "Main Page", "path": "index.html" }],
+            },
+            routes: Vec::new(),
+            websockets: Vec::new(),
+            middleware: Vec::new(),
+            route_metadata: HashMap::new(),
+        }
+    }
+    
+    /// Replaces the whole configuration, which is read the same way as `config.json`.
+    pub fn config(mut self, config: JsonValue) -> ServerBuilder {
+        self.config = config;
+        
+        self
+    }
+    
+    /// Sets a single configuration value, e.g. `.set("deny_unlisted", true)`.
+    pub fn set(mut self, key: &str, value: impl Into<JsonValue>) -> ServerBuilder {
+        self.config[key] = value.into();
+        
+        self
+    }
+    
+    pub fn port(self, port: u16) -> ServerBuilder {
+        self.set("port", port)
+    }
+    
+    pub fn web_root(self, web_root: &str) -> ServerBuilder {
+        self.set("web_root", web_root)
+    }
+    
+    /// Answers requests matching a path pattern with a handler or a closure taking the request.
+    pub fn route(mut self, pattern: &str, handler: impl Handler + Send + Sync + 'static) -> ServerBuilder {
+        self.routes.push((pattern.to_string(), Box::new(handler)));
+        
+        self
+    }
+    
+    /// Accepts WebSocket connections for requests matching a path pattern, see `Server::websocket`.
+    pub fn websocket(mut self, pattern: &str, handler: impl WebSocketHandler + Send + Sync + 'static) -> ServerBuilder {
+        self.websockets.push((pattern.to_string(), Box::new(handler)));
+        
+        self
+    }
+    
+    /// Describes a route in the OpenAPI document, see `Server::describe_route`.
+    pub fn describe(mut self, pattern: &str, metadata: RouteMetadata) -> ServerBuilder {
+        self.route_metadata.insert(pattern.to_string(), metadata);
+        
+        self
+    }
+    
+    pub fn middleware(mut self, middleware: impl Middleware + Send + Sync + 'static) -> ServerBuilder {
+        self.middleware.push(Box::new(middleware));
+        
+        self
+    }
+    
+    /// Creates the server, reporting invalid configuration values and routes together.
+    pub fn build(self) -> Result<Server, ConfigError> {
+        let mut server = Server::new(&self.config)?;
+        let mut errors = Vec::new();
+        
+        for (pattern, handler) in self.routes {
+            match Route::new(&pattern, RouteTarget::Handler(pattern.clone())).and_then(|route| server.site_mut().router.add(route)) {
+                Ok(()) => {
+                    server.code_routes.push((pattern.clone(), RouteTarget::Handler(pattern.clone())));
+                    server.handlers.insert(pattern, handler);
+                }
+                Err(error) => errors.push(route_error("route", &error, &pattern.as_str().into())),
+            }
+        }
+        
+        for (pattern, handler) in self.websockets {
+            match Route::new(&pattern, RouteTarget::WebSocket(pattern.clone())).and_then(|route| server.site_mut().router.add(route)) {
+                Ok(()) => {
+                    server.code_routes.push((pattern.clone(), RouteTarget::WebSocket(pattern.clone())));
+                    server.websocket_handlers.insert(pattern, handler);
+                }
+                Err(error) => errors.push(route_error("websocket", &error, &pattern.as_str().into())),
+            }
+        }
+        
+        ConfigError::check_all(errors)?;
+        
+        server.middleware.extend(self.middleware);
+        server.route_metadata.extend(self.route_metadata);
+        
+        Ok(server)
+    }
+}
+
+impl Default for ServerBuilder {
+    fn default() -> ServerBuilder {
+        ServerBuilder::new()
+    }
+}
+
+/// A server whose sockets are bound but that isn't accepting connections yet, see `Server::bind`.
+pub struct BoundServer {
+    server: Server,
+    listeners: Vec<(TcpListener, Arc<Listener>)>,
+}
+
+impl BoundServer {
+    /// Returns the address the server is bound to, including the port the OS picked if it was configured as 0.
+    pub fn bound_addr(&self) -> SocketAddr {
+        self.listeners[0].0.local_addr().unwrap_or(SocketAddr::new(self.server.get_bind_address(), self.server.get_port()))
+    }
+    
+    /// Returns the address of every bound socket along with its listener, a listener binding both IP stacks has two.
+    pub fn bound_addrs(&self) -> Vec<(SocketAddr, &Listener)> {
+        self.listeners.iter()
+            .filter_map(|(socket, listener)| socket.local_addr().ok().map(|address| (address, listener.as_ref())))
+            .collect()
+    }
+    
+    pub fn get_server(&self) -> &Server {
+        &self.server
+    }
+    
+    /// Starts accepting connections, blocking until the server is shut down.
+    pub fn listen(self) {
+        self.server.serve(&self.listeners);
+    }
+    
+    /// Starts accepting connections on a background thread, see `Server::start`.
+    pub fn start(self) -> ServerHandle {
+        let local_addr = self.bound_addr();
+        let server = Arc::new(self.server);
+        let serving = Arc::clone(&server);
+        let listeners = self.listeners;
+        
+        let thread = thread::spawn(move || serving.serve(&listeners));
+        
+        ServerHandle {
+            local_addr,
+            server,
+            thread,
+        }
+    }
+}
+
+/// A server accepting connections on a background thread, returned by `Server::start`.
+pub struct ServerHandle {
+    local_addr: SocketAddr,
+    server: Arc<Server>,
+    thread: JoinHandle<()>,
+}
+
+impl ServerHandle {
+    /// Returns the address the server is bound to, including the port the OS picked if it was configured as 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+    
+    /// Returns the running server, which can be shared with other threads, e.g. to shut it down from a signal handler.
+    pub fn get_server(&self) -> &Arc<Server> {
+        &self.server
+    }
+    
+    /// Stops accepting new connections and lets the in-flight ones finish, see `Server::shutdown`.
+    pub fn shutdown(&self) {
+        self.server.shutdown();
+    }
+    
+    /// Waits for the server to stop, which only happens after `shutdown` or if the accept loop panics.
+    pub fn join(self) -> Result<(), ServerError> {
+        self.thread.join().map_err(|_| ServerError::Panicked)
+    }
+}
+
+/// The whole configuration with every value checked and converted to the type it's used as, before any file is read.
+struct Config<'a> {
+    verbose: bool,
+    log_filter: LogFilter,
+    log_file: Option<&'a str>,
+    log_stderr: bool,
+    shutdown_grace_period_secs: u64,
+    watch_config: bool,
+    thread_count: u16,
+    listener_settings: Vec<ListenerSettings<'a>>,
+    tcp_backlog: u32,
+    tcp_recv_buffer_bytes: Option<usize>,
+    tcp_send_buffer_bytes: Option<usize>,
+    max_body_size: usize,
+    max_url_length: usize,
+    max_header_bytes: usize,
+    keep_alive_timeout_secs: u64,
+    max_keep_alive_requests: usize,
+    connection_limiter: Option<ConnectionLimiter>,
+    deny_unlisted: bool,
+    connect_allowlist: Option<Vec<String>>,
+    connect_access: AccessList,
+    access: AccessList,
+    trusted_proxies: Vec<IpNet>,
+    error_log_path: Option<&'a str>,
+    log_rotation: Option<LogRotation>,
+    problem_types: HashMap<u16, String>,
+    enable_cache_busting: bool,
+    access_log: Option<&'a str>,
+    access_log_format: AccessLogFormat,
+    access_log_buffer_bytes: usize,
+    log_sample_rate: f64,
+    middleware: Vec<Box<dyn Middleware + Send + Sync>>,
+    robots_txt: Option<RobotsConfig>,
+    favicon: Option<String>,
+    mime_types: MimeTypes,
+    compression: CompressionConfig,
+    serve_precompressed: bool,
+    file_cache: FileCache,
+    head_cache: HeadCache,
+    server_banner: Option<String>,
+    index_files: Vec<String>,
+    disabled_methods: HashSet<Method>,
+    rate_limiter: Option<Box<dyn RateLimiter + Send + Sync>>,
+    auth: Vec<BasicAuth>,
+    jwt: Vec<JwtValidator>,
+    openapi: Option<OpenApiConfig>,
+    well_known_dir: Option<String>,
+    dump_resolved_config_to: Option<String>,
+    site_settings: SiteSettings<'a>,
+    vhost_settings: Vec<(String, SiteSettings<'a>)>,
+    vhost_fallback: bool,
+}
+
+/// The settings of a listener, which becomes a `Listener` once its TLS certificate and key are loaded.
+struct ListenerSettings<'a> {
+    name: String,
+    bind_address: IpAddr,
+    port: u16,
+    force_dual_stack: bool,
+    tls_paths: Option<(&'a str, &'a str)>,
+}
+
+/// Reads the settings of a listener, either from the top level of the configuration if `parent` is empty or from the
+/// entry of the listeners array at `parent`.
+fn load_listener<'a>(config: &'a JsonValue, parent: &str, errors: &mut Vec<ConfigError>) -> ListenerSettings<'a> {
+    let field = |key: &str| if parent.is_empty() { key.to_string() } else { format!("{}.{}", parent, key) };
+    
+    if !parent.is_empty() {
+        check_keys(config, parent, &LISTENER_KEYS, errors);
+    }
+    
+    // Get the port number, 0 lets the OS pick a free one.
+    let port = match config["port"].as_u16() {
+        Some(port) if port == 0 || (port >= 1_024 && port != 65_535) => port,
+        _ => {
+            errors.push(ConfigError::invalid(&field("port"), "must be 0 for any free port or a number between 1.024 and 65.535").with_value(&config["port"]));
+            
+            0
+        }
+    };
+    
+    // Get the bind address, listening on all IPv4 interfaces if it's not specified.
+    let bind_address = if config["bind_address"].is_null() {
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+    } else {
+        match config["bind_address"].as_str().and_then(|address| address.parse().ok()) {
+            Some(bind_address) => bind_address,
+            None => {
+                errors.push(ConfigError::invalid(&field("bind_address"), "must be an IPv4 or IPv6 address").with_value(&config["bind_address"]));
+                
+                IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+            }
+        }
+    };
+    
+    // Get the dual-stack flag, which adds an IPv4 listener to one on "::" or, for loopback-only setups, on "::1".
+    let force_dual_stack = if config["force_dual_stack"].is_null() {
+        false
+    } else {
+        match config["force_dual_stack"].as_bool() {
+            Some(force_dual_stack) => force_dual_stack,
+            None => {
+                errors.push(ConfigError::invalid(&field("force_dual_stack"), "must be a boolean").with_value(&config["force_dual_stack"]));
+                
+                false
+            }
+        }
+    };
+    
+    // Get the certificate and key for TLS, connections stay unencrypted if the block isn't specified or disabled.
+    let tls_paths = if config["tls"].is_null() {
+        None
+    } else if !config["tls"].is_object() {
+        errors.push(ConfigError::invalid(&field("tls"), "must be an object with enabled, cert_path and key_path").with_value(&config["tls"]));
+        
+        None
+    } else {
+        let tls = &config["tls"];
+        check_keys(tls, &field("tls"), &TLS_KEYS, errors);
+        
+        let enabled = if tls["enabled"].is_null() {
+            true
+        } else {
+            match tls["enabled"].as_bool() {
+                Some(enabled) => enabled,
+                None => {
+                    errors.push(ConfigError::invalid(&field("tls.enabled"), "must be a boolean").with_value(&tls["enabled"]));
+                    
+                    false
+                }
+            }
+        };
+        
+        match (tls["cert_path"].as_str(), tls["key_path"].as_str()) {
+            _ if !enabled => None,
+            (Some(cert_path), Some(key_path)) => Some((cert_path, key_path)),
+            (cert_path, key_path) => {
+                if cert_path.is_none() {
+                    errors.push(ConfigError::invalid(&field("tls.cert_path"), "must be the path of a PEM certificate chain").with_value(&tls["cert_path"]));
+                }
+                
+                if key_path.is_none() {
+                    errors.push(ConfigError::invalid(&field("tls.key_path"), "must be the path of a PEM private key").with_value(&tls["key_path"]));
+                }
+                
+                None
+            }
+        }
+    };
+    
+    // Get the name connections are tagged with, which is the port if it's not specified.
+    let name = if parent.is_empty() || config["name"].is_null() {
+        port.to_string()
+    } else {
+        match config["name"].as_str() {
+            Some(name) => name.to_string(),
+            None => {
+                errors.push(ConfigError::invalid(&field("name"), "must be a string").with_value(&config["name"]));
+                
+                port.to_string()
+            }
+        }
+    };
+    
+    ListenerSettings {
+        name,
+        bind_address,
+        port,
+        force_dual_stack,
+        tls_paths,
+    }
+}
+
+/// A page as it's configured, before its file is read.
+struct PageSettings<'a> {
+    name: &'a str,
+    path: &'a str,
+    is_template: bool,
+    headers: Vec<(&'a str, &'a str)>,
+}
+
+/// The settings of a site, the default one or a virtual host, before any of its files are read.
+struct SiteSettings<'a> {
+    web_root: &'a str,
+    error_page_paths: Vec<(u16, &'a str)>,
+    router: Router,
+    proxies: Vec<Proxy>,
+    fastcgi: Vec<FastCgi>,
+    pages: Vec<PageSettings<'a>>,
+}
+
+/// Reads the settings of a site, either from the top level of the configuration if `parent` is empty or from the
+/// virtual host at `parent`.
+fn load_site_settings<'a>(config: &'a JsonValue, parent: &str, errors: &mut Vec<ConfigError>) -> SiteSettings<'a> {
+    let field = |key: &str| if parent.is_empty() { key.to_string() } else { format!("{}.{}", parent, key) };
+    
+    // Get the web root.
+    let web_root = match config["web_root"].as_str() {
+        Some(web_root) => web_root,
+        None => {
+            errors.push(ConfigError::invalid(&field("web_root"), "must be a string").with_value(&config["web_root"]));
+            
+            ""
+        }
+    };
+    
+    // Get the custom error pages, relative to the web root and keyed by status code.
+    let mut error_page_paths = Vec::new();
+    
+    if !config["error_pages"].is_null() && !config["error_pages"].is_object() {
+        errors.push(ConfigError::invalid(&field("error_pages"), "must be an object mapping status codes to file paths").with_value(&config["error_pages"]));
+    } else {
+        for (status_code, path) in config["error_pages"].entries() {
+            let error_page_field = field(&format!("error_pages.{}", status_code));
+            
+            let status_code = match status_code.parse::<u16>() {
+                Ok(status_code) if (400..600).contains(&status_code) => status_code,
+                _ => {
+                    errors.push(ConfigError::invalid(&error_page_field, "must be keyed by an error status code between 400 and 599"));
+                    
+                    continue;
+                }
+            };
+            
+            match path.as_str() {
+                Some(path) => error_page_paths.push((status_code, path)),
+                None => errors.push(ConfigError::invalid(&error_page_field, "must be a file path").with_value(path)),
+            }
+        }
+    }
+    
+    // Get the routes, which map path patterns to files, handlers, upstream servers or FastCGI backends.
+    let mut router = Router::new();
+    let mut proxies = Vec::new();
+    let mut fastcgi = Vec::new();
+    
+    if !config["routes"].is_null() && !config["routes"].is_array() {
+        errors.push(ConfigError::invalid(&field("routes"), "must be an array of route objects").with_value(&config["routes"]));
+    }
+    
+    for (index, route) in config["routes"].members().enumerate() {
+        let route_field = field(&format!("routes[{}]", index));
+        let field = |key: &str| format!("{}.{}", route_field, key);
+        
+        check_keys(route, &route_field, &ROUTE_KEYS, errors);
+        
+        let target = match (route["file"].as_str(), route["handler"].as_str(), route["proxy_pass"].as_str(), route["proxy"].is_object(), route["fastcgi"].is_object()) {
+            (Some(file), None, None, false, false) => RouteTarget::File(file.to_string()),
+            (None, Some(handler), None, false, false) => RouteTarget::Handler(handler.to_string()),
+            // A proxy_pass is a proxy with a single upstream and the default settings.
+            (None, None, Some(url), false, false) => match Upstream::new(url, CircuitBreaker::new(proxy::DEFAULT_FAILURE_THRESHOLD, proxy::DEFAULT_OPEN_DURATION)) {
+                Ok(upstream) => {
+                    proxies.push(Proxy::new(vec!(upstream), BalanceStrategy::RoundRobin, proxy::DEFAULT_UPSTREAM_TIMEOUT));
+                    
+                    RouteTarget::Proxy(proxies.len() - 1)
+                }
+                Err(message) => {
+                    errors.push(ConfigError::invalid(&field("proxy_pass"), &message).with_value(&route["proxy_pass"]));
+                    
+                    continue;
+                }
+            },
+            (None, None, None, true, false) => match load_proxy(&route["proxy"], &field("proxy"), errors) {
+                Some(proxy) => {
+                    proxies.push(proxy);
+                    
+                    RouteTarget::Proxy(proxies.len() - 1)
+                }
+                None => continue,
+            },
+            (None, None, None, false, true) => match load_fastcgi(&route["fastcgi"], &field("fastcgi"), errors) {
+                Some(backend) => {
+                    fastcgi.push(backend);
+                    
+                    RouteTarget::FastCgi(fastcgi.len() - 1)
+                }
+                None => continue,
+            },
+            _ => {
+                errors.push(ConfigError::invalid(&route_field, "must have exactly one of file, handler, proxy_pass, proxy or fastcgi").with_value(route));
+                
+                continue;
+            }
+        };
+        
+        let mut route_entry = match route["path"].as_str().map(|path| Route::new(path, target)) {
+            Some(Ok(route_entry)) => route_entry,
+            Some(Err(error)) => {
+                errors.push(route_error(&field("path"), &error, &route["path"]));
+                
+                continue;
+            }
+            None => {
+                errors.push(ConfigError::invalid(&field("path"), "must be a string").with_value(&route["path"]));
+                
+                continue;
+            }
+        };
+        
+        // Get the headers sent with this route.
+        if !route["headers"].is_null() && !route["headers"].is_object() {
+            errors.push(ConfigError::invalid(&field("headers"), "must be an object mapping header names to values").with_value(&route["headers"]));
+        }
+        
+        for (name, value) in route["headers"].entries() {
+            match value.as_str() {
+                Some(value) => route_entry.add_header(name, value),
+                None => errors.push(ConfigError::invalid(&field(&format!("headers.{}", name)), "must be a string").with_value(value)),
+            }
+        }
+        
+        // Let the route turn compression on or off, regardless of the server-wide setting.
+        if !route["compress"].is_null() {
+            match route["compress"].as_bool() {
+                Some(compress) => route_entry.set_compress(Some(compress)),
+                None => errors.push(ConfigError::invalid(&field("compress"), "must be a boolean").with_value(&route["compress"])),
+            }
+        }
+        
+        // Restrict the route to some methods, the others are answered with 405.
+        if !route["methods"].is_null() && !route["methods"].is_array() {
+            errors.push(ConfigError::invalid(&field("methods"), "must be an array of method names").with_value(&route["methods"]));
+        } else if route["methods"].is_array() {
+            let mut methods = Vec::new();
+            
+            for (method_index, method) in route["methods"].members().enumerate() {
+                match method.as_str().and_then(|method| Method::try_from(method.to_ascii_uppercase().as_str()).ok()) {
+                    Some(method) => methods.push(method),
+                    None => errors.push(ConfigError::invalid(&field(&format!("methods[{}]", method_index)), "must be a method name like GET or POST").with_value(method)),
+                }
+            }
+            
+            route_entry.set_methods(Some(methods));
+        }
+        
+        // Let file routes list directories that don't have an index file.
+        if !route["autoindex"].is_null() {
+            match route["autoindex"].as_bool() {
+                Some(autoindex) => route_entry.set_autoindex(autoindex),
+                None => errors.push(ConfigError::invalid(&field("autoindex"), "must be a boolean").with_value(&route["autoindex"])),
+            }
+        }
+        
+        // Restrict the route to some clients, on top of the server-wide allow and deny lists.
+        let allow = load_cidr_list(route, "allow", &field("allow"), errors);
+        let deny = load_cidr_list(route, "deny", &field("deny"), errors);
+        route_entry.set_access(AccessList::new(allow, deny));
+        
+        if let Err(error) = router.add(route_entry) {
+            errors.push(route_error(&field("path"), &error, &route["path"]));
+        }
+    }
+    
+    // Get the name and path of every page.
+    let mut pages = Vec::new();
+    
+    if !config["pages"].is_null() && !config["pages"].is_array() {
+        errors.push(ConfigError::invalid(&field("pages"), "must be an array of page objects").with_value(&config["pages"]));
+    }
+    
+    for (index, page) in config["pages"].members().enumerate() {
+        let page_field = field(&format!("pages[{}]", index));
+        let field = |key: &str| format!("{}.{}", page_field, key);
+        
+        check_keys(page, &page_field, &PAGE_KEYS, errors);
+        
+        // Templates are rendered on every request, everything else is served as is.
+        let is_template = if page["template"].is_null() {
+            false
+        } else {
+            match page["template"].as_bool() {
+                Some(is_template) => is_template,
+                None => {
+                    errors.push(ConfigError::invalid(&field("template"), "must be a boolean").with_value(&page["template"]));
+                    
+                    false
+                }
+            }
+        };
+        
+        // Get the headers sent with this page.
+        let mut headers = Vec::new();
+        
+        if !page["headers"].is_null() && !page["headers"].is_object() {
+            errors.push(ConfigError::invalid(&field("headers"), "must be an object mapping header names to values").with_value(&page["headers"]));
+        }
+        
+        for (name, value) in page["headers"].entries() {
+            match value.as_str() {
+                Some(value) => headers.push((name, value)),
+                None => errors.push(ConfigError::invalid(&field(&format!("headers.{}", name)), "must be a string").with_value(value)),
+            }
+        }
+        
+        match (page["name"].as_str(), page["path"].as_str()) {
+            (Some(name), Some(path)) => pages.push(PageSettings { name, path, is_template, headers }),
+            (name, path) => {
+                if name.is_none() {
+                    errors.push(ConfigError::invalid(&field("name"), "must be a string").with_value(&page["name"]));
+                }
+                
+                if path.is_none() {
+                    errors.push(ConfigError::invalid(&field("path"), "must be a string").with_value(&page["path"]));
+                }
+            }
+        }
+    }
+    
+    // Two pages serving the same file are almost certainly a copy-paste mistake, report every such path once.
+    let mut seen_paths = HashSet::new();
+    let mut duplicate_paths = Vec::new();
+    
+    for page in &pages {
+        if !seen_paths.insert(page.path) && !duplicate_paths.contains(&page.path) {
+            duplicate_paths.push(page.path);
+        }
+    }
+    
+    for path in duplicate_paths {
+        errors.push(ConfigError::DuplicatePagePath(path.to_string()));
+    }
+    
+    SiteSettings {
+        web_root,
+        error_page_paths,
+        router,
+        proxies,
+        fastcgi,
+        pages,
+    }
+}
+
+/// Reads the files of a site, collecting the ones that are missing or can't be used in `file_errors`.
+///
+/// If `create_missing` is set, a missing web root and missing pages are created rather than reported, and a site
+/// without pages gets an empty index.html.
+fn load_site(settings: SiteSettings, create_missing: bool, file_errors: &mut Vec<ConfigError>) -> Result<Site, ConfigError> {
+    let SiteSettings { web_root, error_page_paths, mut router, proxies, fastcgi, pages: page_settings } = settings;
+    
+    // Check if the web_root directory exists.
+    match fs::metadata(web_root) {
+        Ok(metadata) if !metadata.is_dir() => {
+            file_errors.push(ConfigError::invalid("web_root", "must be a directory").with_value(&web_root.into()));
+        }
+        Ok(_) => {}
+        Err(_) if !create_missing => {
+            file_errors.push(ConfigError::io(web_root, io::Error::new(io::ErrorKind::NotFound, "it does not exist")));
+        }
+        Err(_) => {
+            // Create the web_root directory.
+            match fs::create_dir_all(web_root) {
+                Ok(_) => info!("Created web root directory: {}", web_root),
+                Err(error) => return Err(ConfigError::io(web_root, error)),
+            }
+        }
+    }
+    
+    // Read the error pages now, so a missing one is noticed at startup rather than when it's needed.
+    let mut error_pages = HashMap::new();
+    
+    for (status_code, path) in error_page_paths {
+        match fs::read(format!("{}/{}", web_root, path)) {
+            Ok(contents) => {
+                error_pages.insert(status_code, Page::new(&status_code.to_string(), path, &contents));
+            }
+            Err(error) => file_errors.push(ConfigError::io(&format!("{}/{}", web_root, path), error)),
+        }
+    }
+    
+    // Make sure the pages array is not empty.
+    if page_settings.is_empty() {
+        info!("No pages found in {}, creating an index.html file...", web_root);
+        
+        // Create the file.
+        let page = if create_missing {
+            create_file(format!("{}/{}", web_root, "index.html"))?
+        } else {
+            Page::new("index.html", &format!("{}/{}", web_root, "index.html"), b"")
+        };
+        
+        return Ok(Site {
+            web_root: web_root.to_string(),
+            error_pages,
+            pages: vec!(page),
+            router,
+            proxies,
+            fastcgi,
+        });
+    }
+    
+    let mut pages: Vec<Page> = Vec::new();
+    
+    // Iterate over the pages from the config file.
+    for PageSettings { name, path, is_template, headers } in page_settings {
+        
+        // Make sure the file exists.
+        let mut page = if fs::metadata(format!("{}/{}", web_root, path)).is_err() {
+            if !create_missing {
+                file_errors.push(ConfigError::io(&format!("{}/{}", web_root, path), io::Error::new(io::ErrorKind::NotFound, "it does not exist")));
+                
+                continue;
+            }
+            
+            // Create the file.
+            create_file(format!("{}/{}", web_root, path))?
+        } else {
+            // Get the page contents from the file.
+            let contents = match fs::read(format!("{}/{}", web_root, path)) {
+                Ok(contents) => contents,
+                Err(error) => {
+                    file_errors.push(ConfigError::io(&format!("{}/{}", web_root, path), error));
+                    
+                    continue;
+                }
+            };
+            
+            // Create a new page instance, remembering when the file changed for Last-Modified.
+            let mut page = Page::new(name, path, &contents);
+            page.set_modified(fs::metadata(format!("{}/{}", web_root, path)).and_then(|metadata| metadata.modified()).ok());
+            
+            page
+        };
+        
+        // Compile templates now, so syntax errors are reported at startup rather than on the first request.
+        if is_template {
+            if let Err(error) = page.make_template() {
+                file_errors.push(ConfigError::invalid(&format!("template {}", path), &error.to_string()));
+                
+                continue;
+            }
+        }
+        
+        for (name, value) in headers {
+            page.add_header(name, value);
+        }
+        
+        // Route requests for the page name to the page. Names that aren't paths, or are already routed, are left
+        // out, the page can still be served as the index page.
+        if let Ok(route) = Route::new(page.get_name(), RouteTarget::Page(pages.len())) {
+            let _ = router.add(route);
+        }
+        
+        // Add the page to the pages vector.
+        pages.push(page);
+    }
+    
+    Ok(Site {
+        web_root: web_root.to_string(),
+        error_pages,
+        pages,
+        router,
+        proxies,
+        fastcgi,
+    })
+}
+
+/// Writes the web root, error pages, pages and routes of a site into a configuration object.
+fn dump_site(site: &Site, config: &mut JsonValue) {
+    config["web_root"] = site.web_root.as_str().into();
+    
+    let mut error_pages = JsonValue::new_object();
+    
+    for (status_code, page) in &site.error_pages {
+        error_pages[status_code.to_string()] = page.get_path().into();
+    }
+    
+    config["error_pages"] = error_pages;
+    
+    config["pages"] = site.pages.iter()
+        .map(|page| {
+            let mut headers = JsonValue::new_object();
+            
+            for (name, value) in page.get_headers() {
+                headers[name.as_str()] = value.as_str().into();
+            }
+            
+            json::object! { "name": page.get_name(), "path": page.get_path(), "template": page.is_template(), "headers": headers }
+        })
+        .collect::<Vec<_>>()
+        .into();
+    
+    // Pages are routed by their names, so only the routes from the configuration are listed.
+    let mut routes = site.router.routes()
+        .filter_map(|route| {
+            let mut entry = match route.get_target() {
+                // Pages are listed separately, and WebSocket handlers can only be registered in code.
+                RouteTarget::Page(_) | RouteTarget::WebSocket(_) => return None,
+                RouteTarget::File(file) => json::object! { "path": route.get_pattern(), "file": file.as_str() },
+                RouteTarget::Handler(handler) => json::object! { "path": route.get_pattern(), "handler": handler.as_str() },
+                RouteTarget::Proxy(index) => json::object! { "path": route.get_pattern(), "proxy": dump_proxy(&site.proxies[*index]) },
+                RouteTarget::FastCgi(index) => json::object! { "path": route.get_pattern(), "fastcgi": dump_fastcgi(&site.fastcgi[*index]) },
+            };
+            
+            let mut headers = JsonValue::new_object();
+            
+            for (name, value) in route.get_headers() {
+                headers[name.as_str()] = value.as_str().into();
+            }
+            
+            entry["headers"] = headers;
+            entry["compress"] = route.get_compress().into();
+            entry["autoindex"] = route.is_autoindex().into();
+            
+            if let Some(methods) = route.get_methods() {
+                entry["methods"] = methods.iter().map(|method| method.to_string()).collect::<Vec<_>>().into();
+            }
+            
+            if !route.get_access().is_empty() {
+                entry["allow"] = dump_cidr_list(route.get_access().get_allow());
+                entry["deny"] = dump_cidr_list(route.get_access().get_deny());
+            }
+            
+            Some(entry)
+        })
+        .collect::<Vec<_>>();
+    
+    routes.sort_by(|a, b| a["path"].as_str().cmp(&b["path"].as_str()));
+    config["routes"] = routes.into();
+}
+
+/// Writes the settings of a proxy the way a route's proxy block configures them.
+fn dump_proxy(proxy: &Proxy) -> JsonValue {
+    let circuit_breaker = proxy.get_upstreams()[0].get_circuit_breaker();
+    
+    json::object! {
+        "upstreams": proxy.get_upstreams().iter().map(|upstream| upstream.get_url()).collect::<Vec<_>>(),
+        "strategy": proxy.get_strategy().name(),
+        "timeout_secs": proxy.get_timeout().as_secs(),
+        "failure_threshold": circuit_breaker.get_failure_threshold(),
+        "open_duration_secs": circuit_breaker.get_open_duration().as_secs(),
+        "health_check": proxy.get_health_check().map(|health_check| json::object! {
+            "path": health_check.get_path(),
+            "interval_secs": health_check.get_interval().as_secs(),
+            "timeout_secs": health_check.get_timeout().as_secs(),
+            "expected_status": health_check.get_expected_status(),
+        }),
+    }
+}
+
+/// Writes the settings of a FastCGI backend the way a route's fastcgi block configures them.
+fn dump_fastcgi(backend: &FastCgi) -> JsonValue {
+    let mut params = JsonValue::new_object();
+    
+    for (name, value) in backend.get_params() {
+        params[name.as_str()] = value.as_str().into();
+    }
+    
+    json::object! {
+        "address": backend.get_address().to_string(),
+        "script": backend.get_script(),
+        "timeout_secs": backend.get_timeout().as_secs(),
+        "params": params,
+    }
+}
+
+/// Checks that a virtual host is keyed by a lowercase hostname, optionally with a leading `*.` matching any subdomain.
+fn is_vhost_hostname(hostname: &str) -> bool {
+    let name = hostname.strip_prefix("*.").unwrap_or(hostname);
+    
+    !name.is_empty() && name.split('.').all(|label| {
+        !label.is_empty() && !label.starts_with('-') && !label.ends_with('-')
+            && label.chars().all(|character| character.is_ascii_lowercase() || character.is_ascii_digit() || character == '-')
+    })
+}
+
+/// Returns the hostname a request is for, lowercased and without the port or a trailing dot.
+fn request_hostname(request: &Request) -> Option<String> {
+    let host = request.get_header("Host")?.trim();
+    
+    // IPv6 addresses are bracketed, so their colons aren't mistaken for the port separator.
+    let hostname = match host.strip_prefix('[') {
+        Some(rest) => &host[..rest.find(']').map_or(host.len(), |end| end + 2)],
+        None => host.rsplit_once(':').map_or(host, |(hostname, _)| hostname),
+    };
+    
+    Some(hostname.trim_end_matches('.').to_ascii_lowercase())
+}
+
+/// Reads the upstreams of a proxy route, how requests are spread over them and how failing ones are detected.
+///
+/// Returns `None` if anything is invalid, after pushing every problem to `errors`.
+fn load_proxy(config: &JsonValue, parent: &str, errors: &mut Vec<ConfigError>) -> Option<Proxy> {
+    let field = |key: &str| format!("{}.{}", parent, key);
+    let error_count = errors.len();
+    
+    check_keys(config, parent, &PROXY_KEYS, errors);
+    
+    // Get how the upstream is picked for each request, taking turns if it's not specified.
+    let strategy = if config["strategy"].is_null() {
+        BalanceStrategy::RoundRobin
+    } else {
+        match config["strategy"].as_str().and_then(BalanceStrategy::parse) {
+            Some(strategy) => strategy,
+            None => {
+                errors.push(ConfigError::invalid(&field("strategy"), "must be round_robin or least_connections").with_value(&config["strategy"]));
+                
+                BalanceStrategy::RoundRobin
+            }
+        }
+    };
+    
+    let timeout = load_secs(config, "timeout_secs", &field("timeout_secs"), proxy::DEFAULT_UPSTREAM_TIMEOUT, errors);
+    let open_duration = load_secs(config, "open_duration_secs", &field("open_duration_secs"), proxy::DEFAULT_OPEN_DURATION, errors);
+    
+    // Get how many requests in a row may fail before an upstream's circuit opens.
+    let failure_threshold = if config["failure_threshold"].is_null() {
+        proxy::DEFAULT_FAILURE_THRESHOLD
+    } else {
+        match config["failure_threshold"].as_u32() {
+            Some(failure_threshold) if failure_threshold > 0 => failure_threshold,
+            _ => {
+                errors.push(ConfigError::invalid(&field("failure_threshold"), "must be a number greater than 0").with_value(&config["failure_threshold"]));
+                
+                proxy::DEFAULT_FAILURE_THRESHOLD
+            }
+        }
+    };
+    
+    // Get the upstreams, each with a circuit breaker of its own.
+    let mut upstreams = Vec::new();
+    
+    if !config["upstreams"].is_array() || config["upstreams"].is_empty() {
+        errors.push(ConfigError::invalid(&field("upstreams"), "must be a non-empty array of http:// URLs").with_value(&config["upstreams"]));
+    }
+    
+    for (index, url) in config["upstreams"].members().enumerate() {
+        let upstream = url.as_str()
+            .ok_or_else(|| "must be an http:// URL".to_string())
+            .and_then(|url| Upstream::new(url, CircuitBreaker::new(failure_threshold, open_duration)));
+        
+        match upstream {
+            Ok(upstream) => upstreams.push(upstream),
+            Err(message) => errors.push(ConfigError::invalid(&field(&format!("upstreams[{}]", index)), &message).with_value(url)),
+        }
+    }
+    
+    // Get the health check, upstreams are only taken out of rotation by their circuit breakers without one.
+    let health_check = &config["health_check"];
+    let health_check = if health_check.is_null() {
+        None
+    } else if !health_check.is_object() {
+        errors.push(ConfigError::invalid(&field("health_check"), "must be an object with a path, interval_secs, timeout_secs and expected_status").with_value(health_check));
+        
+        None
+    } else {
+        let health_check_field = field("health_check");
+        let field = |key: &str| format!("{}.{}", health_check_field, key);
+        
+        check_keys(health_check, &health_check_field, &HEALTH_CHECK_KEYS, errors);
+        
+        let path = if health_check["path"].is_null() {
+            "/"
+        } else {
+            match health_check["path"].as_str() {
+                Some(path) if path.starts_with('/') => path,
+                _ => {
+                    errors.push(ConfigError::invalid(&field("path"), "must be a path starting with /").with_value(&health_check["path"]));
+                    
+                    "/"
+                }
+            }
+        };
+        
+        let interval = load_secs(health_check, "interval_secs", &field("interval_secs"), proxy::DEFAULT_HEALTH_CHECK_INTERVAL, errors);
+        let timeout = load_secs(health_check, "timeout_secs", &field("timeout_secs"), proxy::DEFAULT_HEALTH_CHECK_TIMEOUT, errors);
+        
+        let expected_status = if health_check["expected_status"].is_null() {
+            200
+        } else {
+            match health_check["expected_status"].as_u16() {
+                Some(expected_status) if (100..600).contains(&expected_status) => expected_status,
+                _ => {
+                    errors.push(ConfigError::invalid(&field("expected_status"), "must be a status code between 100 and 599").with_value(&health_check["expected_status"]));
+                    
+                    200
+                }
+            }
+        };
+        
+        Some(HealthCheck::new(path, interval, timeout, expected_status))
+    };
+    
+    if errors.len() > error_count {
+        return None;
+    }
+    
+    let proxy = Proxy::new(upstreams, strategy, timeout);
+    
+    match health_check {
+        Some(health_check) => Some(proxy.with_health_check(health_check)),
+        None => Some(proxy),
+    }
+}
+
+/// Reads the path prefixes that ask for credentials, each with a realm and users from the configuration, an htpasswd
+/// file or both.
+fn load_auth(config: &JsonValue, errors: &mut Vec<ConfigError>) -> Vec<BasicAuth> {
+    let mut auth = Vec::new();
+    
+    if !config.is_null() && !config.is_object() {
+        errors.push(ConfigError::invalid("auth", "must be an object mapping path prefixes to credentials").with_value(config));
+    }
+    
+    for (prefix, settings) in config.entries() {
+        let field = |key: &str| format!("auth.{}.{}", prefix, key);
+        
+        if !prefix.starts_with('/') || !settings.is_object() {
+            errors.push(ConfigError::invalid(&format!("auth.{}", prefix), "must be keyed by a path prefix starting with / and hold an object"));
+            
+            continue;
+        }
+        
+        let error_count = errors.len();
+        check_keys(settings, &format!("auth.{}", prefix), &AUTH_KEYS, errors);
+        
+        // Get the realm, which the browser shows when it asks for credentials.
+        let realm = if settings["realm"].is_null() {
+            auth::DEFAULT_REALM
+        } else {
+            match settings["realm"].as_str() {
+                Some(realm) => realm,
+                None => {
+                    errors.push(ConfigError::invalid(&field("realm"), "must be a string").with_value(&settings["realm"]));
+                    
+                    auth::DEFAULT_REALM
+                }
+            }
+        };
+        
+        let mut protected = BasicAuth::new(prefix, realm);
+        
+        // Get the users written in the configuration, whose passwords may be plain text or bcrypt hashes.
+        if !settings["credentials"].is_null() && !settings["credentials"].is_array() {
+            errors.push(ConfigError::invalid(&field("credentials"), "must be an array of user:password strings").with_value(&settings["credentials"]));
+        }
+        
+        for (index, credential) in settings["credentials"].members().enumerate() {
+            match credential.as_str().and_then(|credential| credential.split_once(':')) {
+                Some((user, password)) if !user.is_empty() && !password.is_empty() => protected = protected.with_user(user, password),
+                _ => errors.push(ConfigError::invalid(&field(&format!("credentials[{}]", index)), "must be user:password, the password may be a bcrypt hash").with_value(credential)),
+            }
+        }
+        
+        // Get the users from an htpasswd file.
+        if !settings["htpasswd"].is_null() {
+            match settings["htpasswd"].as_str() {
+                Some(path) => match fs::read_to_string(path).map(|contents| auth::parse_htpasswd(&contents)) {
+                    Ok(Ok(users)) => {
+                        for (user, hash) in users {
+                            protected = protected.with_user(&user, &hash);
+                        }
+                    }
+                    Ok(Err(message)) => errors.push(ConfigError::invalid(&field("htpasswd"), &message).with_value(&settings["htpasswd"])),
+                    Err(error) => errors.push(ConfigError::io(path, error)),
+                },
+                None => errors.push(ConfigError::invalid(&field("htpasswd"), "must be a file path").with_value(&settings["htpasswd"])),
+            }
+        }
+        
+        if errors.len() > error_count {
+            continue;
+        }
+        
+        if protected.get_users().is_empty() {
+            errors.push(ConfigError::invalid(&format!("auth.{}", prefix), "must have at least one user in credentials or htpasswd"));
+            
+            continue;
+        }
+        
+        auth.push(protected);
+    }
+    
+    auth
+}
+
+/// Reads where the OpenAPI document is served and what it calls the API.
+fn load_openapi(config: &JsonValue, errors: &mut Vec<ConfigError>) -> Option<OpenApiConfig> {
+    if config.is_null() {
+        return None;
+    }
+    
+    if !config.is_object() {
+        errors.push(ConfigError::invalid("openapi", "must be an object with title, version and openapi_json_path").with_value(config));
+        
+        return None;
+    }
+    
+    check_keys(config, "openapi", &OPENAPI_KEYS, errors);
+    
+    let mut string = |key: &str, default: &str| match config[key].as_str() {
+        Some(value) => value.to_string(),
+        None if config[key].is_null() => default.to_string(),
+        None => {
+            errors.push(ConfigError::invalid(&format!("openapi.{}", key), "must be a string").with_value(&config[key]));
+            
+            default.to_string()
+        }
+    };
+    
+    let title = string("title", "API");
+    let version = string("version", "1.0.0");
+    let json_path = string("openapi_json_path", "/openapi.json");
+    
+    if !json_path.starts_with('/') || json_path.starts_with(openapi::SWAGGER_UI_PATH) {
+        errors.push(ConfigError::invalid("openapi.openapi_json_path", "must be a path starting with / outside of /swagger-ui/").with_value(&config["openapi_json_path"]));
+    }
+    
+    Some(OpenApiConfig::new(&title, &version, &json_path))
+}
+
+/// Reads the path prefixes that require a JSON Web Token, each with the key its signature is checked with and the
+/// issuer and audience it must have.
+fn load_jwt(config: &JsonValue, errors: &mut Vec<ConfigError>) -> Vec<JwtValidator> {
+    let mut validators = Vec::new();
+    
+    if !config.is_null() && !config.is_object() {
+        errors.push(ConfigError::invalid("jwt", "must be an object mapping path prefixes to token settings").with_value(config));
+    }
+    
+    for (prefix, settings) in config.entries() {
+        let field = |key: &str| format!("jwt.{}.{}", prefix, key);
+        
+        if !prefix.starts_with('/') || !settings.is_object() {
+            errors.push(ConfigError::invalid(&format!("jwt.{}", prefix), "must be keyed by a path prefix starting with / and hold an object"));
+            
+            continue;
+        }
+        
+        let error_count = errors.len();
+        check_keys(settings, &format!("jwt.{}", prefix), &JWT_KEYS, errors);
+        
+        // Get the key, which is an HMAC secret, a PEM public key or the keys published at a JWKS URL.
+        let refresh = load_secs(settings, "jwks_refresh_secs", &field("jwks_refresh_secs"), jwt::DEFAULT_JWKS_REFRESH, errors);
+        
+        let key = match (settings["secret"].as_str(), settings["public_key_path"].as_str(), settings["jwks_url"].as_str()) {
+            (Some(secret), None, None) if !secret.is_empty() => Some(JwtKey::secret(secret.as_bytes())),
+            (None, Some(path), None) => match JwtKey::from_pem_file(path) {
+                Ok(key) => Some(key),
+                Err(message) => {
+                    errors.push(ConfigError::invalid(&field("public_key_path"), &message).with_value(&settings["public_key_path"]));
+                    
+                    None
+                }
+            },
+            (None, None, Some(url)) => match JwtKey::jwks(url, refresh) {
+                Ok(key) => Some(key),
+                Err(message) => {
+                    errors.push(ConfigError::invalid(&field("jwks_url"), &message).with_value(&settings["jwks_url"]));
+                    
+                    None
+                }
+            },
+            _ => {
+                errors.push(ConfigError::invalid(&format!("jwt.{}", prefix), "must have exactly one of a non-empty secret, public_key_path or jwks_url"));
+                
+                None
+            }
+        };
+        
+        // Get the issuer and audience the tokens must name, either is left unchecked if it's not specified.
+        let mut claims = Vec::new();
+        
+        for key in ["issuer", "audience"] {
+            if settings[key].is_null() {
+                continue;
+            }
+            
+            match settings[key].as_str() {
+                Some(value) => claims.push((key, value)),
+                None => errors.push(ConfigError::invalid(&field(key), "must be a string").with_value(&settings[key])),
+            }
+        }
+        
+        let leeway = if settings["leeway_secs"].is_null() {
+            jwt::DEFAULT_LEEWAY
+        } else {
+            match settings["leeway_secs"].as_u64() {
+                Some(leeway) => Duration::from_secs(leeway),
+                None => {
+                    errors.push(ConfigError::invalid(&field("leeway_secs"), "must be a number of seconds").with_value(&settings["leeway_secs"]));
+                    
+                    jwt::DEFAULT_LEEWAY
+                }
+            }
+        };
+        
+        let key = match key {
+            Some(key) if errors.len() == error_count => key,
+            _ => continue,
+        };
+        
+        let mut validator = JwtValidator::new(prefix, key).with_leeway(leeway);
+        
+        for (key, value) in claims {
+            validator = match key {
+                "issuer" => validator.with_issuer(value),
+                _ => validator.with_audience(value),
+            };
+        }
+        
+        validators.push(validator);
+    }
+    
+    validators
+}
+
+/// Reads the address of a FastCGI route's backend, the script requests are passed to and the extra parameters.
+///
+/// Returns `None` if anything is invalid, after pushing every problem to `errors`.
+fn load_fastcgi(config: &JsonValue, parent: &str, errors: &mut Vec<ConfigError>) -> Option<FastCgi> {
+    let field = |key: &str| format!("{}.{}", parent, key);
+    let error_count = errors.len();
+    
+    check_keys(config, parent, &FASTCGI_KEYS, errors);
+    
+    // Get where the backend listens, over TCP or a Unix socket.
+    let address = match config["address"].as_str().map(FastCgiAddress::parse) {
+        Some(Ok(address)) => Some(address),
+        Some(Err(message)) => {
+            errors.push(ConfigError::invalid(&field("address"), &message).with_value(&config["address"]));
+            
+            None
+        }
+        None => {
+            errors.push(ConfigError::invalid(&field("address"), "must be host:port or unix:/path/to/socket").with_value(&config["address"]));
+            
+            None
+        }
+    };
+    
+    // Get the script every request is passed to, the request path names it if it's not specified.
+    let script = if config["script"].is_null() {
+        None
+    } else {
+        match config["script"].as_str() {
+            Some(script) if !script.trim_start_matches('/').is_empty() && !script.split('/').any(|part| part == "..") => Some(script),
+            _ => {
+                errors.push(ConfigError::invalid(&field("script"), "must be a file path inside the web root").with_value(&config["script"]));
+                
+                None
             }
         }
-        Err(_) => panic!("Failed to create file: {}", path),
+    };
+    
+    let timeout = load_secs(config, "timeout_secs", &field("timeout_secs"), fastcgi::DEFAULT_TIMEOUT, errors);
+    
+    // Get the parameters passed along with the CGI ones, e.g. PHP_VALUE.
+    let mut params = Vec::new();
+    
+    if !config["params"].is_null() && !config["params"].is_object() {
+        errors.push(ConfigError::invalid(&field("params"), "must be an object mapping parameter names to strings").with_value(&config["params"]));
+    }
+    
+    for (name, value) in config["params"].entries() {
+        match value.as_str() {
+            Some(value) => params.push((name, value)),
+            None => errors.push(ConfigError::invalid(&field(&format!("params.{}", name)), "must be a string").with_value(value)),
+        }
+    }
+    
+    if errors.len() > error_count {
+        return None;
+    }
+    
+    let mut backend = FastCgi::new(address?).with_timeout(timeout);
+    
+    if let Some(script) = script {
+        backend = backend.with_script(script);
+    }
+    
+    for (name, value) in params {
+        backend = backend.with_param(name, value);
+    }
+    
+    Some(backend)
+}
+
+/// Reads a number of seconds greater than 0, returning the default if it's not specified.
+fn load_secs(config: &JsonValue, key: &str, field: &str, default: Duration, errors: &mut Vec<ConfigError>) -> Duration {
+    if config[key].is_null() {
+        return default;
+    }
+    
+    match config[key].as_u64() {
+        Some(secs) if secs > 0 => Duration::from_secs(secs),
+        _ => {
+            errors.push(ConfigError::invalid(field, "must be a number of seconds greater than 0").with_value(&config[key]));
+            
+            default
+        }
+    }
+}
+
+/// Reads a list of address ranges like `["10.0.0.0/8", "::1"]`, where a missing list is empty.
+fn load_cidr_list(config: &JsonValue, key: &str, field: &str, errors: &mut Vec<ConfigError>) -> Vec<IpNet> {
+    if config[key].is_null() {
+        return Vec::new();
+    }
+    
+    if !config[key].is_array() {
+        errors.push(ConfigError::invalid(field, "must be an array of IP addresses or CIDR ranges").with_value(&config[key]));
+        
+        return Vec::new();
+    }
+    
+    let mut ranges = Vec::new();
+    
+    for (index, range) in config[key].members().enumerate() {
+        match range.as_str().and_then(|range| network::parse_cidr_list(&[range]).ok()) {
+            Some(range) => ranges.extend(range),
+            None => errors.push(ConfigError::invalid(&format!("{}[{}]", field, index), "must be an IP address or a CIDR range like 10.0.0.0/8").with_value(range)),
+        }
+    }
+    
+    ranges
+}
+
+/// Writes address ranges the way `load_cidr_list` reads them.
+fn dump_cidr_list(ranges: &[IpNet]) -> JsonValue {
+    ranges.iter().map(|range| range.to_string()).collect::<Vec<_>>().into()
+}
+
+fn load_log_rotation(config: &JsonValue) -> Result<LogRotation, ConfigError> {
+    // Get the size a log file may grow to, in megabytes.
+    let max_size_bytes = if config["max_size_mb"].is_null() {
+        None
+    } else {
+        match config["max_size_mb"].as_u64() {
+            Some(max_size_mb) if max_size_mb > 0 => Some(max_size_mb * 1_048_576),
+            _ => return Err(ConfigError::invalid("log_rotation.max_size_mb", "must be a number greater than 0").with_value(&config["max_size_mb"])),
+        }
+    };
+    
+    // Get whether to start a new file every day at midnight UTC.
+    let daily = if config["daily"].is_null() {
+        false
+    } else {
+        match config["daily"].as_bool() {
+            Some(daily) => daily,
+            None => return Err(ConfigError::invalid("log_rotation.daily", "must be a boolean").with_value(&config["daily"])),
+        }
+    };
+    
+    if max_size_bytes.is_none() && !daily {
+        return Err(ConfigError::invalid("log_rotation", "must set max_size_mb, daily or both"));
+    }
+    
+    // Get the number of rotated files to keep.
+    let max_files = if config["max_files"].is_null() {
+        DEFAULT_LOG_ROTATION_MAX_FILES
+    } else {
+        match config["max_files"].as_usize() {
+            Some(max_files) => max_files,
+            None => return Err(ConfigError::invalid("log_rotation.max_files", "must be a non-negative number").with_value(&config["max_files"])),
+        }
+    };
+    
+    Ok(LogRotation::new(max_size_bytes, daily, max_files))
+}
+
+fn load_rate_limiter(config: &JsonValue) -> Result<Box<dyn RateLimiter + Send + Sync>, ConfigError> {
+    // Get the algorithm, the token bucket is used if it's not specified.
+    let algorithm = if config["algorithm"].is_null() {
+        RateLimiterAlgorithm::TokenBucket
+    } else {
+        match config["algorithm"].as_str().and_then(RateLimiterAlgorithm::parse) {
+            Some(algorithm) => algorithm,
+            None => return Err(ConfigError::invalid("rate_limit.algorithm", "must be either token_bucket or sliding_window").with_value(&config["algorithm"])),
+        }
+    };
+    
+    let window_secs = match config["window_secs"].as_u64() {
+        Some(window_secs) if window_secs > 0 => window_secs,
+        _ => return Err(ConfigError::invalid("rate_limit.window_secs", "must be a number greater than 0").with_value(&config["window_secs"])),
+    };
+    
+    let max_requests = match config["max_requests"].as_u32() {
+        Some(max_requests) if max_requests > 0 => max_requests,
+        _ => return Err(ConfigError::invalid("rate_limit.max_requests", "must be a number greater than 0").with_value(&config["max_requests"])),
+    };
+    
+    Ok(match algorithm {
+        RateLimiterAlgorithm::TokenBucket => Box::new(TokenBucketRateLimiter::new(window_secs, max_requests)),
+        RateLimiterAlgorithm::SlidingWindow => Box::new(SlidingWindowRateLimiter::new(window_secs, max_requests)),
+    })
+}
+
+#[cfg(feature = "dev")]
+fn load_delay_middleware(config: &JsonValue) -> Result<Box<dyn Middleware + Send + Sync>, ConfigError> {
+    use crate::middleware::DelayMiddleware;
+    
+    let min_ms = config["min_ms"].as_u64();
+    let max_ms = config["max_ms"].as_u64();
+    
+    // Check if the delay range is valid.
+    let (min_ms, max_ms) = match (min_ms, max_ms) {
+        (Some(min_ms), Some(max_ms)) if min_ms <= max_ms => (min_ms, max_ms),
+        _ => return Err(ConfigError::invalid("simulate_latency", "min_ms and max_ms must be numbers with min_ms <= max_ms")),
+    };
+    
+    let mut paths = Vec::new();
+    
+    for path in config["paths"].members() {
+        match path.as_str() {
+            Some(path) => paths.push(path.to_string()),
+            None => return Err(ConfigError::invalid("simulate_latency.paths", "must be strings")),
+        }
+    }
+    
+    Ok(Box::new(DelayMiddleware::new(min_ms, max_ms, paths)))
+}
+
+#[cfg(not(feature = "dev"))]
+fn load_delay_middleware(_config: &JsonValue) -> Result<Box<dyn Middleware + Send + Sync>, ConfigError> {
+    Err(ConfigError::invalid("simulate_latency", "requires a build with the dev feature"))
+}
+
+/// Formats methods as the value of an `Allow` header, e.g. `GET, HEAD, OPTIONS`.
+fn allow_header(methods: &[Method]) -> String {
+    methods.iter().map(|method| method.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+/// Replaces the value at a path like `["listeners[*]", "tls", "key_path"]`, leaving the configuration alone where the path
+/// doesn't exist.
+fn redact(config: &mut JsonValue, path: &[&str]) {
+    let Some((key, rest)) = path.split_first() else {
+        return;
+    };
+    
+    // Indexing would turn anything that isn't an object into one, so check before following the path.
+    let (key, every_item) = match key.strip_suffix("[*]") {
+        Some(key) => (key, true),
+        None => (*key, false),
+    };
+    
+    if !config.is_object() || config[key].is_null() {
+        return;
+    }
+    
+    let targets = match every_item {
+        true => config[key].members_mut().collect::<Vec<_>>(),
+        false => vec![&mut config[key]],
+    };
+    
+    for target in targets {
+        match rest.is_empty() {
+            true => *target = "[redacted]".into(),
+            false => redact(target, rest),
+        }
+    }
+}
+
+/// Reports every key of a configuration object that isn't one of the known ones.
+fn check_keys(object: &JsonValue, parent: &str, known: &[&str], errors: &mut Vec<ConfigError>) {
+    for (key, _) in object.entries() {
+        if !known.contains(&key) {
+            errors.push(ConfigError::unknown(parent, key, known));
+        }
+    }
+}
+
+fn route_error(field: &str, error: &RouteError, pattern: &JsonValue) -> ConfigError {
+    let message = match error {
+        RouteError::Duplicate(_) => "must not be used by more than one route",
+        _ => "must start with a slash, name its parameters and only use * as its last segment",
+    };
+    
+    ConfigError::invalid(field, message).with_value(pattern)
+}
+
+/// Extracts the message from a panic payload.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Unknown panic".to_string()
+    }
+}
+
+/// Replaces the top-level keys of a configuration with the ones in `overrides`.
+///
+/// Listener settings like the port can't be set at the top level next to `listeners`, so they're applied to the
+/// listener instead. With more than one listener it's unclear which one is meant, so they're refused.
+fn apply_overrides(config: &mut JsonValue, overrides: &JsonValue) -> Result<(), ConfigError> {
+    for (key, value) in overrides.entries() {
+        if !LISTENER_KEYS.contains(&key) || !config["listeners"].is_array() || config["listeners"].is_empty() {
+            config[key] = value.clone();
+        } else if config["listeners"].len() == 1 {
+            config["listeners"][0][key] = value.clone();
+        } else {
+            return Err(ConfigError::invalid(key, "can't be overridden for more than one listener, set it on each listener in the configuration file instead"));
+        }
+    }
+    
+    Ok(())
+}
+
+/// Reads and parses a configuration file.
+fn read_config_file(path: &Path) -> Result<JsonValue, ConfigError> {
+    let display_path = path.display().to_string();
+    
+    // The extension picks the format, so check it before reading the file.
+    let format = ConfigFormat::from_path(path)?;
+    
+    // Read the configuration file.
+    let config = fs::read_to_string(path).map_err(|error| ConfigError::io(&display_path, error))?;
+    
+    // Parse the configuration file.
+    format.parse(&config)
+}
+
+fn create_file(path: String) -> Result<Page, ConfigError> {
+    let directory = path.replace(path.split('/').next_back().unwrap(), "");
+    
+    // Make sure all the directories exist before creating the file.
+    if fs::metadata(&directory).is_err() {
+        // Create the directories.
+        match fs::create_dir_all(&directory) {
+            Ok(_) => info!("Created directories: {}", directory),
+            Err(error) => return Err(ConfigError::io(&directory, error)),
+        }
     }
     
-    let name = path.split('/').last().unwrap();
+    // Create an empty file.
+    match fs::write(&path, "") {
+        Ok(_) => info!("Created file: {}", path),
+        Err(error) => return Err(ConfigError::io(&path, error)),
+    }
+    
+    let name = path.split('/').next_back().unwrap();
     
     // Return a new page instance.
-    Page::new(name, &path, "")
+    Ok(Page::new(name, &path, b""))
+}
+
+/// The files, pages and routes requests are matched against, either the default ones or those of a virtual host.
+pub struct Site {
+    web_root: String,
+    error_pages: HashMap<u16, Page>,
+    pages: Vec<Page>,
+    router: Router,
+    proxies: Vec<Proxy>,
+    fastcgi: Vec<FastCgi>,
+}
+
+impl Site {
+    pub fn get_web_root(&self) -> &str {
+        &self.web_root
+    }
+    
+    /// Returns the pages sent instead of the built-in error page, keyed by status code.
+    pub fn get_error_pages(&self) -> &HashMap<u16, Page> {
+        &self.error_pages
+    }
+    
+    pub fn get_pages(&self) -> &Vec<Page> {
+        &self.pages
+    }
+    
+    pub fn get_router(&self) -> &Router {
+        &self.router
+    }
+    
+    /// Returns the upstream servers of the proxy routes, which refer to them by index.
+    pub fn get_proxies(&self) -> &Vec<Proxy> {
+        &self.proxies
+    }
+    
+    /// Returns the backends of the FastCGI routes, which refer to them by index.
+    pub fn get_fastcgi(&self) -> &Vec<FastCgi> {
+        &self.fastcgi
+    }
+}
+
+/// Every site the server serves, swapped as a whole when the configuration is reloaded.
+struct Sites {
+    default: Arc<Site>,
+    /// The virtual hosts by lowercase hostname, which may start with `*.` to match any subdomain.
+    vhosts: HashMap<String, Arc<Site>>,
+    /// Whether requests for other hosts get the default site rather than 421.
+    fallback: bool,
 }
 
 pub struct Page {
     name: String,
     path: String,
-    contents: String,
+    contents: Vec<u8>,
+    modified: Option<SystemTime>,
+    headers: Vec<(String, String)>,
+    is_template: bool,
+    compiled: Option<CompiledTemplate>,
 }
 
 impl Page {
-    fn new(name: &str, path: &str, contents: &str) -> Page {
+    fn new(name: &str, path: &str, contents: &[u8]) -> Page {
         Page {
             name: name.to_string(),
             path: path.to_string(),
-            contents: contents.to_string(),
+            contents: contents.to_vec(),
+            modified: None,
+            headers: Vec::new(),
+            is_template: false,
+            compiled: None,
         }
     }
     
+    /// Turns the page into a template, compiling its contents so they can be rendered on every request.
+    fn make_template(&mut self) -> Result<(), RenderError> {
+        let source = std::str::from_utf8(&self.contents).map_err(|_| RenderError::InvalidUtf8)?;
+        
+        self.compiled = Some(CompiledTemplate::compile(source)?);
+        self.is_template = true;
+        
+        Ok(())
+    }
+    
     pub fn get_name(&self) -> &str {
         &self.name
     }
@@ -335,7 +5240,270 @@ impl Page {
         &self.path
     }
     
-    pub fn get_contents(&self) -> &str {
+    /// Returns the raw file contents, which aren't necessarily text, e.g. for images and fonts.
+    pub fn get_contents(&self) -> &[u8] {
         &self.contents
     }
+    
+    /// Returns when the contents last changed, if it's known.
+    pub fn get_modified(&self) -> Option<SystemTime> {
+        self.modified
+    }
+    
+    pub fn set_modified(&mut self, modified: Option<SystemTime>) {
+        self.modified = modified;
+    }
+    
+    /// Returns the headers added to every response for this page, overriding ones set by the server.
+    pub fn get_headers(&self) -> &Vec<(String, String)> {
+        &self.headers
+    }
+    
+    pub fn add_header(&mut self, name: &str, value: &str) {
+        self.headers.push((name.to_string(), value.to_string()));
+    }
+    
+    pub fn is_template(&self) -> bool {
+        self.is_template
+    }
+    
+    /// Replaces the page contents, recompiling them if the page is a template.
+    pub fn set_contents(&mut self, contents: &[u8]) -> Result<(), RenderError> {
+        if self.is_template {
+            let source = std::str::from_utf8(contents).map_err(|_| RenderError::InvalidUtf8)?;
+            
+            self.compiled = Some(CompiledTemplate::compile(source)?);
+        }
+        
+        self.contents = contents.to_vec();
+        self.modified = Some(SystemTime::now());
+        
+        Ok(())
+    }
+    
+    /// Returns the names of the variables the page uses, none if it isn't a template.
+    pub fn get_template_variables(&self) -> Vec<&str> {
+        self.compiled.as_ref().map(|compiled| compiled.variables().collect()).unwrap_or_default()
+    }
+    
+    pub fn render_template(&self, context: &TemplateContext) -> Result<String, RenderError> {
+        match &self.compiled {
+            Some(compiled) => compiled.render(context),
+            None => Err(RenderError::NotATemplate),
+        }
+    }
+}
+
+/// Pages are equal if they're served from the same file, regardless of their names.
+impl PartialEq for Page {
+    fn eq(&self, other: &Page) -> bool {
+        self.path == other.path
+    }
+}
+
+impl Eq for Page {}
+
+impl fmt::Display for Page {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Page {{ name: {:?}, path: {:?}, size: {}B }}", self.name, self.path, self.contents.len())
+    }
+}
+
+impl fmt::Debug for Page {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Only show the start of the contents to avoid flooding the logs.
+        let mut contents: String = String::from_utf8_lossy(&self.contents[..self.contents.len().min(100)]).into_owned();
+        
+        if self.contents.len() > 100 {
+            contents += "...";
+        }
+        
+        f.debug_struct("Page")
+            .field("name", &self.name)
+            .field("path", &self.path)
+            .field("contents", &contents)
+            .field("is_template", &self.is_template)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// Creates a server whose only page sets `Cache-Control`, in a web root that's removed afterwards by the caller.
+    fn server_with_cached_page(web_root: &Path) -> Server {
+        fs::create_dir_all(web_root).unwrap();
+        fs::write(web_root.join("page.html"), "<p>Page</p>").unwrap();
+        
+        let config = json::object! {
+            "verbose": false,
+            "thread_count": 1,
+            "port": 0,
+            "web_root": web_root.to_str().unwrap(),
+            "pages": [{ "name": "/page", "path": "page.html", "headers": { "Cache-Control": "max-age=60" } }],
+        };
+        
+        Server::new(&config).unwrap()
+    }
+    
+    #[test]
+    fn route_headers_override_page_headers() {
+        let web_root = env::temp_dir().join(format!("web_server_route_headers_{}", std::process::id()));
+        let server = server_with_cached_page(&web_root);
+        let site = server.get_site();
+        
+        let request = Request::parse("GET /page HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let context = ConnectionContext::new(IpAddr::V4(Ipv4Addr::LOCALHOST), &request, Instant::now(), false, "");
+        
+        // The page's own route only has the page's headers.
+        let (page_route, params) = site.router.find("/page").unwrap();
+        let response = server.serve_route(&context, &request, &site, page_route, &params, None);
+        
+        assert_eq!(response.get_header("Cache-Control"), Some("max-age=60"));
+        
+        // A route to the same page replaces them.
+        let mut route = Route::new("/page", RouteTarget::Page(0)).unwrap();
+        route.add_header("Cache-Control", "no-store");
+        
+        let response = server.serve_route(&context, &request, &site, &route, &RouteParams::default(), None);
+        
+        assert_eq!(response.get_header("Cache-Control"), Some("no-store"));
+        assert_eq!(response.get_headers().get_all("Cache-Control").count(), 1);
+        
+        fs::remove_dir_all(web_root).unwrap();
+    }
+    
+    /// Returns the fields of the problems `parse_config` reports, in order.
+    /// Renders a template page with the given source for a GET request to /.
+    fn render(server: &Server, source: &str) -> Response {
+        let mut page = Page::new("/", "template.html", source.as_bytes());
+        page.make_template().unwrap();
+        
+        let request = Request::parse("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let context = ConnectionContext::new(IpAddr::V4(Ipv4Addr::LOCALHOST), &request, Instant::now(), false, "");
+        
+        server.render_page(&context, &request, &page)
+    }
+    
+    #[test]
+    fn templates_render_key_value_entries() {
+        let web_root = env::temp_dir().join(format!("web_server_template_kv_{}", std::process::id()));
+        let server = server_with_cached_page(&web_root);
+        server.kv_store().set("name", "<World>".into());
+        server.kv_store().set("count", 3.into());
+        
+        let response = render(&server, "{{ kv.name }}|{{ kv.count }}|{{ kv.missing }}");
+        
+        assert_eq!(response.get_status_code(), 200);
+        assert_eq!(response.get_body(), b"&lt;World&gt;|3|");
+        
+        fs::remove_dir_all(web_root).unwrap();
+    }
+    
+    #[test]
+    fn template_errors_are_only_logged() {
+        let web_root = env::temp_dir().join(format!("web_server_template_error_{}", std::process::id()));
+        let server = server_with_cached_page(&web_root);
+        
+        let response = render(&server, "<p>{{ secret_setting }}</p>");
+        
+        assert_eq!(response.get_status_code(), 500);
+        assert!(!String::from_utf8_lossy(response.get_body()).contains("secret_setting"));
+        assert!(response.get_error().is_some_and(|error| error.contains("secret_setting")));
+        
+        fs::remove_dir_all(web_root).unwrap();
+    }
+    
+    fn invalid_fields(config: &JsonValue) -> Vec<String> {
+        let errors = match Server::parse_config(config) {
+            Ok(_) => return Vec::new(),
+            Err(ConfigError::Multiple(errors)) => errors,
+            Err(error) => vec![error],
+        };
+        
+        errors.into_iter()
+            .map(|error| match error {
+                ConfigError::InvalidField { field, .. } | ConfigError::MissingField { field, .. } | ConfigError::UnknownField { field, .. } => field,
+                error => panic!("expected a field error, got {}", error),
+            })
+            .collect()
+    }
+    
+    #[test]
+    fn valid_config_is_parsed_with_defaults() {
+        let config = json::object! { "verbose": false, "thread_count": 2, "port": 8080, "web_root": "web" };
+        let parsed = Server::parse_config(&config).unwrap();
+        
+        assert_eq!(parsed.thread_count, 2);
+        assert_eq!(parsed.listener_settings[0].port, 8080);
+        assert_eq!(parsed.max_body_size, DEFAULT_MAX_BODY_SIZE);
+        assert_eq!(parsed.server_banner.as_deref(), Some(DEFAULT_SERVER_BANNER));
+        assert!(!parsed.deny_unlisted);
+    }
+    
+    #[test]
+    fn every_invalid_value_is_reported_at_once() {
+        let config = json::object! {
+            "verbose": "yes",
+            "thread_count": 0,
+            "port": 80,
+            "web_root": "web",
+            "pages": [{ "name": "/", "path": "index.html" }, { "name": "/about", "path": "about.html" }, { "name": "/contact" }],
+            "log_sample_rate": 2,
+        };
+        
+        assert_eq!(invalid_fields(&config), ["verbose", "thread_count", "port", "log_sample_rate", "pages[2].path"]);
+    }
+    
+    #[test]
+    fn missing_values_are_reported_as_missing() {
+        match Server::parse_config(&json::object! { "verbose": true, "thread_count": 1, "port": 8080 }) {
+            Err(ConfigError::MissingField { field, .. }) => assert_eq!(field, "web_root"),
+            Err(error) => panic!("expected web_root to be missing, got {}", error),
+            Ok(_) => panic!("expected web_root to be missing"),
+        }
+    }
+    
+    #[test]
+    fn misspelled_keys_suggest_the_setting() {
+        let config = json::object! { "verbose": true, "thread_count": 1, "port": 8080, "web_root": "web", "max_body_sise": 10, "my_app": {} };
+        
+        match Server::parse_config(&config) {
+            Err(error @ ConfigError::UnknownField { .. }) => assert_eq!(error.to_string(), "Unknown setting max_body_sise, did you mean max_body_size?"),
+            Err(error) => panic!("expected an unknown setting, got {}", error),
+            Ok(_) => panic!("expected max_body_sise to be reported"),
+        }
+    }
+    
+    #[test]
+    fn nested_values_are_reported_by_their_path() {
+        let config = json::object! {
+            "verbose": true,
+            "thread_count": 1,
+            "web_root": "web",
+            "listeners": [{ "name": "a", "port": 8080 }, { "name": "b", "port": 8081, "tls": { "cert_path": "cert.pem" } }],
+            "compression": { "rules": [{ "content_type_prefix": "text/", "never": "no" }] },
+        };
+        
+        assert_eq!(invalid_fields(&config), ["listeners[1].tls.key_path", "compression.rules[0].never"]);
+    }
+    
+    #[test]
+    fn listener_overrides_apply_to_the_only_listener() {
+        let mut config = json::object! { "listeners": [{ "name": "main", "port": 8080 }] };
+        
+        apply_overrides(&mut config, &json::object! { "port": 9090, "verbose": true }).unwrap();
+        
+        assert_eq!(config["listeners"][0]["port"], 9090);
+        assert!(config["port"].is_null());
+        assert_eq!(config["verbose"], true);
+    }
+    
+    #[test]
+    fn listener_overrides_are_refused_with_several_listeners() {
+        let mut config = json::object! { "listeners": [{ "name": "a", "port": 8080 }, { "name": "b", "port": 8081 }] };
+        
+        assert!(apply_overrides(&mut config, &json::object! { "port": 9090 }).is_err());
+    }
 }
@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+use std::net::{self, IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The default number of seconds in-flight connections get to finish once a shutdown is requested.
+pub const DEFAULT_GRACE_PERIOD_SECS: u64 = 30;
+
+/// How often the grace period checks whether every connection has finished.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A connection being handled, along with whether it's waiting for its next request.
+struct TrackedConnection {
+    stream: TcpStream,
+    idle: bool,
+}
+
+/// Stops a server gracefully: the accept loops stop, connections waiting for a request are closed straight away, and
+/// the ones in the middle of a request get a grace period to finish before they're closed as well.
+pub struct ShutdownSignal {
+    requested: AtomicBool,
+    grace_period: Duration,
+    listener_addrs: Mutex<Vec<SocketAddr>>,
+    connections: Mutex<HashMap<u64, TrackedConnection>>,
+    next_id: AtomicU64,
+}
+
+impl ShutdownSignal {
+    pub fn new(grace_period: Duration) -> ShutdownSignal {
+        ShutdownSignal {
+            requested: AtomicBool::new(false),
+            grace_period,
+            listener_addrs: Mutex::new(Vec::new()),
+            connections: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+        }
+    }
+    
+    pub fn get_grace_period(&self) -> Duration {
+        self.grace_period
+    }
+    
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+    
+    /// Returns the number of connections that are still open.
+    pub fn get_connection_count(&self) -> usize {
+        self.connections.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).len()
+    }
+    
+    /// Remembers the addresses the server listens on, so a shutdown can wake up the accept loops.
+    pub fn set_listener_addrs(&self, listener_addrs: Vec<SocketAddr>) {
+        *self.listener_addrs.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = listener_addrs;
+    }
+    
+    /// Keeps track of a connection until the returned tracker is dropped, so a shutdown can wait for it or close it.
+    pub fn track(self: &Arc<Self>, stream: &TcpStream) -> Option<ConnectionTracker> {
+        let stream = stream.try_clone().ok()?;
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        
+        self.connections.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).insert(id, TrackedConnection {
+            stream,
+            idle: false,
+        });
+        
+        Some(ConnectionTracker {
+            signal: Arc::clone(self),
+            id,
+        })
+    }
+    
+    /// Stops accepting connections and starts draining the open ones, returning straight away.
+    ///
+    /// Requesting a shutdown more than once has no further effect.
+    pub fn request(self: &Arc<Self>) {
+        if self.requested.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        
+        // The accept loops block until a connection arrives, so connect to each listener to wake it up.
+        for address in self.listener_addrs.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).iter() {
+            let ip = match address.ip() {
+                IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+                ip => ip,
+            };
+            
+            let _ = TcpStream::connect(SocketAddr::new(ip, address.port()));
+        }
+        
+        // Nobody is waiting on idle connections, so don't make them sit out the keep-alive timeout.
+        for connection in self.connections.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).values() {
+            if connection.idle {
+                let _ = connection.stream.shutdown(net::Shutdown::Read);
+            }
+        }
+        
+        // Close whatever is still open once the grace period is over, which makes the handlers' reads and writes fail.
+        let signal = Arc::clone(self);
+        
+        thread::spawn(move || {
+            let deadline = Instant::now() + signal.grace_period;
+            
+            while Instant::now() < deadline {
+                if signal.get_connection_count() == 0 {
+                    return;
+                }
+                
+                thread::sleep(DRAIN_POLL_INTERVAL);
+            }
+            
+            for connection in signal.connections.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).values() {
+                let _ = connection.stream.shutdown(net::Shutdown::Both);
+            }
+        });
+    }
+}
+
+/// Tracks a connection for its lifetime, see `ShutdownSignal::track`.
+pub struct ConnectionTracker {
+    signal: Arc<ShutdownSignal>,
+    id: u64,
+}
+
+impl ConnectionTracker {
+    /// Marks whether the connection is waiting for its next request, returns false if it should be closed instead
+    /// because the server is shutting down.
+    pub fn set_idle(&self, idle: bool) -> bool {
+        let mut connections = self.signal.connections.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        
+        if let Some(connection) = connections.get_mut(&self.id) {
+            connection.idle = idle;
+        }
+        
+        // Checked while holding the lock, so a shutdown either sees the connection as idle or the connection sees it.
+        !(idle && self.signal.is_requested())
+    }
+}
+
+impl Drop for ConnectionTracker {
+    fn drop(&mut self) {
+        self.signal.connections.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).remove(&self.id);
+    }
+}
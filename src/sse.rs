@@ -0,0 +1,179 @@
+use std::io::{self, Read};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+
+use crate::http::Response;
+use crate::status::StatusCode;
+
+/// How long a stream may go without events before a comment is sent, so proxies and load balancers in between don't
+/// close the connection for being idle, and a client that went away is noticed.
+pub const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// An event of a `text/event-stream` response, see `Response::stream`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Event {
+    data: String,
+    event: Option<String>,
+    id: Option<String>,
+    retry: Option<Duration>,
+}
+
+impl Event {
+    /// Creates an event with the given data, which may span several lines.
+    pub fn new(data: &str) -> Event {
+        Event {
+            data: data.to_string(),
+            event: None,
+            id: None,
+            retry: None,
+        }
+    }
+    
+    /// Sets the event type, which the browser dispatches to the listeners registered for it instead of `onmessage`.
+    pub fn with_event(mut self, event: &str) -> Event {
+        self.event = Some(event.to_string());
+        
+        self
+    }
+    
+    /// Sets the ID, which the browser sends back as `Last-Event-ID` when it reconnects.
+    pub fn with_id(mut self, id: &str) -> Event {
+        self.id = Some(id.to_string());
+        
+        self
+    }
+    
+    /// Sets how long the browser waits before reconnecting after the connection is lost.
+    pub fn with_retry(mut self, retry: Duration) -> Event {
+        self.retry = Some(retry);
+        
+        self
+    }
+    
+    pub fn get_data(&self) -> &str {
+        &self.data
+    }
+    
+    pub fn get_event(&self) -> Option<&str> {
+        self.event.as_deref()
+    }
+    
+    pub fn get_id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+    
+    pub fn get_retry(&self) -> Option<Duration> {
+        self.retry
+    }
+    
+    /// Serializes the event in the wire format, ending with the blank line that dispatches it.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut event = String::new();
+        
+        // Line breaks would end the field early, so they're left out of the single-line fields.
+        if let Some(name) = &self.event {
+            event += &format!("event: {}\n", name.replace(['\r', '\n'], ""));
+        }
+        
+        if let Some(id) = &self.id {
+            event += &format!("id: {}\n", id.replace(['\r', '\n', '\0'], ""));
+        }
+        
+        if let Some(retry) = self.retry {
+            event += &format!("retry: {}\n", retry.as_millis());
+        }
+        
+        // Every line of the data gets a field of its own, the browser joins them with line feeds again.
+        for line in self.data.split('\n') {
+            event += &format!("data: {}\n", line.strip_suffix('\r').unwrap_or(line));
+        }
+        
+        event += "\n";
+        
+        event.into_bytes()
+    }
+}
+
+/// Sends events to the client of a `text/event-stream` response, from any thread.
+///
+/// Sending fails with `BrokenPipe` once the client disconnected, which tells the producer to stop. The response ends
+/// once every sender has been dropped.
+#[derive(Clone, Debug)]
+pub struct EventSender {
+    sender: Sender<Vec<u8>>,
+}
+
+impl EventSender {
+    pub fn send(&self, event: &Event) -> io::Result<()> {
+        self.send_bytes(event.to_bytes())
+    }
+    
+    /// Sends a comment, which the browser ignores, e.g. to keep a connection open that has no events for a while.
+    pub fn send_comment(&self, comment: &str) -> io::Result<()> {
+        let comment = comment.lines().map(|line| format!(": {}\n", line)).collect::<String>();
+        
+        self.send_bytes(format!("{}\n", comment).into_bytes())
+    }
+    
+    fn send_bytes(&self, bytes: Vec<u8>) -> io::Result<()> {
+        self.sender.send(bytes).map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "The client closed the event stream."))
+    }
+}
+
+/// Creates a `text/event-stream` response along with the sender its events are written with, sending a comment
+/// whenever no event was sent for `keep_alive`.
+///
+/// The events are written while the response is sent, so the handler returns the response straight away and keeps
+/// sending from another thread. The connection and the thread serving it stay busy for as long as the stream is open.
+pub fn event_stream(keep_alive: Duration) -> (Response, EventSender) {
+    let (sender, receiver) = mpsc::channel();
+    
+    let mut response = Response::with_status(StatusCode::Ok)
+        .with_header("Content-Type", "text/event-stream")
+        .with_header("Cache-Control", "no-cache")
+        // Ask proxies like nginx not to buffer the events.
+        .with_header("X-Accel-Buffering", "no");
+    
+    response.set_body_stream(EventReader::new(receiver, keep_alive), None);
+    
+    (response, EventSender { sender })
+}
+
+/// The body of an event stream, which reads the events as they're sent and ends once every sender is dropped.
+struct EventReader {
+    receiver: Receiver<Vec<u8>>,
+    keep_alive: Duration,
+    pending: Vec<u8>,
+    position: usize,
+}
+
+impl EventReader {
+    fn new(receiver: Receiver<Vec<u8>>, keep_alive: Duration) -> EventReader {
+        EventReader {
+            receiver,
+            keep_alive,
+            pending: Vec::new(),
+            position: 0,
+        }
+    }
+}
+
+impl Read for EventReader {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        // Wait for the next event once the last one has been read, every event is written as soon as it's sent.
+        if self.position == self.pending.len() {
+            self.pending = match self.receiver.recv_timeout(self.keep_alive) {
+                Ok(bytes) => bytes,
+                Err(RecvTimeoutError::Timeout) => b": ping\n\n".to_vec(),
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            };
+            self.position = 0;
+        }
+        
+        let length = buffer.len().min(self.pending.len() - self.position);
+        buffer[..length].copy_from_slice(&self.pending[self.position..self.position + length]);
+        self.position += length;
+        
+        Ok(length)
+    }
+}
@@ -0,0 +1,147 @@
+use std::fmt;
+
+/// An HTTP status code along with its canonical reason phrase, so the two can't be mismatched.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum StatusCode {
+    Continue = 100,
+    SwitchingProtocols = 101,
+    Ok = 200,
+    Created = 201,
+    Accepted = 202,
+    NoContent = 204,
+    PartialContent = 206,
+    MovedPermanently = 301,
+    Found = 302,
+    SeeOther = 303,
+    NotModified = 304,
+    TemporaryRedirect = 307,
+    PermanentRedirect = 308,
+    BadRequest = 400,
+    Unauthorized = 401,
+    Forbidden = 403,
+    NotFound = 404,
+    MethodNotAllowed = 405,
+    RequestTimeout = 408,
+    Conflict = 409,
+    Gone = 410,
+    LengthRequired = 411,
+    PayloadTooLarge = 413,
+    UriTooLong = 414,
+    UnsupportedMediaType = 415,
+    RangeNotSatisfiable = 416,
+    ExpectationFailed = 417,
+    MisdirectedRequest = 421,
+    UnprocessableContent = 422,
+    UpgradeRequired = 426,
+    TooManyRequests = 429,
+    RequestHeaderFieldsTooLarge = 431,
+    InternalServerError = 500,
+    NotImplemented = 501,
+    BadGateway = 502,
+    ServiceUnavailable = 503,
+    GatewayTimeout = 504,
+    HttpVersionNotSupported = 505,
+}
+
+impl StatusCode {
+    /// Every status code the server knows the reason phrase of.
+    pub const ALL: [StatusCode; 38] = [
+        StatusCode::Continue,
+        StatusCode::SwitchingProtocols,
+        StatusCode::Ok,
+        StatusCode::Created,
+        StatusCode::Accepted,
+        StatusCode::NoContent,
+        StatusCode::PartialContent,
+        StatusCode::MovedPermanently,
+        StatusCode::Found,
+        StatusCode::SeeOther,
+        StatusCode::NotModified,
+        StatusCode::TemporaryRedirect,
+        StatusCode::PermanentRedirect,
+        StatusCode::BadRequest,
+        StatusCode::Unauthorized,
+        StatusCode::Forbidden,
+        StatusCode::NotFound,
+        StatusCode::MethodNotAllowed,
+        StatusCode::RequestTimeout,
+        StatusCode::Conflict,
+        StatusCode::Gone,
+        StatusCode::LengthRequired,
+        StatusCode::PayloadTooLarge,
+        StatusCode::UriTooLong,
+        StatusCode::UnsupportedMediaType,
+        StatusCode::RangeNotSatisfiable,
+        StatusCode::ExpectationFailed,
+        StatusCode::MisdirectedRequest,
+        StatusCode::UnprocessableContent,
+        StatusCode::UpgradeRequired,
+        StatusCode::TooManyRequests,
+        StatusCode::RequestHeaderFieldsTooLarge,
+        StatusCode::InternalServerError,
+        StatusCode::NotImplemented,
+        StatusCode::BadGateway,
+        StatusCode::ServiceUnavailable,
+        StatusCode::GatewayTimeout,
+        StatusCode::HttpVersionNotSupported,
+    ];
+    
+    /// Looks up a status code by its number, e.g. `StatusCode::from_code(404) == Some(StatusCode::NotFound)`.
+    pub fn from_code(code: u16) -> Option<StatusCode> {
+        StatusCode::ALL.iter().copied().find(|status| status.code() == code)
+    }
+    
+    pub fn code(&self) -> u16 {
+        *self as u16
+    }
+    
+    /// Returns the reason phrase RFC 9110 gives the status code.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            StatusCode::Continue => "Continue",
+            StatusCode::SwitchingProtocols => "Switching Protocols",
+            StatusCode::Ok => "OK",
+            StatusCode::Created => "Created",
+            StatusCode::Accepted => "Accepted",
+            StatusCode::NoContent => "No Content",
+            StatusCode::PartialContent => "Partial Content",
+            StatusCode::MovedPermanently => "Moved Permanently",
+            StatusCode::Found => "Found",
+            StatusCode::SeeOther => "See Other",
+            StatusCode::NotModified => "Not Modified",
+            StatusCode::TemporaryRedirect => "Temporary Redirect",
+            StatusCode::PermanentRedirect => "Permanent Redirect",
+            StatusCode::BadRequest => "Bad Request",
+            StatusCode::Unauthorized => "Unauthorized",
+            StatusCode::Forbidden => "Forbidden",
+            StatusCode::NotFound => "Not Found",
+            StatusCode::MethodNotAllowed => "Method Not Allowed",
+            StatusCode::RequestTimeout => "Request Timeout",
+            StatusCode::Conflict => "Conflict",
+            StatusCode::Gone => "Gone",
+            StatusCode::LengthRequired => "Length Required",
+            StatusCode::PayloadTooLarge => "Payload Too Large",
+            StatusCode::UriTooLong => "URI Too Long",
+            StatusCode::UnsupportedMediaType => "Unsupported Media Type",
+            StatusCode::RangeNotSatisfiable => "Range Not Satisfiable",
+            StatusCode::ExpectationFailed => "Expectation Failed",
+            StatusCode::MisdirectedRequest => "Misdirected Request",
+            StatusCode::UnprocessableContent => "Unprocessable Content",
+            StatusCode::UpgradeRequired => "Upgrade Required",
+            StatusCode::TooManyRequests => "Too Many Requests",
+            StatusCode::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
+            StatusCode::InternalServerError => "Internal Server Error",
+            StatusCode::NotImplemented => "Not Implemented",
+            StatusCode::BadGateway => "Bad Gateway",
+            StatusCode::ServiceUnavailable => "Service Unavailable",
+            StatusCode::GatewayTimeout => "Gateway Timeout",
+            StatusCode::HttpVersionNotSupported => "HTTP Version Not Supported",
+        }
+    }
+}
+
+impl fmt::Display for StatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.code(), self.reason())
+    }
+}
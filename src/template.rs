@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::http::escape_html;
+
+/// An error that occurred while compiling or rendering a template.
+#[derive(Debug)]
+pub enum RenderError {
+    /// The page isn't configured as a template.
+    NotATemplate,
+    /// The template source isn't valid UTF-8, so it can't be parsed.
+    InvalidUtf8,
+    /// A `{{` at the given byte offset has no matching `}}`.
+    UnclosedTag(usize),
+    /// A tag doesn't contain a valid variable name.
+    InvalidName(String),
+    /// The template uses a variable the context doesn't have.
+    MissingVariable(String),
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RenderError::NotATemplate => write!(f, "The page is not a template"),
+            RenderError::InvalidUtf8 => write!(f, "The template is not valid UTF-8"),
+            RenderError::UnclosedTag(offset) => write!(f, "Unclosed tag at byte {}", offset),
+            RenderError::InvalidName(name) => write!(f, "Invalid variable name: {:?}", name),
+            RenderError::MissingVariable(name) => write!(f, "Missing template variable: {}", name),
+        }
+    }
+}
+
+impl Error for RenderError {}
+
+/// The values a template can refer to, e.g. `{{ path }}`.
+#[derive(Default)]
+pub struct TemplateContext {
+    values: HashMap<String, String>,
+}
+
+impl TemplateContext {
+    pub fn new() -> TemplateContext {
+        TemplateContext::default()
+    }
+    
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(String::as_str)
+    }
+    
+    pub fn set(&mut self, name: &str, value: &str) {
+        self.values.insert(name.to_string(), value.to_string());
+    }
+}
+
+enum Segment {
+    Text(String),
+    Variable(String),
+}
+
+/// A template that has been parsed once, so rendering it is just a matter of filling in the variables.
+///
+/// Variables are written as `{{ name }}` and their values are HTML-escaped.
+pub struct CompiledTemplate {
+    segments: Vec<Segment>,
+}
+
+impl CompiledTemplate {
+    pub fn compile(source: &str) -> Result<CompiledTemplate, RenderError> {
+        let mut segments = Vec::new();
+        let mut rest = source;
+        
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                segments.push(Segment::Text(rest[..start].to_string()));
+            }
+            
+            let offset = source.len() - rest.len() + start;
+            let end = rest[start..].find("}}").ok_or(RenderError::UnclosedTag(offset))?;
+            let name = rest[start + 2..start + end].trim();
+            
+            // Keep names simple, so a stray "{{" in a page shows up at startup instead of in the output.
+            if name.is_empty() || !name.chars().all(|character| character.is_ascii_alphanumeric() || character == '_' || character == '.') {
+                return Err(RenderError::InvalidName(name.to_string()));
+            }
+            
+            segments.push(Segment::Variable(name.to_string()));
+            
+            rest = &rest[start + end + 2..];
+        }
+        
+        if !rest.is_empty() {
+            segments.push(Segment::Text(rest.to_string()));
+        }
+        
+        Ok(CompiledTemplate { segments })
+    }
+    
+    /// Returns the names of the variables the template uses, in the order they appear.
+    pub fn variables(&self) -> impl Iterator<Item = &str> {
+        self.segments.iter().filter_map(|segment| match segment {
+            Segment::Variable(name) => Some(name.as_str()),
+            Segment::Text(_) => None,
+        })
+    }
+    
+    pub fn render(&self, context: &TemplateContext) -> Result<String, RenderError> {
+        let mut output = String::new();
+        
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) => output += text,
+                Segment::Variable(name) => {
+                    let value = context.get(name).ok_or_else(|| RenderError::MissingVariable(name.clone()))?;
+                    
+                    output += &escape_html(value);
+                }
+            }
+        }
+        
+        Ok(output)
+    }
+}
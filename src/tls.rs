@@ -0,0 +1,104 @@
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+
+use crate::config::ConfigError;
+
+/// Loads the certificate chain and private key from PEM files and builds the rustls configuration from them.
+pub fn load_server_config(cert_path: &str, key_path: &str) -> Result<Arc<ServerConfig>, ConfigError> {
+    let certificates = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+        .map_err(|error| ConfigError::io(cert_path, io::Error::other(error)))?;
+    
+    if certificates.is_empty() {
+        return Err(ConfigError::invalid("tls.cert_path", &format!("{} must contain at least one certificate", cert_path)));
+    }
+    
+    let key = PrivateKeyDer::from_pem_file(key_path).map_err(|error| ConfigError::io(key_path, io::Error::other(error)))?;
+    
+    // Use ring explicitly rather than relying on a process-wide default provider being installed.
+    let mut config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| builder.with_no_client_auth().with_single_cert(certificates, key))
+        .map_err(|error| ConfigError::invalid("tls", &format!("failed to use the certificate and key: {}", error)))?;
+    
+    // Only HTTP/1.x is spoken, so don't let clients negotiate anything else.
+    config.alpn_protocols = vec![b"http/1.1".to_vec(), b"http/1.0".to_vec()];
+    
+    Ok(Arc::new(config))
+}
+
+/// A client connection, which is either plain TCP or terminated with TLS by the server.
+///
+/// The TLS handshake happens on the first read or write, so accepting a connection never blocks on it.
+pub enum ClientStream {
+    Plain(TcpStream),
+    Tls(Box<StreamOwned<ServerConnection, TcpStream>>),
+}
+
+impl ClientStream {
+    /// Wraps an accepted connection, encrypting it if a TLS configuration is given.
+    pub fn new(stream: TcpStream, tls_config: Option<&Arc<ServerConfig>>) -> io::Result<ClientStream> {
+        match tls_config {
+            Some(tls_config) => {
+                let connection = ServerConnection::new(Arc::clone(tls_config)).map_err(io::Error::other)?;
+                
+                Ok(ClientStream::Tls(Box::new(StreamOwned::new(connection, stream))))
+            }
+            None => Ok(ClientStream::Plain(stream)),
+        }
+    }
+    
+    pub fn is_tls(&self) -> bool {
+        matches!(self, ClientStream::Tls(_))
+    }
+    
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.get_tcp_stream().peer_addr()
+    }
+    
+    /// Returns the underlying TCP connection, which carries encrypted bytes if TLS is used.
+    pub fn get_tcp_stream(&self) -> &TcpStream {
+        match self {
+            ClientStream::Plain(stream) => stream,
+            ClientStream::Tls(stream) => stream.get_ref(),
+        }
+    }
+    
+    /// Returns the TCP connection for protocols that take it over, which is only possible without TLS.
+    pub fn into_plain(self) -> Result<TcpStream, ClientStream> {
+        match self {
+            ClientStream::Plain(stream) => Ok(stream),
+            stream => Err(stream),
+        }
+    }
+}
+
+impl Read for ClientStream {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        match self {
+            ClientStream::Plain(stream) => stream.read(buffer),
+            ClientStream::Tls(stream) => stream.read(buffer),
+        }
+    }
+}
+
+impl Write for ClientStream {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        match self {
+            ClientStream::Plain(stream) => stream.write(buffer),
+            ClientStream::Tls(stream) => stream.write(buffer),
+        }
+    }
+    
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ClientStream::Plain(stream) => stream.flush(),
+            ClientStream::Tls(stream) => stream.flush(),
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::Duration;
+
+use crate::network::{self, AccessList};
+use crate::tls::ClientStream;
+
+/// How long each side of a TLS relay is waited on before the other side gets its turn.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Checks whether a CONNECT target is in the allowlist.
+///
+/// Targets are compared as `host:port`, ignoring the case of the host.
+pub fn is_allowed(target: &str, allowlist: &[String]) -> bool {
+    allowlist.iter().any(|allowed| allowed.eq_ignore_ascii_case(target))
+}
+
+/// Resolves an allowed CONNECT target and opens a connection to it.
+///
+/// Resolution only happens after the target passed the allowlist, and every address it resolves to is checked against
+/// `ranges`, literal addresses included. This stops a host name from being rebound to an internal address after it was
+/// allowlisted.
+pub fn open(target: &str, ranges: &AccessList) -> io::Result<TcpStream> {
+    // Resolve the target and drop any addresses it isn't allowed to point to.
+    let addresses: Vec<SocketAddr> = target
+        .to_socket_addrs()?
+        .filter(|address| is_permitted(address.ip(), ranges))
+        .collect();
+    
+    if addresses.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("{} does not resolve to a permitted address!", target)));
+    }
+    
+    TcpStream::connect(&addresses[..])
+}
+
+/// Relays bytes in both directions until either side closes the connection.
+pub fn relay(client: TcpStream, upstream: TcpStream) -> io::Result<()> {
+    let mut client_reader = client.try_clone()?;
+    let mut upstream_writer = upstream.try_clone()?;
+    
+    // Copy the client's bytes to the upstream on a separate thread.
+    let outgoing = thread::spawn(move || {
+        let result = io::copy(&mut client_reader, &mut upstream_writer);
+        
+        // Let the upstream know the client is done sending.
+        let _ = upstream_writer.shutdown(Shutdown::Write);
+        
+        result
+    });
+    
+    // Copy the upstream's bytes back to the client on this thread.
+    let mut upstream_reader = upstream;
+    let mut client_writer = client;
+    let result = io::copy(&mut upstream_reader, &mut client_writer);
+    
+    let _ = client_writer.shutdown(Shutdown::Write);
+    
+    outgoing.join().map_err(|_| io::Error::other("The tunnel thread panicked!"))??;
+    result?;
+    
+    Ok(())
+}
+
+/// Like `relay`, but for a client connection that may be encrypted. A TLS connection can't be split between two
+/// threads, so both sides are polled in turn on this one instead.
+///
+/// Relayed connections are long-lived, e.g. WebSockets, so the keep-alive timeout no longer applies to them.
+pub fn relay_client(client: ClientStream, upstream: TcpStream) -> io::Result<()> {
+    let mut client = match client.into_plain() {
+        Ok(client) => {
+            client.set_read_timeout(None)?;
+            upstream.set_read_timeout(None)?;
+            
+            return relay(client, upstream);
+        }
+        Err(client) => client,
+    };
+    
+    let mut upstream = upstream;
+    client.get_tcp_stream().set_read_timeout(Some(POLL_INTERVAL))?;
+    upstream.set_read_timeout(Some(POLL_INTERVAL))?;
+    
+    let mut buffer = [0; 16_384];
+    
+    loop {
+        match client.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(bytes_read) => upstream.write_all(&buffer[..bytes_read])?,
+            Err(error) if is_timeout(&error) => {}
+            Err(error) => return Err(error),
+        }
+        
+        match upstream.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(bytes_read) => {
+                client.write_all(&buffer[..bytes_read])?;
+                client.flush()?;
+            }
+            Err(error) if is_timeout(&error) => {}
+            Err(error) => return Err(error),
+        }
+    }
+    
+    let _ = upstream.shutdown(Shutdown::Both);
+    
+    Ok(())
+}
+
+/// Returns whether a read gave up because its timeout passed, which is reported differently per platform.
+fn is_timeout(error: &io::Error) -> bool {
+    matches!(error.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+/// Checks whether a tunnel may be opened to an address.
+///
+/// Denied ranges always win. Local and private addresses have to be allowed explicitly, and once any range is allowed,
+/// only addresses within the allowed ranges are.
+fn is_permitted(ip: IpAddr, ranges: &AccessList) -> bool {
+    ranges.is_allowed(ip) && (is_public(ip) || network::ip_in_range(ip, ranges.get_allow()))
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    // IPv4-mapped addresses like `::ffff:10.0.0.1` reach the IPv4 address, so they're checked as one.
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => !(ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified() || ip.is_broadcast()),
+        IpAddr::V6(ip) => !(ip.is_loopback() || ip.is_unspecified() || ip.is_unique_local() || ip.is_unicast_link_local()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn private_addresses_need_to_be_allowed() {
+        let ranges = AccessList::default();
+        
+        assert!(is_permitted("93.184.216.34".parse().unwrap(), &ranges));
+        assert!(!is_permitted("127.0.0.1".parse().unwrap(), &ranges));
+        assert!(!is_permitted("10.0.0.1".parse().unwrap(), &ranges));
+        assert!(!is_permitted("::ffff:10.0.0.1".parse().unwrap(), &ranges));
+        assert!(!is_permitted("::ffff:127.0.0.1".parse().unwrap(), &ranges));
+    }
+    
+    #[test]
+    fn ranges_are_checked() {
+        let allow = network::parse_cidr_list(&["10.0.0.0/8"]).unwrap();
+        let deny = network::parse_cidr_list(&["10.0.0.1"]).unwrap();
+        let ranges = AccessList::new(allow, deny);
+        
+        assert!(is_permitted("10.0.0.2".parse().unwrap(), &ranges));
+        assert!(is_permitted("::ffff:10.0.0.2".parse().unwrap(), &ranges));
+        assert!(!is_permitted("10.0.0.1".parse().unwrap(), &ranges));
+        assert!(!is_permitted("::ffff:10.0.0.1".parse().unwrap(), &ranges));
+        assert!(!is_permitted("93.184.216.34".parse().unwrap(), &ranges));
+    }
+}
@@ -0,0 +1,31 @@
+use std::io;
+use std::net::TcpStream;
+
+use crate::http::Request;
+
+/// Takes over a connection after the client asked to switch protocols with `Connection: Upgrade`, e.g. for h2c.
+///
+/// The server sends `101 Switching Protocols` before handing over the stream, the handler speaks the new protocol
+/// from then on.
+pub trait UpgradeHandler {
+    /// The protocol name as it appears in the `Upgrade` header, compared case-insensitively.
+    fn protocol(&self) -> &str;
+    
+    fn handle(&self, stream: TcpStream, request: Request) -> io::Result<()>;
+}
+
+/// Returns the protocols a request asks to upgrade to, in order of preference, if it asks for an upgrade at all.
+pub fn requested_protocols(request: &Request) -> Vec<&str> {
+    let wants_upgrade = request
+        .get_header("Connection")
+        .is_some_and(|connection| connection.split(',').any(|option| option.trim().eq_ignore_ascii_case("upgrade")));
+    
+    if !wants_upgrade {
+        return Vec::new();
+    }
+    
+    match request.get_header("Upgrade") {
+        Some(upgrade) => upgrade.split(',').map(str::trim).filter(|protocol| !protocol.is_empty()).collect(),
+        None => Vec::new(),
+    }
+}
@@ -0,0 +1,406 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ring::digest;
+
+use crate::http::{Method, Request};
+use crate::tls::ClientStream;
+use crate::upgrade;
+
+/// The GUID RFC 6455 appends to the client's key before hashing it into `Sec-WebSocket-Accept`.
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The only protocol version clients may ask for, every browser speaks it.
+pub const VERSION: &str = "13";
+
+/// The largest message a client may send, counting all of its fragments, before the connection is closed.
+pub const MAX_MESSAGE_BYTES: usize = 16 * 1024 * 1024;
+
+/// Close codes from RFC 6455 §7.4.1 the server sends itself.
+pub const CLOSE_NORMAL: u16 = 1000;
+pub const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+pub const CLOSE_INVALID_DATA: u16 = 1007;
+pub const CLOSE_TOO_BIG: u16 = 1009;
+
+/// The close code reported when the client closed without giving one.
+const CLOSE_NO_STATUS: u16 = 1005;
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+/// Talks to a client over a WebSocket connection, after the server accepted its handshake.
+///
+/// Handlers are registered for a path pattern, see `Server::websocket`, and get the connection along with the request
+/// that opened it. They're expected to loop until the client closes the connection.
+pub trait WebSocketHandler {
+    fn handle(&self, socket: &mut WebSocket, request: &Request) -> io::Result<()>;
+}
+
+/// Lets a closure like `|socket, request| Ok(())` be used as a WebSocket handler.
+impl<F> WebSocketHandler for F
+where
+    F: Fn(&mut WebSocket, &Request) -> io::Result<()>,
+{
+    fn handle(&self, socket: &mut WebSocket, request: &Request) -> io::Result<()> {
+        self(socket, request)
+    }
+}
+
+/// Why a WebSocket handshake was refused.
+#[derive(Debug, PartialEq, Eq)]
+pub enum HandshakeError {
+    /// The client asked for a protocol version other than 13, it's answered with 426 and the supported version.
+    UnsupportedVersion,
+    /// The `Sec-WebSocket-Key` is missing or isn't 16 bytes encoded with base64.
+    InvalidKey,
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandshakeError::UnsupportedVersion => write!(f, "Only version {} of the WebSocket protocol is supported.", VERSION),
+            HandshakeError::InvalidKey => write!(f, "The Sec-WebSocket-Key header must be 16 bytes encoded with base64."),
+        }
+    }
+}
+
+impl Error for HandshakeError {}
+
+/// A complete message, reassembled from its fragments.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// The close code and reason, which is 1005 and empty if the client didn't give one.
+    Close(u16, String),
+}
+
+/// Returns whether a request asks to switch to the WebSocket protocol, valid or not.
+pub fn is_upgrade(request: &Request) -> bool {
+    *request.get_method() == Method::Get
+        && upgrade::requested_protocols(request).iter().any(|protocol| protocol.eq_ignore_ascii_case("websocket"))
+}
+
+/// Checks the handshake headers of an upgrade request and returns the `Sec-WebSocket-Accept` value to answer with.
+pub fn accept_handshake(request: &Request) -> Result<String, HandshakeError> {
+    if request.get_header("Sec-WebSocket-Version").map(str::trim) != Some(VERSION) {
+        return Err(HandshakeError::UnsupportedVersion);
+    }
+    
+    // The key is only ever hashed, but a malformed one means the client doesn't speak the protocol.
+    let key = request.get_header("Sec-WebSocket-Key").map(str::trim).unwrap_or_default();
+    
+    match BASE64.decode(key) {
+        Ok(decoded) if decoded.len() == 16 => Ok(accept_key(key)),
+        _ => Err(HandshakeError::InvalidKey),
+    }
+}
+
+/// Hashes a client's `Sec-WebSocket-Key` into the `Sec-WebSocket-Accept` value proving the server understood it.
+pub fn accept_key(key: &str) -> String {
+    let hash = digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, format!("{}{}", key, ACCEPT_GUID).as_bytes());
+    
+    BASE64.encode(hash.as_ref())
+}
+
+/// The server's end of a WebSocket connection.
+///
+/// Pings are answered while reading, and a close from the client is answered before it's returned.
+pub struct WebSocket {
+    stream: ClientStream,
+    buffered: Vec<u8>,
+    max_message_bytes: usize,
+    close_sent: bool,
+}
+
+impl WebSocket {
+    /// Wraps a connection whose handshake has been answered, `buffered` are the bytes the client sent right after it.
+    pub fn new(stream: ClientStream, buffered: &[u8]) -> WebSocket {
+        WebSocket {
+            stream,
+            buffered: buffered.to_vec(),
+            max_message_bytes: MAX_MESSAGE_BYTES,
+            close_sent: false,
+        }
+    }
+    
+    pub fn with_max_message_bytes(mut self, max_message_bytes: usize) -> WebSocket {
+        self.max_message_bytes = max_message_bytes;
+        
+        self
+    }
+    
+    pub fn get_max_message_bytes(&self) -> usize {
+        self.max_message_bytes
+    }
+    
+    /// Returns whether the server has sent its close frame, after which nothing else can be sent.
+    pub fn is_close_sent(&self) -> bool {
+        self.close_sent
+    }
+    
+    /// Limits how long `read_message` waits, so a handler can do other work, e.g. send pings, while a client is quiet.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.get_tcp_stream().set_read_timeout(timeout)
+    }
+    
+    /// Reads the next message, reassembling fragmented ones.
+    ///
+    /// A client breaking the protocol gets a close frame with the matching code and an `InvalidData` error is
+    /// returned.
+    pub fn read_message(&mut self) -> io::Result<Message> {
+        let mut fragments: Option<(u8, Vec<u8>)> = None;
+        
+        loop {
+            let (fin, opcode, payload) = self.read_frame()?;
+            
+            match opcode {
+                OPCODE_CONTINUATION => match fragments.as_mut() {
+                    Some((_, message)) => {
+                        if message.len() + payload.len() > self.max_message_bytes {
+                            return Err(self.fail(CLOSE_TOO_BIG, "The message is too large."));
+                        }
+                        
+                        message.extend(payload);
+                    }
+                    None => return Err(self.fail(CLOSE_PROTOCOL_ERROR, "A continuation frame was sent without a message to continue.")),
+                },
+                OPCODE_TEXT | OPCODE_BINARY => {
+                    if fragments.is_some() {
+                        return Err(self.fail(CLOSE_PROTOCOL_ERROR, "A new message was started before the last one ended."));
+                    }
+                    
+                    fragments = Some((opcode, payload));
+                }
+                // Control frames may arrive between the fragments of a message.
+                OPCODE_PING => {
+                    if !self.close_sent {
+                        self.write_frame(OPCODE_PONG, &payload)?;
+                    }
+                    
+                    continue;
+                }
+                OPCODE_PONG => return Ok(Message::Pong(payload)),
+                OPCODE_CLOSE => {
+                    let (code, reason) = match payload.len() {
+                        0 => (CLOSE_NO_STATUS, String::new()),
+                        1 => return Err(self.fail(CLOSE_PROTOCOL_ERROR, "The close frame has a truncated code.")),
+                        _ => match String::from_utf8(payload[2..].to_vec()) {
+                            Ok(reason) => (u16::from_be_bytes([payload[0], payload[1]]), reason),
+                            Err(_) => return Err(self.fail(CLOSE_INVALID_DATA, "The close reason isn't valid UTF-8.")),
+                        },
+                    };
+                    
+                    // Echo the code to complete the closing handshake, unless the server started it.
+                    if !self.close_sent {
+                        let echoed = if code == CLOSE_NO_STATUS { CLOSE_NORMAL } else { code };
+                        
+                        self.close(echoed, "")?;
+                    }
+                    
+                    return Ok(Message::Close(code, reason));
+                }
+                _ => return Err(self.fail(CLOSE_PROTOCOL_ERROR, &format!("The opcode {:#x} is unknown.", opcode))),
+            }
+            
+            if !fin {
+                continue;
+            }
+            
+            return match fragments.take() {
+                Some((OPCODE_TEXT, message)) => match String::from_utf8(message) {
+                    Ok(text) => Ok(Message::Text(text)),
+                    Err(_) => Err(self.fail(CLOSE_INVALID_DATA, "The text message isn't valid UTF-8.")),
+                },
+                Some((_, message)) => Ok(Message::Binary(message)),
+                None => continue,
+            };
+        }
+    }
+    
+    /// Sends a message in a single frame. A `Close` message starts the closing handshake, the client's answer is
+    /// returned by `read_message`.
+    pub fn send(&mut self, message: &Message) -> io::Result<()> {
+        match message {
+            Message::Text(text) => self.write_frame(OPCODE_TEXT, text.as_bytes()),
+            Message::Binary(data) => self.write_frame(OPCODE_BINARY, data),
+            Message::Ping(data) => self.write_frame(OPCODE_PING, data),
+            Message::Pong(data) => self.write_frame(OPCODE_PONG, data),
+            Message::Close(code, reason) => self.close(*code, reason),
+        }
+    }
+    
+    /// Sends a close frame with the given code and reason, nothing can be sent after it.
+    pub fn close(&mut self, code: u16, reason: &str) -> io::Result<()> {
+        if self.close_sent {
+            return Ok(());
+        }
+        
+        let mut payload = code.to_be_bytes().to_vec();
+        payload.extend(reason.as_bytes());
+        
+        self.write_frame(OPCODE_CLOSE, &payload)?;
+        self.close_sent = true;
+        
+        Ok(())
+    }
+    
+    /// Reads a single frame and unmasks its payload, returning whether it's the last fragment, its opcode and payload.
+    fn read_frame(&mut self) -> io::Result<(bool, u8, Vec<u8>)> {
+        let mut head = [0; 2];
+        self.read_exact(&mut head)?;
+        
+        let fin = head[0] & 0x80 != 0;
+        let opcode = head[0] & 0x0F;
+        
+        // No extensions are negotiated, so the reserved bits must be clear.
+        if head[0] & 0x70 != 0 {
+            return Err(self.fail(CLOSE_PROTOCOL_ERROR, "A reserved bit is set."));
+        }
+        
+        // Clients mask every frame, so that caches in between can't be poisoned with crafted bytes (RFC 6455 §10.3).
+        if head[1] & 0x80 == 0 {
+            return Err(self.fail(CLOSE_PROTOCOL_ERROR, "The frame isn't masked."));
+        }
+        
+        let length = match head[1] & 0x7F {
+            126 => {
+                let mut length = [0; 2];
+                self.read_exact(&mut length)?;
+                
+                u64::from(u16::from_be_bytes(length))
+            }
+            127 => {
+                let mut length = [0; 8];
+                self.read_exact(&mut length)?;
+                
+                u64::from_be_bytes(length)
+            }
+            length => u64::from(length),
+        };
+        
+        if opcode >= OPCODE_CLOSE && (!fin || length > 125) {
+            return Err(self.fail(CLOSE_PROTOCOL_ERROR, "The control frame is fragmented or longer than 125 bytes."));
+        }
+        
+        // Refuse the frame before reading it, rather than buffering a client's idea of a length.
+        if length > self.max_message_bytes as u64 {
+            return Err(self.fail(CLOSE_TOO_BIG, "The message is too large."));
+        }
+        
+        let mut mask = [0; 4];
+        self.read_exact(&mut mask)?;
+        
+        let mut payload = vec![0; length as usize];
+        self.read_exact(&mut payload)?;
+        
+        for (index, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[index % 4];
+        }
+        
+        Ok((fin, opcode, payload))
+    }
+    
+    fn write_frame(&mut self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        if self.close_sent {
+            return Err(io::Error::new(io::ErrorKind::NotConnected, "The WebSocket connection is closing."));
+        }
+        
+        // The server never masks its frames.
+        let mut frame = vec![0x80 | opcode];
+        
+        match payload.len() {
+            length if length < 126 => frame.push(length as u8),
+            length if length <= usize::from(u16::MAX) => {
+                frame.push(126);
+                frame.extend((length as u16).to_be_bytes());
+            }
+            length => {
+                frame.push(127);
+                frame.extend((length as u64).to_be_bytes());
+            }
+        }
+        
+        frame.extend(payload);
+        
+        self.stream.write_all(&frame)?;
+        self.stream.flush()
+    }
+    
+    /// Reads the bytes the client sent along with the handshake first, then from the connection.
+    fn read_exact(&mut self, buffer: &mut [u8]) -> io::Result<()> {
+        let buffered = buffer.len().min(self.buffered.len());
+        buffer[..buffered].copy_from_slice(&self.buffered[..buffered]);
+        self.buffered.drain(..buffered);
+        
+        self.stream.read_exact(&mut buffer[buffered..])
+    }
+    
+    /// Closes the connection with the code of a protocol violation and returns the error to give up with.
+    fn fail(&mut self, code: u16, message: &str) -> io::Error {
+        let _ = self.close(code, message);
+        
+        io::Error::new(io::ErrorKind::InvalidData, message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn handshake(headers: &str) -> Request {
+        Request::parse(&format!("GET /chat HTTP/1.1\r\nHost: localhost\r\n{}\r\n", headers)).unwrap()
+    }
+    
+    #[test]
+    fn keys_are_hashed_as_in_the_rfc() {
+        // The example from RFC 6455 §1.3.
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+    
+    #[test]
+    fn upgrades_are_recognised_among_other_options() {
+        assert!(is_upgrade(&handshake("Connection: keep-alive, Upgrade\r\nUpgrade: WebSocket\r\n")));
+        assert!(!is_upgrade(&handshake("Upgrade: websocket\r\n")));
+        assert!(!is_upgrade(&handshake("Connection: Upgrade\r\nUpgrade: h2c\r\n")));
+        
+        let post = Request::parse("POST /chat HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade\r\nUpgrade: websocket\r\n\r\n").unwrap();
+        
+        assert!(!is_upgrade(&post));
+    }
+    
+    #[test]
+    fn valid_handshakes_are_accepted() {
+        let request = handshake("Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n");
+        
+        assert_eq!(accept_handshake(&request), Ok("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=".to_string()));
+    }
+    
+    #[test]
+    fn other_versions_are_unsupported() {
+        for headers in ["Sec-WebSocket-Version: 8\r\n", ""] {
+            let request = handshake(&format!("{}Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n", headers));
+            
+            assert_eq!(accept_handshake(&request), Err(HandshakeError::UnsupportedVersion), "{:?}", headers);
+        }
+    }
+    
+    #[test]
+    fn keys_must_be_sixteen_bytes_of_base64() {
+        for headers in ["Sec-WebSocket-Key: not base64!\r\n", "Sec-WebSocket-Key: c2hvcnQ=\r\n", ""] {
+            let request = handshake(&format!("Sec-WebSocket-Version: 13\r\n{}", headers));
+            
+            assert_eq!(accept_handshake(&request), Err(HandshakeError::InvalidKey), "{:?}", headers);
+        }
+    }
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::TempDir;
+use json::JsonValue;
+
+/// Waits for the access log to have a number of entries, since they're written after the response is sent.
+fn entries(path: &Path, count: usize) -> Vec<JsonValue> {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    
+    loop {
+        let lines = fs::read_to_string(path).unwrap_or_default();
+        
+        if lines.lines().count() >= count {
+            return lines.lines().map(|line| json::parse(line).unwrap()).collect();
+        }
+        
+        assert!(Instant::now() < deadline, "the access log has {:?}", lines);
+        
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn requests_are_logged_as_ndjson() {
+    let directory = TempDir::new(&[("www/index.html", b"<p>Home</p>")]);
+    let access_log = directory.path().join("access.log");
+    
+    let server = common::start(&directory.path().join("www"), json::object! {
+        "pages": [{ "name": "/", "path": "index.html" }],
+        "access_log": access_log.to_str().unwrap(),
+        "access_log_format": "ndjson",
+        "access_log_buffer_bytes": 0,
+    });
+    
+    let found = common::get(server.local_addr(), "/", &[]);
+    common::get(server.local_addr(), "/missing", &[]);
+    
+    let entries = entries(&access_log, 2);
+    
+    assert_eq!(entries[0]["method"], "GET");
+    assert_eq!(entries[0]["path"], "/");
+    assert_eq!(entries[0]["status"], 200);
+    assert_eq!(entries[0]["bytes"], found.body.len());
+    assert_eq!(entries[0]["ip"], "127.0.0.1");
+    assert!(entries[0]["request_id"].as_str().is_some_and(|request_id| request_id.len() == 36));
+    assert_eq!(entries[1]["path"], "/missing");
+    assert_eq!(entries[1]["status"], 404);
+}
+
+#[test]
+fn unknown_formats_are_rejected() {
+    let directory = TempDir::new(&[("index.html", b"<p>Home</p>")]);
+    
+    let config = json::object! {
+        "verbose": false,
+        "thread_count": 2,
+        "port": 0,
+        "web_root": directory.path().to_str().unwrap(),
+        "access_log_format": "json",
+    };
+    
+    let error = web_server::server::Server::new(&config).map(|_| ()).unwrap_err();
+    
+    assert!(format!("{:?}", error).contains("access_log_format"), "{:?}", error);
+}
@@ -0,0 +1,115 @@
+#![allow(dead_code)]
+
+use std::env;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use json::JsonValue;
+use web_server::server::{Server, ServerHandle};
+
+/// A response as it came off the wire.
+pub struct RawResponse {
+    pub status_code: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl RawResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+}
+
+/// A directory for a test's files, which is removed once the test is done with it.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Creates the directory with the files it's given, by their paths relative to it.
+    pub fn new(files: &[(&str, &[u8])]) -> TempDir {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        
+        let path = env::temp_dir().join(format!("web_server_test_{}_{}", process::id(), COUNTER.fetch_add(1, Ordering::Relaxed)));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        
+        for (name, contents) in files {
+            let file = path.join(name);
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(file, contents).unwrap();
+        }
+        
+        TempDir { path }
+    }
+    
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Starts a quiet server on a free port, serving `web_root` with the given settings on top. Errors are logged next to
+/// the web root rather than to stderr.
+pub fn start(web_root: &Path, settings: JsonValue) -> ServerHandle {
+    let mut config = json::object! {
+        "verbose": false,
+        "thread_count": 2,
+        "port": 0,
+        "bind_address": "127.0.0.1",
+        "web_root": web_root.to_str().unwrap(),
+        "error_log": web_root.with_extension("error.log").to_str().unwrap(),
+        "pages": [],
+    };
+    
+    for (key, value) in settings.entries() {
+        config[key] = value.clone();
+    }
+    
+    Server::new(&config).unwrap().start().unwrap()
+}
+
+/// Sends a request as is, so paths reach the server without a client normalizing them first.
+pub fn send(address: SocketAddr, request: &str) -> RawResponse {
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+    
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    
+    let header_end = response.windows(4).position(|window| window == b"\r\n\r\n").expect("the response has no headers");
+    let head = String::from_utf8_lossy(&response[..header_end]).to_string();
+    let mut lines = head.split("\r\n");
+    
+    let status_code = lines.next().unwrap().split(' ').nth(1).unwrap().parse().unwrap();
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect();
+    
+    RawResponse {
+        status_code,
+        headers,
+        body: response[header_end + 4..].to_vec(),
+    }
+}
+
+/// Sends a GET request for a path with extra header lines like `Range: bytes=0-1`, closing the connection after it.
+pub fn get(address: SocketAddr, path: &str, headers: &[&str]) -> RawResponse {
+    let mut request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n", path);
+    
+    for header in headers {
+        request += &format!("{}\r\n", header);
+    }
+    
+    send(address, &(request + "\r\n"))
+}
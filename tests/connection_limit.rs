@@ -0,0 +1,69 @@
+mod common;
+
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::TempDir;
+use web_server::server::ServerHandle;
+
+/// Starts a server that allows a single open connection per client.
+fn site() -> (TempDir, ServerHandle) {
+    let directory = TempDir::new(&[("index.html", b"<p>Home</p>")]);
+    
+    let server = common::start(directory.path(), json::object! {
+        "pages": [{ "name": "/", "path": "index.html" }],
+        "max_connections_per_ip": 1,
+    });
+    
+    (directory, server)
+}
+
+#[test]
+fn connections_over_the_cap_are_refused() {
+    let (_directory, server) = site();
+    
+    let _open = TcpStream::connect(server.local_addr()).unwrap();
+    let response = common::get(server.local_addr(), "/", &[]);
+    
+    assert_eq!(response.status_code, 429);
+    assert_eq!(response.header("Connection"), Some("close"));
+    assert!(response.body.is_empty());
+}
+
+#[test]
+fn closed_connections_free_their_slot() {
+    let (_directory, server) = site();
+    
+    let open = TcpStream::connect(server.local_addr()).unwrap();
+    
+    assert_eq!(common::get(server.local_addr(), "/", &[]).status_code, 429);
+    
+    drop(open);
+    
+    // The slot is freed once the server notices the connection is gone.
+    let deadline = Instant::now() + Duration::from_secs(5);
+    
+    loop {
+        let status_code = common::get(server.local_addr(), "/", &[]).status_code;
+        
+        if status_code == 200 {
+            break;
+        }
+        
+        assert!(Instant::now() < deadline, "the slot was never freed");
+        
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn requests_on_one_connection_are_not_capped() {
+    let (_directory, server) = site();
+    
+    let request = "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n".repeat(3) + "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+    let response = common::send(server.local_addr(), &request);
+    
+    assert_eq!(response.status_code, 200);
+    assert_eq!(String::from_utf8_lossy(&response.body).matches("HTTP/1.1 200 OK").count(), 3);
+}
@@ -0,0 +1,51 @@
+mod common;
+
+use common::TempDir;
+use web_server::server::ServerHandle;
+
+/// Creates a web root with a page, a routed directory and a file nothing points to.
+fn site(deny_unlisted: bool) -> (TempDir, ServerHandle) {
+    let directory = TempDir::new(&[("index.html", b"<p>Home</p>"), ("docs/a.txt", b"a"), ("backup.sql", b"DROP TABLE users;")]);
+    
+    let server = common::start(directory.path(), json::object! {
+        "deny_unlisted": deny_unlisted,
+        "pages": [{ "name": "/", "path": "index.html" }],
+        "routes": [{ "path": "/docs/*", "file": "docs" }],
+    });
+    
+    (directory, server)
+}
+
+#[test]
+fn unlisted_files_are_not_found() {
+    let (_directory, server) = site(true);
+    
+    let response = common::get(server.local_addr(), "/backup.sql", &[]);
+    
+    assert_eq!(response.status_code, 404);
+    assert!(!String::from_utf8_lossy(&response.body).contains("DROP TABLE"));
+}
+
+#[test]
+fn pages_and_routes_are_served_when_unlisted_files_are_denied() {
+    let (_directory, server) = site(true);
+    
+    assert_eq!(common::get(server.local_addr(), "/", &[]).body, b"<p>Home</p>");
+    assert_eq!(common::get(server.local_addr(), "/docs/a.txt", &[]).body, b"a");
+}
+
+#[test]
+fn unlisted_files_are_served_by_default() {
+    let (_directory, server) = site(false);
+    
+    assert_eq!(common::get(server.local_addr(), "/backup.sql", &[]).status_code, 200);
+}
+
+#[test]
+fn unknown_paths_are_not_found_either_way() {
+    for deny_unlisted in [false, true] {
+        let (_directory, server) = site(deny_unlisted);
+        
+        assert_eq!(common::get(server.local_addr(), "/missing.html", &[]).status_code, 404, "deny_unlisted: {}", deny_unlisted);
+    }
+}
@@ -0,0 +1,54 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use common::TempDir;
+
+/// Waits for the error log to have as many entries as expected, since they're written once the response is sent.
+fn entries(path: &Path, expected: usize) -> Vec<String> {
+    for _ in 0..50 {
+        let entries = fs::read_to_string(path).unwrap_or_default().lines().map(String::from).collect::<Vec<_>>();
+        
+        if entries.len() >= expected {
+            return entries;
+        }
+        
+        thread::sleep(Duration::from_millis(20));
+    }
+    
+    panic!("the error log never got {} entries", expected);
+}
+
+#[test]
+fn upstream_failures_are_logged_once_with_their_cause() {
+    let directory = TempDir::new(&[("index.html", b"<p>Home</p>")]);
+    let server = common::start(directory.path(), json::object! {
+        "pages": [{ "name": "/", "path": "index.html" }],
+        "routes": [{ "path": "/api/*", "proxy_pass": "http://127.0.0.1:1" }],
+    });
+    
+    assert_eq!(common::get(server.local_addr(), "/api/users", &[]).status_code, 502);
+    
+    let entries = entries(&directory.path().with_extension("error.log"), 1);
+    
+    assert_eq!(entries.len(), 1, "{:?}", entries);
+    assert!(entries[0].contains(" 502 Failed to proxy to http://127.0.0.1:1"), "{}", entries[0]);
+}
+
+#[test]
+fn client_errors_are_logged_by_their_status() {
+    let directory = TempDir::new(&[("index.html", b"<p>Home</p>")]);
+    let server = common::start(directory.path(), json::object! {
+        "pages": [{ "name": "/", "path": "index.html" }],
+    });
+    
+    assert_eq!(common::get(server.local_addr(), "/missing.html", &[]).status_code, 404);
+    
+    let entries = entries(&directory.path().with_extension("error.log"), 1);
+    
+    assert_eq!(entries.len(), 1, "{:?}", entries);
+    assert!(entries[0].ends_with(" 404 Not Found"), "{}", entries[0]);
+}
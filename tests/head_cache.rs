@@ -0,0 +1,89 @@
+mod common;
+
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use common::TempDir;
+use web_server::http::{Request, Response};
+use web_server::server::{Server, ServerHandle};
+
+/// Starts a server with a file, and a handler that counts how often it's called.
+fn site() -> (TempDir, ServerHandle, Arc<AtomicUsize>) {
+    let directory = TempDir::new(&[("index.html", b"<p>Home</p>"), ("status.txt", b"up")]);
+    
+    let config = json::object! {
+        "verbose": false,
+        "thread_count": 2,
+        "port": 0,
+        "bind_address": "127.0.0.1",
+        "web_root": directory.path().to_str().unwrap(),
+        "error_log": directory.path().with_extension("error.log").to_str().unwrap(),
+        "pages": [{ "name": "/", "path": "index.html" }],
+        "head_cache_ttl_secs": 60,
+    };
+    
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counted = Arc::clone(&calls);
+    
+    let mut server = Server::new(&config).unwrap();
+    server.route("/count", move |_: &Request| {
+        let calls = counted.fetch_add(1, Ordering::SeqCst) + 1;
+        
+        Response::ok().with_header("X-Calls", &calls.to_string())
+    }).unwrap();
+    
+    (directory, server.start().unwrap(), calls)
+}
+
+/// Sends a HEAD request with extra header lines, closing the connection after it.
+fn head(server: &ServerHandle, path: &str, headers: &[&str]) -> common::RawResponse {
+    let mut request = format!("HEAD {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n", path);
+    
+    for header in headers {
+        request += &format!("{}\r\n", header);
+    }
+    
+    common::send(server.local_addr(), &(request + "\r\n"))
+}
+
+#[test]
+fn static_files_are_answered_from_the_cache() {
+    let (directory, server, _) = site();
+    
+    assert_eq!(head(&server, "/status.txt", &[]).header("Content-Length"), Some("2"));
+    
+    fs::write(directory.path().join("status.txt"), b"down").unwrap();
+    
+    assert_eq!(head(&server, "/status.txt", &[]).header("Content-Length"), Some("2"));
+    assert_eq!(common::get(server.local_addr(), "/status.txt", &[]).body, b"down");
+}
+
+#[test]
+fn requests_with_credentials_bypass_the_cache() {
+    let (directory, server, _) = site();
+    
+    head(&server, "/status.txt", &[]);
+    fs::write(directory.path().join("status.txt"), b"down").unwrap();
+    
+    assert_eq!(head(&server, "/status.txt", &["Cookie: session=1"]).header("Content-Length"), Some("4"));
+    assert_eq!(head(&server, "/status.txt", &["Authorization: Bearer token"]).header("Content-Length"), Some("4"));
+}
+
+#[test]
+fn handlers_are_never_answered_from_the_cache() {
+    let (_directory, server, calls) = site();
+    
+    head(&server, "/count", &[]);
+    
+    assert_eq!(head(&server, "/count", &[]).header("X-Calls"), Some("2"));
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn empty_head_bodies_are_not_tagged() {
+    let (_directory, server, _) = site();
+    
+    assert_eq!(head(&server, "/count", &[]).header("ETag"), None);
+    assert!(head(&server, "/status.txt", &[]).header("ETag").is_some());
+}
@@ -0,0 +1,55 @@
+mod common;
+
+use common::TempDir;
+use web_server::server::ServerHandle;
+
+const CONTENTS: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+
+/// Serves a page with fixed contents, returning its ETag and Last-Modified date.
+fn site() -> (TempDir, ServerHandle, String, String) {
+    let web_root = TempDir::new(&[("download.txt", CONTENTS)]);
+    let server = common::start(web_root.path(), json::object! { "pages": [{ "name": "/download", "path": "download.txt" }] });
+    
+    let response = common::get(server.local_addr(), "/download", &[]);
+    assert_eq!(response.status_code, 200);
+    
+    let etag = response.header("ETag").expect("the page has no ETag").to_string();
+    let last_modified = response.header("Last-Modified").expect("the page has no Last-Modified").to_string();
+    
+    (web_root, server, etag, last_modified)
+}
+
+#[test]
+fn matching_etag_sends_the_range() {
+    let (_web_root, server, etag, _) = site();
+    
+    let response = common::get(server.local_addr(), "/download", &["Range: bytes=0-9", &format!("If-Range: {}", etag)]);
+    
+    assert_eq!(response.status_code, 206);
+    assert_eq!(response.header("Content-Range"), Some("bytes 0-9/36"));
+    assert_eq!(response.body, &CONTENTS[..10]);
+}
+
+#[test]
+fn unchanged_date_sends_the_range() {
+    let (_web_root, server, _, last_modified) = site();
+    
+    let response = common::get(server.local_addr(), "/download", &["Range: bytes=10-", &format!("If-Range: {}", last_modified)]);
+    
+    assert_eq!(response.status_code, 206);
+    assert_eq!(response.header("Content-Range"), Some("bytes 10-35/36"));
+    assert_eq!(response.body, &CONTENTS[10..]);
+}
+
+#[test]
+fn changed_resource_sends_everything() {
+    let (_web_root, server, _, _) = site();
+    
+    for if_range in ["\"outdated\"", "Thu, 01 Jan 1970 00:00:00 GMT"] {
+        let response = common::get(server.local_addr(), "/download", &["Range: bytes=0-9", &format!("If-Range: {}", if_range)]);
+        
+        assert_eq!(response.status_code, 200, "{}", if_range);
+        assert_eq!(response.header("Content-Range"), None, "{}", if_range);
+        assert_eq!(response.body, CONTENTS, "{}", if_range);
+    }
+}
@@ -0,0 +1,97 @@
+mod common;
+
+use common::TempDir;
+use web_server::server::ServerHandle;
+
+/// Starts a server with small limits on the URL and the headers.
+fn site() -> (TempDir, ServerHandle) {
+    let directory = TempDir::new(&[("index.html", b"<p>Home</p>")]);
+    
+    let server = common::start(directory.path(), json::object! {
+        "pages": [{ "name": "/", "path": "index.html" }],
+        "max_url_length": 100,
+        "max_header_bytes": 1_024,
+    });
+    
+    (directory, server)
+}
+
+#[test]
+fn urls_up_to_the_limit_are_served() {
+    let (_directory, server) = site();
+    
+    let path = format!("/?{}", "a".repeat(98));
+    
+    assert_eq!(common::get(server.local_addr(), &path, &[]).status_code, 200);
+}
+
+#[test]
+fn longer_urls_are_refused() {
+    let (_directory, server) = site();
+    
+    for length in [101, 10_000] {
+        let path = format!("/?{}", "a".repeat(length - 2));
+        let response = common::get(server.local_addr(), &path, &[]);
+        
+        assert_eq!(response.status_code, 414, "{}", length);
+        assert_eq!(response.header("Connection"), Some("close"), "{}", length);
+    }
+}
+
+#[test]
+fn long_urls_are_refused_before_the_request_line_ends() {
+    let (_directory, server) = site();
+    
+    // The request line never ends, so only its length can give the server a reason to answer.
+    let request = format!("GET /{}", "a".repeat(200));
+    
+    assert_eq!(common::send(server.local_addr(), &request).status_code, 414);
+}
+
+#[test]
+fn headers_are_limited_to_the_byte() {
+    let (_directory, server) = site();
+    
+    // The head counts the request line and every header, up to and including the empty line that ends it.
+    let head = "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nX-Padding: \r\n\r\n";
+    let request = head.replace("X-Padding: ", &format!("X-Padding: {}", "a".repeat(1_024 - head.len())));
+    
+    assert_eq!(request.len(), 1_024);
+    assert_eq!(common::send(server.local_addr(), &request).status_code, 200);
+    
+    let request = request.replace("X-Padding: ", "X-Padding: a");
+    
+    assert_eq!(common::send(server.local_addr(), &request).status_code, 431);
+}
+
+#[test]
+fn larger_headers_are_refused() {
+    let (_directory, server) = site();
+    
+    for length in [1_000, 100_000] {
+        let response = common::get(server.local_addr(), "/", &[&format!("X-Padding: {}", "a".repeat(length))]);
+        
+        assert_eq!(response.status_code, 431, "{}", length);
+        assert_eq!(response.header("Connection"), Some("close"), "{}", length);
+    }
+}
+
+#[test]
+fn headers_are_refused_before_they_end() {
+    let (_directory, server) = site();
+    
+    // The headers never end, so only their size can give the server a reason to answer.
+    let request = format!("GET / HTTP/1.1\r\nHost: localhost\r\nX-Padding: {}", "a".repeat(2_000));
+    
+    assert_eq!(common::send(server.local_addr(), &request).status_code, 431);
+}
+
+#[test]
+fn bodies_do_not_count_towards_the_header_limit() {
+    let (_directory, server) = site();
+    
+    let request = format!("POST / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: 4000\r\n\r\n{}", "a".repeat(4_000));
+    
+    // Pages only answer GET and HEAD, so getting that far means the headers were accepted.
+    assert_eq!(common::send(server.local_addr(), &request).status_code, 405);
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use std::fs;
+use std::panic;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use common::TempDir;
+use web_server::http::{Request, Response};
+use web_server::server::{Server, ServerHandle};
+
+/// `admin:secret`, Base64-encoded for Basic authentication.
+const CREDENTIALS: &str = "Authorization: Basic YWRtaW46c2VjcmV0";
+
+/// Creates a server whose metrics need signing in, with a handler that always panics.
+fn server(directory: &TempDir) -> Server {
+    let config = json::object! {
+        "verbose": false,
+        "thread_count": 2,
+        "port": 0,
+        "bind_address": "127.0.0.1",
+        "web_root": directory.path().to_str().unwrap(),
+        "error_log": directory.path().with_extension("error.log").to_str().unwrap(),
+        "pages": [{ "name": "/", "path": "index.html" }],
+        "auth": { "/metrics": { "credentials": ["admin:secret"] } },
+    };
+    
+    let mut server = Server::new(&config).unwrap();
+    server.route("/panic", |_: &Request| -> Response { panic!("the handler gave up") }).unwrap();
+    
+    server
+}
+
+fn start(directory: &TempDir) -> ServerHandle {
+    server(directory).start().unwrap()
+}
+
+#[test]
+fn panics_are_exported_as_a_counter() {
+    let directory = TempDir::new(&[("index.html", b"<p>Home</p>")]);
+    let server = start(&directory);
+    
+    assert_eq!(common::get(server.local_addr(), "/panic", &[]).status_code, 500);
+    
+    let response = common::get(server.local_addr(), "/metrics", &[CREDENTIALS]);
+    let body = String::from_utf8_lossy(&response.body);
+    
+    assert_eq!(response.status_code, 200);
+    assert_eq!(response.header("Content-Type"), Some("text/plain; version=0.0.4; charset=utf-8"));
+    assert!(body.contains("# TYPE web_server_panics_total counter\n"), "{}", body);
+    assert!(body.contains("\nweb_server_panics_total 1\n"), "{}", body);
+}
+
+#[test]
+fn metrics_need_signing_in() {
+    let directory = TempDir::new(&[("index.html", b"<p>Home</p>")]);
+    let server = start(&directory);
+    
+    assert_eq!(common::get(server.local_addr(), "/metrics", &[]).status_code, 401);
+}
+
+#[test]
+fn the_panic_hook_hands_panics_on_to_the_previous_one() {
+    let directory = TempDir::new(&[("index.html", b"<p>Home</p>")]);
+    let called = Arc::new(AtomicBool::new(false));
+    let previous_called = Arc::clone(&called);
+    
+    panic::set_hook(Box::new(move |_| previous_called.store(true, Ordering::SeqCst)));
+    server(&directory).install_panic_hook();
+    
+    let _ = panic::catch_unwind(|| panic!("the hooks should both see this"));
+    let _ = panic::take_hook();
+    
+    let error_log = fs::read_to_string(directory.path().with_extension("error.log")).unwrap();
+    
+    assert!(called.load(Ordering::SeqCst));
+    assert!(error_log.contains("Panic: the hooks should both see this at tests/metrics.rs"), "{}", error_log);
+}
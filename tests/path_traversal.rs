@@ -0,0 +1,65 @@
+mod common;
+
+use common::TempDir;
+use web_server::server::ServerHandle;
+
+/// Creates a web root next to a file that must never be served.
+fn site() -> (TempDir, ServerHandle) {
+    let directory = TempDir::new(&[("www/index.html", b"<p>Home</p>"), ("www/docs/a.txt", b"a"), ("secret.txt", b"secret")]);
+    let web_root = directory.path().join("www");
+    
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(directory.path().join("secret.txt"), web_root.join("link.txt")).unwrap();
+    
+    let server = common::start(&web_root, json::object! {});
+    
+    (directory, server)
+}
+
+#[test]
+fn files_inside_the_web_root_are_served() {
+    let (_directory, server) = site();
+    
+    let response = common::get(server.local_addr(), "/docs/a.txt", &[]);
+    
+    assert_eq!(response.status_code, 200);
+    assert_eq!(response.body, b"a");
+}
+
+#[test]
+fn dot_dot_segments_are_refused() {
+    let (_directory, server) = site();
+    
+    for path in ["/../secret.txt", "/docs/../../secret.txt", "/docs/.."] {
+        let response = common::get(server.local_addr(), path, &[]);
+        
+        assert_eq!(response.status_code, 403, "{}", path);
+        assert!(!String::from_utf8_lossy(&response.body).contains("secret"), "{}", path);
+    }
+}
+
+#[test]
+fn percent_encoded_dots_are_refused() {
+    let (_directory, server) = site();
+    
+    for path in ["/%2e%2e/secret.txt", "/%2E%2E/secret.txt", "/docs/%2e%2e%2f%2e%2e%2fsecret.txt", "/.%2e/secret.txt"] {
+        assert_eq!(common::get(server.local_addr(), path, &[]).status_code, 403, "{}", path);
+    }
+}
+
+#[test]
+fn backslashes_are_refused() {
+    let (_directory, server) = site();
+    
+    for path in ["/..\\secret.txt", "/docs\\..\\..\\secret.txt", "/..%5csecret.txt", "/docs%5C..%5C..%5Csecret.txt"] {
+        assert_eq!(common::get(server.local_addr(), path, &[]).status_code, 403, "{}", path);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks_out_of_the_web_root_are_refused() {
+    let (_directory, server) = site();
+    
+    assert_eq!(common::get(server.local_addr(), "/link.txt", &[]).status_code, 403);
+}
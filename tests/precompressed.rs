@@ -0,0 +1,102 @@
+mod common;
+
+use std::io::{Read, Write};
+
+use common::TempDir;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use web_server::compression::Encoding;
+
+const PAGE: &str = "<!doctype html><title>Precompressed</title><p>Served as HTML, not as a gzip archive.</p>";
+
+fn gzip(contents: &str) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(contents.as_bytes()).unwrap();
+    
+    encoder.finish().unwrap()
+}
+
+fn gunzip(contents: &[u8]) -> String {
+    let mut decompressed = String::new();
+    GzDecoder::new(contents).read_to_string(&mut decompressed).unwrap();
+    
+    decompressed
+}
+
+#[test]
+fn html_gz_is_served_as_gzipped_html() {
+    let compressed = gzip(PAGE);
+    let web_root = TempDir::new(&[("page.html.gz", &compressed)]);
+    
+    // Files small enough to cache are served from memory, larger ones are streamed from disk.
+    for max_file_size_bytes in [1_048_576, 1] {
+        let server = common::start(web_root.path(), json::object! { "file_cache": { "max_file_size_bytes": max_file_size_bytes } });
+        let response = common::get(server.local_addr(), "/page.html.gz", &["Accept-Encoding: gzip"]);
+        
+        assert_eq!(response.status_code, 200);
+        assert!(response.header("Content-Type").is_some_and(|content_type| content_type.starts_with("text/html")));
+        assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+        assert_eq!(gunzip(&response.body), PAGE);
+    }
+}
+
+#[test]
+fn html_gz_is_decompressed_for_clients_without_gzip() {
+    let web_root = TempDir::new(&[("page.html.gz", &gzip(PAGE))]);
+    let server = common::start(web_root.path(), json::object! {});
+    
+    let response = common::get(server.local_addr(), "/page.html.gz", &["Accept-Encoding: identity"]);
+    
+    assert_eq!(response.status_code, 200);
+    assert!(response.header("Content-Type").is_some_and(|content_type| content_type.starts_with("text/html")));
+    assert_eq!(response.header("Content-Encoding"), None);
+    assert_eq!(String::from_utf8_lossy(&response.body), PAGE);
+}
+
+#[test]
+fn html_br_is_served_as_brotli_compressed_html() {
+    let compressed = Encoding::Brotli.compress(PAGE.as_bytes()).unwrap();
+    let web_root = TempDir::new(&[("page.html.br", &compressed)]);
+    let server = common::start(web_root.path(), json::object! {});
+    
+    let response = common::get(server.local_addr(), "/page.html.br", &["Accept-Encoding: gzip, br"]);
+    
+    assert_eq!(response.status_code, 200);
+    assert!(response.header("Content-Type").is_some_and(|content_type| content_type.starts_with("text/html")));
+    assert_eq!(response.header("Content-Encoding"), Some("br"));
+    assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+    assert_eq!(Encoding::Brotli.decompress(&response.body).unwrap(), PAGE.as_bytes());
+}
+
+#[test]
+fn precompressed_files_are_served_as_is_when_disabled() {
+    let compressed = gzip(PAGE);
+    let web_root = TempDir::new(&[("page.html.gz", &compressed)]);
+    let server = common::start(web_root.path(), json::object! { "serve_precompressed": false });
+    
+    let response = common::get(server.local_addr(), "/page.html.gz", &["Accept-Encoding: gzip"]);
+    
+    assert_eq!(response.status_code, 200);
+    assert_eq!(response.header("Content-Encoding"), None);
+    assert_eq!(response.body, compressed);
+}
+
+#[test]
+fn responses_are_compressed_with_the_preferred_encoding() {
+    let page = PAGE.repeat(50);
+    let web_root = TempDir::new(&[("index.html", page.as_bytes())]);
+    let server = common::start(web_root.path(), json::object! { "pages": [{ "name": "/", "path": "index.html" }] });
+    
+    for (accept_encoding, expected) in [("gzip", Some(Encoding::Gzip)), ("gzip;q=0.5, br", Some(Encoding::Brotli)), ("br;q=0, gzip", Some(Encoding::Gzip)), ("identity", None)] {
+        let response = common::get(server.local_addr(), "/", &[&format!("Accept-Encoding: {}", accept_encoding)]);
+        let body = match expected {
+            Some(encoding) => encoding.decompress(&response.body).unwrap(),
+            None => response.body.clone(),
+        };
+        
+        assert_eq!(response.status_code, 200, "{}", accept_encoding);
+        assert_eq!(response.header("Content-Encoding"), expected.map(|encoding| encoding.name()), "{}", accept_encoding);
+        assert_eq!(body, page.as_bytes(), "{}", accept_encoding);
+    }
+}
@@ -0,0 +1,117 @@
+mod common;
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use common::TempDir;
+use web_server::server::ServerHandle;
+
+/// An upstream that answers every request with a status code, echoing the request head as the body.
+struct Upstream {
+    address: SocketAddr,
+    requests: Arc<AtomicUsize>,
+}
+
+impl Upstream {
+    fn start(status_code: u16) -> Upstream {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&requests);
+        
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => return,
+                };
+                
+                counter.fetch_add(1, Ordering::SeqCst);
+                
+                let mut head = String::new();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                
+                while reader.read_line(&mut head).is_ok_and(|bytes_read| bytes_read > 2) {}
+                
+                let _ = write!(stream, "HTTP/1.1 {} Status\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status_code, head.len(), head);
+            }
+        });
+        
+        Upstream { address, requests }
+    }
+    
+    fn get_requests(&self) -> usize {
+        self.requests.load(Ordering::SeqCst)
+    }
+}
+
+/// Starts a server proxying `/api/*` to the given upstream URLs, opening a circuit after two failures.
+fn site(upstreams: &[String]) -> (TempDir, ServerHandle) {
+    let directory = TempDir::new(&[("index.html", b"<p>Home</p>")]);
+    
+    let server = common::start(directory.path(), json::object! {
+        "pages": [{ "name": "/", "path": "index.html" }],
+        "routes": [{ "path": "/api/*", "proxy": { "upstreams": upstreams, "failure_threshold": 2, "open_duration_secs": 60 } }],
+    });
+    
+    (directory, server)
+}
+
+/// Returns a URL nothing listens on.
+fn closed_url() -> String {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    
+    format!("http://{}", listener.local_addr().unwrap())
+}
+
+#[test]
+fn requests_are_forwarded_with_the_client_address() {
+    let upstream = Upstream::start(200);
+    let (_directory, server) = site(&[format!("http://{}", upstream.address)]);
+    
+    let response = common::get(server.local_addr(), "/api/users?page=2", &["X-Trace: abc"]);
+    let body = String::from_utf8(response.body).unwrap();
+    
+    assert_eq!(response.status_code, 200);
+    assert!(body.starts_with("GET /api/users?page=2 HTTP/1.1\r\n"), "{}", body);
+    assert!(body.contains("X-Trace: abc\r\n"), "{}", body);
+    assert!(body.contains("X-Forwarded-For: 127.0.0.1\r\n"), "{}", body);
+}
+
+#[test]
+fn unreachable_upstreams_are_bad_gateways() {
+    let (_directory, server) = site(&[closed_url()]);
+    
+    assert_eq!(common::get(server.local_addr(), "/api/users", &[]).status_code, 502);
+}
+
+#[test]
+fn circuits_open_after_server_errors() {
+    let upstream = Upstream::start(500);
+    let (_directory, server) = site(&[format!("http://{}", upstream.address)]);
+    
+    assert_eq!(common::get(server.local_addr(), "/api/users", &[]).status_code, 500);
+    assert_eq!(common::get(server.local_addr(), "/api/users", &[]).status_code, 500);
+    
+    // The open circuit refuses the request without trying the upstream.
+    let response = common::get(server.local_addr(), "/api/users", &[]);
+    let retry_after = response.header("Retry-After").and_then(|retry_after| retry_after.parse::<u64>().ok());
+    
+    assert_eq!(response.status_code, 503);
+    assert!(retry_after.is_some_and(|retry_after| retry_after > 55 && retry_after <= 60), "{:?}", retry_after);
+    assert_eq!(upstream.get_requests(), 2);
+}
+
+#[test]
+fn failing_upstreams_are_skipped() {
+    let upstream = Upstream::start(200);
+    let (_directory, server) = site(&[closed_url(), format!("http://{}", upstream.address)]);
+    
+    // Round robin sends every other request to the broken upstream until its circuit opens.
+    let statuses = (0..6).map(|_| common::get(server.local_addr(), "/api/users", &[]).status_code).collect::<Vec<_>>();
+    
+    assert_eq!(statuses, [502, 200, 502, 200, 200, 200]);
+}
@@ -0,0 +1,48 @@
+mod common;
+
+use common::TempDir;
+use web_server::server::ServerHandle;
+
+const CONTENTS: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+
+fn site() -> (TempDir, ServerHandle) {
+    let web_root = TempDir::new(&[("download.txt", CONTENTS)]);
+    let server = common::start(web_root.path(), json::object! { "pages": [{ "name": "/download", "path": "download.txt" }] });
+    
+    (web_root, server)
+}
+
+#[test]
+fn several_ranges_are_sent_as_multipart() {
+    let (_web_root, server) = site();
+    
+    let response = common::get(server.local_addr(), "/download", &["Range: bytes=0-1, -2"]);
+    let body = String::from_utf8_lossy(&response.body);
+    
+    assert_eq!(response.status_code, 206);
+    assert!(response.header("Content-Type").unwrap().starts_with("multipart/byteranges; boundary="));
+    assert!(body.contains("Content-Range: bytes 0-1/36\r\n\r\n01\r\n"), "{}", body);
+    assert!(body.contains("Content-Range: bytes 34-35/36\r\n\r\nyz\r\n"), "{}", body);
+    assert_eq!(response.header("Content-Length"), Some(response.body.len().to_string().as_str()));
+}
+
+#[test]
+fn ranges_past_the_end_are_not_satisfiable() {
+    let (_web_root, server) = site();
+    
+    let response = common::get(server.local_addr(), "/download", &["Range: bytes=100-"]);
+    
+    assert_eq!(response.status_code, 416);
+    assert_eq!(response.header("Content-Range"), Some("bytes */36"));
+}
+
+#[test]
+fn ranges_are_advertised_and_ignored_when_malformed() {
+    let (_web_root, server) = site();
+    
+    let response = common::get(server.local_addr(), "/download", &["Range: lines=1-2"]);
+    
+    assert_eq!(response.status_code, 200);
+    assert_eq!(response.header("Accept-Ranges"), Some("bytes"));
+    assert_eq!(response.body, CONTENTS);
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use common::TempDir;
+use web_server::server::{Server, ServerHandle};
+
+/// Starts a server that allows two requests a minute per client.
+fn site(algorithm: &str) -> (TempDir, ServerHandle) {
+    let directory = TempDir::new(&[("index.html", b"<p>Home</p>")]);
+    
+    let server = common::start(directory.path(), json::object! {
+        "pages": [{ "name": "/", "path": "index.html" }],
+        "rate_limit": { "algorithm": algorithm, "window_secs": 60, "max_requests": 2 },
+    });
+    
+    (directory, server)
+}
+
+#[test]
+fn requests_over_the_limit_are_refused() {
+    for algorithm in ["token_bucket", "sliding_window"] {
+        let (_directory, server) = site(algorithm);
+        
+        assert_eq!(common::get(server.local_addr(), "/", &[]).status_code, 200, "{}", algorithm);
+        assert_eq!(common::get(server.local_addr(), "/", &[]).status_code, 200, "{}", algorithm);
+        
+        let response = common::get(server.local_addr(), "/", &[]);
+        
+        assert_eq!(response.status_code, 429, "{}", algorithm);
+        assert!(!String::from_utf8_lossy(&response.body).contains("Home"), "{}", algorithm);
+    }
+}
+
+#[test]
+fn refused_requests_say_when_to_retry() {
+    // A token is earned every 30 seconds, whereas the window only frees up once the first request is a minute old.
+    for (algorithm, max_retry_after) in [("token_bucket", 30), ("sliding_window", 60)] {
+        let (_directory, server) = site(algorithm);
+        
+        common::get(server.local_addr(), "/", &[]);
+        common::get(server.local_addr(), "/", &[]);
+        
+        let response = common::get(server.local_addr(), "/", &[]);
+        let retry_after = response.header("Retry-After").and_then(|retry_after| retry_after.parse::<u64>().ok());
+        
+        assert!(retry_after.is_some_and(|retry_after| retry_after > max_retry_after - 5 && retry_after <= max_retry_after), "{}: {:?}", algorithm, retry_after);
+    }
+}
+
+#[test]
+fn unknown_algorithms_are_rejected() {
+    let directory = TempDir::new(&[("index.html", b"<p>Home</p>")]);
+    
+    let config = json::object! {
+        "verbose": false,
+        "thread_count": 2,
+        "port": 0,
+        "web_root": directory.path().to_str().unwrap(),
+        "rate_limit": { "algorithm": "leaky_bucket", "window_secs": 60, "max_requests": 2 },
+    };
+    
+    let error = Server::new(&config).map(|_| ()).unwrap_err();
+    
+    assert!(format!("{:?}", error).contains("rate_limit.algorithm"), "{:?}", error);
+}
@@ -0,0 +1,160 @@
+mod common;
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+use common::TempDir;
+use web_server::http::{BodyReader, Request, Response};
+use web_server::router::{Handler, RouteParams};
+use web_server::server::{Server, ServerHandle};
+
+/// Answers with how many pieces the body arrived in and how many bytes they added up to.
+struct PieceCounter;
+
+impl Handler for PieceCounter {
+    fn handle(&self, _request: &Request, _params: &RouteParams) -> Response {
+        unreachable!("the body is always streamed to this handler");
+    }
+    
+    fn handle_body(&self, _request: &Request, _params: &RouteParams, body: &mut BodyReader) -> io::Result<Response> {
+        let (mut pieces, mut bytes) = (0, 0);
+        
+        while let Some(piece) = body.read_chunk()? {
+            pieces += 1;
+            bytes += piece.len();
+        }
+        
+        let mut response = Response::ok();
+        response.set_body(&format!("{} {}", pieces, bytes));
+        
+        Ok(response)
+    }
+}
+
+/// Starts a server with a streaming handler, a buffering one and one that ignores the body.
+fn site() -> (TempDir, ServerHandle) {
+    let directory = TempDir::new(&[("index.html", b"<p>Home</p>")]);
+    
+    let config = json::object! {
+        "verbose": false,
+        "thread_count": 2,
+        "port": 0,
+        "bind_address": "127.0.0.1",
+        "web_root": directory.path().to_str().unwrap(),
+        "error_log": directory.path().with_extension("error.log").to_str().unwrap(),
+        "pages": [{ "name": "/", "path": "index.html" }],
+        "max_body_size": 100_000,
+    };
+    
+    let mut server = Server::new(&config).unwrap();
+    server.route("/count", PieceCounter).unwrap();
+    server.route("/echo", |request: &Request| {
+        let mut response = Response::ok();
+        response.set_body_bytes(request.get_body());
+        
+        response
+    }).unwrap();
+    server.route("/ignore", |_: &Request| Response::ok()).unwrap();
+    
+    (directory, server.start().unwrap())
+}
+
+/// Posts a body with the given length to a path.
+fn post(server: &ServerHandle, path: &str, body: &[u8]) -> common::RawResponse {
+    let mut request = format!("POST {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n", path, body.len()).into_bytes();
+    request.extend(body);
+    
+    common::send(server.local_addr(), &String::from_utf8(request).unwrap())
+}
+
+#[test]
+fn handlers_read_the_body_as_it_arrives() {
+    let (_directory, server) = site();
+    
+    let response = post(&server, "/count", &[b'a'; 50_000]);
+    let body = String::from_utf8(response.body).unwrap();
+    let (pieces, bytes) = body.split_once(' ').unwrap();
+    
+    assert_eq!(response.status_code, 200);
+    assert_eq!(bytes, "50000");
+    assert!(pieces.parse::<usize>().unwrap() > 1, "{}", body);
+}
+
+#[test]
+fn handlers_that_dont_stream_get_the_whole_body() {
+    let (_directory, server) = site();
+    
+    let response = post(&server, "/echo", &[b'a'; 50_000]);
+    
+    assert_eq!(response.status_code, 200);
+    assert_eq!(response.body, vec![b'a'; 50_000]);
+}
+
+#[test]
+fn unread_bodies_are_skipped_before_the_next_request() {
+    let (_directory, server) = site();
+    
+    let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+    stream.write_all(b"POST /ignore HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello").unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+    
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    
+    assert_eq!(response.matches("HTTP/1.1 200 OK").count(), 2, "{}", response);
+    assert!(response.ends_with("<p>Home</p>"), "{}", response);
+}
+
+#[test]
+fn streamed_bodies_over_the_limit_are_refused() {
+    let (_directory, server) = site();
+    
+    let request = "POST /count HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nTransfer-Encoding: chunked\r\n\r\n186a1\r\n";
+    
+    assert_eq!(common::send(server.local_addr(), request).status_code, 413);
+}
+
+#[test]
+fn malformed_streamed_bodies_are_refused() {
+    let (_directory, server) = site();
+    
+    let request = "POST /count HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n";
+    
+    assert_eq!(common::send(server.local_addr(), request).status_code, 400);
+}
+
+
+/// Sends a request as is and returns everything the server answers with, interim responses included.
+fn exchange(server: &ServerHandle, request: &str) -> String {
+    let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+    
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    
+    response
+}
+
+#[test]
+fn clients_about_to_send_a_body_are_told_to_continue() {
+    let (_directory, server) = site();
+    
+    let response = exchange(&server, "POST /echo HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nExpect: 100-continue\r\nContent-Length: 2\r\n\r\nhi");
+    
+    assert!(response.starts_with("HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.ends_with("\r\n\r\nhi"), "{}", response);
+}
+
+#[test]
+fn requests_without_a_body_are_not_told_to_continue() {
+    let (_directory, server) = site();
+    
+    for request in [
+        "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nExpect: 100-continue\r\n\r\n",
+        "POST /echo HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nExpect: 100-continue\r\nContent-Length: 0\r\n\r\n",
+    ] {
+        let response = exchange(&server, request);
+        
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    }
+}
@@ -0,0 +1,138 @@
+mod common;
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+use common::TempDir;
+use web_server::http::Request;
+use web_server::server::{Server, ServerHandle};
+use web_server::websocket::{Message, WebSocket};
+
+const KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+
+/// Starts a server with a WebSocket route that echoes text messages.
+fn site() -> (TempDir, ServerHandle) {
+    let directory = TempDir::new(&[("index.html", b"<p>Home</p>")]);
+    
+    let config = json::object! {
+        "verbose": false,
+        "thread_count": 2,
+        "port": 0,
+        "bind_address": "127.0.0.1",
+        "web_root": directory.path().to_str().unwrap(),
+        "error_log": directory.path().with_extension("error.log").to_str().unwrap(),
+        "pages": [{ "name": "/", "path": "index.html" }],
+    };
+    
+    let mut server = Server::new(&config).unwrap();
+    server.websocket("/echo", |socket: &mut WebSocket, _: &Request| -> io::Result<()> {
+        loop {
+            match socket.read_message()? {
+                Message::Text(text) => socket.send(&Message::Text(text))?,
+                Message::Close(..) => return Ok(()),
+                _ => {}
+            }
+        }
+    }).unwrap();
+    
+    (directory, server.start().unwrap())
+}
+
+/// Sends an upgrade request for `/echo` with the given handshake headers.
+fn upgrade(server: &ServerHandle, headers: &str) -> common::RawResponse {
+    common::send(server.local_addr(), &format!("GET /echo HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade\r\nUpgrade: websocket\r\n{}\r\n", headers))
+}
+
+/// Writes a masked frame, the way a client has to.
+fn write_frame(stream: &mut TcpStream, opcode: u8, payload: &[u8]) {
+    let mask = [1, 2, 3, 4];
+    let mut frame = vec![0x80 | opcode, 0x80 | payload.len() as u8];
+    frame.extend(mask);
+    frame.extend(payload.iter().enumerate().map(|(index, byte)| byte ^ mask[index % 4]));
+    
+    stream.write_all(&frame).unwrap();
+}
+
+/// Reads a short, unmasked frame from the server.
+fn read_frame(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+    let mut head = [0; 2];
+    stream.read_exact(&mut head).unwrap();
+    
+    let mut payload = vec![0; usize::from(head[1] & 0x7F)];
+    stream.read_exact(&mut payload).unwrap();
+    
+    (head[0] & 0x0F, payload)
+}
+
+#[test]
+fn valid_handshakes_switch_protocols() {
+    let (_directory, server) = site();
+    
+    let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+    write!(stream, "GET /echo HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade\r\nUpgrade: websocket\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: {}\r\n\r\n", KEY).unwrap();
+    
+    // Read the head one byte at a time, so no frame is consumed along with it.
+    let mut head = Vec::new();
+    
+    while !head.ends_with(b"\r\n\r\n") {
+        let mut byte = [0];
+        stream.read_exact(&mut byte).unwrap();
+        head.push(byte[0]);
+    }
+    
+    let head = String::from_utf8(head).unwrap();
+    
+    assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"), "{}", head);
+    assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"), "{}", head);
+    
+    write_frame(&mut stream, 0x1, b"hello");
+    
+    assert_eq!(read_frame(&mut stream), (0x1, b"hello".to_vec()));
+    
+    // The close code is echoed to complete the closing handshake.
+    write_frame(&mut stream, 0x8, &1000u16.to_be_bytes());
+    
+    assert_eq!(read_frame(&mut stream), (0x8, 1000u16.to_be_bytes().to_vec()));
+}
+
+#[test]
+fn other_versions_are_upgrade_required() {
+    let (_directory, server) = site();
+    
+    let response = upgrade(&server, &format!("Sec-WebSocket-Version: 8\r\nSec-WebSocket-Key: {}\r\n", KEY));
+    
+    assert_eq!(response.status_code, 426);
+    assert_eq!(response.header("Sec-WebSocket-Version"), Some("13"));
+    assert_eq!(response.header("Upgrade"), Some("websocket"));
+}
+
+#[test]
+fn malformed_keys_are_bad_requests() {
+    let (_directory, server) = site();
+    
+    let response = upgrade(&server, "Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: c2hvcnQ=\r\n");
+    
+    assert_eq!(response.status_code, 400);
+    assert_eq!(response.header("Sec-WebSocket-Accept"), None);
+}
+
+#[test]
+fn plain_requests_for_a_websocket_route_are_upgrade_required() {
+    let (_directory, server) = site();
+    
+    let response = common::get(server.local_addr(), "/echo", &[]);
+    
+    assert_eq!(response.status_code, 426);
+    assert_eq!(response.header("Upgrade"), Some("websocket"));
+}
+
+#[test]
+fn upgrades_elsewhere_are_served_as_plain_requests() {
+    let (_directory, server) = site();
+    
+    let request = format!("GET / HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade, close\r\nUpgrade: websocket\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: {}\r\n\r\n", KEY);
+    let response = common::send(server.local_addr(), &request);
+    
+    assert_eq!(response.status_code, 200);
+    assert_eq!(response.body, b"<p>Home</p>");
+}